@@ -0,0 +1,13 @@
+use mesher_basic::{MailboxConfig, MailboxServer};
+
+fn main() {
+  let mut args = std::env::args().skip(1);
+  let sock = args.next().unwrap_or("[::1]:18560".to_owned());
+
+  let server = MailboxServer::start(&sock[..], MailboxConfig::default()).expect("Failed to start mailbox server");
+  println!("Serving mailboxes at http://{}/", server.addr());
+
+  loop {
+    std::thread::park();
+  }
+}
@@ -0,0 +1,141 @@
+use mesher::prelude::*;
+
+use std::{
+  collections::HashMap,
+  sync::{
+    mpsc::{channel, Receiver, Sender},
+    Arc, Mutex,
+  },
+};
+
+/// Connects any number of [`Channel`](struct.Channel.html) transports in the same process, so they can send to each other by name.
+///
+/// A channel listening on `chan:name` will receive everything any other channel from the same hub sends to `chan:name`.
+/// Paths are told apart by their authority and path, so `chan:name/a` and `chan:name/b` can go to different channels; the query is ignored.
+/// Hubs are entirely separate from each other, and clones share the same underlying hub.
+#[derive(Clone, Default)]
+pub struct ChannelHub {
+  inboxes: Arc<Mutex<HashMap<String, Sender<Vec<u8>>>>>,
+}
+
+impl ChannelHub {
+  /// Creates a new hub with nothing attached to it.
+  pub fn new() -> ChannelHub {
+    ChannelHub::default()
+  }
+
+  /// Creates a new channel transport attached to this hub.
+  pub fn transport(&self) -> Channel {
+    Channel::new_with_target(Target::Hub(self.clone()))
+  }
+}
+
+/// The name a path's inbox is filed under in a hub.
+fn inbox_name(path: &MesherUrl) -> String {
+  format!("{}{}", path.authority(), path.path())
+}
+
+enum Target {
+  Hub(ChannelHub),
+  Peer(Sender<Vec<u8>>),
+}
+
+/// A transport which sends data over in-process channels, for meshers running in different threads of the same program.
+///
+/// There are two ways to connect them:
+///
+/// - [`Channel::pair`](#method.pair) creates two transports linked directly to each other.
+///   Everything one sends goes to the other, regardless of the path, and there's no need to listen.
+/// - [`ChannelHub::transport`](struct.ChannelHub.html#method.transport) creates transports which can reach any other from the same hub, by the path they listen on.
+///
+/// Either way, since it needs to know what it's connected to, this can't be created through [`Mesher::add_transport`](../mesher/struct.Mesher.html#method.add_transport):
+///
+/// ```
+/// use mesher::prelude::*;
+/// use mesher_basic::ChannelHub;
+///
+/// let hub = ChannelHub::new();
+/// let node_transport = hub.transport();
+/// std::thread::spawn(move || {
+///   let mut node = Mesher::unsigned(vec![encrypt::gen_keypair().1]);
+///   node.add_transport_instance("chan", node_transport);
+///   node.listen_on("chan:node").expect("Failed to listen");
+///   // ...
+/// });
+/// ```
+pub struct Channel {
+  target: Target,
+  sender: Sender<Vec<u8>>,
+  receiver: Receiver<Vec<u8>>,
+}
+
+impl Channel {
+  fn new_with_target(target: Target) -> Channel {
+    let (sender, receiver) = channel();
+    Channel {
+      target,
+      sender,
+      receiver,
+    }
+  }
+
+  /// Creates two transports linked directly to each other.
+  pub fn pair() -> (Channel, Channel) {
+    let (a_sender, a_receiver) = channel();
+    let (b_sender, b_receiver) = channel();
+    let a = Channel {
+      target: Target::Peer(b_sender.clone()),
+      sender: a_sender.clone(),
+      receiver: a_receiver,
+    };
+    let b = Channel {
+      target: Target::Peer(a_sender),
+      sender: b_sender,
+      receiver: b_receiver,
+    };
+    (a, b)
+  }
+}
+
+impl Transport for Channel {
+  fn new(_scheme: &str) -> fail::Result<Self> {
+    Err(fail::MesherFail::SetupFailure(
+      "Channel must be created through Channel::pair or ChannelHub::transport".to_owned(),
+    ))
+  }
+
+  fn send(&mut self, path: MesherUrl, blob: Vec<u8>) -> fail::Result<()> {
+    match &self.target {
+      Target::Peer(peer) => peer
+        .send(blob)
+        .map_err(|_| fail::MesherFail::SendFailure("Other end of channel pair was dropped".to_owned())),
+      Target::Hub(hub) => {
+        let name = inbox_name(&path);
+        let mut inboxes = hub.inboxes.lock().expect("poisoned lock?");
+        let inbox = inboxes
+          .get(&name)
+          .ok_or_else(|| fail::MesherFail::SendFailure(format!("Nothing listening on {}", path)))?;
+        if inbox.send(blob).is_err() {
+          inboxes.remove(&name);
+          return Err(fail::MesherFail::SendFailure(format!(
+            "Channel listening on {} was dropped",
+            path
+          )));
+        }
+        Ok(())
+      }
+    }
+  }
+
+  fn listen(&mut self, path: MesherUrl) -> fail::Result<()> {
+    if let Target::Hub(hub) = &self.target {
+      let mut inboxes = hub.inboxes.lock().expect("poisoned lock?");
+      inboxes.insert(inbox_name(&path), self.sender.clone());
+    }
+    Ok(())
+  }
+
+  fn receive(&mut self) -> fail::Result<Vec<Vec<u8>>> {
+    Ok(self.receiver.try_iter().collect())
+  }
+}
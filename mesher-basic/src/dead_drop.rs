@@ -0,0 +1,241 @@
+use mesher::prelude::*;
+
+use std::{
+  fs,
+  io::{self, prelude::*},
+  path::{Path, PathBuf},
+  time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// The extension on complete blobs, ready to be picked up.
+const BLOB_EXT: &str = "blob";
+
+/// The extension on blobs which are still being written.
+const PARTIAL_EXT: &str = "partial";
+
+/// The extension on blobs which a listener has claimed, and is in the middle of reading.
+const CLAIMED_EXT: &str = "claimed";
+
+fn dir_from_url(path: &MesherUrl) -> fail::Result<PathBuf> {
+  if !path.authority().is_empty() || path.path().is_empty() {
+    return Err(fail::MesherFail::InvalidURL(format!(
+      "not an absolute directory path: {}",
+      path
+    )));
+  }
+  Ok(PathBuf::from(path.path()))
+}
+
+fn nanos_since_epoch() -> u128 {
+  SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .unwrap_or_default()
+    .as_nanos()
+}
+
+/// The name a listener renames a blob to while it's reading it, which records when it was claimed.
+///
+/// The file's modification time can't be used for that, since it's still whenever the blob was written, which could be long ago if it was carried over from somewhere else.
+fn claimed_path(blob: &Path) -> PathBuf {
+  blob.with_extension(format!("{:024x}.{}", nanos_since_epoch(), CLAIMED_EXT))
+}
+
+/// The name a claimed blob had before it was claimed.
+fn released_path(claimed: &Path) -> PathBuf {
+  // the claim's timestamp is the extension on the stem
+  let stem = claimed
+    .file_stem()
+    .map(Path::new)
+    .and_then(Path::file_stem)
+    .unwrap_or_default();
+  claimed.with_file_name(stem).with_extension(BLOB_EXT)
+}
+
+/// How long ago a half-written or half-read file was last worked on.
+fn age(path: &Path, meta: &fs::Metadata) -> Duration {
+  let claimed_at = path
+    .file_stem()
+    .and_then(|stem| Path::new(stem).extension())
+    .and_then(|stamp| u64::from_str_radix(stamp.to_str()?, 16).ok())
+    .map(|nanos| UNIX_EPOCH + Duration::from_nanos(nanos));
+  let since = match path.extension().and_then(|e| e.to_str()) {
+    Some(CLAIMED_EXT) => claimed_at.or_else(|| meta.modified().ok()),
+    _ => meta.modified().ok(),
+  };
+  since.and_then(|t| t.elapsed().ok()).unwrap_or_default()
+}
+
+/// Settings for a [`DeadDrop`](struct.DeadDrop.html) transport which apply to every directory it uses.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeadDropConfig {
+  /// The largest blob that will be picked up.
+  /// Larger files are left where they are.
+  pub max_blob_size: u64,
+  /// How old a half-written or half-read file has to be before it's assumed the program working on it died.
+  /// Half-written files are then deleted, and half-read ones are put back to be picked up again.
+  /// This should be long enough for the slowest sync or copy to finish, and for the slowest listener to read the largest blob, or that blob could be received twice.
+  pub abandoned_after: Duration,
+}
+
+impl Default for DeadDropConfig {
+  fn default() -> DeadDropConfig {
+    DeadDropConfig {
+      max_blob_size: 1024 * 1024,
+      abandoned_after: Duration::from_secs(60 * 60),
+    }
+  }
+}
+
+/// Passes blobs along through a directory, e.g. `dir:/media/usb/mesher`.
+///
+/// Sending writes each blob to its own file in the directory, and receiving picks up every file there and deletes it.
+/// Nothing else about the directory matters, so anything that moves files around can carry the blobs:
+/// a USB stick carried between machines, a synced folder, or a shared network filesystem.
+///
+/// Files are written under a temporary name and renamed once they're complete, so a listener never sees half a blob.
+/// Their names start with when they were written, so blobs are picked up roughly in the order they were sent, and end with a random part, so senders on different machines never collide.
+/// Listeners claim a file by renaming it before reading it, so if several are polling the same directory, each blob is still only received once.
+///
+/// The directory has to already exist, both to send and to listen; it's never created.
+/// That way, a USB stick that isn't plugged in is an error, rather than a directory quietly created where it would have been mounted.
+/// If a directory disappears after it's been listened on, it's skipped when receiving until it comes back.
+pub struct DeadDrop {
+  config: DeadDropConfig,
+  dirs: Vec<PathBuf>,
+  counter: u64,
+}
+
+impl DeadDrop {
+  /// Creates a dead-drop transport with its own blob size limit and abandoned-claim timeout.
+  pub fn with_config(_scheme: &str, config: DeadDropConfig) -> DeadDrop {
+    DeadDrop {
+      config,
+      dirs: vec![],
+      counter: 0,
+    }
+  }
+
+  /// Comes up with a name for a new blob which sorts after everything sent earlier, and won't collide with anyone else's.
+  fn unique_name(&mut self) -> fail::Result<String> {
+    let mut random = [0; 8];
+    ring::rand::SecureRandom::fill(&ring::rand::SystemRandom::new(), &mut random)
+      .map_err(|_| fail::MesherFail::SendFailure("Failed to generate a file name".to_owned()))?;
+    let nanos = nanos_since_epoch();
+    self.counter = self.counter.wrapping_add(1);
+    Ok(format!(
+      "{:024x}-{:08x}-{:016x}",
+      nanos,
+      self.counter,
+      u64::from_be_bytes(random)
+    ))
+  }
+
+  /// Picks up every complete blob in the directory, oldest first, and cleans up after anything abandoned.
+  fn collect(&self, dir: &Path) -> io::Result<Vec<Vec<u8>>> {
+    let mut ready = vec![];
+    for entry in fs::read_dir(dir)? {
+      let path = entry?.path();
+      // symlinks are never followed, so nothing outside the directory can be read or deleted through one
+      let meta = match fs::symlink_metadata(&path) {
+        Ok(m) if m.is_file() => m,
+        _ => continue,
+      };
+      match path.extension().and_then(|e| e.to_str()) {
+        Some(BLOB_EXT) if meta.len() <= self.config.max_blob_size => ready.push(path),
+        Some(PARTIAL_EXT) if age(&path, &meta) > self.config.abandoned_after => {
+          let _ = fs::remove_file(&path);
+        }
+        // the blob was complete, only the listener reading it went away, so it's put back to be picked up again
+        Some(CLAIMED_EXT) if age(&path, &meta) > self.config.abandoned_after => {
+          let released = released_path(&path);
+          if fs::rename(&path, &released).is_ok() && meta.len() <= self.config.max_blob_size {
+            ready.push(released);
+          }
+        }
+        _ => (),
+      }
+    }
+    ready.sort();
+
+    let mut blobs = vec![];
+    for path in ready {
+      let claimed = claimed_path(&path);
+      // if this fails, another listener got to it first
+      if fs::rename(&path, &claimed).is_err() {
+        continue;
+      }
+      // it could have been swapped for a symlink since it was checked
+      if !fs::symlink_metadata(&claimed).is_ok_and(|m| m.is_file()) {
+        let _ = fs::rename(&claimed, &path);
+        continue;
+      }
+      let mut blob = vec![];
+      match fs::File::open(&claimed).and_then(|mut f| f.read_to_end(&mut blob)) {
+        Ok(_) => {
+          let _ = fs::remove_file(&claimed);
+          blobs.push(blob);
+        }
+        // put it back for the next try
+        Err(_) => {
+          let _ = fs::rename(&claimed, &path);
+        }
+      }
+    }
+    Ok(blobs)
+  }
+}
+
+impl Transport for DeadDrop {
+  fn new(scheme: &str) -> fail::Result<Self> {
+    Ok(DeadDrop::with_config(scheme, DeadDropConfig::default()))
+  }
+
+  fn send(&mut self, path: MesherUrl, blob: Vec<u8>) -> fail::Result<()> {
+    let dir = dir_from_url(&path)?;
+    if !dir.is_dir() {
+      return Err(fail::MesherFail::SendFailure(format!(
+        "{} isn't a directory",
+        dir.display()
+      )));
+    }
+    let name = self.unique_name()?;
+    let partial = dir.join(format!("{}.{}", name, PARTIAL_EXT));
+    let written = fs::File::create(&partial)
+      .and_then(|mut f| f.write_all(&blob).and_then(|_| f.sync_all()))
+      .and_then(|_| fs::rename(&partial, dir.join(format!("{}.{}", name, BLOB_EXT))));
+    if let Err(e) = written {
+      let _ = fs::remove_file(&partial);
+      return Err(fail::MesherFail::SendFailure(format!(
+        "Failed to write to {}: {:?}",
+        dir.display(),
+        e
+      )));
+    }
+    Ok(())
+  }
+
+  fn listen(&mut self, path: MesherUrl) -> fail::Result<()> {
+    let dir = dir_from_url(&path)?;
+    if !dir.is_dir() {
+      return Err(fail::MesherFail::ListenFailure(format!(
+        "{} isn't a directory",
+        dir.display()
+      )));
+    }
+    if !self.dirs.contains(&dir) {
+      self.dirs.push(dir);
+    }
+    Ok(())
+  }
+
+  fn receive(&mut self) -> fail::Result<Vec<Vec<u8>>> {
+    let mut blobs = vec![];
+    for dir in &self.dirs {
+      // a directory might be missing for a while, e.g. if it's on a USB stick, so it's just skipped until it's back
+      if let Ok(mut from_dir) = self.collect(dir) {
+        blobs.append(&mut from_dir);
+      }
+    }
+    Ok(blobs)
+  }
+}
@@ -0,0 +1,518 @@
+use mesher::prelude::*;
+
+use ring::rand::{SecureRandom, SystemRandom};
+use std::{
+  io,
+  net::{Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket},
+  time::{Duration, Instant},
+};
+
+/// The record type blobs are carried in.
+/// TXT records can hold arbitrary bytes, and unlike the NULL records some other tunnels use, nearly every resolver passes them along.
+pub(crate) const TXT: u16 = 16;
+
+const CLASS_IN: u16 = 1;
+
+/// The largest message sent either way, which is as large as a DNS message over UDP can be without EDNS.
+pub(crate) const MAX_MESSAGE: usize = 512;
+
+/// The longest a name can be, written out with dots and without a trailing one.
+const MAX_NAME: usize = 253;
+
+const MAX_LABEL: usize = 63;
+
+/// The most queries a single blob can be uploaded in, which keeps the index and count in every query to at most 5 digits.
+pub(crate) const MAX_CHUNKS: usize = 99_999;
+
+pub(crate) const NOERROR: u8 = 0;
+pub(crate) const FORMERR: u8 = 1;
+pub(crate) const REFUSED: u8 = 5;
+
+/// Base32 without padding, lowercased, since resolvers don't have to preserve the case of names.
+const BASE32: &[u8; 32] = b"abcdefghijklmnopqrstuvwxyz234567";
+
+pub(crate) fn base32_encode(data: &[u8]) -> String {
+  let mut out = String::with_capacity((data.len() * 8).div_ceil(5));
+  let (mut buffer, mut bits) = (0u32, 0);
+  for &byte in data {
+    buffer = (buffer << 8) | byte as u32;
+    bits += 8;
+    while bits >= 5 {
+      bits -= 5;
+      out.push(BASE32[(buffer >> bits) as usize & 31] as char);
+    }
+  }
+  if bits > 0 {
+    out.push(BASE32[(buffer << (5 - bits)) as usize & 31] as char);
+  }
+  out
+}
+
+/// Decodes base32 in either case, returning `None` if there's anything in it that isn't base32.
+pub(crate) fn base32_decode(text: &str) -> Option<Vec<u8>> {
+  let mut out = Vec::with_capacity(text.len() * 5 / 8);
+  let (mut buffer, mut bits) = (0u32, 0);
+  for c in text.bytes() {
+    let value = BASE32.iter().position(|&b| b == c.to_ascii_lowercase())? as u32;
+    buffer = (buffer << 5) | value;
+    bits += 5;
+    if bits >= 8 {
+      bits -= 8;
+      out.push((buffer >> bits) as u8);
+    }
+  }
+  Some(out)
+}
+
+fn is_label(label: &str) -> bool {
+  !label.is_empty() && label.len() <= MAX_LABEL && label.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-')
+}
+
+/// Whether the name is made of nothing but ordinary hostname labels, and is short enough to be a name at all.
+pub(crate) fn is_name(name: &str) -> bool {
+  name.len() <= MAX_NAME && name.split('.').all(is_label)
+}
+
+/// Splits a path into the zone the tunnel's server is authoritative for and the mailbox on it, both lowercased.
+fn mailbox_from_url(path: &MesherUrl) -> fail::Result<(String, String)> {
+  let zone = path.authority().trim_end_matches('.').to_ascii_lowercase();
+  let mailbox = path.path().strip_prefix('/').unwrap_or_default().to_ascii_lowercase();
+  if !is_name(&zone) || !is_label(&mailbox) {
+    return Err(fail::MesherFail::InvalidURL(format!(
+      "DNS mailboxes look like dns:tunnel.example.com/mailbox, not {}",
+      path
+    )));
+  }
+  Ok((zone, mailbox))
+}
+
+fn u16_at(msg: &[u8], pos: usize) -> Option<u16> {
+  Some(u16::from_be_bytes([*msg.get(pos)?, *msg.get(pos + 1)?]))
+}
+
+/// Writes out a name in its wire format, as a series of length-prefixed labels.
+/// It has to be a valid name already.
+fn write_name(name: &str, out: &mut Vec<u8>) {
+  for label in name.split('.').filter(|l| !l.is_empty()) {
+    out.push(label.len() as u8);
+    out.extend_from_slice(label.as_bytes());
+  }
+  out.push(0);
+}
+
+/// Reads a name in its wire format starting at `*pos`, following any compression pointers, and moves `*pos` past it.
+fn read_name(msg: &[u8], pos: &mut usize) -> Option<String> {
+  let mut labels = vec![];
+  let mut at = *pos;
+  let mut jumped = false;
+  // pointers have to point backwards, so they can't loop, but this still bounds how much work a hostile message can cause
+  for _ in 0..256 {
+    let len = *msg.get(at)? as usize;
+    if len == 0 {
+      if !jumped {
+        *pos = at + 1;
+      }
+      return Some(labels.join("."));
+    } else if len & 0xC0 == 0xC0 {
+      let target = ((len & 0x3F) << 8) | *msg.get(at + 1)? as usize;
+      if target >= at {
+        return None;
+      }
+      if !jumped {
+        *pos = at + 2;
+        jumped = true;
+      }
+      at = target;
+    } else if len <= MAX_LABEL {
+      labels.push(String::from_utf8_lossy(msg.get(at + 1..at + 1 + len)?).into_owned());
+      at += 1 + len;
+    } else {
+      return None;
+    }
+  }
+  None
+}
+
+/// A query received by a server, with just the parts it needs to answer it.
+pub(crate) struct Query {
+  pub(crate) id: u16,
+  pub(crate) recursion_desired: bool,
+  pub(crate) name: String,
+  pub(crate) qtype: u16,
+  /// The question exactly as it was sent, to be copied into the response.
+  question: Vec<u8>,
+}
+
+impl Query {
+  /// Parses a standard query with a single question, returning `None` if it's anything else.
+  pub(crate) fn parse(msg: &[u8]) -> Option<Query> {
+    let id = u16_at(msg, 0)?;
+    let flags = u16_at(msg, 2)?;
+    // it has to be a query, not a response, and a standard one
+    if flags & 0xF800 != 0 || u16_at(msg, 4)? != 1 {
+      return None;
+    }
+    let mut end = 12;
+    let name = read_name(msg, &mut end)?;
+    let qtype = u16_at(msg, end)?;
+    end += 4;
+    Some(Query {
+      id,
+      recursion_desired: flags & 0x0100 != 0,
+      name,
+      qtype,
+      question: msg.get(12..end)?.to_vec(),
+    })
+  }
+
+  /// How many bytes of TXT data fit in a response to this query without going over the message size limit.
+  pub(crate) fn txt_capacity(&self) -> usize {
+    // the header, the question, and the answer's compressed name, type, class, TTL, and length
+    let available = MAX_MESSAGE.saturating_sub(12 + self.question.len() + 12);
+    // each string of up to 255 bytes costs another byte for its length
+    available.saturating_sub(available.div_ceil(256))
+  }
+
+  /// Builds a response to the query, with a TXT record holding the data if there is any.
+  pub(crate) fn respond(&self, rcode: u8, txt: Option<&[u8]>) -> Vec<u8> {
+    let mut msg = Vec::with_capacity(MAX_MESSAGE);
+    msg.extend_from_slice(&self.id.to_be_bytes());
+    // a response, with an authoritative answer
+    msg.push(0x84 | self.recursion_desired as u8);
+    msg.push(rcode);
+    msg.extend_from_slice(&[0, 1, 0, txt.is_some() as u8, 0, 0, 0, 0]);
+    msg.extend_from_slice(&self.question);
+    if let Some(txt) = txt {
+      // a pointer back to the name in the question
+      msg.extend_from_slice(&[0xC0, 12]);
+      msg.extend_from_slice(&TXT.to_be_bytes());
+      msg.extend_from_slice(&CLASS_IN.to_be_bytes());
+      // a TTL of 0, since every answer is only good once
+      msg.extend_from_slice(&[0, 0, 0, 0]);
+      let strings = txt.chunks(255).collect::<Vec<_>>();
+      // TXT records have to have at least one string, even if it's empty
+      let rdlen = txt.len() + strings.len().max(1);
+      msg.extend_from_slice(&(rdlen as u16).to_be_bytes());
+      if strings.is_empty() {
+        msg.push(0);
+      }
+      for string in strings {
+        msg.push(string.len() as u8);
+        msg.extend_from_slice(string);
+      }
+    }
+    msg
+  }
+}
+
+fn build_query(id: u16, name: &str) -> Vec<u8> {
+  let mut msg = Vec::with_capacity(MAX_MESSAGE);
+  msg.extend_from_slice(&id.to_be_bytes());
+  // a standard query, asking for recursion so resolvers pass it along
+  msg.extend_from_slice(&[0x01, 0x00]);
+  // one question, and nothing else
+  msg.extend_from_slice(&[0, 1, 0, 0, 0, 0, 0, 0]);
+  write_name(name, &mut msg);
+  msg.extend_from_slice(&TXT.to_be_bytes());
+  msg.extend_from_slice(&CLASS_IN.to_be_bytes());
+  msg
+}
+
+/// Parses the response to a query, returning its response code and the contents of its TXT records, all concatenated.
+/// Returns `None` if it isn't a response to that query at all.
+fn parse_response(msg: &[u8], id: u16, name: &str) -> Option<(u8, Option<Vec<u8>>)> {
+  if u16_at(msg, 0)? != id || msg.get(2)? & 0x80 == 0 || u16_at(msg, 4)? != 1 {
+    return None;
+  }
+  let rcode = msg.get(3)? & 0x0F;
+  let answers = u16_at(msg, 6)?;
+  let mut pos = 12;
+  // resolvers can change the case of the names they pass along
+  if !read_name(msg, &mut pos)?.eq_ignore_ascii_case(name) {
+    return None;
+  }
+  pos += 4;
+
+  let mut txt: Option<Vec<u8>> = None;
+  for _ in 0..answers {
+    read_name(msg, &mut pos)?;
+    let rtype = u16_at(msg, pos)?;
+    let rdlen = u16_at(msg, pos + 8)? as usize;
+    let rdata = msg.get(pos + 10..pos + 10 + rdlen)?;
+    pos += 10 + rdlen;
+    if rtype != TXT {
+      continue;
+    }
+    let txt = txt.get_or_insert_with(Vec::new);
+    let mut at = 0;
+    while at < rdata.len() {
+      let len = rdata[at] as usize;
+      txt.extend_from_slice(rdata.get(at + 1..at + 1 + len)?);
+      at += 1 + len;
+    }
+  }
+  Some((rcode, txt))
+}
+
+/// Settings for a [`DNS`](struct.DNS.html) transport which apply to every mailbox it uses.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DnsConfig {
+  /// Where queries are sent.
+  /// This can be the tunnel's server itself, or any resolver that will pass queries on to it, like the one the network provides.
+  /// The default is a resolver on the local machine.
+  pub resolver: SocketAddr,
+  /// How long to wait for the response to each query.
+  pub timeout: Duration,
+  /// How many more times a query is sent if there's no response, before giving up on it.
+  pub retries: usize,
+  /// How often mailboxes are checked for new blobs.
+  /// Calling `receive` more often than this returns nothing, rather than hammering the server.
+  pub poll_interval: Duration,
+  /// The largest blob that will be accepted from a mailbox.
+  /// Larger ones are thrown away without being downloaded.
+  pub max_packet_size: usize,
+  /// The most blobs taken from mailboxes in a single `receive`, counting any that were too large and thrown away.
+  /// Each one takes several queries, so this keeps a full mailbox from holding up `receive` for minutes.
+  /// Whatever's left is taken on the next call, without waiting for the poll interval.
+  pub max_blobs_per_receive: usize,
+}
+
+impl Default for DnsConfig {
+  fn default() -> DnsConfig {
+    DnsConfig {
+      resolver: (Ipv4Addr::LOCALHOST, 53).into(),
+      timeout: Duration::from_secs(2),
+      retries: 2,
+      poll_interval: Duration::from_secs(5),
+      max_packet_size: 1024 * 1024,
+      max_blobs_per_receive: 16,
+    }
+  }
+}
+
+/// Sends and receives blobs through mailboxes on a [`DnsServer`](struct.DnsServer.html), using nothing but DNS queries, e.g. `dns:tunnel.example.com/3f9a61c2`.
+///
+/// The path's authority is the zone the server is authoritative for, and its path is the mailbox, which has to be a single DNS label.
+/// Queries go to [`DnsConfig::resolver`](struct.DnsConfig.html#structfield.resolver), so if that's a resolver which follows the zone's delegation, blobs get through networks where DNS is the only thing allowed out.
+///
+/// Sending splits the blob into base32-encoded chunks small enough to fit in a name, and sends one TXT query per chunk.
+/// Receiving asks for each mailbox's first blob, as many bytes as fit in a response at a time, then tells the server it can be removed, repeating until the mailbox is empty or [`DnsConfig::max_blobs_per_receive`](struct.DnsConfig.html#structfield.max_blobs_per_receive) have been taken.
+/// Every query has a name that's never been asked before, so resolvers' caches don't get in the way.
+///
+/// That's a lot of queries for even small blobs, around one per 100 bytes sent and one per 400 received, so this is slow, and very visible to anyone watching the network's DNS.
+/// Anyone who knows a mailbox's name can empty it, so their names should be long and random, and only one node should listen on each.
+/// Mailboxes are only checked every [`DnsConfig::poll_interval`](struct.DnsConfig.html#structfield.poll_interval), and a server that can't be reached is skipped until the next check.
+pub struct DNS {
+  random: SystemRandom,
+  config: DnsConfig,
+  mailboxes: Vec<(String, String)>,
+  last_poll: Option<Instant>,
+}
+
+impl DNS {
+  /// Creates a DNS transport that uses a particular resolver, timeouts, and polling rate.
+  pub fn with_config(_scheme: &str, config: DnsConfig) -> DNS {
+    DNS {
+      random: SystemRandom::new(),
+      config,
+      mailboxes: vec![],
+      last_poll: None,
+    }
+  }
+
+  fn random_u32(&self) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    self
+      .random
+      .fill(&mut bytes)
+      .map_err(|_| io::Error::other("failed to generate random number"))?;
+    Ok(u32::from_be_bytes(bytes))
+  }
+
+  /// Sends a TXT query for the name, retrying if there's no response, and returns the contents of the TXT records in the response.
+  fn ask(&self, name: &str) -> io::Result<Vec<u8>> {
+    let local: SocketAddr = if self.config.resolver.is_ipv6() {
+      (Ipv6Addr::UNSPECIFIED, 0).into()
+    } else {
+      (Ipv4Addr::UNSPECIFIED, 0).into()
+    };
+    // a new socket every time means a new source port, which makes responses harder to spoof
+    let socket = UdpSocket::bind(local)?;
+    socket.connect(self.config.resolver)?;
+    let id = self.random_u32()? as u16;
+    let query = build_query(id, name);
+    let mut buf = [0; 4096];
+
+    for _ in 0..=self.config.retries {
+      socket.send(&query)?;
+      let deadline = Instant::now() + self.config.timeout;
+      loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining == Duration::from_secs(0) {
+          break;
+        }
+        socket.set_read_timeout(Some(remaining))?;
+        let len = match socket.recv(&mut buf) {
+          Ok(len) => len,
+          Err(e) if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut => break,
+          Err(e) => return Err(e),
+        };
+        match parse_response(&buf[..len], id, name) {
+          // something else, or something garbled, so keep waiting for the real response
+          None => continue,
+          Some((NOERROR, Some(txt))) => return Ok(txt),
+          Some((NOERROR, None)) => return Err(io::Error::other("no TXT record in the response")),
+          Some((rcode, _)) => return Err(io::Error::other(format!("response code {}", rcode))),
+        }
+      }
+    }
+    Err(io::Error::new(io::ErrorKind::TimedOut, "no response"))
+  }
+
+  /// Gets part of the first blob in the mailbox, starting at `offset`, along with its full length and the tag the server gave it.
+  /// `None` means the mailbox is empty.
+  fn fetch(&self, zone: &str, mailbox: &str, offset: usize) -> io::Result<Option<(usize, u32, Vec<u8>)>> {
+    let name = format!("{:08x}.{}.get.{}.{}", self.random_u32()?, offset, mailbox, zone);
+    let mut txt = self.ask(&name)?;
+    if txt.is_empty() {
+      return Ok(None);
+    }
+    if txt.len() < 8 {
+      return Err(io::Error::new(io::ErrorKind::InvalidData, "truncated response"));
+    }
+    let data = txt.split_off(8);
+    let len = u32::from_be_bytes([txt[0], txt[1], txt[2], txt[3]]) as usize;
+    let tag = u32::from_be_bytes([txt[4], txt[5], txt[6], txt[7]]);
+    Ok(Some((len, tag, data)))
+  }
+
+  /// Gets what's waiting in the mailbox, one blob at a time, until it's empty or `allowed` more have been taken.
+  /// Blobs are removed from the mailbox as they're added to `blobs`, so they're kept even if something goes wrong partway through.
+  fn collect(&self, zone: &str, mailbox: &str, allowed: &mut usize, blobs: &mut Vec<Vec<u8>>) -> io::Result<()> {
+    'blobs: while *allowed > 0 {
+      let (len, tag, mut blob) = match self.fetch(zone, mailbox, 0)? {
+        Some(first) => first,
+        None => break,
+      };
+      if len <= self.config.max_packet_size {
+        while blob.len() < len {
+          match self.fetch(zone, mailbox, blob.len())? {
+            Some((next_len, next_tag, more)) if next_len == len && next_tag == tag => {
+              if more.is_empty() {
+                return Err(io::Error::new(
+                  io::ErrorKind::InvalidData,
+                  "server stopped partway through a blob",
+                ));
+              }
+              blob.extend_from_slice(&more);
+            }
+            // something else took the blob first, so start over on whatever's next
+            _ => continue 'blobs,
+          }
+        }
+        if blob.len() != len {
+          return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "server sent more than the whole blob",
+          ));
+        }
+      }
+      let name = format!("{:08x}.{:08x}.ack.{}.{}", self.random_u32()?, tag, mailbox, zone);
+      self.ask(&name)?;
+      *allowed -= 1;
+      if len <= self.config.max_packet_size {
+        blobs.push(blob);
+      }
+    }
+    Ok(())
+  }
+}
+
+impl Transport for DNS {
+  fn new(scheme: &str) -> fail::Result<Self> {
+    Ok(DNS::with_config(scheme, DnsConfig::default()))
+  }
+
+  fn send(&mut self, path: MesherUrl, blob: Vec<u8>) -> fail::Result<()> {
+    let (zone, mailbox) = mailbox_from_url(&path)?;
+    let send_fail = |e: io::Error| fail::MesherFail::SendFailure(format!("Failed to send to {}: {}", path, e));
+
+    // everything after the data: the chunk's index, the number of chunks, the blob's ID, `up`, the mailbox, and the zone
+    let digits = MAX_CHUNKS.to_string().len();
+    let suffix_len = digits + 1 + digits + 1 + 8 + 1 + 2 + 1 + mailbox.len() + 1 + zone.len();
+    let available = MAX_NAME.saturating_sub(suffix_len);
+    // each label of data needs a dot after it
+    let chars = available - available.div_ceil(MAX_LABEL + 1);
+    let chunk_size = chars * 5 / 8;
+    if chunk_size == 0 {
+      return Err(fail::MesherFail::InvalidURL(format!(
+        "no room for any data in names under {}",
+        path
+      )));
+    }
+    let chunks = if blob.is_empty() {
+      vec![&blob[..]]
+    } else {
+      blob.chunks(chunk_size).collect()
+    };
+    if chunks.len() > MAX_CHUNKS {
+      return Err(fail::MesherFail::SendFailure(format!(
+        "blob too large to send to {}",
+        path
+      )));
+    }
+
+    let id = self.random_u32().map_err(send_fail)?;
+    for (index, chunk) in chunks.iter().enumerate() {
+      let mut name = String::with_capacity(MAX_NAME);
+      let encoded = base32_encode(chunk);
+      for label in encoded.as_bytes().chunks(MAX_LABEL) {
+        // base32 is all ASCII, so it can be split anywhere
+        name.push_str(std::str::from_utf8(label).expect("base32 isn't ASCII?"));
+        name.push('.');
+      }
+      name.push_str(&format!(
+        "{}.{}.{:08x}.up.{}.{}",
+        index,
+        chunks.len(),
+        id,
+        mailbox,
+        zone
+      ));
+      self.ask(&name).map_err(send_fail)?;
+    }
+    Ok(())
+  }
+
+  fn listen(&mut self, path: MesherUrl) -> fail::Result<()> {
+    let mailbox = mailbox_from_url(&path)?;
+    if !self.mailboxes.contains(&mailbox) {
+      self.mailboxes.push(mailbox);
+      // check the new mailbox straight away, rather than waiting for the next poll
+      self.last_poll = None;
+    }
+    Ok(())
+  }
+
+  fn receive(&mut self) -> fail::Result<Vec<Vec<u8>>> {
+    if let Some(last) = self.last_poll {
+      if last.elapsed() < self.config.poll_interval {
+        return Ok(vec![]);
+      }
+    }
+    self.last_poll = Some(Instant::now());
+
+    let mut blobs = vec![];
+    let mut allowed = self.config.max_blobs_per_receive;
+    for (zone, mailbox) in &self.mailboxes {
+      // the server might be unreachable for a while, so it's just skipped until it's back
+      let _ = self.collect(zone, mailbox, &mut allowed, &mut blobs);
+    }
+    if allowed == 0 && !self.mailboxes.is_empty() {
+      // there may well be more, so check again next time, starting with the next mailbox so a busy one can't hog every call
+      self.last_poll = None;
+      self.mailboxes.rotate_left(1);
+    }
+    Ok(blobs)
+  }
+}
@@ -0,0 +1,282 @@
+use mesher::prelude::*;
+
+use crate::dns::{base32_decode, is_name, Query, FORMERR, MAX_CHUNKS, MAX_MESSAGE, NOERROR, REFUSED, TXT};
+
+use ring::rand::{SecureRandom, SystemRandom};
+use std::{
+  collections::{hash_map::Entry, HashMap, VecDeque},
+  net::{SocketAddr, ToSocketAddrs, UdpSocket},
+  sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+  },
+  thread::Builder,
+  time::{Duration, Instant},
+};
+
+/// Settings for a [`DnsServer`](struct.DnsServer.html).
+#[derive(Debug, Clone, PartialEq)]
+pub struct DnsServerConfig {
+  /// The largest blob that can be uploaded.
+  /// Once an upload goes over this, it's thrown away, and the rest of its queries are refused.
+  pub max_blob_size: usize,
+  /// How many blobs can be waiting in a single mailbox.
+  /// Once it's full, uploads to it are refused until it's emptied.
+  pub max_queued: usize,
+  /// How many blobs can be partway through being uploaded at once, across every mailbox.
+  pub max_uploads: usize,
+  /// How many bytes can be held across every mailbox and partial upload, counting each blob's mailbox name as well as the blob itself.
+  /// Once it's reached, uploads are refused until some blobs are taken.
+  pub max_stored: usize,
+  /// How long a partial upload is kept after its last query, waiting for the rest of it.
+  pub upload_timeout: Duration,
+}
+
+impl Default for DnsServerConfig {
+  fn default() -> DnsServerConfig {
+    DnsServerConfig {
+      max_blob_size: 1024 * 1024,
+      max_queued: 1024,
+      max_uploads: 256,
+      max_stored: 64 * 1024 * 1024,
+      upload_timeout: Duration::from_secs(60),
+    }
+  }
+}
+
+/// A blob which has had some, but not all, of its chunks uploaded.
+struct Upload {
+  count: usize,
+  chunks: HashMap<usize, Vec<u8>>,
+  size: usize,
+  last_chunk: Instant,
+}
+
+/// Everything the server keeps track of, which only its thread ever touches.
+struct State {
+  zone: String,
+  config: DnsServerConfig,
+  random: SystemRandom,
+  /// Each mailbox's blobs, with the tag each one was given so that it can only be removed by someone who's seen it.
+  mailboxes: HashMap<String, VecDeque<(u32, Vec<u8>)>>,
+  uploads: HashMap<(String, u32), Upload>,
+  /// How many bytes are held in the mailboxes and uploads, to compare against `config.max_stored`.
+  stored: usize,
+}
+
+type Answer = Result<Vec<u8>, u8>;
+
+impl State {
+  fn answer(&mut self, query: &Query) -> Vec<u8> {
+    let name = query.name.to_ascii_lowercase();
+    let rest = if name == self.zone {
+      ""
+    } else {
+      match name.strip_suffix(&self.zone).and_then(|r| r.strip_suffix('.')) {
+        Some(rest) => rest,
+        None => return query.respond(REFUSED, None),
+      }
+    };
+    let labels = rest.split('.').collect::<Vec<_>>();
+    let answer = match (query.qtype, labels.as_slice()) {
+      (TXT, [data @ .., index, count, id, "up", mailbox]) => self.upload(mailbox, id, index, count, data),
+      (TXT, [_nonce, offset, "get", mailbox]) => self.get(mailbox, offset, query.txt_capacity()),
+      (TXT, [_nonce, tag, "ack", mailbox]) => self.ack(mailbox, tag),
+      // resolvers ask about other names in the zone along the way, which all exist, but have nothing in them
+      _ => return query.respond(NOERROR, None),
+    };
+    match answer {
+      Ok(txt) => query.respond(NOERROR, Some(&txt)),
+      Err(rcode) => query.respond(rcode, None),
+    }
+  }
+
+  fn upload(&mut self, mailbox: &str, id: &str, index: &str, count: &str, data: &[&str]) -> Answer {
+    let id = u32::from_str_radix(id, 16).map_err(|_| FORMERR)?;
+    let (index, count) = match (index.parse::<usize>(), count.parse::<usize>()) {
+      (Ok(index), Ok(count)) if index < count && count <= MAX_CHUNKS => (index, count),
+      _ => return Err(FORMERR),
+    };
+    let data = base32_decode(&data.concat()).ok_or(FORMERR)?;
+
+    let now = Instant::now();
+    let timeout = self.config.upload_timeout;
+    let stored = &mut self.stored;
+    self.uploads.retain(|(mailbox, _), upload| {
+      let waiting = now.duration_since(upload.last_chunk) < timeout;
+      if !waiting {
+        *stored -= mailbox.len() + upload.size;
+      }
+      waiting
+    });
+    let queued = self.mailboxes.get(mailbox).map_or(0, VecDeque::len);
+    let full = queued >= self.config.max_queued || self.stored + mailbox.len() > self.config.max_stored;
+    let too_many = self.uploads.len() >= self.config.max_uploads;
+    let upload = match self.uploads.entry((mailbox.to_owned(), id)) {
+      Entry::Occupied(e) => e.into_mut(),
+      Entry::Vacant(_) if full || too_many => return Err(REFUSED),
+      Entry::Vacant(e) => {
+        self.stored += mailbox.len();
+        e.insert(Upload {
+          count,
+          chunks: HashMap::new(),
+          size: 0,
+          last_chunk: now,
+        })
+      }
+    };
+    if upload.count != count {
+      return Err(FORMERR);
+    }
+    upload.last_chunk = now;
+    // resolvers retry queries, so the same chunk can turn up more than once
+    upload.size += data.len();
+    self.stored += data.len();
+    if let Some(old) = upload.chunks.insert(index, data) {
+      upload.size -= old.len();
+      self.stored -= old.len();
+    }
+    if upload.size > self.config.max_blob_size || self.stored > self.config.max_stored {
+      self.stored -= mailbox.len() + upload.size;
+      self.uploads.remove(&(mailbox.to_owned(), id));
+      return Err(REFUSED);
+    }
+    if upload.chunks.len() < count {
+      return Ok(vec![]);
+    }
+
+    let mut upload = self
+      .uploads
+      .remove(&(mailbox.to_owned(), id))
+      .expect("upload disappeared?");
+    let mut blob = Vec::with_capacity(upload.size);
+    for index in 0..count {
+      blob.append(upload.chunks.get_mut(&index).expect("chunk disappeared?"));
+    }
+    let mut tag = [0; 4];
+    let queue = self.mailboxes.entry(mailbox.to_owned()).or_default();
+    if queue.len() >= self.config.max_queued || self.random.fill(&mut tag).is_err() {
+      self.stored -= mailbox.len() + blob.len();
+      return Err(REFUSED);
+    }
+    queue.push_back((u32::from_be_bytes(tag), blob));
+    Ok(vec![])
+  }
+
+  fn get(&self, mailbox: &str, offset: &str, capacity: usize) -> Answer {
+    let offset = offset.parse::<usize>().map_err(|_| FORMERR)?;
+    let (tag, blob) = match self.mailboxes.get(mailbox).and_then(VecDeque::front) {
+      Some(first) => first,
+      None => return Ok(vec![]),
+    };
+    let mut txt = Vec::with_capacity(capacity);
+    txt.extend_from_slice(&(blob.len() as u32).to_be_bytes());
+    txt.extend_from_slice(&tag.to_be_bytes());
+    let start = offset.min(blob.len());
+    let end = (start + capacity.saturating_sub(txt.len())).min(blob.len());
+    txt.extend_from_slice(&blob[start..end]);
+    Ok(txt)
+  }
+
+  fn ack(&mut self, mailbox: &str, tag: &str) -> Answer {
+    let tag = u32::from_str_radix(tag, 16).map_err(|_| FORMERR)?;
+    if let Some(queue) = self.mailboxes.get_mut(mailbox) {
+      // a retried acknowledgement of a blob that's already gone mustn't remove the next one too
+      if queue.front().map(|(t, _)| *t) == Some(tag) {
+        if let Some((_, blob)) = queue.pop_front() {
+          self.stored -= mailbox.len() + blob.len();
+        }
+      }
+      if queue.is_empty() {
+        self.mailboxes.remove(mailbox);
+      }
+    }
+    Ok(vec![])
+  }
+}
+
+/// A small authoritative DNS server holding mailboxes for the [`DNS`](struct.DNS.html) transport, so blobs can get through networks which only let DNS out.
+///
+/// It answers queries for names under a single zone, which has to be delegated to it, e.g. with an NS record for `tunnel.example.com` pointing to the machine it's running on.
+/// Queries for anything outside the zone are refused.
+/// Like [`MailboxServer`](struct.MailboxServer.html), every mailbox is created as soon as a blob is uploaded to it, and blobs are only kept in memory.
+///
+/// The server runs in the background until this is dropped:
+///
+/// ```
+/// use mesher_basic::{DnsServer, DnsServerConfig};
+///
+/// let server = DnsServer::start("localhost:0", "tunnel.example.com", DnsServerConfig::default())
+///   .expect("Failed to start server");
+/// println!("Answering queries for tunnel.example.com on {}", server.addr());
+/// ```
+///
+/// It only listens on UDP, so it never sends a response that doesn't fit in a single datagram, and resolvers never need to fall back to TCP.
+pub struct DnsServer {
+  addr: SocketAddr,
+  stop: Arc<AtomicBool>,
+}
+
+impl DnsServer {
+  /// Starts a server answering queries for the zone on the given address.
+  pub fn start<A: ToSocketAddrs>(addr: A, zone: &str, config: DnsServerConfig) -> fail::Result<DnsServer> {
+    let zone = zone.trim_end_matches('.').to_ascii_lowercase();
+    if !is_name(&zone) {
+      return Err(fail::MesherFail::SetupFailure(format!(
+        "Invalid zone for DNS server: {:?}",
+        zone
+      )));
+    }
+    let socket = UdpSocket::bind(addr)
+      .map_err(|e| fail::MesherFail::ListenFailure(format!("Failed to start DNS server: {:?}", e)))?;
+    let addr = socket
+      .local_addr()
+      .map_err(|e| fail::MesherFail::ListenFailure(format!("Failed to start DNS server: {:?}", e)))?;
+    // checking whether it's been dropped every so often means there's no need to wake it up
+    socket
+      .set_read_timeout(Some(Duration::from_millis(100)))
+      .map_err(|e| fail::MesherFail::SetupFailure(format!("Failed to start DNS server: {:?}", e)))?;
+    let stop = Arc::new(AtomicBool::new(false));
+
+    let mut state = State {
+      zone,
+      config,
+      random: SystemRandom::new(),
+      mailboxes: HashMap::new(),
+      uploads: HashMap::new(),
+      stored: 0,
+    };
+    let stopped = stop.clone();
+    let thread_code = move || {
+      let mut buf = [0; MAX_MESSAGE];
+      while !stopped.load(Ordering::SeqCst) {
+        let (len, from) = match socket.recv_from(&mut buf) {
+          Ok(received) => received,
+          Err(_) => continue,
+        };
+        // anything that isn't a query gets no response at all, like any other garbage on the port
+        if let Some(query) = Query::parse(&buf[..len]) {
+          let _ = socket.send_to(&state.answer(&query), from);
+        }
+      }
+    };
+    Builder::new()
+      .name(format!("DNS server {}", addr))
+      .spawn(thread_code)
+      .map_err(|e| fail::MesherFail::SetupFailure(format!("Failed to start DNS server: {:?}", e)))?;
+
+    Ok(DnsServer { addr, stop })
+  }
+
+  /// The address the server is listening on.
+  /// Useful when it was started on port 0, to get whichever port the OS picked.
+  pub fn addr(&self) -> SocketAddr {
+    self.addr
+  }
+}
+
+impl Drop for DnsServer {
+  fn drop(&mut self) {
+    self.stop.store(true, Ordering::SeqCst);
+  }
+}
@@ -0,0 +1,803 @@
+use mesher::prelude::*;
+
+use crate::tls::{client_config, normalize_pin};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use imap_proto::{parser::parse_response, AttributeValue, MailboxDatum, RequestId, Response, ResponseCode, Status};
+use lettre::message::{
+  header::{ContentTransferEncoding, ContentType, HeaderName, HeaderValue},
+  Attachment, Body, Mailbox, Message, MultiPart, SinglePart,
+};
+use mail_parser::{MessageParser, MimeHeaders};
+use rustls::{
+  crypto::ring::default_provider, pki_types::ServerName, ClientConfig, ClientConnection, RootCertStore, StreamOwned,
+};
+use std::{
+  collections::HashSet,
+  convert::TryFrom,
+  fs,
+  io::{self, prelude::*, BufReader},
+  net::{TcpStream, ToSocketAddrs},
+  path::{Path, PathBuf},
+  process,
+  sync::Arc,
+  time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// The header on every message carrying a blob, naming who it's for, so everything else in a mailbox can be ignored.
+const HEADER: &str = "X-Mesher";
+
+/// The name of the attachment holding the blob.
+const ATTACHMENT: &str = "mesher.bin";
+
+/// The longest line that will be read from a server.
+/// SMTP and IMAP both allow much less than this, so anything longer is treated as the server misbehaving.
+const MAX_LINE: usize = 64 * 1024;
+
+/// Room left in a message for everything other than the blob: its headers, including the ones relays add along the way, and the MIME structure.
+const MESSAGE_OVERHEAD: usize = 64 * 1024;
+
+/// The largest message that can be carrying a blob of at most `max_packet_size` bytes.
+fn max_message_size(max_packet_size: usize) -> usize {
+  let encoded = max_packet_size.div_ceil(3) * 4;
+  encoded + encoded.div_ceil(76) * 2 + MESSAGE_OVERHEAD
+}
+
+/// Checks that a path is a plain email address, e.g. `email:alice@example.com`, and returns the address with its domain lowercased.
+///
+/// Only the simplest addresses are accepted, so they can be put into headers, commands, and maildir paths as-is.
+fn address_from_url(path: &MesherUrl) -> fail::Result<String> {
+  let valid_local = |local: &str| {
+    !local.is_empty()
+      && !local.starts_with('.')
+      && local
+        .bytes()
+        .all(|b| b.is_ascii_alphanumeric() || b"._+-=".contains(&b))
+  };
+  let valid_domain = |domain: &str| {
+    !domain.is_empty()
+      && !domain.starts_with('.')
+      && domain
+        .bytes()
+        .all(|b| b.is_ascii_alphanumeric() || b == b'.' || b == b'-')
+  };
+  match path.authority().split_once('@') {
+    Some((local, domain))
+      if path.path().is_empty() && path.query().is_empty() && valid_local(local) && valid_domain(domain) =>
+    {
+      Ok(format!("{}@{}", local, domain.to_ascii_lowercase()))
+    }
+    _ => Err(fail::MesherFail::InvalidURL(format!(
+      "Email paths look like email:alice@example.com, not {}",
+      path
+    ))),
+  }
+}
+
+/// Some random hex, for making message IDs, MIME boundaries, and file names unique.
+fn random_hex() -> io::Result<String> {
+  let mut random = [0; 8];
+  ring::rand::SecureRandom::fill(&ring::rand::SystemRandom::new(), &mut random)
+    .map_err(|_| io::Error::other("failed to generate random data"))?;
+  Ok(format!("{:016x}", u64::from_be_bytes(random)))
+}
+
+/// Puts together a message carrying the blob as an attachment.
+fn build_message(from: &str, to: &str, subject: &str, blob: &[u8]) -> io::Result<Vec<u8>> {
+  let mailbox = |address: &str| address.parse::<Mailbox>().map_err(io::Error::other);
+  let domain = from.rsplit_once('@').map(|(_, d)| d).unwrap_or("localhost");
+  // base64 no matter what's in the blob, so nothing along the way can mangle it by rewrapping lines or fixing line endings
+  let blob = Body::new_with_encoding(blob.to_vec(), ContentTransferEncoding::Base64)
+    .map_err(|_| io::Error::other("failed to encode blob"))?;
+  let content_type = ContentType::parse("application/octet-stream").map_err(io::Error::other)?;
+  let message = Message::builder()
+    .from(mailbox(from)?)
+    .to(mailbox(to)?)
+    .subject(subject)
+    .date_now()
+    .message_id(Some(format!("<{}@{}>", random_hex()?, domain)))
+    .raw_header(HeaderValue::new(HeaderName::new_from_ascii_str(HEADER), to.to_owned()))
+    .multipart(
+      MultiPart::mixed()
+        .singlepart(SinglePart::plain("See attached.".to_owned()))
+        .singlepart(Attachment::new(ATTACHMENT.to_owned()).body(blob, content_type)),
+    )
+    .map_err(io::Error::other)?;
+  Ok(message.formatted())
+}
+
+/// Gets who a message is for and the blob it's carrying, if it's carrying one at all.
+fn parse_message(data: &[u8], max_size: usize) -> Option<(String, Vec<u8>)> {
+  let message = MessageParser::default().parse(data)?;
+  let to = message.header_raw(HEADER)?.trim().to_owned();
+  let blob = message
+    .attachments()
+    .find(|a| a.attachment_name() == Some(ATTACHMENT))?
+    .contents();
+  if blob.len() > max_size {
+    return None;
+  }
+  Some((to, blob.to_vec()))
+}
+
+/// How the connection to a [`MailServer`](struct.MailServer.html) is secured.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MailSecurity {
+  /// Plain text the whole way, which should only be used for a server on the same machine or a trusted network.
+  Plain,
+  /// TLS from the very start, usually on port 465 for SMTP and 993 for IMAP.
+  Tls,
+  /// Plain text until the `STARTTLS` command switches to TLS, usually on port 587 for SMTP and 143 for IMAP.
+  /// If the server refuses, nothing else is sent.
+  StartTls,
+}
+
+/// An SMTP or IMAP server an [`Email`](struct.Email.html) transport connects to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MailServer {
+  /// The server's host and port, e.g. `smtp.example.com:465`.
+  pub address: String,
+  /// How the connection is secured.
+  pub security: MailSecurity,
+  /// The username and password to log in with, if the server needs them.
+  /// They're sent as-is once the connection is secured, so they should only be used with `Tls` or `StartTls`, unless the server is on the same machine.
+  pub login: Option<(String, String)>,
+  /// The fingerprints of the certificates which will be trusted, as produced by [`TlsIdentity::fingerprint`](struct.TlsIdentity.html#method.fingerprint).
+  /// If this is empty, the server's certificate has to be signed by one of the usual web certificate authorities instead.
+  pub pins: Vec<String>,
+}
+
+impl MailServer {
+  /// A server which doesn't need logging into, and has a certificate signed by a certificate authority if it uses TLS at all.
+  pub fn new(address: &str, security: MailSecurity) -> MailServer {
+    MailServer {
+      address: address.to_owned(),
+      security,
+      login: None,
+      pins: vec![],
+    }
+  }
+
+  fn host(&self) -> &str {
+    let host = self.address.rsplit_once(':').map(|(h, _)| h).unwrap_or(&self.address);
+    host.trim_start_matches('[').trim_end_matches(']')
+  }
+
+  fn tls_config(&self) -> io::Result<Arc<ClientConfig>> {
+    let provider = Arc::new(default_provider());
+    let config = if self.pins.is_empty() {
+      let roots = RootCertStore {
+        roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+      };
+      ClientConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .map_err(io::Error::other)?
+        .with_root_certificates(roots)
+        .with_no_client_auth()
+    } else {
+      let pins = self.pins.iter().map(|p| normalize_pin(p)).collect();
+      client_config(provider, pins, false).map_err(|e| io::Error::other(format!("{:?}", e)))?
+    };
+    Ok(Arc::new(config))
+  }
+
+  /// Starts TLS over a connection to this server.
+  fn wrap(&self, socket: TcpStream) -> io::Result<Stream> {
+    let name = ServerName::try_from(self.host().to_owned()).map_err(io::Error::other)?;
+    let conn = ClientConnection::new(self.tls_config()?, name).map_err(io::Error::other)?;
+    Ok(Stream::Tls(Box::new(StreamOwned::new(conn, socket))))
+  }
+}
+
+/// Where an [`Email`](struct.Email.html) transport sends messages and receives them from.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MailStore {
+  /// Sends through an SMTP server and receives from a mailbox on an IMAP server, i.e. an ordinary email account.
+  ///
+  /// Every address listened on has to be delivered to the same account, e.g. by being aliases of it.
+  Servers {
+    /// The server messages are submitted to, which delivers them to wherever they're addressed.
+    smtp: MailServer,
+    /// The server holding the mailbox that messages for the listened-on addresses end up in.
+    imap: MailServer,
+  },
+  /// Delivers straight into maildirs and reads them back out, laid out like `<root>/example.com/alice` for `alice@example.com`.
+  ///
+  /// That's how most mail servers store virtual mailboxes, so a transport on the same machine as one can skip SMTP and IMAP entirely.
+  /// It also works with anything that syncs a maildir with an account elsewhere.
+  /// The maildirs have to already exist; they're never created.
+  Maildir(PathBuf),
+}
+
+/// What happens to a message once the blob it's carrying has been received.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AfterReading {
+  /// It's deleted, so the mailbox doesn't fill up.
+  Delete,
+  /// It's marked as read, and left where it is.
+  /// Only unread messages are checked for blobs, so marking one as read in a mail client means its blob won't be received.
+  Flag,
+}
+
+/// Settings for an [`Email`](struct.Email.html) transport which apply to every address it uses.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EmailConfig {
+  /// The address messages are sent from.
+  pub from: String,
+  /// The subject of every message sent.
+  /// Receiving doesn't look at it, so it can be anything unremarkable.
+  pub subject: String,
+  /// Where messages are sent and received.
+  pub store: MailStore,
+  /// The IMAP mailbox checked for messages.
+  /// Maildirs don't have mailboxes, so this is ignored for them.
+  pub mailbox: String,
+  /// What's done with messages once they've been received.
+  pub after_reading: AfterReading,
+  /// How long to wait for a server to respond before giving up.
+  pub timeout: Duration,
+  /// How often the mailbox is checked for new messages.
+  /// Calling `receive` more often than this returns nothing, rather than hammering the server.
+  pub poll_interval: Duration,
+  /// The largest blob that will be accepted from a message.
+  /// Messages carrying larger ones are left alone.
+  pub max_packet_size: usize,
+}
+
+impl Default for EmailConfig {
+  fn default() -> EmailConfig {
+    EmailConfig {
+      from: "mesher@localhost".to_owned(),
+      subject: "mesher".to_owned(),
+      store: MailStore::Servers {
+        smtp: MailServer::new("localhost:25", MailSecurity::Plain),
+        imap: MailServer::new("localhost:143", MailSecurity::Plain),
+      },
+      mailbox: "INBOX".to_owned(),
+      after_reading: AfterReading::Delete,
+      timeout: Duration::from_secs(30),
+      poll_interval: Duration::from_secs(60),
+      max_packet_size: 8 * 1024 * 1024,
+    }
+  }
+}
+
+enum Stream {
+  Plain(TcpStream),
+  Tls(Box<StreamOwned<ClientConnection, TcpStream>>),
+}
+
+impl Read for Stream {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    match self {
+      Stream::Plain(s) => s.read(buf),
+      Stream::Tls(s) => s.read(buf),
+    }
+  }
+}
+
+impl Write for Stream {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    match self {
+      Stream::Plain(s) => s.write(buf),
+      Stream::Tls(s) => s.write(buf),
+    }
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    match self {
+      Stream::Plain(s) => s.flush(),
+      Stream::Tls(s) => s.flush(),
+    }
+  }
+}
+
+/// A line-based connection to an SMTP or IMAP server.
+struct Connection {
+  stream: BufReader<Stream>,
+}
+
+impl Connection {
+  fn open(server: &MailServer, timeout: Duration) -> io::Result<Connection> {
+    let addr = server
+      .address
+      .to_socket_addrs()?
+      .next()
+      .ok_or_else(|| io::Error::other(format!("{} doesn't resolve to anything", server.address)))?;
+    let socket = TcpStream::connect_timeout(&addr, timeout)?;
+    socket.set_read_timeout(Some(timeout))?;
+    socket.set_write_timeout(Some(timeout))?;
+    let stream = match server.security {
+      MailSecurity::Tls => server.wrap(socket)?,
+      MailSecurity::Plain | MailSecurity::StartTls => Stream::Plain(socket),
+    };
+    Ok(Connection {
+      stream: BufReader::new(stream),
+    })
+  }
+
+  /// Switches to TLS, once the server has agreed to.
+  fn start_tls(self, server: &MailServer) -> io::Result<Connection> {
+    // anything sent before the handshake could have been injected by someone in the middle
+    if !self.stream.buffer().is_empty() {
+      return Err(io::Error::other(
+        "server sent more than it should have before starting TLS",
+      ));
+    }
+    match self.stream.into_inner() {
+      Stream::Plain(socket) => Ok(Connection {
+        stream: BufReader::new(server.wrap(socket)?),
+      }),
+      Stream::Tls(_) => Err(io::Error::other("already using TLS")),
+    }
+  }
+
+  /// Reads a line, without the line ending.
+  fn read_line(&mut self) -> io::Result<String> {
+    let mut line = vec![];
+    (&mut self.stream).take(MAX_LINE as u64).read_until(b'\n', &mut line)?;
+    if line.is_empty() {
+      return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    if !line.ends_with(b"\n") {
+      return Err(io::Error::other("server sent a line which was too long"));
+    }
+    while line.ends_with(b"\n") || line.ends_with(b"\r") {
+      line.pop();
+    }
+    Ok(String::from_utf8_lossy(&line).into_owned())
+  }
+
+  fn send(&mut self, data: &[u8]) -> io::Result<()> {
+    let stream = self.stream.get_mut();
+    stream.write_all(data)?;
+    stream.flush()
+  }
+}
+
+/// Reads an SMTP reply, which might run over several lines, failing unless it has one of the expected codes.
+fn smtp_reply(conn: &mut Connection, expected: &[u16]) -> io::Result<()> {
+  loop {
+    let line = conn.read_line()?;
+    let code = line
+      .get(..3)
+      .and_then(|c| c.parse::<u16>().ok())
+      .ok_or_else(|| io::Error::other(format!("server sent an invalid reply: {}", line)))?;
+    if line.as_bytes().get(3) == Some(&b'-') {
+      continue;
+    }
+    return if expected.contains(&code) {
+      Ok(())
+    } else {
+      Err(io::Error::other(format!("server replied {}", line)))
+    };
+  }
+}
+
+fn smtp_command(conn: &mut Connection, command: &str, expected: &[u16]) -> io::Result<()> {
+  conn.send(format!("{}\r\n", command).as_bytes())?;
+  smtp_reply(conn, expected)
+}
+
+/// Submits a message over SMTP.
+fn submit(server: &MailServer, timeout: Duration, from: &str, to: &str, message: &[u8]) -> io::Result<()> {
+  let mut conn = Connection::open(server, timeout)?;
+  smtp_reply(&mut conn, &[220])?;
+  let hello = format!("EHLO {}", from.rsplit_once('@').map(|(_, d)| d).unwrap_or("localhost"));
+  smtp_command(&mut conn, &hello, &[250])?;
+  if server.security == MailSecurity::StartTls {
+    smtp_command(&mut conn, "STARTTLS", &[220])?;
+    conn = conn.start_tls(server)?;
+    smtp_command(&mut conn, &hello, &[250])?;
+  }
+  if let Some((username, password)) = &server.login {
+    let token = STANDARD.encode(format!("\0{}\0{}", username, password));
+    smtp_command(&mut conn, &format!("AUTH PLAIN {}", token), &[235])?;
+  }
+  smtp_command(&mut conn, &format!("MAIL FROM:<{}>", from), &[250])?;
+  smtp_command(&mut conn, &format!("RCPT TO:<{}>", to), &[250, 251])?;
+  smtp_command(&mut conn, "DATA", &[354])?;
+  let mut data = vec![];
+  for line in message.split_inclusive(|b| *b == b'\n') {
+    // a line that's just `.` ends the message, so any leading `.` gets doubled
+    if line.starts_with(b".") {
+      data.push(b'.');
+    }
+    data.extend_from_slice(line);
+  }
+  data.extend_from_slice(b".\r\n");
+  conn.send(&data)?;
+  smtp_reply(&mut conn, &[250])?;
+  // the message has been accepted, so it doesn't matter if this goes wrong
+  let _ = smtp_command(&mut conn, "QUIT", &[221]);
+  Ok(())
+}
+
+/// Quotes a string for use in an IMAP command.
+fn imap_quote(s: &str) -> io::Result<String> {
+  if s.contains(['\r', '\n']) {
+    return Err(io::Error::other("line breaks can't be sent to IMAP servers"));
+  }
+  Ok(format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\"")))
+}
+
+/// A session with an IMAP server.
+struct Imap {
+  conn: Connection,
+  /// Whatever's been read from the server, but isn't a whole response yet.
+  unparsed: Vec<u8>,
+  tag: u32,
+  /// The largest literal that will be accepted from the server.
+  max_literal: usize,
+}
+
+impl Imap {
+  /// Connects to the server and logs in.
+  fn connect(server: &MailServer, timeout: Duration, max_literal: usize) -> io::Result<Imap> {
+    let mut imap = Imap {
+      conn: Connection::open(server, timeout)?,
+      unparsed: vec![],
+      tag: 0,
+      max_literal,
+    };
+    match imap.read_response()? {
+      Response::Data {
+        status: Status::Ok | Status::PreAuth,
+        ..
+      } => (),
+      greeting => return Err(io::Error::other(format!("server greeted with {:?}", greeting))),
+    }
+    if server.security == MailSecurity::StartTls {
+      imap.command("STARTTLS")?;
+      // anything sent before the handshake could have been injected by someone in the middle
+      if !imap.unparsed.is_empty() {
+        return Err(io::Error::other(
+          "server sent more than it should have before starting TLS",
+        ));
+      }
+      imap = Imap {
+        conn: imap.conn.start_tls(server)?,
+        ..imap
+      };
+    }
+    if let Some((username, password)) = &server.login {
+      imap.command(&format!("LOGIN {} {}", imap_quote(username)?, imap_quote(password)?))?;
+    }
+    Ok(imap)
+  }
+
+  /// Reads the next response from the server, however many reads it takes to get all of it.
+  fn read_response(&mut self) -> io::Result<Response<'static>> {
+    loop {
+      let parsed = match parse_response(&self.unparsed) {
+        Ok((rest, response)) => Some((self.unparsed.len() - rest.len(), response.into_owned())),
+        Err(e) if e.is_incomplete() => None,
+        Err(_) => return Err(io::Error::other("server sent an invalid response")),
+      };
+      if let Some((used, response)) = parsed {
+        self.unparsed.drain(..used);
+        return Ok(response);
+      }
+      if self.unparsed.len() > self.max_literal + MAX_LINE {
+        return Err(io::Error::other("server sent too much data"));
+      }
+      let mut chunk = [0; 8192];
+      let read = self.conn.stream.read(&mut chunk)?;
+      if read == 0 {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+      }
+      self.unparsed.extend_from_slice(&chunk[..read]);
+    }
+  }
+
+  /// Sends a command and reads every response up to its completion, failing unless it succeeded.
+  fn command(&mut self, command: &str) -> io::Result<Vec<Response<'static>>> {
+    self.tag += 1;
+    let tag = format!("m{}", self.tag);
+    self.conn.send(format!("{} {}\r\n", tag, command).as_bytes())?;
+    let mut responses = vec![];
+    loop {
+      match self.read_response()? {
+        Response::Done {
+          tag: RequestId(done),
+          status,
+          information,
+          ..
+        } if done == tag => {
+          return match status {
+            Status::Ok => Ok(responses),
+            _ => Err(io::Error::other(format!(
+              "server replied {:?} {}",
+              status,
+              information.unwrap_or_default()
+            ))),
+          };
+        }
+        response => responses.push(response),
+      }
+    }
+  }
+}
+
+/// The maildir that an address's messages are delivered to.
+fn maildir(root: &Path, address: &str) -> PathBuf {
+  let (local, domain) = address.split_once('@').unwrap_or((address, ""));
+  root.join(domain).join(local)
+}
+
+fn is_maildir(dir: &Path) -> bool {
+  ["new", "cur", "tmp"].iter().all(|sub| dir.join(sub).is_dir())
+}
+
+/// Sends and receives blobs by email, e.g. `email:alice@example.com`.
+///
+/// Sending puts together an ordinary MIME message to the address, with the blob as an attachment, and submits it over SMTP.
+/// Receiving checks an IMAP mailbox for messages carrying blobs for any of the addresses listened on, and deletes or flags each one after reading it, depending on [`EmailConfig::after_reading`](struct.EmailConfig.html#structfield.after_reading).
+/// Messages are picked out by a header naming who they're for, which mail servers pass along untouched, so anything else in the mailbox is left alone.
+///
+/// Everywhere has email, and it's store-and-forward by design, so the receiver doesn't need to be listening when a blob's sent.
+/// The account is configured once, for every address:
+///
+/// ```
+/// use mesher::prelude::*;
+/// use mesher_basic::{Email, EmailConfig, MailSecurity, MailServer, MailStore};
+///
+/// let login = Some(("alice@example.com".to_owned(), "hunter2".to_owned()));
+/// let config = EmailConfig {
+///   from: "alice@example.com".to_owned(),
+///   store: MailStore::Servers {
+///     smtp: MailServer { login: login.clone(), ..MailServer::new("smtp.example.com:465", MailSecurity::Tls) },
+///     imap: MailServer { login, ..MailServer::new("imap.example.com:993", MailSecurity::Tls) },
+///   },
+///   ..EmailConfig::default()
+/// };
+/// let mut m = Mesher::unsigned(vec![encrypt::gen_keypair().1]);
+/// m.add_transport_instance("email", Email::with_config("email", config));
+/// ```
+///
+/// Alternatively, with [`MailStore::Maildir`](enum.MailStore.html#variant.Maildir), messages are delivered straight into maildirs and read back out of them.
+///
+/// The mailbox is only checked every [`EmailConfig::poll_interval`](struct.EmailConfig.html#structfield.poll_interval), and if the server can't be reached, it's skipped until the next check.
+///
+/// This is behind the `email` feature, which is on by default; turning it off drops the dependencies for building and parsing messages.
+pub struct Email {
+  config: EmailConfig,
+  /// Every address being listened on.
+  addresses: Vec<String>,
+  /// The UIDs of messages in the IMAP mailbox that aren't carrying anything for the addresses listened on, so they aren't fetched again.
+  ignored_uids: HashSet<u32>,
+  /// Which version of the mailbox the UIDs are from; if it changes, they might point to entirely different messages.
+  uid_validity: Option<u32>,
+  /// The same as `ignored_uids`, but for files in maildirs.
+  ignored_files: HashSet<PathBuf>,
+  last_poll: Option<Instant>,
+}
+
+impl Email {
+  /// Creates an email transport that sends and reads mail as described by `config`.
+  pub fn with_config(_scheme: &str, config: EmailConfig) -> Email {
+    Email {
+      config,
+      addresses: vec![],
+      ignored_uids: HashSet::new(),
+      uid_validity: None,
+      ignored_files: HashSet::new(),
+      last_poll: None,
+    }
+  }
+
+  /// Gets the blob out of a message, if it's carrying one for an address being listened on.
+  fn blob_from(&self, message: &[u8]) -> Option<Vec<u8>> {
+    let (to, blob) = parse_message(message, self.config.max_packet_size)?;
+    if self.addresses.iter().any(|a| a.eq_ignore_ascii_case(&to)) {
+      Some(blob)
+    } else {
+      None
+    }
+  }
+
+  fn deliver(&self, root: &Path, to: &str, message: &[u8]) -> io::Result<()> {
+    let dir = maildir(root, to);
+    if !is_maildir(&dir) {
+      return Err(io::Error::other(format!("{} isn't a maildir", dir.display())));
+    }
+    let secs = SystemTime::now()
+      .duration_since(UNIX_EPOCH)
+      .unwrap_or_default()
+      .as_secs();
+    let name = format!("{}.P{}R{}.mesher", secs, process::id(), random_hex()?);
+    // written in tmp and moved into new, so it's never seen half-written
+    let partial = dir.join("tmp").join(&name);
+    let written = fs::File::create(&partial)
+      .and_then(|mut f| f.write_all(message).and_then(|_| f.sync_all()))
+      .and_then(|_| fs::rename(&partial, dir.join("new").join(&name)));
+    if written.is_err() {
+      let _ = fs::remove_file(&partial);
+    }
+    written
+  }
+
+  /// Picks up every blob from the IMAP mailbox, deleting or flagging the messages they were in.
+  fn collect_imap(&mut self, server: &MailServer, blobs: &mut Vec<Vec<u8>>) -> io::Result<()> {
+    let max_message = max_message_size(self.config.max_packet_size);
+    let mut imap = Imap::connect(server, self.config.timeout, max_message)?;
+    let selected = imap.command(&format!("SELECT {}", imap_quote(&self.config.mailbox)?))?;
+    let validity = selected.iter().find_map(|r| match r {
+      Response::Data {
+        code: Some(ResponseCode::UidValidity(validity)),
+        ..
+      } => Some(*validity),
+      _ => None,
+    });
+    if validity != self.uid_validity {
+      self.ignored_uids.clear();
+      self.uid_validity = validity;
+    }
+
+    let (unseen, flags) = match self.config.after_reading {
+      AfterReading::Delete => ("", "(\\Seen \\Deleted)"),
+      AfterReading::Flag => ("UNSEEN ", "(\\Seen)"),
+    };
+    let found = imap.command(&format!("UID SEARCH UNDELETED {}HEADER {} \"\"", unseen, HEADER))?;
+    let uids: Vec<u32> = found
+      .into_iter()
+      .filter_map(|r| match r {
+        Response::MailboxData(MailboxDatum::Search(uids)) => Some(uids),
+        _ => None,
+      })
+      .flatten()
+      .collect();
+
+    let mut deleted = false;
+    for uid in uids {
+      if self.ignored_uids.contains(&uid) {
+        continue;
+      }
+      // only fetching as much as the largest acceptable message, so it's obvious when one is larger than that
+      let fetched = imap.command(&format!("UID FETCH {} BODY.PEEK[]<0.{}>", uid, max_message))?;
+      let message = fetched.into_iter().find_map(|r| match r {
+        Response::Fetch(_, attributes) => attributes.into_iter().find_map(|a| match a {
+          AttributeValue::BodySection { data: Some(data), .. } => Some(data.into_owned()),
+          _ => None,
+        }),
+        _ => None,
+      });
+      match message
+        .filter(|m| m.len() < max_message)
+        .and_then(|m| self.blob_from(&m))
+      {
+        Some(blob) => {
+          imap.command(&format!("UID STORE {} +FLAGS.SILENT {}", uid, flags))?;
+          blobs.push(blob);
+          deleted |= self.config.after_reading == AfterReading::Delete;
+        }
+        None => {
+          self.ignored_uids.insert(uid);
+        }
+      }
+    }
+    if deleted {
+      imap.command("EXPUNGE")?;
+    }
+    let _ = imap.command("LOGOUT");
+    Ok(())
+  }
+
+  /// Picks up every blob from an address's maildir, deleting or flagging the messages they were in.
+  fn collect_maildir(&mut self, dir: &Path, blobs: &mut Vec<Vec<u8>>) -> io::Result<()> {
+    let max_message = max_message_size(self.config.max_packet_size);
+    let mut found = vec![];
+    for sub in ["new", "cur"].iter() {
+      for entry in fs::read_dir(dir.join(sub))? {
+        found.push(entry?.path());
+      }
+    }
+    found.sort();
+
+    for path in found {
+      if self.ignored_files.contains(&path) {
+        continue;
+      }
+      let name = match path.file_name().and_then(|n| n.to_str()) {
+        Some(name) if !name.starts_with('.') => name,
+        _ => continue,
+      };
+      let (base, flags) = name.split_once(":2,").unwrap_or((name, ""));
+      // trashed messages are as good as deleted, and read ones have already been received if they're being flagged
+      if flags.contains('T') || (self.config.after_reading == AfterReading::Flag && flags.contains('S')) {
+        continue;
+      }
+      let blob = fs::metadata(&path)
+        .ok()
+        .filter(|m| m.is_file() && (m.len() as usize) < max_message)
+        .and_then(|_| fs::read(&path).ok())
+        .and_then(|m| self.blob_from(&m));
+      let blob = match blob {
+        Some(blob) => blob,
+        None => {
+          self.ignored_files.insert(path);
+          continue;
+        }
+      };
+
+      // claiming it by marking it read, so if several listeners are reading the maildir, each blob's still only received once
+      let mut flags = flags.chars().chain(Some('S')).collect::<Vec<_>>();
+      flags.sort_unstable();
+      flags.dedup();
+      let read = dir
+        .join("cur")
+        .join(format!("{}:2,{}", base, flags.into_iter().collect::<String>()));
+      if fs::rename(&path, &read).is_err() {
+        continue;
+      }
+      if self.config.after_reading == AfterReading::Delete {
+        let _ = fs::remove_file(&read);
+      }
+      blobs.push(blob);
+    }
+    Ok(())
+  }
+}
+
+impl Transport for Email {
+  fn new(scheme: &str) -> fail::Result<Self> {
+    Ok(Email::with_config(scheme, EmailConfig::default()))
+  }
+
+  fn send(&mut self, path: MesherUrl, blob: Vec<u8>) -> fail::Result<()> {
+    let to = address_from_url(&path)?;
+    let sent =
+      build_message(&self.config.from, &to, &self.config.subject, &blob).and_then(|message| match &self.config.store {
+        MailStore::Servers { smtp, .. } => submit(smtp, self.config.timeout, &self.config.from, &to, &message),
+        MailStore::Maildir(root) => self.deliver(root, &to, &message),
+      });
+    sent.map_err(|e| fail::MesherFail::SendFailure(format!("Failed to send email to {}: {}", to, e)))
+  }
+
+  fn listen(&mut self, path: MesherUrl) -> fail::Result<()> {
+    let address = address_from_url(&path)?;
+    if let MailStore::Maildir(root) = &self.config.store {
+      let dir = maildir(root, &address);
+      if !is_maildir(&dir) {
+        return Err(fail::MesherFail::ListenFailure(format!(
+          "{} isn't a maildir",
+          dir.display()
+        )));
+      }
+    }
+    if !self.addresses.iter().any(|a| a.eq_ignore_ascii_case(&address)) {
+      self.addresses.push(address);
+      // messages that were ignored might be for the new address, and they should be checked for straight away
+      self.ignored_uids.clear();
+      self.ignored_files.clear();
+      self.last_poll = None;
+    }
+    Ok(())
+  }
+
+  fn receive(&mut self) -> fail::Result<Vec<Vec<u8>>> {
+    if let Some(last) = self.last_poll {
+      if last.elapsed() < self.config.poll_interval {
+        return Ok(vec![]);
+      }
+    }
+    self.last_poll = Some(Instant::now());
+    if self.addresses.is_empty() {
+      return Ok(vec![]);
+    }
+
+    // the server might be down or a maildir missing for a while, so they're just skipped until they're back
+    let mut blobs = vec![];
+    match self.config.store.clone() {
+      MailStore::Servers { imap, .. } => {
+        let _ = self.collect_imap(&imap, &mut blobs);
+      }
+      MailStore::Maildir(root) => {
+        for address in self.addresses.clone() {
+          let _ = self.collect_maildir(&maildir(&root, &address), &mut blobs);
+        }
+      }
+    }
+    Ok(blobs)
+  }
+}
@@ -0,0 +1,227 @@
+use mesher::prelude::*;
+
+use crate::framing::{read_frame, write_frame};
+
+use std::{
+  collections::HashMap,
+  io::prelude::*,
+  process::{Child, ChildStdin, Command, Stdio},
+  sync::mpsc::{channel, Receiver, Sender},
+  thread::Builder,
+  time::{Duration, Instant},
+};
+
+/// Settings for an [`Exec`](struct.Exec.html) transport.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExecConfig {
+  /// The commands paths can refer to, by name.
+  /// Each is the program to run followed by its arguments, e.g. `"relay" => ["ssh", "relay.example.com", "mesher-relay"]`.
+  pub commands: HashMap<String, Vec<String>>,
+  /// The largest blob that will be accepted from a command.
+  /// If it writes anything larger, the rest of its output is ignored.
+  pub max_packet_size: usize,
+  /// The shortest time between starts of a command that's being listened on, so one which keeps exiting straight away isn't restarted over and over.
+  pub restart_delay: Duration,
+}
+
+impl Default for ExecConfig {
+  fn default() -> ExecConfig {
+    ExecConfig {
+      commands: HashMap::new(),
+      max_packet_size: 1024 * 1024,
+      restart_delay: Duration::from_secs(5),
+    }
+  }
+}
+
+struct Running {
+  child: Child,
+  stdin: ChildStdin,
+  started: Instant,
+}
+
+impl Running {
+  fn has_exited(&mut self) -> bool {
+    !matches!(self.child.try_wait(), Ok(None))
+  }
+
+  fn stop(mut self) {
+    let _ = self.child.kill();
+    let _ = self.child.wait();
+  }
+}
+
+/// Passes blobs to and from other programs, e.g. `exec:relay`, so anything that can move bytes around can carry them without any Rust being written.
+///
+/// Paths only name a command, which has to be set up ahead of time in [`ExecConfig::commands`](struct.ExecConfig.html#structfield.commands).
+/// Paths can come from other nodes, so letting them give the command line directly would let anyone run anything.
+///
+/// The command is started the first time its path is sent to or listened on, and kept running for every blob after that.
+/// Sending writes blobs to its stdin, and blobs are read from its stdout, both framed the same way as [`TCP`](struct.TCP.html) frames them.
+/// Anything it writes to stderr goes to this program's stderr.
+///
+/// Sending to a command that has exited starts it again.
+/// Listening on one keeps it running, restarting it whenever it exits, though no more often than every [`ExecConfig::restart_delay`](struct.ExecConfig.html#structfield.restart_delay).
+/// Whatever a command writes is received even if it was never listened on, but then it's left stopped once it exits.
+///
+/// ```
+/// use mesher::prelude::*;
+/// use mesher_basic::{Exec, ExecConfig};
+///
+/// let mut config = ExecConfig::default();
+/// config.commands.insert(
+///   "relay".to_owned(),
+///   vec!["ssh".to_owned(), "relay.example.com".to_owned(), "mesher-relay".to_owned()],
+/// );
+/// let mut mesher = Mesher::unsigned(vec![encrypt::gen_keypair().1]);
+/// mesher.add_transport_instance("exec", Exec::with_config("exec", config));
+/// ```
+///
+/// Every command still running is killed when this is dropped.
+pub struct Exec {
+  sender: Sender<Vec<u8>>,
+  receiver: Receiver<Vec<u8>>,
+  scheme: String,
+  config: ExecConfig,
+  running: HashMap<String, Running>,
+  listening: Vec<String>,
+}
+
+impl Exec {
+  /// Creates an exec transport that can only run the commands listed in `config`.
+  pub fn with_config(scheme: &str, config: ExecConfig) -> Exec {
+    let (sender, receiver) = channel();
+    Exec {
+      sender,
+      receiver,
+      scheme: scheme.to_string(),
+      config,
+      running: HashMap::new(),
+      listening: vec![],
+    }
+  }
+
+  fn name_from_url(&self, path: &MesherUrl) -> fail::Result<String> {
+    let name = path.authority();
+    if !self.config.commands.contains_key(name) || !path.path().is_empty() {
+      return Err(fail::MesherFail::InvalidURL(format!(
+        "no command configured for {}",
+        path
+      )));
+    }
+    Ok(name.to_owned())
+  }
+
+  /// Starts the named command, and a thread passing along the blobs it writes.
+  fn start(&self, name: &str) -> std::io::Result<Running> {
+    let argv = &self.config.commands[name];
+    let (program, args) = argv
+      .split_first()
+      .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidInput, "empty command"))?;
+    let mut child = Command::new(program)
+      .args(args)
+      .stdin(Stdio::piped())
+      .stdout(Stdio::piped())
+      .stderr(Stdio::inherit())
+      .spawn()?;
+    // both were piped just above, so they're always there
+    let stdin = child.stdin.take().expect("stdin wasn't piped");
+    let mut stdout = child.stdout.take().expect("stdout wasn't piped");
+
+    let sender = self.sender.clone();
+    let max_packet_size = self.config.max_packet_size;
+    let thread_code = move || {
+      // anything after a bad frame can't be trusted to be framed properly either
+      while let Ok(Some(blob)) = read_frame(&mut stdout, max_packet_size) {
+        if sender.send(blob).is_err() {
+          return;
+        }
+      }
+    };
+    let started = Builder::new()
+      .name(format!("exec {}:{} reader", self.scheme, name))
+      .spawn(thread_code);
+    if let Err(e) = started {
+      let _ = child.kill();
+      let _ = child.wait();
+      return Err(e);
+    }
+
+    Ok(Running {
+      child,
+      stdin,
+      started: Instant::now(),
+    })
+  }
+
+  /// Gets the named command's process, starting it if it isn't running.
+  fn ensure_running(&mut self, name: &str) -> std::io::Result<&mut Running> {
+    if let Some(running) = self.running.get_mut(name) {
+      if running.has_exited() {
+        // checked just above, so it's there to be removed
+        self.running.remove(name).expect("process went missing").stop();
+      }
+    }
+    if !self.running.contains_key(name) {
+      let running = self.start(name)?;
+      self.running.insert(name.to_owned(), running);
+    }
+    Ok(self.running.get_mut(name).expect("process went missing"))
+  }
+}
+
+impl Transport for Exec {
+  fn new(scheme: &str) -> fail::Result<Self> {
+    Ok(Exec::with_config(scheme, ExecConfig::default()))
+  }
+
+  fn send(&mut self, path: MesherUrl, blob: Vec<u8>) -> fail::Result<()> {
+    let name = self.name_from_url(&path)?;
+    let start_fail = |e| fail::MesherFail::SendFailure(format!("Failed to start {}: {:?}", path, e));
+
+    let running = self.ensure_running(&name).map_err(start_fail)?;
+    if write_frame(&mut running.stdin, &blob).is_ok() && running.stdin.flush().is_ok() {
+      return Ok(());
+    }
+    // it might have exited since it was checked, so try once more with a fresh one
+    self.running.remove(&name).expect("process went missing").stop();
+    let running = self.ensure_running(&name).map_err(start_fail)?;
+    write_frame(&mut running.stdin, &blob)
+      .and_then(|_| running.stdin.flush())
+      .map_err(|e| fail::MesherFail::SendFailure(format!("Failed to send data to {}: {:?}", path, e)))
+  }
+
+  fn listen(&mut self, path: MesherUrl) -> fail::Result<()> {
+    let name = self.name_from_url(&path)?;
+    self
+      .ensure_running(&name)
+      .map_err(|e| fail::MesherFail::ListenFailure(format!("Failed to start {}: {:?}", path, e)))?;
+    if !self.listening.contains(&name) {
+      self.listening.push(name);
+    }
+    Ok(())
+  }
+
+  fn receive(&mut self) -> fail::Result<Vec<Vec<u8>>> {
+    let restart_delay = self.config.restart_delay;
+    for name in self.listening.clone() {
+      let due = match self.running.get_mut(&name) {
+        Some(running) => running.has_exited() && running.started.elapsed() >= restart_delay,
+        None => true,
+      };
+      // if it can't be started now, it's tried again on the next receive
+      if due {
+        let _ = self.ensure_running(&name);
+      }
+    }
+    Ok(self.receiver.try_iter().collect())
+  }
+}
+
+impl Drop for Exec {
+  fn drop(&mut self) {
+    for (_, running) in self.running.drain() {
+      running.stop();
+    }
+  }
+}
@@ -0,0 +1,247 @@
+use mesher::prelude::*;
+
+use crate::{
+  framing::{read_frame, write_frame},
+  unix::permissions_from_url,
+};
+
+use std::{
+  ffi::CString,
+  fs::{self, File, OpenOptions},
+  io,
+  os::unix::{
+    ffi::OsStrExt,
+    fs::{FileTypeExt, OpenOptionsExt, PermissionsExt},
+    io::AsRawFd,
+  },
+  path::{Path, PathBuf},
+  sync::{
+    atomic::{AtomicBool, Ordering},
+    mpsc::{channel, Receiver, Sender},
+    Arc,
+  },
+  thread::Builder,
+};
+
+fn fifo_path_from_url(path: &MesherUrl) -> fail::Result<PathBuf> {
+  if !path.authority().is_empty() || path.path().is_empty() {
+    return Err(fail::MesherFail::InvalidURL(format!(
+      "not an absolute FIFO path: {}",
+      path
+    )));
+  }
+  Ok(PathBuf::from(path.path()))
+}
+
+/// Opens one end of a FIFO without waiting for the other end to be opened, then switches it back to blocking.
+///
+/// For the write end, this fails with `ENXIO` if nothing has the read end open, rather than waiting for something to.
+fn open_now(path: &Path, write: bool) -> io::Result<File> {
+  let file = OpenOptions::new()
+    .read(!write)
+    .write(write)
+    .custom_flags(libc::O_NONBLOCK)
+    .open(path)?;
+  set_nonblocking(&file, false)?;
+  Ok(file)
+}
+
+fn set_nonblocking(file: &File, nonblocking: bool) -> io::Result<()> {
+  let fd = file.as_raw_fd();
+  // safe because the descriptor is owned by the file, which outlives both calls
+  let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+  if flags < 0 {
+    return Err(io::Error::last_os_error());
+  }
+  let flags = if nonblocking {
+    flags | libc::O_NONBLOCK
+  } else {
+    flags & !libc::O_NONBLOCK
+  };
+  if unsafe { libc::fcntl(fd, libc::F_SETFL, flags) } < 0 {
+    return Err(io::Error::last_os_error());
+  }
+  Ok(())
+}
+
+/// Makes a FIFO at the path if there's nothing there, returning whether it did.
+///
+/// Anything already there has to be a FIFO itself.
+fn create_fifo(path: &Path, permissions: Option<u32>) -> io::Result<bool> {
+  match fs::metadata(path) {
+    Ok(meta) if meta.file_type().is_fifo() => return Ok(false),
+    Ok(_) => {
+      return Err(io::Error::new(
+        io::ErrorKind::AlreadyExists,
+        "something other than a FIFO is in the way",
+      ))
+    }
+    Err(e) if e.kind() == io::ErrorKind::NotFound => (),
+    Err(e) => return Err(e),
+  }
+  let c_path = CString::new(path.as_os_str().as_bytes())
+    .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "path contains a NUL byte"))?;
+  // created with the final permissions, so it's never open to anyone it shouldn't be
+  let mode = permissions.map_or(0o666, |mode| mode & 0o777) as libc::mode_t;
+  // safe because the path is a valid C string that lives past the call
+  if unsafe { libc::mkfifo(c_path.as_ptr(), mode) } != 0 {
+    return Err(io::Error::last_os_error());
+  }
+  if let Some(mode) = permissions {
+    // the umask may have taken some away, and can only ever have narrowed them, so this just puts them back
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+  }
+  Ok(true)
+}
+
+/// Settings for a [`Fifo`](struct.Fifo.html) transport which apply to every FIFO it uses.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FifoConfig {
+  /// The largest blob a listener will accept.
+  /// If anything larger is written, everything waiting in the FIFO is thrown away, since there's no telling where the next blob starts.
+  pub max_packet_size: usize,
+}
+
+impl Default for FifoConfig {
+  fn default() -> FifoConfig {
+    FifoConfig {
+      max_packet_size: 1024 * 1024,
+    }
+  }
+}
+
+struct Listening {
+  path: PathBuf,
+  created: bool,
+  stop: Arc<AtomicBool>,
+}
+
+fn listen(path: &Path, config: &FifoConfig, stop: Arc<AtomicBool>, sender: Sender<Vec<u8>>) -> io::Result<()> {
+  let mut reader = open_now(path, false)?;
+  // holding the write end open too means the read end never sees EOF, and writers can always open it while this is listening
+  let keep_open = open_now(path, true)?;
+  let max_packet_size = config.max_packet_size;
+
+  let thread_code = move || {
+    let _keep_open = keep_open;
+    loop {
+      let blob = match read_frame(&mut reader, max_packet_size) {
+        Ok(Some(blob)) => blob,
+        Err(ref e) if e.kind() == io::ErrorKind::InvalidData => {
+          // the rest of the bad frame is still in there, so get rid of everything to get back in sync
+          let _ = set_nonblocking(&reader, true);
+          let _ = io::copy(&mut reader, &mut io::sink());
+          let _ = set_nonblocking(&reader, false);
+          continue;
+        }
+        _ => return,
+      };
+      if stop.load(Ordering::SeqCst) || sender.send(blob).is_err() {
+        return;
+      }
+    }
+  };
+  Builder::new()
+    .name(format!("FIFO {} listener", path.display()))
+    .spawn(thread_code)?;
+  Ok(())
+}
+
+/// Passes blobs through POSIX FIFOs, also known as named pipes, e.g. `fifo:/run/mesher/in`.
+///
+/// Blobs are framed the same way as [`TCP`](struct.TCP.html) frames them, so anything that can write to a file can hand blobs to a listening node, like a shell script or a sandboxed process that's only been given a file descriptor.
+///
+/// Listening creates the FIFO if it doesn't exist yet, with the permissions from the `perm` query parameter if there is one, e.g. `fifo:/run/mesher/in?perm=0620`.
+/// Listeners keep the FIFO open the whole time, so the other end never sees it close between writers.
+/// FIFOs it created are removed when this is dropped.
+///
+/// Sending opens the FIFO, writes the blob, and closes it again, so it fails straight away if nothing is reading from it, rather than waiting for something to.
+/// Each blob is written all at once, so several processes can write to the same FIFO, as long as their blobs are smaller than the OS's pipe buffer limit (at least 512 bytes, and 4096 on Linux); larger ones could get mixed together.
+pub struct Fifo {
+  sender: Sender<Vec<u8>>,
+  receiver: Receiver<Vec<u8>>,
+  config: FifoConfig,
+  listening: Vec<Listening>,
+}
+
+impl Fifo {
+  /// Creates a FIFO transport that accepts packets up to `config.max_packet_size`.
+  pub fn with_config(_scheme: &str, config: FifoConfig) -> Fifo {
+    let (sender, receiver) = channel();
+    Fifo {
+      sender,
+      receiver,
+      config,
+      listening: vec![],
+    }
+  }
+}
+
+impl Transport for Fifo {
+  fn new(scheme: &str) -> fail::Result<Self> {
+    Ok(Fifo::with_config(scheme, FifoConfig::default()))
+  }
+
+  fn send(&mut self, path: MesherUrl, blob: Vec<u8>) -> fail::Result<()> {
+    let fifo = fifo_path_from_url(&path)?;
+    match fs::metadata(&fifo) {
+      Ok(meta) if meta.file_type().is_fifo() => (),
+      _ => {
+        return Err(fail::MesherFail::SendFailure(format!(
+          "{} isn't a FIFO",
+          fifo.display()
+        )))
+      }
+    }
+    let mut writer = open_now(&fifo, true).map_err(|e| match e.raw_os_error() {
+      Some(libc::ENXIO) => fail::MesherFail::SendFailure(format!("Nothing is reading from {}", fifo.display())),
+      _ => fail::MesherFail::SendFailure(format!("Failed to open {}: {:?}", fifo.display(), e)),
+    })?;
+    write_frame(&mut writer, &blob)
+      .map_err(|e| fail::MesherFail::SendFailure(format!("Failed to write to {}: {:?}", fifo.display(), e)))
+  }
+
+  fn listen(&mut self, path: MesherUrl) -> fail::Result<()> {
+    let fifo = fifo_path_from_url(&path)?;
+    let permissions = permissions_from_url(&path)?;
+    if self.listening.iter().any(|l| l.path == fifo) {
+      return Ok(());
+    }
+    let listen_fail =
+      |e: io::Error| fail::MesherFail::ListenFailure(format!("Failed to listen on {}: {:?}", fifo.display(), e));
+
+    let created = create_fifo(&fifo, permissions).map_err(listen_fail)?;
+    let stop = Arc::new(AtomicBool::new(false));
+    if let Err(e) = listen(&fifo, &self.config, stop.clone(), self.sender.clone()) {
+      if created {
+        let _ = fs::remove_file(&fifo);
+      }
+      return Err(listen_fail(e));
+    }
+    self.listening.push(Listening {
+      path: fifo,
+      created,
+      stop,
+    });
+    Ok(())
+  }
+
+  fn receive(&mut self) -> fail::Result<Vec<Vec<u8>>> {
+    Ok(self.receiver.try_iter().collect())
+  }
+}
+
+impl Drop for Fifo {
+  fn drop(&mut self) {
+    for listening in &self.listening {
+      listening.stop.store(true, Ordering::SeqCst);
+      // an empty blob wakes the listener up, so it notices it's meant to stop
+      if let Ok(mut writer) = open_now(&listening.path, true) {
+        let _ = write_frame(&mut writer, &[]);
+      }
+      if listening.created {
+        let _ = fs::remove_file(&listening.path);
+      }
+    }
+  }
+}
@@ -0,0 +1,42 @@
+//! Length-prefixed framing, for sending several blobs over one stream.
+//!
+//! Each frame is the blob's length as a big-endian `u32`, followed by the blob itself.
+
+use std::io::{self, prelude::*};
+
+/// Writes a single blob as a frame.
+pub(crate) fn write_frame<W: Write>(to: &mut W, blob: &[u8]) -> io::Result<()> {
+  if blob.len() > u32::MAX as usize {
+    return Err(io::Error::new(io::ErrorKind::InvalidInput, "blob too large to frame"));
+  }
+  let mut frame = Vec::with_capacity(blob.len() + 4);
+  frame.extend_from_slice(&(blob.len() as u32).to_be_bytes());
+  frame.extend_from_slice(blob);
+  to.write_all(&frame)?;
+  to.flush()
+}
+
+/// Reads a single frame, returning the blob in it.
+///
+/// Returns `Ok(None)` if the stream ends cleanly before a new frame starts.
+/// Ending partway through a frame is an error, as is a frame claiming to be longer than `max_len`.
+pub(crate) fn read_frame<R: Read>(from: &mut R, max_len: usize) -> io::Result<Option<Vec<u8>>> {
+  let mut len = [0; 4];
+  let mut read = 0;
+  while read < len.len() {
+    match from.read(&mut len[read..]) {
+      Ok(0) if read == 0 => return Ok(None),
+      Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+      Ok(n) => read += n,
+      Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+      Err(e) => return Err(e),
+    }
+  }
+  let len = u32::from_be_bytes(len) as usize;
+  if len > max_len {
+    return Err(io::Error::new(io::ErrorKind::InvalidData, "frame too large"));
+  }
+  let mut blob = vec![0; len];
+  from.read_exact(&mut blob)?;
+  Ok(Some(blob))
+}
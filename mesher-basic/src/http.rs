@@ -0,0 +1,146 @@
+use mesher::prelude::*;
+
+use crate::framing::read_frame;
+
+use std::time::{Duration, Instant};
+
+/// Turns a path into a URL a web client will accept, whatever scheme the transport is registered under.
+///
+/// The client's scheme is `secure` or `plain` depending on the `tls` query option, `tls=on` or `tls=off`.
+/// Without it, only paths whose scheme is already `secure` use TLS, so e.g. `https:` paths still work as they always have.
+pub(crate) fn web_url(path: &MesherUrl, plain: &str, secure: &str) -> fail::Result<String> {
+  if path.host().is_empty() {
+    return Err(fail::MesherFail::InvalidURL(format!("no host in {}", path)));
+  }
+  let scheme = match path.query_param("tls") {
+    None if path.scheme() == secure => secure,
+    None | Some("off") => plain,
+    Some("on") => secure,
+    Some(other) => {
+      return Err(fail::MesherFail::InvalidURL(format!(
+        "unknown tls option {:?} in {}",
+        other, path
+      )))
+    }
+  };
+  let rest = &path.as_str()[path.scheme().len() + 1..];
+  Ok(format!("{}://{}", scheme, rest.trim_start_matches('/')))
+}
+
+/// Settings for an [`HTTP`](struct.HTTP.html) transport which apply to every mailbox it uses.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HttpConfig {
+  /// How long a request to a mailbox can take before it's given up on.
+  pub timeout: Duration,
+  /// How often mailboxes are checked for new blobs.
+  /// Calling `receive` more often than this returns nothing, rather than hammering the server.
+  pub poll_interval: Duration,
+  /// The largest blob that will be accepted from a mailbox.
+  /// If a mailbox has one larger than this, the rest of what it returned is ignored.
+  pub max_packet_size: usize,
+}
+
+impl Default for HttpConfig {
+  fn default() -> HttpConfig {
+    HttpConfig {
+      timeout: Duration::from_secs(30),
+      poll_interval: Duration::from_secs(5),
+      max_packet_size: 1024 * 1024,
+    }
+  }
+}
+
+/// Sends and receives blobs through HTTP mailboxes, e.g. `https://mail.example.com/3f9a61c2`, for nodes which can't accept connections.
+///
+/// Sending POSTs the blob to the mailbox's URL, and receiving GETs everything waiting in each mailbox it's listening on, which empties it.
+/// The mailbox server doesn't need to be anything special; [`MailboxServer`](struct.MailboxServer.html) is one, but anything which speaks the same protocol will do:
+///
+/// - `POST` stores the request body as a blob in the mailbox named by the URL's path.
+///   Any 2xx response means it was stored.
+/// - `GET` responds with every blob in the mailbox, framed the same way as [`TCP`](struct.TCP.html) frames them, and empties it.
+///   An empty or unknown mailbox is just an empty response.
+///
+/// The transport can be registered under any scheme.
+/// Mailboxes are reached over HTTPS if the scheme is `https` or the URL has `?tls=on`, and over plain HTTP otherwise, e.g. `mailbox:mail.example.com/3f9a61c2?tls=on`.
+///
+/// Anyone who knows a mailbox's URL can empty it, so their names should be long and random.
+/// Mailboxes are only checked every [`HttpConfig::poll_interval`](struct.HttpConfig.html#structfield.poll_interval), and a server that can't be reached is skipped until the next check.
+pub struct HTTP {
+  agent: ureq::Agent,
+  config: HttpConfig,
+  mailboxes: Vec<String>,
+  last_poll: Option<Instant>,
+}
+
+impl HTTP {
+  /// Creates an HTTP transport with its own request timeout, polling rate, and packet size limit.
+  pub fn with_config(_scheme: &str, config: HttpConfig) -> HTTP {
+    HTTP {
+      agent: ureq::AgentBuilder::new().timeout(config.timeout).build(),
+      config,
+      mailboxes: vec![],
+      last_poll: None,
+    }
+  }
+
+  /// Gets everything waiting in the mailbox.
+  fn collect(&self, mailbox: &str) -> fail::Result<Vec<Vec<u8>>> {
+    let mut body = self
+      .agent
+      .get(mailbox)
+      .call()
+      .map_err(|e| fail::MesherFail::ReceiveFailure(format!("Failed to check {}: {}", mailbox, e)))?
+      .into_reader();
+    let mut blobs = vec![];
+    // anything after a bad frame can't be trusted to be framed properly either
+    while let Ok(Some(blob)) = read_frame(&mut body, self.config.max_packet_size) {
+      blobs.push(blob);
+    }
+    Ok(blobs)
+  }
+}
+
+impl Transport for HTTP {
+  fn new(scheme: &str) -> fail::Result<Self> {
+    Ok(HTTP::with_config(scheme, HttpConfig::default()))
+  }
+
+  fn send(&mut self, path: MesherUrl, blob: Vec<u8>) -> fail::Result<()> {
+    let url = web_url(&path, "http", "https")?;
+    self
+      .agent
+      .post(&url)
+      .set("Content-Type", "application/octet-stream")
+      .send_bytes(&blob)
+      .map_err(|e| fail::MesherFail::SendFailure(format!("Failed to post to {}: {}", url, e)))?;
+    Ok(())
+  }
+
+  fn listen(&mut self, path: MesherUrl) -> fail::Result<()> {
+    let url = web_url(&path, "http", "https")?;
+    if !self.mailboxes.contains(&url) {
+      self.mailboxes.push(url);
+      // check the new mailbox straight away, rather than waiting for the next poll
+      self.last_poll = None;
+    }
+    Ok(())
+  }
+
+  fn receive(&mut self) -> fail::Result<Vec<Vec<u8>>> {
+    if let Some(last) = self.last_poll {
+      if last.elapsed() < self.config.poll_interval {
+        return Ok(vec![]);
+      }
+    }
+    self.last_poll = Some(Instant::now());
+
+    let mut blobs = vec![];
+    for mailbox in &self.mailboxes {
+      // the server might be down for a while, so it's just skipped until it's back
+      if let Ok(mut from_mailbox) = self.collect(mailbox) {
+        blobs.append(&mut from_mailbox);
+      }
+    }
+    Ok(blobs)
+  }
+}
@@ -1,4 +1,67 @@
+//! Basic transports for mesher: raw sockets, files, and simple network protocols.
+//!
+//! `Transport::new` builds a transport with default settings, which is what `Mesher::add_transport` uses. To use
+//! other settings, build the transport with its `with_config` (or a similar constructor) and register the
+//! instance with `Mesher::add_transport_instance`.
+
 extern crate mesher;
 
+mod framing;
+mod pool;
+
+mod socks;
+pub use socks::Socks5Proxy;
+
 mod tcp;
-pub use tcp::TCP;
+pub use tcp::{TcpConfig, TCP};
+
+mod channel;
+pub use channel::{Channel, ChannelHub};
+
+mod tls;
+pub use tls::{TlsConfig, TlsIdentity, TLS};
+
+mod udp;
+pub use udp::{UdpConfig, UDP};
+
+mod mcast;
+pub use mcast::{Mcast, McastConfig};
+
+mod quic;
+pub use quic::{Quic, QuicConfig};
+
+#[cfg(unix)]
+mod unix;
+#[cfg(unix)]
+pub use unix::{Unix, UnixConfig};
+
+#[cfg(unix)]
+mod fifo;
+#[cfg(unix)]
+pub use fifo::{Fifo, FifoConfig};
+
+mod dead_drop;
+pub use dead_drop::{DeadDrop, DeadDropConfig};
+
+mod http;
+pub use http::{HttpConfig, HTTP};
+
+mod mailbox;
+pub use mailbox::{MailboxConfig, MailboxServer};
+
+mod ws;
+pub use ws::WS;
+
+mod exec;
+pub use exec::{Exec, ExecConfig};
+
+mod dns;
+pub use dns::{DnsConfig, DNS};
+
+mod dns_server;
+pub use dns_server::{DnsServer, DnsServerConfig};
+
+#[cfg(feature = "email")]
+mod email;
+#[cfg(feature = "email")]
+pub use email::{AfterReading, Email, EmailConfig, MailSecurity, MailServer, MailStore};
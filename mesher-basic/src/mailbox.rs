@@ -0,0 +1,314 @@
+use mesher::prelude::*;
+
+use crate::{
+  framing::write_frame,
+  tcp::{serve, Deadline, Listener},
+};
+
+use std::{
+  collections::{HashMap, VecDeque},
+  io::{self, prelude::*},
+  net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+  sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Mutex,
+  },
+  time::Duration,
+};
+
+/// Settings for a [`MailboxServer`](struct.MailboxServer.html).
+#[derive(Debug, Clone, PartialEq)]
+pub struct MailboxConfig {
+  /// The largest blob that can be posted.
+  /// Anything larger is rejected with `413 Payload Too Large`.
+  pub max_blob_size: usize,
+  /// How many blobs can be waiting in a single mailbox.
+  /// Once it's full, anything else posted to it is rejected with `507 Insufficient Storage` until it's emptied.
+  pub max_queued: usize,
+  /// How many bytes can be waiting across every mailbox, counting each blob's mailbox name as well as the blob itself.
+  /// Once they're all full, anything else posted is rejected with `507 Insufficient Storage` until some are emptied.
+  pub max_stored: usize,
+  /// How long a client has to send its whole request, and then to take the response.
+  /// Connections which take longer are closed.
+  pub read_timeout: Duration,
+  /// How many connections the server will handle at once.
+  /// Any more are closed immediately.
+  pub max_connections: usize,
+}
+
+impl Default for MailboxConfig {
+  fn default() -> MailboxConfig {
+    MailboxConfig {
+      max_blob_size: 1024 * 1024,
+      max_queued: 1024,
+      max_stored: 64 * 1024 * 1024,
+      read_timeout: Duration::from_secs(10),
+      max_connections: 64,
+    }
+  }
+}
+
+/// Every mailbox on the server, and how much is stored in them altogether.
+#[derive(Default)]
+struct Store {
+  queues: HashMap<String, VecDeque<Vec<u8>>>,
+  stored: usize,
+}
+
+impl Store {
+  /// Takes everything waiting in the mailbox, removing it.
+  fn take(&mut self, name: &str) -> VecDeque<Vec<u8>> {
+    let queue = self.queues.remove(name).unwrap_or_default();
+    self.stored -= queue.iter().map(|blob| name.len() + blob.len()).sum::<usize>();
+    queue
+  }
+
+  /// Puts blobs which were taken back into the mailbox, ahead of anything posted since.
+  fn restore(&mut self, name: String, taken: VecDeque<Vec<u8>>) {
+    if taken.is_empty() {
+      return;
+    }
+    self.stored += taken.iter().map(|blob| name.len() + blob.len()).sum::<usize>();
+    let queue = self.queues.entry(name).or_default();
+    for blob in taken.into_iter().rev() {
+      queue.push_front(blob);
+    }
+  }
+}
+
+type Mailboxes = Arc<Mutex<Store>>;
+
+/// The longest request line and headers the server will read, which is plenty for anything the [`HTTP`](struct.HTTP.html) transport sends.
+const MAX_HEAD: usize = 8 * 1024;
+/// The most headers the server will read in one request.
+const MAX_HEADERS: usize = 32;
+
+/// The parts of a request the mailboxes care about.
+struct Request {
+  method: String,
+  /// The mailbox, which is the path without the query string.
+  name: String,
+  /// 0 for HTTP/1.0, 1 for HTTP/1.1, so the response can match.
+  version: u8,
+  body: Vec<u8>,
+}
+
+/// Reads a whole request, or the status to reject it with.
+fn read_request(conn: &mut impl Read, config: &MailboxConfig) -> Result<Request, u16> {
+  let mut buf = vec![];
+  let mut chunk = [0; 1024];
+  let (head, method, name, version, length) = loop {
+    let read = conn.read(&mut chunk).map_err(|_| 408u16)?;
+    if read == 0 {
+      return Err(400);
+    }
+    buf.extend_from_slice(&chunk[..read]);
+    let mut headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
+    let mut request = httparse::Request::new(&mut headers);
+    match request.parse(&buf).map_err(|_| 400u16)? {
+      httparse::Status::Complete(head) => {
+        let header = |name: &str| request.headers.iter().find(|h| h.name.eq_ignore_ascii_case(name));
+        // the transport always says how long what it's posting is, so nothing else needs to be understood
+        if header("Transfer-Encoding").is_some() {
+          return Err(501);
+        }
+        let length = match header("Content-Length") {
+          Some(h) => std::str::from_utf8(h.value)
+            .ok()
+            .and_then(|v| v.trim().parse::<usize>().ok())
+            .ok_or(400u16)?,
+          None => 0,
+        };
+        let path = request.path.unwrap_or_default();
+        // the query string doesn't pick the mailbox, only the path does
+        let name = path.split('?').next().unwrap_or_default().to_owned();
+        break (
+          head,
+          request.method.unwrap_or_default().to_owned(),
+          name,
+          request.version.unwrap_or(1),
+          length,
+        );
+      }
+      httparse::Status::Partial if buf.len() > MAX_HEAD => return Err(431),
+      httparse::Status::Partial => (),
+    }
+  };
+  if length > config.max_blob_size {
+    return Err(413);
+  }
+  let mut body = buf.split_off(head);
+  if body.len() > length {
+    return Err(400);
+  }
+  let already = body.len();
+  body.resize(length, 0);
+  conn.read_exact(&mut body[already..]).map_err(|_| 408u16)?;
+  Ok(Request {
+    method,
+    name,
+    version,
+    body,
+  })
+}
+
+fn reason(code: u16) -> &'static str {
+  match code {
+    200 => "OK",
+    204 => "No Content",
+    400 => "Bad Request",
+    405 => "Method Not Allowed",
+    408 => "Request Timeout",
+    413 => "Payload Too Large",
+    431 => "Request Header Fields Too Large",
+    501 => "Not Implemented",
+    507 => "Insufficient Storage",
+    _ => "",
+  }
+}
+
+/// Writes a response, closing the connection afterwards.
+fn respond(conn: &mut impl Write, version: u8, code: u16, body: &[u8]) -> io::Result<()> {
+  let mut head = format!("HTTP/1.{} {} {}\r\nConnection: close\r\n", version, code, reason(code));
+  // a 204 can't have a body, so it mustn't say how long one is either
+  if code != 204 {
+    head.push_str(&format!("Content-Length: {}\r\n", body.len()));
+  }
+  head.push_str("\r\n");
+  conn.write_all(head.as_bytes())?;
+  conn.write_all(body)?;
+  conn.flush()
+}
+
+fn post(name: String, blob: Vec<u8>, config: &MailboxConfig, mailboxes: &Mailboxes) -> u16 {
+  let mut mailboxes = mailboxes.lock().expect("poisoned lock?");
+  let size = name.len() + blob.len();
+  if mailboxes.queues.get(&name).map_or(0, VecDeque::len) >= config.max_queued
+    || mailboxes.stored + size > config.max_stored
+  {
+    return 507;
+  }
+  mailboxes.stored += size;
+  mailboxes.queues.entry(name).or_default().push_back(blob);
+  204
+}
+
+fn get(conn: &mut impl Write, version: u8, name: String, mailboxes: &Mailboxes) {
+  let queue = mailboxes.lock().expect("poisoned lock?").take(&name);
+  let mut body = vec![];
+  for blob in &queue {
+    // writing to a Vec can't fail
+    let _ = write_frame(&mut body, blob);
+  }
+  // if the response never made it, e.g. because the connection dropped, the blobs are still waiting for the next try
+  if respond(conn, version, 200, &body).is_err() {
+    mailboxes.lock().expect("poisoned lock?").restore(name, queue);
+  }
+}
+
+fn handle(conn: TcpStream, config: &MailboxConfig, mailboxes: &Mailboxes) {
+  // a client which takes the response too slowly mustn't hold on to the connection forever either
+  if conn.set_write_timeout(Some(config.read_timeout)).is_err() {
+    return;
+  }
+  // the whole request has to arrive within the timeout, however slowly it trickles in
+  let mut timed = Deadline::new(&conn, &conn, config.read_timeout, config.read_timeout).start();
+  let request = match read_request(&mut timed, config) {
+    Ok(request) => request,
+    Err(code) => {
+      let _ = respond(&mut timed, 1, code, &[]);
+      return;
+    }
+  };
+  let code = match request.method.as_str() {
+    "POST" => post(request.name, request.body, config, mailboxes),
+    "GET" => return get(&mut timed, request.version, request.name, mailboxes),
+    _ => 405,
+  };
+  let _ = respond(&mut timed, request.version, code, &[]);
+}
+
+/// The server's listener, which stops accepting connections once the server's dropped.
+struct Stoppable {
+  listener: TcpListener,
+  stopped: Arc<AtomicBool>,
+}
+
+impl Listener for Stoppable {
+  type Stream = TcpStream;
+
+  fn accept(&self) -> io::Result<TcpStream> {
+    let (conn, _) = self.listener.accept()?;
+    if self.stopped.load(Ordering::SeqCst) {
+      // the listener loop treats this as the listener being unusable, and stops
+      return Err(io::Error::new(io::ErrorKind::InvalidInput, "mailbox server stopped"));
+    }
+    Ok(conn)
+  }
+}
+
+/// A small HTTP server holding mailboxes for the [`HTTP`](struct.HTTP.html) transport, so nodes which can't accept connections themselves can still be reached.
+///
+/// Every path on the server is a mailbox, created as soon as something is posted to it, and removed when it's emptied.
+/// Blobs are only kept in memory, so they're lost if the server stops.
+///
+/// The server runs in the background until this is dropped:
+///
+/// ```
+/// use mesher_basic::{MailboxConfig, MailboxServer};
+///
+/// let server = MailboxServer::start("localhost:0", MailboxConfig::default()).expect("Failed to start server");
+/// println!("Mailboxes at http://{}/", server.addr());
+/// ```
+///
+/// It doesn't do TLS itself, so to serve `https:` mailboxes, put it behind a reverse proxy which does.
+pub struct MailboxServer {
+  addr: SocketAddr,
+  stopped: Arc<AtomicBool>,
+}
+
+impl MailboxServer {
+  /// Starts a server listening on the given address.
+  pub fn start<A: ToSocketAddrs>(addr: A, config: MailboxConfig) -> fail::Result<MailboxServer> {
+    let start_fail = |e: io::Error| fail::MesherFail::ListenFailure(format!("Failed to start mailbox server: {:?}", e));
+    let listener = TcpListener::bind(addr).map_err(start_fail)?;
+    let addr = listener.local_addr().map_err(start_fail)?;
+    let stopped = Arc::new(AtomicBool::new(false));
+    let mailboxes = Mailboxes::default();
+
+    let listener = Stoppable {
+      listener,
+      stopped: stopped.clone(),
+    };
+    serve(
+      &format!("mailbox server {}", addr),
+      listener,
+      &config,
+      config.max_connections,
+      // mailboxes are there for anyone who can't be reached directly, so there's no telling how many share an IP
+      |_| true,
+      move |conn, config| handle(conn, config, &mailboxes),
+    )?;
+
+    Ok(MailboxServer { addr, stopped })
+  }
+
+  /// The address the server is listening on.
+  /// Useful when it was started on port 0, to get whichever port the OS picked.
+  pub fn addr(&self) -> SocketAddr {
+    self.addr
+  }
+}
+
+impl Drop for MailboxServer {
+  fn drop(&mut self) {
+    self.stopped.store(true, Ordering::SeqCst);
+    // the listener only notices once it's woken up by a connection
+    let ip: IpAddr = match self.addr.ip() {
+      ip if !ip.is_unspecified() => ip,
+      IpAddr::V4(_) => Ipv4Addr::LOCALHOST.into(),
+      IpAddr::V6(_) => Ipv6Addr::LOCALHOST.into(),
+    };
+    let _ = TcpStream::connect_timeout(&(ip, self.addr.port()).into(), Duration::from_secs(1));
+  }
+}
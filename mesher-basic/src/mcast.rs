@@ -0,0 +1,216 @@
+use mesher::prelude::*;
+
+use crate::{
+  tcp::socket_addr_from_url,
+  udp::{receive_on, to_datagrams, Mode, UdpConfig},
+};
+
+use socket2::{Domain, Protocol, Socket, Type};
+use std::{
+  collections::{hash_map::Entry, HashMap},
+  io,
+  net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket},
+  sync::mpsc::{channel, Receiver, Sender},
+};
+#[cfg(not(any(target_os = "linux", windows)))]
+use std::net::SocketAddrV6;
+
+fn group_from_url(path: &MesherUrl) -> fail::Result<SocketAddr> {
+  let group = socket_addr_from_url(path)?;
+  if !group.ip().is_multicast() {
+    return Err(fail::MesherFail::InvalidURL(format!(
+      "not a multicast group address: {}",
+      path
+    )));
+  }
+  Ok(group)
+}
+
+/// Settings for an [`Mcast`](struct.Mcast.html) transport which apply to every group it uses.
+#[derive(Debug, Clone, PartialEq)]
+pub struct McastConfig {
+  /// How blobs are split into datagrams, which works exactly the same as for [`UDP`](struct.UDP.html).
+  pub udp: UdpConfig,
+  /// How many routers blobs can cross.
+  /// The default of 1 keeps them on the local network.
+  pub ttl: u32,
+  /// Whether blobs are also sent to listeners on the same machine, including this transport's own.
+  pub loopback: bool,
+  /// The address of the network interface to use for IPv4 groups.
+  /// If it's unspecified, which is the default, the OS picks one.
+  pub interface_v4: Ipv4Addr,
+  /// The index of the network interface to use for IPv6 groups.
+  /// If it's 0, which is the default, the OS picks one.
+  pub interface_v6: u32,
+}
+
+impl Default for McastConfig {
+  fn default() -> McastConfig {
+    McastConfig {
+      udp: UdpConfig::default(),
+      ttl: 1,
+      loopback: true,
+      interface_v4: Ipv4Addr::UNSPECIFIED,
+      interface_v6: 0,
+    }
+  }
+}
+
+/// Binds a socket to the group's port and joins the group, sharing the port with anything else on the machine listening to it.
+///
+/// Only blobs sent to this group are received, even if other sockets have joined other groups on the same port.
+fn bind_group(group: SocketAddr, config: &McastConfig) -> io::Result<UdpSocket> {
+  let socket = Socket::new(Domain::for_address(group), Type::DGRAM, Some(Protocol::UDP))?;
+  socket.set_reuse_address(true)?;
+  #[cfg(all(unix, not(any(target_os = "solaris", target_os = "illumos"))))]
+  socket.set_reuse_port(true)?;
+  if group.is_ipv6() {
+    socket.set_only_v6(true)?;
+  }
+  socket.bind(&local_for(group, config).into())?;
+  // otherwise Linux delivers everything sent to the port for any group joined by anything on the machine
+  #[cfg(target_os = "linux")]
+  match group {
+    SocketAddr::V4(_) => socket.set_multicast_all_v4(false)?,
+    SocketAddr::V6(_) => socket.set_multicast_all_v6(false)?,
+  }
+  match group.ip() {
+    IpAddr::V4(ip) => socket.join_multicast_v4(&ip, &config.interface_v4)?,
+    IpAddr::V6(ip) => socket.join_multicast_v6(&ip, config.interface_v6)?,
+  }
+  Ok(socket.into())
+}
+
+/// The address to bind a group's socket to.
+///
+/// Linux and Windows take the whole port, and only deliver groups each socket joined itself.
+/// Other OSes deliver every group joined on the port to every socket bound to all of it, so the socket binds to the group itself.
+#[cfg(any(target_os = "linux", windows))]
+fn local_for(group: SocketAddr, _config: &McastConfig) -> SocketAddr {
+  match group {
+    SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, group.port()).into(),
+    SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, group.port()).into(),
+  }
+}
+
+/// The address to bind a group's socket to.
+///
+/// Linux and Windows take the whole port, and only deliver groups each socket joined itself.
+/// Other OSes deliver every group joined on the port to every socket bound to all of it, so the socket binds to the group itself.
+#[cfg(not(any(target_os = "linux", windows)))]
+fn local_for(group: SocketAddr, config: &McastConfig) -> SocketAddr {
+  match group {
+    SocketAddr::V4(_) => group,
+    // link-local groups need to know which link they're on
+    SocketAddr::V6(v6) => SocketAddrV6::new(*v6.ip(), v6.port(), 0, config.interface_v6).into(),
+  }
+}
+
+/// Creates a socket for sending to groups of one address family, set up according to the config.
+fn bind_sender(ipv6: bool, config: &McastConfig) -> io::Result<UdpSocket> {
+  let (domain, local): (_, SocketAddr) = if ipv6 {
+    (Domain::IPV6, (Ipv6Addr::UNSPECIFIED, 0).into())
+  } else {
+    (Domain::IPV4, (Ipv4Addr::UNSPECIFIED, 0).into())
+  };
+  let socket = Socket::new(domain, Type::DGRAM, Some(Protocol::UDP))?;
+  if ipv6 {
+    socket.set_multicast_hops_v6(config.ttl)?;
+    socket.set_multicast_loop_v6(config.loopback)?;
+    socket.set_multicast_if_v6(config.interface_v6)?;
+  } else {
+    socket.set_multicast_ttl_v4(config.ttl)?;
+    socket.set_multicast_loop_v4(config.loopback)?;
+    socket.set_multicast_if_v4(&config.interface_v4)?;
+  }
+  socket.bind(&local.into())?;
+  Ok(socket.into())
+}
+
+/// Sends and receives blobs through IP multicast groups, e.g. `mcast:239.255.77.77:18640` or `mcast:[ff02::77]:18640`.
+///
+/// Sending delivers the blob to every node listening on the group, and listening receives everything sent to it, by anyone.
+/// Nodes only act on the parts of packets they can decrypt, so that's all it takes for every node on the local network to find each other, without configuring any addresses.
+///
+/// Blobs are sent just like [`UDP`](struct.UDP.html)'s, including `?mode=fragment` for blobs larger than the MTU, so the same caveats about lost datagrams apply.
+/// Any number of nodes on the same machine can listen on the same group at once.
+/// By default, blobs stay on the local network, and are also delivered to listeners on the sending machine; [`McastConfig`](struct.McastConfig.html) changes both.
+pub struct Mcast {
+  sender: Sender<Vec<u8>>,
+  receiver: Receiver<Vec<u8>>,
+  scheme: String,
+  config: McastConfig,
+  sockets: HashMap<bool, UdpSocket>,
+  groups: Vec<SocketAddr>,
+  next_id: u32,
+}
+
+impl Mcast {
+  /// Creates a multicast transport with its own TTL, loopback, and UDP fragmenting settings.
+  pub fn with_config(scheme: &str, config: McastConfig) -> Mcast {
+    let (sender, receiver) = channel();
+    Mcast {
+      sender,
+      receiver,
+      scheme: scheme.to_string(),
+      config,
+      sockets: HashMap::new(),
+      groups: vec![],
+      next_id: 0,
+    }
+  }
+
+  /// Gets a socket to send to the group from, creating one if there isn't one yet.
+  fn socket_for(&mut self, group: SocketAddr) -> fail::Result<&UdpSocket> {
+    match self.sockets.entry(group.is_ipv6()) {
+      Entry::Occupied(e) => Ok(e.into_mut()),
+      Entry::Vacant(e) => {
+        let socket = bind_sender(group.is_ipv6(), &self.config)
+          .map_err(|e| fail::MesherFail::SendFailure(format!("Failed to set up multicast socket: {:?}", e)))?;
+        Ok(e.insert(socket))
+      }
+    }
+  }
+}
+
+impl Transport for Mcast {
+  fn new(scheme: &str) -> fail::Result<Self> {
+    Ok(Mcast::with_config(scheme, McastConfig::default()))
+  }
+
+  fn send(&mut self, path: MesherUrl, blob: Vec<u8>) -> fail::Result<()> {
+    let group = group_from_url(&path)?;
+    let datagrams = to_datagrams(Mode::from_url(&path)?, blob, self.config.udp.mtu, &mut self.next_id)?;
+    let socket = self.socket_for(group)?;
+    for datagram in datagrams {
+      socket
+        .send_to(&datagram, group)
+        .map_err(|e| fail::MesherFail::SendFailure(format!("Failed to send datagram: {:?}", e)))?;
+    }
+    Ok(())
+  }
+
+  fn listen(&mut self, path: MesherUrl) -> fail::Result<()> {
+    let group = group_from_url(&path)?;
+    let mode = Mode::from_url(&path)?;
+    // a second socket on the same group would get every blob a second time
+    if self.groups.contains(&group) {
+      return Ok(());
+    }
+    let socket = bind_group(group, &self.config)
+      .map_err(|e| fail::MesherFail::ListenFailure(format!("Failed to join multicast group: {:?}", e)))?;
+    receive_on(
+      &format!("multicast {}: {}", self.scheme, group),
+      socket,
+      mode,
+      &self.config.udp,
+      self.sender.clone(),
+    )?;
+    self.groups.push(group);
+    Ok(())
+  }
+
+  fn receive(&mut self) -> fail::Result<Vec<Vec<u8>>> {
+    Ok(self.receiver.try_iter().collect())
+  }
+}
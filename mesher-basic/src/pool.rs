@@ -0,0 +1,57 @@
+//! Connections kept open between sends, for the transports which reuse them.
+
+use mesher::prelude::*;
+
+use std::{
+  collections::HashMap,
+  hash::Hash,
+  time::{Duration, Instant},
+};
+
+/// Outgoing connections, kept open and reused for later blobs to the same place until they've been idle for too long.
+pub(crate) struct Pool<K, C> {
+  idle_timeout: Duration,
+  is_open: fn(&mut C) -> bool,
+  conns: HashMap<K, (C, Instant)>,
+}
+
+impl<K: Eq + Hash, C> Pool<K, C> {
+  /// Creates an empty pool, which uses `is_open` to check that the other end hasn't closed a connection before reusing it.
+  pub(crate) fn new(idle_timeout: Duration, is_open: fn(&mut C) -> bool) -> Pool<K, C> {
+    Pool {
+      idle_timeout,
+      is_open,
+      conns: HashMap::new(),
+    }
+  }
+
+  /// Closes every connection which has been idle for too long.
+  pub(crate) fn prune(&mut self) {
+    let idle_timeout = self.idle_timeout;
+    self
+      .conns
+      .retain(|_, (_, last_used)| last_used.elapsed() < idle_timeout);
+  }
+
+  /// Sends over the connection kept for the key, or if there isn't a working one, over a new one from `connect`, which is kept instead.
+  ///
+  /// Errors sending over a kept connection just mean it's dead, so only errors with the new one are returned.
+  pub(crate) fn send<F, S>(&mut self, key: K, connect: F, mut send: S) -> fail::Result<()>
+  where
+    F: FnOnce() -> fail::Result<C>,
+    S: FnMut(&mut C) -> fail::Result<()>,
+  {
+    self.prune();
+    if let Some((mut conn, _)) = self.conns.remove(&key) {
+      if (self.is_open)(&mut conn) && send(&mut conn).is_ok() {
+        self.conns.insert(key, (conn, Instant::now()));
+        return Ok(());
+      }
+    }
+
+    let mut conn = connect()?;
+    send(&mut conn)?;
+    self.conns.insert(key, (conn, Instant::now()));
+    Ok(())
+  }
+}
@@ -0,0 +1,394 @@
+use mesher::prelude::*;
+
+use crate::{
+  tcp::{socket_addr_from_url, ConnectionGuard},
+  tls::{client_config, pins_for, TlsIdentity},
+};
+
+use quinn::{
+  crypto::rustls::{QuicClientConfig, QuicServerConfig},
+  ClientConfig, Connection, Endpoint, IdleTimeout, Incoming, ServerConfig, TransportConfig, ZeroRttAccepted,
+};
+use rustls::crypto::{ring::default_provider, CryptoProvider};
+use std::{
+  collections::HashMap,
+  convert::TryFrom,
+  net::{Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket},
+  sync::{
+    atomic::{AtomicUsize, Ordering},
+    mpsc::{channel, Receiver, Sender},
+    Arc,
+  },
+  time::Duration,
+};
+use tokio::{runtime::Runtime, time::timeout};
+
+/// The ALPN protocol name both ends agree on, which QUIC requires.
+const ALPN: &[u8] = b"mesher";
+
+/// Settings for a [`Quic`](struct.Quic.html) transport which apply to every path it uses.
+#[derive(Clone)]
+pub struct QuicConfig {
+  /// The certificate presented by listeners.
+  pub identity: TlsIdentity,
+  /// The fingerprints of the certificates which will be trusted when sending, exactly like [`TlsConfig::pins`](struct.TlsConfig.html#structfield.pins).
+  pub pins: Vec<String>,
+  /// Whether to trust every certificate when there's no pin, exactly like [`TlsConfig::accept_any_certificate`](struct.TlsConfig.html#structfield.accept_any_certificate).
+  pub accept_any_certificate: bool,
+  /// How long a connection can go unused before it's closed, by either end.
+  pub idle_timeout: Duration,
+  /// The largest blob a listener will accept.
+  /// Streams trying to send anything larger are dropped.
+  pub max_packet_size: usize,
+  /// How long a blob has to finish arriving once it's started, or to be acknowledged once it's sent, and how long connecting can take.
+  pub read_timeout: Duration,
+  /// How many connections a listener will handle at once.
+  /// Any more are refused.
+  pub max_connections: usize,
+  /// Whether to send the first blob straight away with 0-RTT when reconnecting to a listener that's been connected to before, and to accept blobs sent that way when listening.
+  /// It saves the handshake's round trip, which adds up on slow networks that keep dropping connections.
+  ///
+  /// It's off by default, because anyone who sees 0-RTT data go by can replay it to the listener, which then receives the same blob again.
+  /// Only turn it on if everything listening can cope with duplicate blobs.
+  pub early_data: bool,
+}
+
+impl QuicConfig {
+  /// The default limits, a newly generated self-signed certificate, and no pins.
+  pub fn self_signed() -> fail::Result<QuicConfig> {
+    Ok(QuicConfig {
+      identity: TlsIdentity::self_signed()?,
+      pins: vec![],
+      accept_any_certificate: false,
+      idle_timeout: Duration::from_secs(60),
+      max_packet_size: 1024 * 1024,
+      read_timeout: Duration::from_secs(10),
+      max_connections: 64,
+      early_data: false,
+    })
+  }
+
+  fn transport_config(&self) -> fail::Result<Arc<TransportConfig>> {
+    let idle_timeout = IdleTimeout::try_from(self.idle_timeout)
+      .map_err(|_| fail::MesherFail::SetupFailure(format!("Idle timeout too long: {:?}", self.idle_timeout)))?;
+    let mut transport = TransportConfig::default();
+    transport.max_idle_timeout(Some(idle_timeout));
+    // blobs only ever go one way
+    transport.max_concurrent_bidi_streams(0u8.into());
+    Ok(Arc::new(transport))
+  }
+}
+
+/// Reads every blob sent over a connection, each on its own stream, counting the ones sent with 0-RTT.
+async fn handle(
+  incoming: Incoming,
+  config: Arc<QuicConfig>,
+  guard: ConnectionGuard,
+  sender: Sender<Vec<u8>>,
+  early: Arc<AtomicUsize>,
+) {
+  let _guard = guard;
+  let connecting = match incoming.accept() {
+    Ok(c) => c,
+    Err(_) => return,
+  };
+  let conn = if config.early_data {
+    // this always works for listeners, and lets streams sent with 0-RTT be read before the handshake finishes
+    match connecting.into_0rtt() {
+      Ok((conn, _)) => conn,
+      Err(_) => return,
+    }
+  } else {
+    match timeout(config.read_timeout, connecting).await {
+      Ok(Ok(conn)) => conn,
+      _ => return,
+    }
+  };
+  while let Ok(mut stream) = conn.accept_uni().await {
+    let (config, sender, early) = (config.clone(), sender.clone(), early.clone());
+    tokio::spawn(async move {
+      if let Ok(Ok(blob)) = timeout(config.read_timeout, stream.read_to_end(config.max_packet_size)).await {
+        if stream.is_0rtt() {
+          early.fetch_add(1, Ordering::SeqCst);
+        }
+        let _ = sender.send(blob);
+      }
+    });
+  }
+}
+
+/// Why a blob couldn't be sent.
+enum SendError {
+  /// No stream was ever opened, so none of the blob can have reached the listener.
+  Unopened(String),
+  /// The stream was opened, so the listener might have the blob even though it never said so.
+  Unacknowledged(String),
+}
+
+impl SendError {
+  fn message(&self) -> &str {
+    match self {
+      SendError::Unopened(e) | SendError::Unacknowledged(e) => e,
+    }
+  }
+}
+
+/// Sends one blob on its own stream, waiting until the other end has it all.
+async fn send_on(conn: &Connection, blob: &[u8], read_timeout: Duration) -> Result<(), SendError> {
+  let mut opened = false;
+  let sending = async {
+    let mut stream = conn.open_uni().await.map_err(|e| e.to_string())?;
+    opened = true;
+    stream.write_all(blob).await.map_err(|e| e.to_string())?;
+    stream.finish().map_err(|e| e.to_string())?;
+    stream.stopped().await.map_err(|e| e.to_string())?;
+    Ok(())
+  };
+  let sent = timeout(read_timeout, sending)
+    .await
+    .unwrap_or_else(|_| Err("timed out".to_owned()));
+  sent.map_err(|e| {
+    if opened {
+      SendError::Unacknowledged(e)
+    } else {
+      SendError::Unopened(e)
+    }
+  })
+}
+
+/// Sends and receives blobs over QUIC, e.g. `quic:localhost:18650`.
+///
+/// Each blob gets its own stream, and streams are multiplexed over one connection per address, kept open until it's been idle for [`QuicConfig::idle_timeout`](struct.QuicConfig.html#structfield.idle_timeout).
+/// That means a lost packet only holds up the blob it was part of, rather than every blob behind it like with [`TCP`](struct.TCP.html).
+/// Sending only returns once the listener has acknowledged the whole blob.
+/// If a pooled connection turns out to be dead, the blob is only sent again over a new one if it never got as far as a stream, so a blob is never sent twice, though it can be lost.
+///
+/// It's built for nodes whose network changes out from under them, like phones:
+///
+/// - Connections survive the sender's address changing, so a node moving between networks keeps its connections.
+///   Call [`rebind`](#method.rebind) after a network change to make sure they move over.
+/// - Reconnecting to a listener that's been connected to before resumes the earlier session, rather than checking its certificate all over again.
+///   With [`QuicConfig::early_data`](struct.QuicConfig.html#structfield.early_data) turned on, the first blob is sent straight away with 0-RTT, too, at the cost of letting it be replayed.
+///
+/// Certificates are checked against pins exactly like [`TLS`](struct.TLS.html), including the `pin` query parameter.
+pub struct Quic {
+  sender: Sender<Vec<u8>>,
+  receiver: Receiver<Vec<u8>>,
+  config: Arc<QuicConfig>,
+  provider: Arc<CryptoProvider>,
+  server_config: ServerConfig,
+  // declared before the runtime, so they're dropped while it's still running
+  client_configs: HashMap<Vec<String>, ClientConfig>,
+  endpoints: HashMap<bool, Endpoint>,
+  listeners: Vec<Endpoint>,
+  pool: HashMap<(String, Vec<String>), Connection>,
+  early_received: Arc<AtomicUsize>,
+  runtime: Runtime,
+}
+
+impl Quic {
+  /// Creates a QUIC transport with its own identity, certificate pins, and connection settings.
+  pub fn with_config(scheme: &str, config: QuicConfig) -> fail::Result<Quic> {
+    let provider = Arc::new(default_provider());
+    let mut server_crypto = config.identity.server_config(provider.clone())?;
+    server_crypto.alpn_protocols = vec![ALPN.to_vec()];
+    if config.early_data {
+      // QUIC only allows all or nothing
+      server_crypto.max_early_data_size = u32::MAX;
+    }
+    let server_crypto = QuicServerConfig::try_from(server_crypto)
+      .map_err(|e| fail::MesherFail::SetupFailure(format!("Failed to configure QUIC: {}", e)))?;
+    let mut server_config = ServerConfig::with_crypto(Arc::new(server_crypto));
+    server_config.transport_config(config.transport_config()?);
+
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+      .worker_threads(2)
+      .thread_name(format!("QUIC {}: worker", scheme))
+      .enable_all()
+      .build()
+      .map_err(|e| fail::MesherFail::SetupFailure(format!("Failed to start QUIC runtime: {:?}", e)))?;
+
+    let (sender, receiver) = channel();
+    Ok(Quic {
+      sender,
+      receiver,
+      config: Arc::new(config),
+      provider,
+      server_config,
+      client_configs: HashMap::new(),
+      endpoints: HashMap::new(),
+      listeners: vec![],
+      pool: HashMap::new(),
+      early_received: Arc::new(AtomicUsize::new(0)),
+      runtime,
+    })
+  }
+
+  /// The fingerprint of the certificate this transport's listeners present, for others to pin.
+  pub fn fingerprint(&self) -> String {
+    self.config.identity.fingerprint()
+  }
+
+  /// How many blobs this transport's listeners have received with 0-RTT, any of which could have been replays.
+  /// It stays at 0 unless [`QuicConfig::early_data`](struct.QuicConfig.html#structfield.early_data) is on.
+  pub fn early_data_received(&self) -> usize {
+    self.early_received.load(Ordering::SeqCst)
+  }
+
+  /// Moves every outgoing connection onto a new local socket, without reconnecting.
+  ///
+  /// Call this when the network changes, e.g. when a phone switches from Wi-Fi to mobile data, since the old socket may be stuck on a network that's gone.
+  pub fn rebind(&mut self) -> fail::Result<()> {
+    let _context = self.runtime.enter();
+    for (ipv6, endpoint) in &self.endpoints {
+      UdpSocket::bind(local_addr(*ipv6))
+        .and_then(|socket| endpoint.rebind(socket))
+        .map_err(|e| fail::MesherFail::SetupFailure(format!("Failed to rebind QUIC socket: {:?}", e)))?;
+    }
+    Ok(())
+  }
+
+  /// Gets the endpoint to connect to the address from, creating one if there isn't one yet.
+  /// There's one for IPv4 and one for IPv6, for the same reasons as with [`UDP`](struct.UDP.html).
+  fn endpoint_for(&mut self, to: SocketAddr) -> fail::Result<Endpoint> {
+    if let Some(endpoint) = self.endpoints.get(&to.is_ipv6()) {
+      return Ok(endpoint.clone());
+    }
+    let _context = self.runtime.enter();
+    let endpoint = Endpoint::client(local_addr(to.is_ipv6()))
+      .map_err(|e| fail::MesherFail::SendFailure(format!("Failed to bind QUIC socket: {:?}", e)))?;
+    self.endpoints.insert(to.is_ipv6(), endpoint.clone());
+    Ok(endpoint)
+  }
+
+  /// Gets the config for connecting with the pins, creating it if it hasn't been used yet.
+  ///
+  /// They're kept around because each remembers the session tickets that let reconnects resume a session, and send with 0-RTT if that's turned on.
+  fn client_config_for(&mut self, pins: &[String]) -> fail::Result<ClientConfig> {
+    if let Some(config) = self.client_configs.get(pins) {
+      return Ok(config.clone());
+    }
+    let mut crypto = client_config(self.provider.clone(), pins.to_vec(), self.config.accept_any_certificate)?;
+    crypto.alpn_protocols = vec![ALPN.to_vec()];
+    crypto.enable_early_data = self.config.early_data;
+    let crypto = QuicClientConfig::try_from(crypto)
+      .map_err(|e| fail::MesherFail::SetupFailure(format!("Failed to configure QUIC: {}", e)))?;
+    let mut config = ClientConfig::new(Arc::new(crypto));
+    config.transport_config(self.config.transport_config()?);
+    self.client_configs.insert(pins.to_vec(), config.clone());
+    Ok(config)
+  }
+
+  /// Connects to the listener, with 0-RTT if it's turned on and there's a session to resume.
+  /// If it was, this also returns whether the listener went on to accept the 0-RTT data.
+  fn connect(
+    &mut self,
+    path: &MesherUrl,
+    addr: SocketAddr,
+    pins: &[String],
+  ) -> fail::Result<(Connection, Option<ZeroRttAccepted>)> {
+    let endpoint = self.endpoint_for(addr)?;
+    let client_config = self.client_config_for(pins)?;
+    let connecting = {
+      let _context = self.runtime.enter();
+      endpoint
+        .connect_with(client_config, addr, path.host())
+        .map_err(|e| fail::MesherFail::SendFailure(format!("Failed to start QUIC connection: {}", e)))?
+    };
+    let connecting = if self.config.early_data {
+      match connecting.into_0rtt() {
+        Ok((conn, accepted)) => return Ok((conn, Some(accepted))),
+        // there's no session to resume, so do the whole handshake first
+        Err(connecting) => connecting,
+      }
+    } else {
+      connecting
+    };
+    let read_timeout = self.config.read_timeout;
+    let conn = self
+      .runtime
+      .block_on(async { timeout(read_timeout, connecting).await })
+      .map_err(|_| fail::MesherFail::SendFailure("Timed out establishing QUIC connection".to_owned()))?
+      .map_err(|e| fail::MesherFail::SendFailure(format!("Failed to establish QUIC connection: {}", e)))?;
+    Ok((conn, None))
+  }
+}
+
+fn local_addr(ipv6: bool) -> SocketAddr {
+  if ipv6 {
+    (Ipv6Addr::UNSPECIFIED, 0).into()
+  } else {
+    (Ipv4Addr::UNSPECIFIED, 0).into()
+  }
+}
+
+impl Transport for Quic {
+  fn new(scheme: &str) -> fail::Result<Self> {
+    Quic::with_config(scheme, QuicConfig::self_signed()?)
+  }
+
+  fn send(&mut self, path: MesherUrl, blob: Vec<u8>) -> fail::Result<()> {
+    let addr = socket_addr_from_url(&path)?;
+    let key = (path.authority().to_owned(), pins_for(&path, &self.config.pins));
+    let read_timeout = self.config.read_timeout;
+    self.pool.retain(|_, c| c.close_reason().is_none());
+
+    let send_fail = |e: SendError| fail::MesherFail::SendFailure(format!("Failed to send data: {}", e.message()));
+    if let Some(conn) = self.pool.get(&key) {
+      match self.runtime.block_on(send_on(conn, &blob, read_timeout)) {
+        Ok(()) => return Ok(()),
+        // the old connection is dead, but the listener might have the blob anyway, so it can't be sent again
+        Err(e @ SendError::Unacknowledged(_)) => {
+          self.pool.remove(&key);
+          return Err(send_fail(e));
+        }
+        // it never went anywhere, so fall through and send it over a new connection
+        Err(SendError::Unopened(_)) => self.pool.remove(&key),
+      };
+    }
+
+    let (conn, early) = self.connect(&path, addr, &key.1)?;
+    let mut sent = self.runtime.block_on(send_on(&conn, &blob, read_timeout));
+    if let (Err(_), Some(accepted)) = (&sent, early) {
+      // if the listener rejected the 0-RTT data, it threw the stream away unread, but the connection carries on as normal
+      let rejected = self.runtime.block_on(timeout(read_timeout, accepted)) == Ok(false);
+      if rejected && conn.close_reason().is_none() {
+        sent = self.runtime.block_on(send_on(&conn, &blob, read_timeout));
+      }
+    }
+    sent.map_err(send_fail)?;
+    self.pool.insert(key, conn);
+    Ok(())
+  }
+
+  fn listen(&mut self, path: MesherUrl) -> fail::Result<()> {
+    let addr = socket_addr_from_url(&path)?;
+    let endpoint = {
+      let _context = self.runtime.enter();
+      Endpoint::server(self.server_config.clone(), addr)
+        .map_err(|e| fail::MesherFail::ListenFailure(format!("Failed to bind listener: {:?}", e)))?
+    };
+
+    let accepting = endpoint.clone();
+    let config = self.config.clone();
+    let (sender, early) = (self.sender.clone(), self.early_received.clone());
+    let open = Arc::new(AtomicUsize::new(0));
+    self.runtime.spawn(async move {
+      while let Some(incoming) = accepting.accept().await {
+        if open.fetch_add(1, Ordering::SeqCst) >= config.max_connections {
+          open.fetch_sub(1, Ordering::SeqCst);
+          incoming.refuse();
+          continue;
+        }
+        let guard = ConnectionGuard(open.clone());
+        tokio::spawn(handle(incoming, config.clone(), guard, sender.clone(), early.clone()));
+      }
+    });
+    self.listeners.push(endpoint);
+    Ok(())
+  }
+
+  fn receive(&mut self) -> fail::Result<Vec<Vec<u8>>> {
+    Ok(self.receiver.try_iter().collect())
+  }
+}
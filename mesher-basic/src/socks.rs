@@ -0,0 +1,154 @@
+//! Dialing out through a SOCKS5 proxy, as described in [RFC 1928](https://tools.ietf.org/html/rfc1928) and [RFC 1929](https://tools.ietf.org/html/rfc1929).
+
+use std::{
+  io::{self, prelude::*},
+  net::{IpAddr, SocketAddr, TcpStream},
+  time::Duration,
+};
+
+const VERSION: u8 = 5;
+const NO_AUTH: u8 = 0;
+const USER_PASS: u8 = 2;
+const NO_ACCEPTABLE: u8 = 0xFF;
+const CONNECT: u8 = 1;
+const IPV4: u8 = 1;
+const DOMAIN: u8 = 3;
+const IPV6: u8 = 4;
+
+/// A SOCKS5 proxy to make outgoing connections through, e.g. Tor or `ssh -D`.
+///
+/// Hostnames are passed to the proxy to resolve, rather than resolved locally, so names only the proxy knows about, like Tor's `.onion` addresses, work too.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Socks5Proxy {
+  /// Where the proxy is listening, e.g. `127.0.0.1:9050` for Tor.
+  pub addr: SocketAddr,
+  /// The username and password to give the proxy, if it wants them.
+  /// Tor doesn't check them, but keeps connections with different credentials on different circuits.
+  pub credentials: Option<(String, String)>,
+}
+
+fn protocol_error(what: &str) -> io::Error {
+  io::Error::new(io::ErrorKind::InvalidData, format!("SOCKS5 proxy {}", what))
+}
+
+fn reply_error(code: u8) -> io::Error {
+  let reason = match code {
+    1 => "general failure",
+    2 => "connection not allowed by ruleset",
+    3 => "network unreachable",
+    4 => "host unreachable",
+    5 => "connection refused",
+    6 => "TTL expired",
+    7 => "command not supported",
+    8 => "address type not supported",
+    _ => "unknown error",
+  };
+  io::Error::other(format!("SOCKS5 proxy couldn't connect: {}", reason))
+}
+
+impl Socks5Proxy {
+  /// Connects to the host and port through the proxy, returning the connection once the proxy has set it up.
+  ///
+  /// The proxy gets `timeout` to respond at each step.
+  pub(crate) fn connect(&self, host: &str, port: u16, timeout: Duration) -> io::Result<TcpStream> {
+    let mut conn = TcpStream::connect(self.addr)?;
+    conn.set_read_timeout(Some(timeout))?;
+    conn.set_write_timeout(Some(timeout))?;
+
+    self.authenticate(&mut conn)?;
+
+    let mut request = vec![VERSION, CONNECT, 0];
+    match host.parse::<IpAddr>() {
+      Ok(IpAddr::V4(ip)) => {
+        request.push(IPV4);
+        request.extend_from_slice(&ip.octets());
+      }
+      Ok(IpAddr::V6(ip)) => {
+        request.push(IPV6);
+        request.extend_from_slice(&ip.octets());
+      }
+      Err(_) => {
+        if host.len() > u8::MAX as usize {
+          return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "hostname too long for SOCKS5",
+          ));
+        }
+        request.push(DOMAIN);
+        request.push(host.len() as u8);
+        request.extend_from_slice(host.as_bytes());
+      }
+    }
+    request.extend_from_slice(&port.to_be_bytes());
+    conn.write_all(&request)?;
+
+    let mut reply = [0; 4];
+    conn.read_exact(&mut reply)?;
+    if reply[0] != VERSION {
+      return Err(protocol_error("replied with the wrong version"));
+    }
+    if reply[1] != 0 {
+      return Err(reply_error(reply[1]));
+    }
+    // the address the proxy connected from isn't any use, but it has to be read to get past it
+    let bound_len = match reply[3] {
+      IPV4 => 4,
+      IPV6 => 16,
+      DOMAIN => {
+        let mut len = [0];
+        conn.read_exact(&mut len)?;
+        len[0] as usize
+      }
+      _ => return Err(protocol_error("replied with an unknown address type")),
+    };
+    let mut bound = vec![0; bound_len + 2];
+    conn.read_exact(&mut bound)?;
+
+    conn.set_read_timeout(None)?;
+    conn.set_write_timeout(None)?;
+    Ok(conn)
+  }
+
+  fn authenticate(&self, conn: &mut TcpStream) -> io::Result<()> {
+    let greeting: &[u8] = match self.credentials {
+      Some(_) => &[VERSION, 2, NO_AUTH, USER_PASS],
+      None => &[VERSION, 1, NO_AUTH],
+    };
+    conn.write_all(greeting)?;
+    let mut chosen = [0; 2];
+    conn.read_exact(&mut chosen)?;
+    if chosen[0] != VERSION {
+      return Err(protocol_error("replied with the wrong version"));
+    }
+    match (chosen[1], &self.credentials) {
+      (NO_AUTH, _) => Ok(()),
+      (USER_PASS, Some((user, pass))) => {
+        if user.len() > u8::MAX as usize || pass.len() > u8::MAX as usize {
+          return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "credentials too long for SOCKS5",
+          ));
+        }
+        let mut auth = vec![1, user.len() as u8];
+        auth.extend_from_slice(user.as_bytes());
+        auth.push(pass.len() as u8);
+        auth.extend_from_slice(pass.as_bytes());
+        conn.write_all(&auth)?;
+        let mut status = [0; 2];
+        conn.read_exact(&mut status)?;
+        if status[1] != 0 {
+          return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "SOCKS5 proxy rejected the credentials",
+          ));
+        }
+        Ok(())
+      }
+      (NO_ACCEPTABLE, _) => Err(io::Error::new(
+        io::ErrorKind::PermissionDenied,
+        "SOCKS5 proxy wants credentials that weren't given",
+      )),
+      _ => Err(protocol_error("chose an authentication method that wasn't offered")),
+    }
+  }
+}
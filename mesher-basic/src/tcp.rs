@@ -1,83 +1,487 @@
 use mesher::prelude::*;
 
+use crate::{
+  framing::{read_frame, write_frame},
+  pool::Pool,
+  socks::Socks5Proxy,
+};
+
 use std::{
-  io::prelude::*,
-  net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
-  sync::mpsc::{channel, Receiver, Sender},
-  thread::Builder,
+  collections::{HashMap, VecDeque},
+  io::{self, prelude::*},
+  net::{IpAddr, SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+  sync::{
+    atomic::{AtomicUsize, Ordering},
+    mpsc::{channel, Receiver, Sender},
+    Arc,
+  },
+  thread::{sleep, Builder},
+  time::{Duration, Instant},
 };
 
-fn socket_addr_from_string(scheme: &str, path: String) -> fail::Result<SocketAddr> {
-  let (_, path) = path.split_at(scheme.len() + 1);
+pub(crate) fn socket_addr_from_url(path: &MesherUrl) -> fail::Result<SocketAddr> {
   let get_path_fail = || fail::MesherFail::InvalidURL(format!("not a valid socket address format: {}", path));
   path
+    .authority()
     .to_socket_addrs()
     .map_err(|_| get_path_fail())?
     .next()
     .ok_or_else(get_path_fail)
 }
 
-fn listen(scheme: &str, addr: SocketAddr, sender: Sender<Vec<u8>>) -> fail::Result<()> {
-  let tcp_listen = TcpListener::bind(addr)
-    .map_err(|e| fail::MesherFail::ListenFailure(format!("Failed to bind listener: {:?}", e)))?;
+/// Opens a connection to the host and port in the path, through [`TcpConfig::proxy`](struct.TcpConfig.html#structfield.proxy) if there is one.
+///
+/// This is shared by every TCP-based transport, so they can all be proxied.
+/// When proxied, the host is never resolved locally, so it only needs to make sense to the proxy.
+/// Either way, connecting gives up after [`TcpConfig::read_timeout`](struct.TcpConfig.html#structfield.read_timeout).
+pub(crate) fn dial(path: &MesherUrl, default_port: Option<u16>, config: &TcpConfig) -> fail::Result<TcpStream> {
+  let port = path
+    .port()
+    .or(default_port)
+    .ok_or_else(|| fail::MesherFail::InvalidURL(format!("no port in {}", path)))?;
+  let conn = match &config.proxy {
+    Some(proxy) => proxy.connect(path.host(), port, config.read_timeout),
+    None => connect_timeout(path.host(), port, config.read_timeout),
+  };
+  conn.map_err(|e| fail::MesherFail::SendFailure(format!("Failed to establish TCP connection: {:?}", e)))
+}
 
-  let thread_code = move || {
-    for conn in tcp_listen.incoming() {
-      let mut conn = match conn {
-        Ok(c) => c,
-        Err(_) => continue,
-      };
-      let mut bytes = vec![];
-      if conn.read_to_end(&mut bytes).is_err() {
-        continue;
+/// Connects to the first address the host resolves to that answers in time.
+fn connect_timeout(host: &str, port: u16, timeout: Duration) -> io::Result<TcpStream> {
+  let mut last_error = io::Error::new(io::ErrorKind::NotFound, format!("{} doesn't resolve to anything", host));
+  for addr in (host, port).to_socket_addrs()? {
+    match TcpStream::connect_timeout(&addr, timeout) {
+      Ok(conn) => return Ok(conn),
+      Err(e) => last_error = e,
+    }
+  }
+  Err(last_error)
+}
+
+/// How blobs are delimited on a TCP connection, chosen by the `mode` query parameter of the path.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+  /// `mode=oneshot`, the default: each blob gets its own connection, and the end of the connection is the end of the blob.
+  OneShot,
+  /// `mode=framed`: each blob is length-prefixed, so many can be sent over one long-lived connection.
+  Framed,
+}
+
+impl Mode {
+  fn from_url(path: &MesherUrl) -> fail::Result<Mode> {
+    match path.query_param("mode") {
+      None | Some("oneshot") => Ok(Mode::OneShot),
+      Some("framed") => Ok(Mode::Framed),
+      Some(other) => Err(fail::MesherFail::InvalidURL(format!(
+        "unknown TCP mode {:?} in {}",
+        other, path
+      ))),
+    }
+  }
+}
+
+/// Settings for a [`TCP`](struct.TCP.html) transport which apply to every path it uses.
+///
+/// Most of these limit what a listener will put up with, so that a single misbehaving client can't tie it up or exhaust its memory.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TcpConfig {
+  /// How long a framed connection can go unused before it's closed, by either end.
+  /// Idle connections this end opened are closed the next time anything is sent or received.
+  pub idle_timeout: Duration,
+  /// The largest blob a listener will accept.
+  /// Connections trying to send anything larger are closed.
+  pub max_packet_size: usize,
+  /// How long a listener will wait for a whole blob to arrive, once it's started arriving.
+  /// Connections which take longer are closed.
+  pub read_timeout: Duration,
+  /// How many connections a listener will handle at once.
+  /// Any more are closed immediately.
+  pub max_connections: usize,
+  /// How many connections a listener will accept from a single IP address within each `rate_window`.
+  /// Any more are closed immediately.
+  pub max_connections_per_ip: usize,
+  /// The sliding window that `max_connections_per_ip` applies over.
+  pub rate_window: Duration,
+  /// A SOCKS5 proxy to make outgoing connections through, e.g. to send over Tor or an SSH tunnel.
+  /// Listeners aren't affected.
+  pub proxy: Option<Socks5Proxy>,
+}
+
+impl Default for TcpConfig {
+  fn default() -> TcpConfig {
+    TcpConfig {
+      idle_timeout: Duration::from_secs(60),
+      max_packet_size: 1024 * 1024,
+      read_timeout: Duration::from_secs(10),
+      max_connections: 64,
+      max_connections_per_ip: 120,
+      rate_window: Duration::from_secs(60),
+      proxy: None,
+    }
+  }
+}
+
+/// Checks whether the other end of a connection has closed it, without blocking or consuming anything.
+pub(crate) fn is_open(stream: &TcpStream) -> bool {
+  if stream.set_nonblocking(true).is_err() {
+    return false;
+  }
+  let open = match stream.peek(&mut [0]) {
+    // there shouldn't be anything to read, but if there is, it's still open
+    Ok(n) => n > 0,
+    Err(e) => e.kind() == io::ErrorKind::WouldBlock,
+  };
+  stream.set_nonblocking(false).is_ok() && open
+}
+
+/// A socket whose reads can be given a timeout, so a [`Deadline`](struct.Deadline.html) can be enforced on it.
+pub(crate) trait ReadTimeout {
+  fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+}
+
+impl ReadTimeout for TcpStream {
+  fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+    TcpStream::set_read_timeout(self, timeout)
+  }
+}
+
+impl<T: ReadTimeout + ?Sized> ReadTimeout for &T {
+  fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+    (**self).set_read_timeout(timeout)
+  }
+}
+
+/// Reads from something layered over a socket, waiting up to the idle timeout for data to start arriving, but failing once the read timeout has passed after that, no matter how slowly the data is trickling in.
+///
+/// For plain sockets, `inner` is just the socket itself.
+/// Writes go straight through to `inner`.
+pub(crate) struct Deadline<S, R> {
+  socket: S,
+  inner: R,
+  idle_timeout: Duration,
+  read_timeout: Duration,
+  deadline: Option<Instant>,
+}
+
+impl<S: ReadTimeout, R> Deadline<S, R> {
+  pub(crate) fn new(socket: S, inner: R, idle_timeout: Duration, read_timeout: Duration) -> Deadline<S, R> {
+    Deadline {
+      socket,
+      inner,
+      idle_timeout,
+      read_timeout,
+      deadline: None,
+    }
+  }
+
+  /// Starts the read timeout now, rather than when data starts arriving, e.g. for a handshake.
+  pub(crate) fn start(mut self) -> Deadline<S, R> {
+    self.deadline = Some(Instant::now() + self.read_timeout);
+    self
+  }
+
+  /// Goes back to waiting up to the idle timeout, for the next thing to start arriving.
+  pub(crate) fn reset(&mut self) {
+    self.deadline = None;
+  }
+}
+
+impl<S: ReadTimeout, R: Read> Read for Deadline<S, R> {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    let timeout = match self.deadline {
+      None => self.idle_timeout,
+      Some(deadline) => deadline.saturating_duration_since(Instant::now()),
+    };
+    if timeout == Duration::from_secs(0) {
+      return Err(io::ErrorKind::TimedOut.into());
+    }
+    self.socket.set_read_timeout(Some(timeout))?;
+    let read = self.inner.read(buf)?;
+    if self.deadline.is_none() && read > 0 {
+      self.deadline = Some(Instant::now() + self.read_timeout);
+    }
+    Ok(read)
+  }
+}
+
+impl<S, R: Write> Write for Deadline<S, R> {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    self.inner.write(buf)
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    self.inner.flush()
+  }
+}
+
+fn handle_framed(conn: TcpStream, config: &TcpConfig, sender: Sender<Vec<u8>>) {
+  let mut reader = Deadline::new(&conn, &conn, config.idle_timeout, config.read_timeout);
+  loop {
+    reader.reset();
+    let blob = match read_frame(&mut reader, config.max_packet_size) {
+      Ok(Some(blob)) => blob,
+      _ => return,
+    };
+    if sender.send(blob).is_err() {
+      return;
+    }
+  }
+}
+
+fn handle_oneshot(conn: TcpStream, config: &TcpConfig, sender: Sender<Vec<u8>>) {
+  let reader = Deadline::new(&conn, &conn, config.idle_timeout, config.read_timeout).start();
+  let mut bytes = vec![];
+  // read one past the limit, to tell whether it was exceeded
+  if reader
+    .take(config.max_packet_size as u64 + 1)
+    .read_to_end(&mut bytes)
+    .is_err()
+  {
+    return;
+  }
+  if bytes.len() <= config.max_packet_size {
+    let _ = sender.send(bytes);
+  }
+}
+
+/// Tracks recent connections from each IP, to enforce [`TcpConfig::max_connections_per_ip`](struct.TcpConfig.html#structfield.max_connections_per_ip).
+struct RateLimiter {
+  max: usize,
+  window: Duration,
+  recent: HashMap<IpAddr, VecDeque<Instant>>,
+}
+
+impl RateLimiter {
+  /// Records a connection from the IP, returning whether it's within the limit.
+  fn allow(&mut self, ip: IpAddr) -> bool {
+    let now = Instant::now();
+    let window = self.window;
+    self.recent.retain(|_, times| {
+      while times.front().is_some_and(|t| now.duration_since(*t) >= window) {
+        times.pop_front();
       }
-      if sender.send(bytes).is_err() {
-        return;
+      !times.is_empty()
+    });
+    let times = self.recent.entry(ip).or_default();
+    if times.len() >= self.max {
+      return false;
+    }
+    times.push_back(now);
+    true
+  }
+}
+
+/// Decrements the count of open connections when the connection's handler finishes, however it finishes.
+pub(crate) struct ConnectionGuard(pub(crate) Arc<AtomicUsize>);
+
+impl Drop for ConnectionGuard {
+  fn drop(&mut self) {
+    self.0.fetch_sub(1, Ordering::SeqCst);
+  }
+}
+
+/// Something which connections can be accepted from, so [`serve`](fn.serve.html) can work with any kind of socket.
+pub(crate) trait Listener: Send + 'static {
+  type Stream: Send + 'static;
+
+  fn accept(&self) -> io::Result<Self::Stream>;
+}
+
+impl Listener for TcpListener {
+  type Stream = TcpStream;
+
+  fn accept(&self) -> io::Result<TcpStream> {
+    TcpListener::accept(self).map(|(conn, _)| conn)
+  }
+}
+
+/// Whether an error accepting a connection means the listener can never accept another, rather than that this one failed, or that something's temporarily run out, like file descriptors.
+fn is_fatal(e: &io::Error) -> bool {
+  if let io::ErrorKind::InvalidInput | io::ErrorKind::Unsupported = e.kind() {
+    return true;
+  }
+  #[cfg(unix)]
+  if let Some(libc::EBADF) | Some(libc::ENOTSOCK) = e.raw_os_error() {
+    return true;
+  }
+  false
+}
+
+/// The longest the listener waits before trying to accept again, after failing to.
+const MAX_ACCEPT_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Starts a thread accepting connections from an already bound listener, handling at most `max_connections` at once.
+///
+/// Each connection `admit` allows is handed to `handler` on its own thread; the rest are closed immediately.
+/// This is shared by every stream-based transport, so they all get the same protection against misbehaving clients.
+///
+/// If accepting fails, e.g. because the process is out of file descriptors, the listener backs off before trying again, rather than spinning.
+/// If the listener itself is broken, it stops for good.
+pub(crate) fn serve<L, C, A, H>(
+  name: &str,
+  listener: L,
+  config: &C,
+  max_connections: usize,
+  mut admit: A,
+  handler: H,
+) -> fail::Result<()>
+where
+  L: Listener,
+  C: Clone + Send + Sync + 'static,
+  A: FnMut(&L::Stream) -> bool + Send + 'static,
+  H: Fn(L::Stream, &C) + Send + Sync + 'static,
+{
+  let config = Arc::new(config.clone());
+  let handler = Arc::new(handler);
+  let open = Arc::new(AtomicUsize::new(0));
+
+  let conn_name = format!("{} connection", name);
+  let mut backoff = Duration::from_millis(0);
+  let thread_code = move || loop {
+    let conn = match listener.accept() {
+      Ok(c) => c,
+      Err(e) if is_fatal(&e) => return,
+      Err(_) => {
+        backoff = (backoff * 2).clamp(Duration::from_millis(10), MAX_ACCEPT_BACKOFF);
+        sleep(backoff);
+        continue;
       }
+    };
+    backoff = Duration::from_millis(0);
+    if !admit(&conn) {
+      continue;
     }
+    if open.fetch_add(1, Ordering::SeqCst) >= max_connections {
+      open.fetch_sub(1, Ordering::SeqCst);
+      continue;
+    }
+    let guard = ConnectionGuard(open.clone());
+    let (config, handler) = (config.clone(), handler.clone());
+    // no thread means nobody reads this connection, so close it rather than leave the peer hanging
+    let _ = Builder::new().name(conn_name.clone()).spawn(move || {
+      let _guard = guard;
+      handler(conn, &config)
+    });
   };
 
   Builder::new()
-    .name(format!("TCP {}:{} listener", scheme, addr))
+    .name(format!("{} listener", name))
     .spawn(thread_code)
-    .map_err(|e| fail::MesherFail::SetupFailure(format!("Faield to start TCP {}: listener: {:?}", scheme, e)))?;
+    .map_err(|e| fail::MesherFail::SetupFailure(format!("Failed to start {} listener: {:?}", name, e)))?;
 
   Ok(())
 }
 
+/// Binds a TCP listener and starts a thread accepting connections on it, enforcing the limits in the config.
+///
+/// Each accepted connection is handed to `handler` on its own thread.
+/// This is shared by every TCP-based transport, so they can all be rate limited per IP as well.
+pub(crate) fn listen_with<H>(name: &str, addr: SocketAddr, config: &TcpConfig, handler: H) -> fail::Result<()>
+where
+  H: Fn(TcpStream, &TcpConfig) + Send + Sync + 'static,
+{
+  let tcp_listen = TcpListener::bind(addr)
+    .map_err(|e| fail::MesherFail::ListenFailure(format!("Failed to bind listener: {:?}", e)))?;
+  let mut limiter = RateLimiter {
+    max: config.max_connections_per_ip,
+    window: config.rate_window,
+    recent: HashMap::new(),
+  };
+  let admit = move |conn: &TcpStream| match conn.peer_addr() {
+    Ok(peer) => limiter.allow(peer.ip()),
+    Err(_) => false,
+  };
+  serve(
+    &format!("{} {}", name, addr),
+    tcp_listen,
+    config,
+    config.max_connections,
+    admit,
+    handler,
+  )
+}
+
+fn listen(scheme: &str, addr: SocketAddr, mode: Mode, config: &TcpConfig, sender: Sender<Vec<u8>>) -> fail::Result<()> {
+  listen_with(&format!("TCP {}:", scheme), addr, config, move |conn, config| {
+    let sender = sender.clone();
+    match mode {
+      Mode::Framed => handle_framed(conn, config, sender),
+      Mode::OneShot => handle_oneshot(conn, config, sender),
+    }
+  })
+}
+
+/// Sends and receives blobs over TCP, e.g. `tcp:localhost:18540`.
+///
+/// By default, each blob gets a new connection, which is closed afterwards, so that it works with every existing node.
+/// Adding `?mode=framed` to the path length-prefixes blobs instead, and keeps connections open and reuses them for later blobs to the same address until they've been idle for [`TcpConfig::idle_timeout`](struct.TcpConfig.html#structfield.idle_timeout).
+/// Both ends of a connection have to agree on the mode, so it should be the same on the listening path and on the paths sending to it.
+///
+/// To send through Tor or an SSH tunnel, set a SOCKS5 [`TcpConfig::proxy`](struct.TcpConfig.html#structfield.proxy); then hostnames only the proxy can resolve, like `tcp:example.onion:18540`, work too.
 pub struct TCP {
   sender: Sender<Vec<u8>>,
   receiver: Receiver<Vec<u8>>,
   scheme: String,
+  config: TcpConfig,
+  pool: Pool<String, TcpStream>,
 }
 
-impl Transport for TCP {
-  fn new(scheme: &str) -> fail::Result<Self> {
+impl TCP {
+  /// Creates a TCP transport with custom timeouts, connection limits, or a SOCKS5 proxy.
+  pub fn with_config(scheme: &str, config: TcpConfig) -> TCP {
     let (sender, receiver) = channel();
-    Ok(TCP {
+    TCP {
       scheme: scheme.to_string(),
       sender,
       receiver,
-    })
+      pool: Pool::new(config.idle_timeout, |stream| is_open(stream)),
+      config,
+    }
   }
 
-  fn send(&mut self, path: String, blob: Vec<u8>) -> fail::Result<()> {
-    let sock = socket_addr_from_string(&self.scheme, path)?;
-    let mut out = TcpStream::connect(sock)
-      .map_err(|e| fail::MesherFail::SendFailure(format!("Failed to establish TCP connection: {:?}", e)))?;
+  fn send_oneshot(&mut self, path: &MesherUrl, blob: &[u8]) -> fail::Result<()> {
+    let mut out = dial(path, None, &self.config)?;
     out
-      .write_all(&blob)
+      .write_all(blob)
       .map_err(|e| fail::MesherFail::SendFailure(format!("Failed to send data: {:?}", e)))?;
     Ok(())
   }
 
-  fn listen(&mut self, path: String) -> fail::Result<()> {
-    let sock = socket_addr_from_string(&self.scheme, path)?;
-    listen(&self.scheme, sock, self.sender.clone())?;
+  fn send_framed(&mut self, path: &MesherUrl, blob: &[u8]) -> fail::Result<()> {
+    let config = &self.config;
+    // the address can't be resolved here when it's proxied, so connections are pooled by what the path says instead
+    self.pool.send(
+      path.authority().to_owned(),
+      || dial(path, None, config),
+      |stream| {
+        write_frame(stream, blob).map_err(|e| fail::MesherFail::SendFailure(format!("Failed to send data: {:?}", e)))
+      },
+    )
+  }
+}
+
+impl Transport for TCP {
+  fn new(scheme: &str) -> fail::Result<Self> {
+    Ok(TCP::with_config(scheme, TcpConfig::default()))
+  }
+
+  fn send(&mut self, path: MesherUrl, blob: Vec<u8>) -> fail::Result<()> {
+    match Mode::from_url(&path)? {
+      Mode::Framed => self.send_framed(&path, &blob),
+      Mode::OneShot => self.send_oneshot(&path, &blob),
+    }
+  }
+
+  fn listen(&mut self, path: MesherUrl) -> fail::Result<()> {
+    let sock = socket_addr_from_url(&path)?;
+    let mode = Mode::from_url(&path)?;
+    listen(&self.scheme, sock, mode, &self.config, self.sender.clone())?;
     Ok(())
   }
 
   fn receive(&mut self) -> fail::Result<Vec<Vec<u8>>> {
+    // a node which stops sending would otherwise hold its last connections open forever
+    self.pool.prune();
     Ok(self.receiver.try_iter().collect())
   }
 }
@@ -0,0 +1,374 @@
+use mesher::prelude::*;
+
+use crate::{
+  framing::{read_frame, write_frame},
+  pool::Pool,
+  tcp::{dial, listen_with, socket_addr_from_url, Deadline, TcpConfig},
+};
+
+use rustls::{
+  client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+  crypto::{ring::default_provider, verify_tls12_signature, verify_tls13_signature, CryptoProvider},
+  pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer, ServerName, UnixTime},
+  ClientConfig, ClientConnection, DigitallySignedStruct, ServerConfig, ServerConnection, SignatureScheme, StreamOwned,
+};
+use std::{
+  convert::TryFrom,
+  io,
+  net::TcpStream,
+  sync::{
+    mpsc::{channel, Receiver, Sender},
+    Arc,
+  },
+};
+
+/// Turns a certificate into its fingerprint: the SHA-256 hash of its DER encoding, in lowercase hex.
+fn fingerprint(cert: &[u8]) -> String {
+  ring::digest::digest(&ring::digest::SHA256, cert)
+    .as_ref()
+    .iter()
+    .map(|b| format!("{:02x}", b))
+    .collect()
+}
+
+/// Puts a fingerprint into the same form as [`fingerprint`](fn.fingerprint.html) produces, so they can be compared.
+/// Colons and case are ignored, so `AB:CD:...` is the same as `abcd...`.
+pub(crate) fn normalize_pin(pin: &str) -> String {
+  pin
+    .chars()
+    .filter(|c| *c != ':')
+    .collect::<String>()
+    .to_ascii_lowercase()
+}
+
+/// The pins a connection along the path has to match: the path's own `pin` if it has one, or else the configured ones.
+pub(crate) fn pins_for(path: &MesherUrl, configured: &[String]) -> Vec<String> {
+  match path.query_param("pin") {
+    Some(pin) => vec![normalize_pin(pin)],
+    None => configured.iter().map(|p| normalize_pin(p)).collect(),
+  }
+}
+
+/// The certificate and private key a [`TLS`](struct.TLS.html) listener presents to anyone connecting to it.
+#[derive(Clone)]
+pub struct TlsIdentity {
+  chain: Vec<Vec<u8>>,
+  key: Vec<u8>,
+}
+
+impl TlsIdentity {
+  /// Generates a new self-signed certificate, valid for `localhost`.
+  ///
+  /// Since nothing can vouch for it, anyone connecting should pin its [`fingerprint`](#method.fingerprint).
+  pub fn self_signed() -> fail::Result<TlsIdentity> {
+    let generated = rcgen::generate_simple_self_signed(vec!["localhost".to_owned()])
+      .map_err(|e| fail::MesherFail::SetupFailure(format!("Failed to generate certificate: {:?}", e)))?;
+    Ok(TlsIdentity {
+      chain: vec![generated.cert.der().to_vec()],
+      key: generated.key_pair.serialize_der(),
+    })
+  }
+
+  /// Uses an existing certificate chain, leaf first, and its PKCS#8 private key, both DER-encoded.
+  pub fn from_der(chain: Vec<Vec<u8>>, pkcs8_key: Vec<u8>) -> TlsIdentity {
+    TlsIdentity { chain, key: pkcs8_key }
+  }
+
+  /// The fingerprint of the leaf certificate, for others to pin: the SHA-256 hash of its DER encoding, in lowercase hex.
+  pub fn fingerprint(&self) -> String {
+    self.chain.first().map(|c| fingerprint(c)).unwrap_or_default()
+  }
+
+  pub(crate) fn server_config(&self, provider: Arc<CryptoProvider>) -> fail::Result<ServerConfig> {
+    let chain = self.chain.iter().map(|c| CertificateDer::from(c.clone())).collect();
+    let key = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(self.key.clone()));
+    ServerConfig::builder_with_provider(provider)
+      .with_safe_default_protocol_versions()
+      .and_then(|b| b.with_no_client_auth().with_single_cert(chain, key))
+      .map_err(|e| fail::MesherFail::SetupFailure(format!("Invalid TLS identity: {:?}", e)))
+  }
+}
+
+/// Settings for a [`TLS`](struct.TLS.html) transport which apply to every path it uses.
+#[derive(Clone)]
+pub struct TlsConfig {
+  /// The limits on connections, which work exactly the same as for [`TCP`](struct.TCP.html).
+  pub tcp: TcpConfig,
+  /// The certificate presented by listeners.
+  pub identity: TlsIdentity,
+  /// The fingerprints of the certificates which will be trusted when sending, as produced by [`TlsIdentity::fingerprint`](struct.TlsIdentity.html#method.fingerprint).
+  /// A path's own `pin` takes precedence over these.
+  pub pins: Vec<String>,
+  /// Whether to trust every certificate when sending along a path with no pin, if there are no pins here either.
+  ///
+  /// That still hides the traffic from passive observers, but anyone who can intercept the connection can read it, so it's off by default, and sending without a pin fails.
+  pub accept_any_certificate: bool,
+}
+
+impl TlsConfig {
+  /// The default limits, a newly generated self-signed certificate, and no pins.
+  pub fn self_signed() -> fail::Result<TlsConfig> {
+    Ok(TlsConfig {
+      tcp: TcpConfig::default(),
+      identity: TlsIdentity::self_signed()?,
+      pins: vec![],
+      accept_any_certificate: false,
+    })
+  }
+}
+
+/// Checks a server's certificate against a set of pinned fingerprints, rather than any certificate authority.
+///
+/// If there are no pins, every certificate is accepted; [`client_config`](fn.client_config.html) only allows that when it's been explicitly asked for.
+#[derive(Debug)]
+struct PinVerifier {
+  pins: Vec<String>,
+  provider: Arc<CryptoProvider>,
+}
+
+impl ServerCertVerifier for PinVerifier {
+  fn verify_server_cert(
+    &self,
+    end_entity: &CertificateDer<'_>,
+    _intermediates: &[CertificateDer<'_>],
+    _server_name: &ServerName<'_>,
+    _ocsp_response: &[u8],
+    _now: UnixTime,
+  ) -> Result<ServerCertVerified, rustls::Error> {
+    if self.pins.is_empty() || self.pins.contains(&fingerprint(end_entity)) {
+      Ok(ServerCertVerified::assertion())
+    } else {
+      Err(rustls::Error::General("certificate doesn't match any pin".to_owned()))
+    }
+  }
+
+  fn verify_tls12_signature(
+    &self,
+    message: &[u8],
+    cert: &CertificateDer<'_>,
+    dss: &DigitallySignedStruct,
+  ) -> Result<HandshakeSignatureValid, rustls::Error> {
+    verify_tls12_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+  }
+
+  fn verify_tls13_signature(
+    &self,
+    message: &[u8],
+    cert: &CertificateDer<'_>,
+    dss: &DigitallySignedStruct,
+  ) -> Result<HandshakeSignatureValid, rustls::Error> {
+    verify_tls13_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+  }
+
+  fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+    self.provider.signature_verification_algorithms.supported_schemes()
+  }
+}
+
+/// Builds the config for connecting to servers, trusting only certificates which match the pins.
+///
+/// If there are no pins, this fails, unless `accept_any_certificate` is set, in which case every certificate is trusted.
+pub(crate) fn client_config(
+  provider: Arc<CryptoProvider>,
+  pins: Vec<String>,
+  accept_any_certificate: bool,
+) -> fail::Result<ClientConfig> {
+  if pins.is_empty() && !accept_any_certificate {
+    return Err(fail::MesherFail::SendFailure(
+      "No pin to check the certificate against; add one, or set accept_any_certificate".to_owned(),
+    ));
+  }
+  let verifier = PinVerifier {
+    pins,
+    provider: provider.clone(),
+  };
+  Ok(
+    ClientConfig::builder_with_provider(provider)
+      .with_safe_default_protocol_versions()
+      .map_err(|e| fail::MesherFail::SetupFailure(format!("Failed to configure TLS: {:?}", e)))?
+      .dangerous()
+      .with_custom_certificate_verifier(Arc::new(verifier))
+      .with_no_client_auth(),
+  )
+}
+
+type ClientStream = StreamOwned<ClientConnection, TcpStream>;
+
+/// Checks whether the other end of a connection has closed it, without blocking.
+///
+/// Unlike with plain TCP, the server may have sent things after the handshake, like session tickets, so those are processed along the way.
+fn is_open(stream: &mut ClientStream) -> bool {
+  if stream.sock.set_nonblocking(true).is_err() {
+    return false;
+  }
+  let open = loop {
+    match stream.conn.read_tls(&mut stream.sock) {
+      Ok(0) => break false,
+      Ok(_) => match stream.conn.process_new_packets() {
+        Ok(state) if state.peer_has_closed() => break false,
+        Ok(_) => continue,
+        Err(_) => break false,
+      },
+      Err(e) if e.kind() == io::ErrorKind::WouldBlock => break true,
+      Err(_) => break false,
+    }
+  };
+  stream.sock.set_nonblocking(false).is_ok() && open
+}
+
+fn handle(conn: TcpStream, config: &TcpConfig, server_config: Arc<ServerConfig>, sender: Sender<Vec<u8>>) {
+  let mut tls = match ServerConnection::new(server_config) {
+    Ok(t) => t,
+    Err(_) => return,
+  };
+  let mut sock = &conn;
+
+  // the handshake gets the read timeout, just like any other data arriving
+  let mut handshake = Deadline::new(&conn, &conn, config.idle_timeout, config.read_timeout).start();
+  while tls.is_handshaking() {
+    if tls.complete_io(&mut handshake).is_err() {
+      return;
+    }
+  }
+
+  loop {
+    // wait as long as the idle timeout for the next frame to start, unless part of it has already been decrypted...
+    let buffered = match tls.process_new_packets() {
+      Ok(state) => state.plaintext_bytes_to_read() > 0,
+      Err(_) => return,
+    };
+    if !buffered {
+      if conn.set_read_timeout(Some(config.idle_timeout)).is_err() {
+        return;
+      }
+      match conn.peek(&mut [0]) {
+        Ok(n) if n > 0 => (),
+        _ => return,
+      }
+    }
+    // ...but once it has, it only gets the read timeout to finish
+    let inner = rustls::Stream::new(&mut tls, &mut sock);
+    let mut reader = Deadline::new(&conn, inner, config.idle_timeout, config.read_timeout).start();
+    let blob = match read_frame(&mut reader, config.max_packet_size) {
+      Ok(Some(blob)) => blob,
+      _ => return,
+    };
+    if sender.send(blob).is_err() {
+      return;
+    }
+  }
+}
+
+/// Sends and receives blobs over TLS, e.g. `tls:localhost:18540`.
+///
+/// Blobs are framed and connections reused exactly like [`TCP`](struct.TCP.html)'s `mode=framed`, but everything, including the framing, is encrypted.
+/// That means someone watching the connection can't see where one blob ends and the next begins.
+///
+/// Nodes are rarely going to have certificates signed by a certificate authority, so they aren't checked against one.
+/// Instead, certificates are checked against pinned fingerprints, from [`TlsIdentity::fingerprint`](struct.TlsIdentity.html#method.fingerprint):
+///
+/// - If the path has a `pin`, e.g. `tls:localhost:18540?pin=3a7f...`, the certificate must match it.
+/// - Otherwise, if [`TlsConfig::pins`](struct.TlsConfig.html#structfield.pins) isn't empty, the certificate must match one of them.
+/// - Otherwise, sending fails, unless [`TlsConfig::accept_any_certificate`](struct.TlsConfig.html#structfield.accept_any_certificate) is set.
+///   Then any certificate is accepted, which still hides the traffic from passive observers, but not from an active attacker.
+///
+/// When created through [`Mesher::add_transport`](../mesher/struct.Mesher.html#method.add_transport), listeners use a newly generated self-signed certificate.
+/// To use a specific one, or to set pins, create it with [`TLS::with_config`](#method.with_config) instead:
+///
+/// ```
+/// use mesher::prelude::*;
+/// use mesher_basic::{TlsConfig, TLS};
+///
+/// let mut config = TlsConfig::self_signed().expect("Failed to generate certificate");
+/// config.pins.push("3a7f...".to_owned());
+/// let mut mesher = Mesher::unsigned(vec![encrypt::gen_keypair().1]);
+/// mesher.add_transport_instance("tls", TLS::with_config("tls", config).expect("Failed to create transport"));
+/// ```
+pub struct TLS {
+  sender: Sender<Vec<u8>>,
+  receiver: Receiver<Vec<u8>>,
+  scheme: String,
+  config: TlsConfig,
+  provider: Arc<CryptoProvider>,
+  server_config: Arc<ServerConfig>,
+  pool: Pool<(String, Vec<String>), ClientStream>,
+}
+
+impl TLS {
+  /// Creates a TLS transport that presents `config.identity` and trusts only the pinned certificates.
+  pub fn with_config(scheme: &str, config: TlsConfig) -> fail::Result<TLS> {
+    let provider = Arc::new(default_provider());
+    let server_config = Arc::new(config.identity.server_config(provider.clone())?);
+    let (sender, receiver) = channel();
+    Ok(TLS {
+      sender,
+      receiver,
+      scheme: scheme.to_string(),
+      provider,
+      server_config,
+      pool: Pool::new(config.tcp.idle_timeout, is_open),
+      config,
+    })
+  }
+
+  /// The fingerprint of the certificate this transport's listeners present, for others to pin.
+  pub fn fingerprint(&self) -> String {
+    self.config.identity.fingerprint()
+  }
+
+  fn connect(
+    config: &TlsConfig,
+    provider: &Arc<CryptoProvider>,
+    path: &MesherUrl,
+    pins: Vec<String>,
+  ) -> fail::Result<ClientStream> {
+    let client_config = client_config(provider.clone(), pins, config.accept_any_certificate)?;
+    let config = &config.tcp;
+    let name = ServerName::try_from(path.host().to_owned())
+      .map_err(|_| fail::MesherFail::InvalidURL(format!("not a valid TLS server name: {}", path)))?;
+    let conn = ClientConnection::new(Arc::new(client_config), name)
+      .map_err(|e| fail::MesherFail::SendFailure(format!("Failed to start TLS connection: {:?}", e)))?;
+    let tcp = dial(path, None, config)?;
+    // don't let a silent server hang the handshake forever
+    tcp
+      .set_read_timeout(Some(config.read_timeout))
+      .map_err(|e| fail::MesherFail::SendFailure(format!("Failed to configure TCP connection: {:?}", e)))?;
+    Ok(StreamOwned::new(conn, tcp))
+  }
+}
+
+impl Transport for TLS {
+  fn new(scheme: &str) -> fail::Result<Self> {
+    TLS::with_config(scheme, TlsConfig::self_signed()?)
+  }
+
+  fn send(&mut self, path: MesherUrl, blob: Vec<u8>) -> fail::Result<()> {
+    let key = (path.authority().to_owned(), pins_for(&path, &self.config.pins));
+    let (config, provider, pins) = (&self.config, &self.provider, key.1.clone());
+    self.pool.send(
+      key,
+      || TLS::connect(config, provider, &path, pins),
+      |stream| {
+        write_frame(stream, &blob).map_err(|e| fail::MesherFail::SendFailure(format!("Failed to send data: {:?}", e)))
+      },
+    )
+  }
+
+  fn listen(&mut self, path: MesherUrl) -> fail::Result<()> {
+    let sock = socket_addr_from_url(&path)?;
+    let server_config = self.server_config.clone();
+    let sender = self.sender.clone();
+    listen_with(
+      &format!("TLS {}:", self.scheme),
+      sock,
+      &self.config.tcp,
+      move |conn, config| handle(conn, config, server_config.clone(), sender.clone()),
+    )
+  }
+
+  fn receive(&mut self) -> fail::Result<Vec<Vec<u8>>> {
+    // a node which stops sending would otherwise hold its last connections open forever
+    self.pool.prune();
+    Ok(self.receiver.try_iter().collect())
+  }
+}
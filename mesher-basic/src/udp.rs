@@ -0,0 +1,353 @@
+use mesher::prelude::*;
+
+use crate::tcp::socket_addr_from_url;
+
+use std::{
+  collections::{hash_map::Entry, HashMap},
+  net::{SocketAddr, UdpSocket},
+  sync::mpsc::{channel, Receiver, Sender},
+  thread::Builder,
+  time::{Duration, Instant},
+};
+
+/// The size of the header on each fragment: the blob's ID as a `u32`, then the fragment's index and the total number of fragments as `u16`s, all big-endian.
+const FRAGMENT_HEADER: usize = 8;
+
+/// The largest datagram that can be received, i.e. the largest UDP payload.
+const MAX_DATAGRAM: usize = 65507;
+
+/// How blobs are put into datagrams, chosen by the `mode` query parameter of the path.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Mode {
+  /// `mode=whole`, the default: each blob is sent as a single datagram, so blobs larger than the MTU can't be sent.
+  Whole,
+  /// `mode=fragment`: blobs are split into as many datagrams as they need, each with a small header, and put back together by the listener.
+  Fragment,
+}
+
+impl Mode {
+  pub(crate) fn from_url(path: &MesherUrl) -> fail::Result<Mode> {
+    match path.query_param("mode") {
+      None | Some("whole") => Ok(Mode::Whole),
+      Some("fragment") => Ok(Mode::Fragment),
+      Some(other) => Err(fail::MesherFail::InvalidURL(format!(
+        "unknown UDP mode {:?} in {}",
+        other, path
+      ))),
+    }
+  }
+}
+
+/// Settings for a [`UDP`](struct.UDP.html) transport which apply to every path it uses.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UdpConfig {
+  /// The largest datagram that will be sent, not counting the IP and UDP headers.
+  /// Anything bigger risks being fragmented by IP, or dropped along the way.
+  /// The default of 1200 should get through nearly any network, including over IPv6 and VPNs.
+  ///
+  /// Listeners ignore fragments claiming their blob needs more fragments than one of `max_blob_size` would at this MTU, so both ends should use the same one.
+  pub mtu: usize,
+  /// The largest blob a listener will reassemble from fragments.
+  /// Fragments of anything larger are ignored.
+  pub max_blob_size: usize,
+  /// How long a listener will hold on to fragments of a blob, waiting for the rest to arrive.
+  /// UDP doesn't guarantee delivery, so if any fragment is lost, this is when the others are thrown away.
+  pub reassembly_timeout: Duration,
+  /// The most memory a listener will use holding on to fragments of incomplete blobs, across every sender.
+  /// Any more fragments are ignored until some of those blobs are finished or time out.
+  pub max_reassembly_memory: usize,
+}
+
+impl Default for UdpConfig {
+  fn default() -> UdpConfig {
+    UdpConfig {
+      mtu: 1200,
+      max_blob_size: 1024 * 1024,
+      reassembly_timeout: Duration::from_secs(10),
+      max_reassembly_memory: 16 * 1024 * 1024,
+    }
+  }
+}
+
+/// The fragments of a single blob which have arrived so far.
+struct Partial {
+  fragments: Vec<Option<Vec<u8>>>,
+  size: usize,
+  started: Instant,
+}
+
+impl Partial {
+  /// Roughly how much memory the partial blob is taking up, including the slots for fragments which haven't arrived yet.
+  fn footprint(&self) -> usize {
+    self.fragments.len() * std::mem::size_of::<Option<Vec<u8>>>() + self.size
+  }
+}
+
+/// Puts fragmented blobs back together, keeping track of the ones which are still incomplete.
+struct Reassembler {
+  max_blob_size: usize,
+  max_count: usize,
+  max_memory: usize,
+  timeout: Duration,
+  partial: HashMap<(SocketAddr, u32), Partial>,
+  memory: usize,
+}
+
+impl Reassembler {
+  fn new(config: &UdpConfig) -> Reassembler {
+    Reassembler {
+      max_blob_size: config.max_blob_size,
+      // anything split into more fragments than this can't have come from a sender with the same settings
+      max_count: config.max_blob_size / config.mtu.saturating_sub(FRAGMENT_HEADER).max(1) + 1,
+      max_memory: config.max_reassembly_memory,
+      timeout: config.reassembly_timeout,
+      partial: HashMap::new(),
+      memory: 0,
+    }
+  }
+
+  /// Stops holding on to a partial blob, returning it if it was there.
+  fn remove(&mut self, key: &(SocketAddr, u32)) -> Option<Partial> {
+    let partial = self.partial.remove(key)?;
+    self.memory -= partial.footprint();
+    Some(partial)
+  }
+
+  /// Handles one fragment, returning the whole blob if that was the last one missing.
+  fn add(&mut self, from: SocketAddr, datagram: &[u8]) -> Option<Vec<u8>> {
+    let timeout = self.timeout;
+    let mut freed = 0;
+    self.partial.retain(|_, p| {
+      let keep = p.started.elapsed() < timeout;
+      if !keep {
+        freed += p.footprint();
+      }
+      keep
+    });
+    self.memory -= freed;
+
+    if datagram.len() < FRAGMENT_HEADER {
+      return None;
+    }
+    let id = u32::from_be_bytes([datagram[0], datagram[1], datagram[2], datagram[3]]);
+    let index = u16::from_be_bytes([datagram[4], datagram[5]]) as usize;
+    let count = u16::from_be_bytes([datagram[6], datagram[7]]) as usize;
+    let body = &datagram[FRAGMENT_HEADER..];
+    if index >= count || count > self.max_count {
+      return None;
+    }
+
+    let key = (from, id);
+    let (max_blob_size, max_memory, memory) = (self.max_blob_size, self.max_memory, self.memory);
+    let partial = match self.partial.entry(key) {
+      Entry::Occupied(e) => e.into_mut(),
+      Entry::Vacant(e) => {
+        let partial = Partial {
+          fragments: vec![None; count],
+          size: 0,
+          started: Instant::now(),
+        };
+        if memory + partial.footprint() > max_memory {
+          return None;
+        }
+        self.memory += partial.footprint();
+        e.insert(partial)
+      }
+    };
+    // a fragment that doesn't agree with the others means the ID was reused, or someone's meddling
+    if partial.fragments.len() != count || partial.fragments[index].is_some() {
+      return None;
+    }
+    if self.memory + body.len() > max_memory {
+      return None;
+    }
+    self.memory += body.len();
+    partial.size += body.len();
+    partial.fragments[index] = Some(body.to_vec());
+    if partial.size > max_blob_size {
+      self.remove(&key);
+      return None;
+    }
+    if partial.fragments.iter().any(Option::is_none) {
+      return None;
+    }
+    let partial = self.remove(&key)?;
+    Some(partial.fragments.into_iter().flatten().flatten().collect())
+  }
+}
+
+/// Starts a thread receiving blobs on an already bound socket, putting them back together first if they're fragmented.
+///
+/// This is shared by every datagram-based transport, so they all split blobs up the same way.
+pub(crate) fn receive_on(
+  name: &str,
+  socket: UdpSocket,
+  mode: Mode,
+  config: &UdpConfig,
+  sender: Sender<Vec<u8>>,
+) -> fail::Result<()> {
+  let mut reassembler = Reassembler::new(config);
+
+  let thread_code = move || {
+    let mut buf = vec![0; MAX_DATAGRAM];
+    loop {
+      let (len, from) = match socket.recv_from(&mut buf) {
+        Ok(r) => r,
+        Err(_) => continue,
+      };
+      let blob = match mode {
+        Mode::Whole => Some(buf[..len].to_vec()),
+        Mode::Fragment => reassembler.add(from, &buf[..len]),
+      };
+      if let Some(blob) = blob {
+        if sender.send(blob).is_err() {
+          return;
+        }
+      }
+    }
+  };
+
+  Builder::new()
+    .name(format!("{} listener", name))
+    .spawn(thread_code)
+    .map_err(|e| fail::MesherFail::SetupFailure(format!("Failed to start {} listener: {:?}", name, e)))?;
+
+  Ok(())
+}
+
+/// Splits a blob into fragments of at most `mtu` bytes each, header included.
+fn fragment(blob: &[u8], mtu: usize, id: u32) -> fail::Result<Vec<Vec<u8>>> {
+  if mtu <= FRAGMENT_HEADER {
+    return Err(fail::MesherFail::SendFailure(format!(
+      "MTU of {} is too small to fit any fragments",
+      mtu
+    )));
+  }
+  let body_size = mtu - FRAGMENT_HEADER;
+  // even an empty blob needs one fragment, to say it exists
+  let count = blob.len().div_ceil(body_size).max(1);
+  if count > u16::MAX as usize {
+    return Err(fail::MesherFail::SendFailure(format!(
+      "Blob of {} bytes needs too many fragments",
+      blob.len()
+    )));
+  }
+
+  let fragments = (0..count)
+    .map(|index| {
+      let body = &blob[(index * body_size).min(blob.len())..((index + 1) * body_size).min(blob.len())];
+      let mut datagram = Vec::with_capacity(FRAGMENT_HEADER + body.len());
+      datagram.extend_from_slice(&id.to_be_bytes());
+      datagram.extend_from_slice(&(index as u16).to_be_bytes());
+      datagram.extend_from_slice(&(count as u16).to_be_bytes());
+      datagram.extend_from_slice(body);
+      datagram
+    })
+    .collect();
+  Ok(fragments)
+}
+
+/// Turns a blob into the datagrams to send for it, using up an ID from `next_id` if it needs fragmenting.
+pub(crate) fn to_datagrams(mode: Mode, blob: Vec<u8>, mtu: usize, next_id: &mut u32) -> fail::Result<Vec<Vec<u8>>> {
+  match mode {
+    Mode::Whole if blob.len() > mtu => Err(fail::MesherFail::SendFailure(format!(
+      "Blob of {} bytes is larger than the MTU of {}; try mode=fragment",
+      blob.len(),
+      mtu
+    ))),
+    Mode::Whole => Ok(vec![blob]),
+    Mode::Fragment => {
+      let id = *next_id;
+      *next_id = next_id.wrapping_add(1);
+      fragment(&blob, mtu, id)
+    }
+  }
+}
+
+/// Sends and receives blobs as UDP datagrams, e.g. `udp:localhost:18550`.
+///
+/// UDP is fast, but there's no guarantee that a blob will arrive, or arrive only once, or arrive in order.
+/// Mesher's packets already tolerate that, so it's mostly useful for meshes on networks which are known to be reliable, or where latency matters more than loss.
+///
+/// By default, each blob is sent as a single datagram, and blobs larger than the [`mtu`](#method.mtu) fail to send.
+/// Adding `?mode=fragment` to the path splits them into several datagrams instead, which the listener puts back together.
+/// If any one of those is lost, the whole blob is.
+/// Like [`TCP`](struct.TCP.html)'s modes, both ends have to agree on the mode, so it should be the same on the listening path and on the paths sending to it.
+pub struct UDP {
+  sender: Sender<Vec<u8>>,
+  receiver: Receiver<Vec<u8>>,
+  scheme: String,
+  config: UdpConfig,
+  sockets: HashMap<bool, UdpSocket>,
+  next_id: u32,
+}
+
+impl UDP {
+  /// Creates a UDP transport with a different MTU, blob size limit, or reassembly limits.
+  pub fn with_config(scheme: &str, config: UdpConfig) -> UDP {
+    let (sender, receiver) = channel();
+    UDP {
+      sender,
+      receiver,
+      scheme: scheme.to_string(),
+      config,
+      sockets: HashMap::new(),
+      next_id: 0,
+    }
+  }
+
+  /// The largest datagram this transport will send, which is also the largest blob it can send without `mode=fragment`.
+  pub fn mtu(&self) -> usize {
+    self.config.mtu
+  }
+
+  /// Gets a socket to send to the address from, binding one if there isn't one yet.
+  /// There's one for IPv4 and one for IPv6, since a socket can only send to addresses of the same family.
+  fn socket_for(&mut self, to: SocketAddr) -> fail::Result<&UdpSocket> {
+    match self.sockets.entry(to.is_ipv6()) {
+      Entry::Occupied(e) => Ok(e.into_mut()),
+      Entry::Vacant(e) => {
+        let local = if to.is_ipv6() { "[::]:0" } else { "0.0.0.0:0" };
+        let socket = UdpSocket::bind(local)
+          .map_err(|e| fail::MesherFail::SendFailure(format!("Failed to bind socket: {:?}", e)))?;
+        Ok(e.insert(socket))
+      }
+    }
+  }
+}
+
+impl Transport for UDP {
+  fn new(scheme: &str) -> fail::Result<Self> {
+    Ok(UDP::with_config(scheme, UdpConfig::default()))
+  }
+
+  fn send(&mut self, path: MesherUrl, blob: Vec<u8>) -> fail::Result<()> {
+    let sock = socket_addr_from_url(&path)?;
+    let datagrams = to_datagrams(Mode::from_url(&path)?, blob, self.config.mtu, &mut self.next_id)?;
+    let socket = self.socket_for(sock)?;
+    for datagram in datagrams {
+      socket
+        .send_to(&datagram, sock)
+        .map_err(|e| fail::MesherFail::SendFailure(format!("Failed to send datagram: {:?}", e)))?;
+    }
+    Ok(())
+  }
+
+  fn listen(&mut self, path: MesherUrl) -> fail::Result<()> {
+    let sock = socket_addr_from_url(&path)?;
+    let mode = Mode::from_url(&path)?;
+    let socket =
+      UdpSocket::bind(sock).map_err(|e| fail::MesherFail::ListenFailure(format!("Failed to bind socket: {:?}", e)))?;
+    receive_on(
+      &format!("UDP {}: {}", self.scheme, sock),
+      socket,
+      mode,
+      &self.config,
+      self.sender.clone(),
+    )
+  }
+
+  fn receive(&mut self) -> fail::Result<Vec<Vec<u8>>> {
+    Ok(self.receiver.try_iter().collect())
+  }
+}
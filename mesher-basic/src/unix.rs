@@ -0,0 +1,259 @@
+use mesher::prelude::*;
+
+use crate::{
+  framing::{read_frame, write_frame},
+  pool::Pool,
+  tcp::{serve, Deadline, Listener, ReadTimeout},
+};
+
+use std::{
+  fs,
+  io::{self, prelude::*},
+  os::unix::{
+    fs::{FileTypeExt, PermissionsExt},
+    net::{UnixListener, UnixStream},
+  },
+  path::{Path, PathBuf},
+  sync::mpsc::{channel, Receiver, Sender},
+  time::Duration,
+};
+
+fn socket_path_from_url(path: &MesherUrl) -> fail::Result<PathBuf> {
+  if !path.authority().is_empty() || path.path().is_empty() {
+    return Err(fail::MesherFail::InvalidURL(format!(
+      "not an absolute socket path: {}",
+      path
+    )));
+  }
+  Ok(PathBuf::from(path.path()))
+}
+
+/// Reads the permissions for a file a listener creates from the `perm` query parameter, in octal like `chmod` takes, e.g. `perm=0660`.
+pub(crate) fn permissions_from_url(path: &MesherUrl) -> fail::Result<Option<u32>> {
+  match path.query_param("perm") {
+    None => Ok(None),
+    Some(mode) => match u32::from_str_radix(mode, 8) {
+      Ok(mode) if mode <= 0o7777 => Ok(Some(mode)),
+      _ => Err(fail::MesherFail::InvalidURL(format!(
+        "invalid permissions {:?} in {}",
+        mode, path
+      ))),
+    },
+  }
+}
+
+/// Settings for a [`Unix`](struct.Unix.html) transport which apply to every path it uses.
+///
+/// These work the same as the equivalent settings in [`TcpConfig`](struct.TcpConfig.html).
+/// There's no per-client rate limit, since every client is on the same host, and the socket's permissions decide who they can be.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnixConfig {
+  /// How long a connection can go unused before it's closed, by either end.
+  pub idle_timeout: Duration,
+  /// The largest blob a listener will accept.
+  /// Connections trying to send anything larger are closed.
+  pub max_packet_size: usize,
+  /// How long a listener will wait for a whole blob to arrive, once it's started arriving.
+  /// Connections which take longer are closed.
+  pub read_timeout: Duration,
+  /// How many connections a listener will handle at once.
+  /// Any more are closed immediately.
+  pub max_connections: usize,
+}
+
+impl Default for UnixConfig {
+  fn default() -> UnixConfig {
+    UnixConfig {
+      idle_timeout: Duration::from_secs(60),
+      max_packet_size: 1024 * 1024,
+      read_timeout: Duration::from_secs(10),
+      max_connections: 64,
+    }
+  }
+}
+
+impl ReadTimeout for UnixStream {
+  fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+    UnixStream::set_read_timeout(self, timeout)
+  }
+}
+
+impl Listener for UnixListener {
+  type Stream = UnixStream;
+
+  fn accept(&self) -> io::Result<UnixStream> {
+    UnixListener::accept(self).map(|(conn, _)| conn)
+  }
+}
+
+fn handle(conn: UnixStream, config: &UnixConfig, sender: Sender<Vec<u8>>) {
+  let mut reader = Deadline::new(&conn, &conn, config.idle_timeout, config.read_timeout);
+  loop {
+    reader.reset();
+    let blob = match read_frame(&mut reader, config.max_packet_size) {
+      Ok(Some(blob)) => blob,
+      _ => return,
+    };
+    if sender.send(blob).is_err() {
+      return;
+    }
+  }
+}
+
+/// Makes sure nothing is in the way of binding a socket at the path.
+///
+/// A socket file with nothing listening on it is left over from a listener that didn't clean up after itself, e.g. because it crashed, so it's removed.
+/// Anything else there is left alone, and is an error.
+fn clear_stale(path: &Path) -> fail::Result<()> {
+  let meta = match fs::symlink_metadata(path) {
+    Ok(meta) => meta,
+    Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+    Err(e) => {
+      return Err(fail::MesherFail::ListenFailure(format!(
+        "Failed to check {}: {:?}",
+        path.display(),
+        e
+      )))
+    }
+  };
+  if !meta.file_type().is_socket() {
+    return Err(fail::MesherFail::ListenFailure(format!(
+      "{} already exists and isn't a socket",
+      path.display()
+    )));
+  }
+  if UnixStream::connect(path).is_ok() {
+    return Err(fail::MesherFail::ListenFailure(format!(
+      "Something is already listening on {}",
+      path.display()
+    )));
+  }
+  fs::remove_file(path)
+    .map_err(|e| fail::MesherFail::ListenFailure(format!("Failed to remove stale socket {}: {:?}", path.display(), e)))
+}
+
+/// Binds a listener at the path, with the given permissions.
+///
+/// To make sure no one can connect before the permissions are set, it's bound under a temporary name and only moved into place afterwards.
+fn bind(path: &Path, permissions: Option<u32>) -> fail::Result<UnixListener> {
+  let listen_fail = |what: &str, e: io::Error| fail::MesherFail::ListenFailure(format!("Failed to {}: {:?}", what, e));
+  clear_stale(path)?;
+  let name = path
+    .file_name()
+    .ok_or_else(|| fail::MesherFail::InvalidURL(format!("not a socket path: {}", path.display())))?;
+  let temp = path.with_file_name(format!(".{}.{}", name.to_string_lossy(), std::process::id()));
+  clear_stale(&temp)?;
+
+  let listener = UnixListener::bind(&temp).map_err(|e| listen_fail("bind socket", e))?;
+  let placed = permissions
+    .map_or(Ok(()), |mode| {
+      fs::set_permissions(&temp, fs::Permissions::from_mode(mode))
+    })
+    .map_err(|e| listen_fail("set socket permissions", e))
+    .and_then(|_| fs::rename(&temp, path).map_err(|e| listen_fail("move socket into place", e)));
+  if let Err(e) = placed {
+    let _ = fs::remove_file(&temp);
+    return Err(e);
+  }
+  Ok(listener)
+}
+
+/// Checks whether the other end of a connection has closed it, without blocking.
+///
+/// Listeners never send anything, so if there is something to read, it's safe to throw away.
+fn is_open(mut stream: &UnixStream) -> bool {
+  if stream.set_nonblocking(true).is_err() {
+    return false;
+  }
+  let open = match stream.read(&mut [0]) {
+    Ok(n) => n > 0,
+    Err(e) => e.kind() == io::ErrorKind::WouldBlock,
+  };
+  stream.set_nonblocking(false).is_ok() && open
+}
+
+/// Sends and receives blobs over Unix domain sockets, e.g. `unix:/run/mesher/node.sock`, for talking to other programs on the same host.
+///
+/// Blobs are framed and connections reused exactly like [`TCP`](struct.TCP.html)'s `mode=framed`.
+///
+/// Access control is left to the filesystem: only users who can write to the socket file can send to it.
+/// Adding `?perm=0660` (or any other octal permissions) to the listening path sets the socket file's permissions, before anyone has a chance to connect.
+///
+/// If a socket file is left over from a listener which has since died, it's replaced when listening on the same path.
+/// Socket files are removed when the transport that created them is dropped.
+pub struct Unix {
+  sender: Sender<Vec<u8>>,
+  receiver: Receiver<Vec<u8>>,
+  scheme: String,
+  config: UnixConfig,
+  pool: Pool<PathBuf, UnixStream>,
+  bound: Vec<PathBuf>,
+}
+
+impl Unix {
+  /// Creates a unix socket transport with custom timeouts or connection limits.
+  pub fn with_config(scheme: &str, config: UnixConfig) -> Unix {
+    let (sender, receiver) = channel();
+    Unix {
+      sender,
+      receiver,
+      scheme: scheme.to_string(),
+      pool: Pool::new(config.idle_timeout, |stream| is_open(stream)),
+      config,
+      bound: vec![],
+    }
+  }
+}
+
+impl Transport for Unix {
+  fn new(scheme: &str) -> fail::Result<Self> {
+    Ok(Unix::with_config(scheme, UnixConfig::default()))
+  }
+
+  fn send(&mut self, path: MesherUrl, blob: Vec<u8>) -> fail::Result<()> {
+    let sock = socket_path_from_url(&path)?;
+    let connect = || {
+      UnixStream::connect(&sock)
+        .map_err(|e| fail::MesherFail::SendFailure(format!("Failed to connect to {}: {:?}", sock.display(), e)))
+    };
+    self.pool.send(sock.clone(), connect, |stream| {
+      write_frame(stream, &blob).map_err(|e| fail::MesherFail::SendFailure(format!("Failed to send data: {:?}", e)))
+    })
+  }
+
+  fn listen(&mut self, path: MesherUrl) -> fail::Result<()> {
+    let sock = socket_path_from_url(&path)?;
+    let permissions = permissions_from_url(&path)?;
+    let listener = bind(&sock, permissions)?;
+    let sender = self.sender.clone();
+    let served = serve(
+      &format!("unix {}: {}", self.scheme, sock.display()),
+      listener,
+      &self.config,
+      self.config.max_connections,
+      // everyone who can connect is on the same host, so there's no per-client rate limit
+      |_| true,
+      move |conn, config| handle(conn, config, sender.clone()),
+    );
+    if let Err(e) = served {
+      let _ = fs::remove_file(&sock);
+      return Err(e);
+    }
+    self.bound.push(sock);
+    Ok(())
+  }
+
+  fn receive(&mut self) -> fail::Result<Vec<Vec<u8>>> {
+    // a node which stops sending would otherwise hold its last connections open forever
+    self.pool.prune();
+    Ok(self.receiver.try_iter().collect())
+  }
+}
+
+impl Drop for Unix {
+  fn drop(&mut self) {
+    for path in &self.bound {
+      let _ = fs::remove_file(path);
+    }
+  }
+}
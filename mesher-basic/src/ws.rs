@@ -0,0 +1,189 @@
+use mesher::prelude::*;
+
+use crate::{
+  http::web_url,
+  pool::Pool,
+  tcp::{dial, listen_with, socket_addr_from_url, Deadline, TcpConfig},
+};
+
+use std::{
+  io,
+  net::TcpStream,
+  sync::mpsc::{channel, Receiver, Sender},
+};
+use tungstenite::{
+  handshake::server::{ErrorResponse, Request, Response},
+  http,
+  protocol::WebSocketConfig,
+  stream::MaybeTlsStream,
+  Message, WebSocket,
+};
+
+type ClientSocket = WebSocket<MaybeTlsStream<TcpStream>>;
+
+/// Turns a path into a URL a WebSocket client will accept, using TLS for `wss:` paths or those with `?tls=on`.
+fn ws_url(path: &MesherUrl) -> fail::Result<String> {
+  web_url(path, "ws", "wss")
+}
+
+fn ws_config(config: &TcpConfig) -> WebSocketConfig {
+  WebSocketConfig::default()
+    .max_message_size(Some(config.max_packet_size))
+    .max_frame_size(Some(config.max_packet_size))
+}
+
+// the error type is decided by tungstenite, not us
+#[allow(clippy::result_large_err)]
+fn handle(conn: TcpStream, config: &TcpConfig, expected_path: &str, sender: Sender<Vec<u8>>) {
+  // the handshake gets the read timeout, just like any other data arriving
+  let timed = Deadline::new(&conn, &conn, config.idle_timeout, config.read_timeout).start();
+  let check_path = |request: &Request, response: Response| -> Result<Response, ErrorResponse> {
+    if request.uri().path() == expected_path {
+      Ok(response)
+    } else {
+      let mut not_found = ErrorResponse::new(None);
+      *not_found.status_mut() = http::StatusCode::NOT_FOUND;
+      Err(not_found)
+    }
+  };
+  let mut ws = match tungstenite::accept_hdr_with_config(timed, check_path, Some(ws_config(config))) {
+    Ok(ws) => ws,
+    Err(_) => return,
+  };
+
+  loop {
+    // each message gets as long as the idle timeout to start arriving, but only the read timeout to finish once it has
+    ws.get_mut().reset();
+    match ws.read() {
+      Ok(Message::Binary(blob)) => {
+        if sender.send(blob.to_vec()).is_err() {
+          return;
+        }
+      }
+      // pings are answered automatically, and anything else isn't meant for us
+      Ok(_) => (),
+      Err(_) => return,
+    }
+  }
+}
+
+/// Gets the TCP connection underneath a client's WebSocket, whether or not it's encrypted.
+fn tcp_of(ws: &ClientSocket) -> Option<&TcpStream> {
+  match ws.get_ref() {
+    MaybeTlsStream::Plain(s) => Some(s),
+    MaybeTlsStream::Rustls(s) => Some(s.get_ref()),
+    _ => None,
+  }
+}
+
+fn set_nonblocking(ws: &ClientSocket, nonblocking: bool) -> bool {
+  tcp_of(ws).is_some_and(|s| s.set_nonblocking(nonblocking).is_ok())
+}
+
+/// Checks whether the other end of a connection has closed it, without blocking.
+///
+/// Listeners never send any messages, so anything that has arrived, like a pong, is safe to throw away.
+fn is_open(ws: &mut ClientSocket) -> bool {
+  if !set_nonblocking(ws, true) {
+    return false;
+  }
+  let open = loop {
+    match ws.read() {
+      Ok(Message::Close(_)) => break false,
+      Ok(_) => continue,
+      Err(tungstenite::Error::Io(ref e)) if e.kind() == io::ErrorKind::WouldBlock => break true,
+      Err(_) => break false,
+    }
+  };
+  set_nonblocking(ws, false) && open
+}
+
+/// Sends and receives blobs over WebSockets, e.g. `wss://relay.example.com/mesher`, each blob as a single binary message.
+///
+/// To anything watching the network, this looks like any other web page holding a WebSocket open, so it gets through networks which only allow web traffic.
+/// Connections are kept open and reused for later blobs to the same URL until they've been idle for [`TcpConfig::idle_timeout`](struct.TcpConfig.html#structfield.idle_timeout).
+///
+/// The transport can be registered under any scheme, and uses TLS if it's `wss` or the URL has `?tls=on`, e.g. `relay:relay.example.com/mesher?tls=on`.
+/// Sending works either way; TLS servers are checked against the usual web certificate authorities.
+/// Listening only works without TLS, e.g. `ws:0.0.0.0:18580/mesher`, and only accepts WebSockets opened to that exact path.
+/// To accept `wss:` connections, put it behind a web server which handles the TLS, which also lets it share a port with an ordinary website.
+pub struct WS {
+  sender: Sender<Vec<u8>>,
+  receiver: Receiver<Vec<u8>>,
+  scheme: String,
+  config: TcpConfig,
+  pool: Pool<String, ClientSocket>,
+}
+
+impl WS {
+  /// Creates a WebSocket transport with custom timeouts or connection limits.
+  ///
+  /// These are the same settings the [`TCP`](struct.TCP.html) transport uses, and work the same way.
+  pub fn with_config(scheme: &str, config: TcpConfig) -> WS {
+    let (sender, receiver) = channel();
+    WS {
+      sender,
+      receiver,
+      scheme: scheme.to_string(),
+      pool: Pool::new(config.idle_timeout, is_open),
+      config,
+    }
+  }
+
+  fn connect(config: &TcpConfig, path: &MesherUrl, url: &str) -> fail::Result<ClientSocket> {
+    let send_fail =
+      |what: &str, e: &dyn std::fmt::Display| fail::MesherFail::SendFailure(format!("Failed to {}: {}", what, e));
+    let default_port = if url.starts_with("wss:") { 443 } else { 80 };
+    let stream = dial(path, Some(default_port), config)?;
+    // don't let a silent server hang the handshake forever
+    stream
+      .set_read_timeout(Some(config.read_timeout))
+      .map_err(|e| send_fail("configure TCP connection", &e))?;
+    let (ws, _) = tungstenite::client_tls_with_config(url, stream, Some(ws_config(config)), None)
+      .map_err(|e| send_fail("open WebSocket", &e))?;
+    Ok(ws)
+  }
+}
+
+impl Transport for WS {
+  fn new(scheme: &str) -> fail::Result<Self> {
+    Ok(WS::with_config(scheme, TcpConfig::default()))
+  }
+
+  fn send(&mut self, path: MesherUrl, blob: Vec<u8>) -> fail::Result<()> {
+    let url = ws_url(&path)?;
+    let config = &self.config;
+    self.pool.send(
+      url.clone(),
+      || WS::connect(config, &path, &url),
+      |ws| {
+        ws.send(Message::binary(blob.clone()))
+          .map_err(|e| fail::MesherFail::SendFailure(format!("Failed to send data: {}", e)))
+      },
+    )
+  }
+
+  fn listen(&mut self, path: MesherUrl) -> fail::Result<()> {
+    if ws_url(&path)?.starts_with("wss:") {
+      return Err(fail::MesherFail::InvalidURL(format!(
+        "WebSockets can only listen without TLS, not on {}",
+        path
+      )));
+    }
+    let sock = socket_addr_from_url(&path)?;
+    let expected_path = if path.path().is_empty() { "/" } else { path.path() }.to_owned();
+    let sender = self.sender.clone();
+    listen_with(
+      &format!("WebSocket {}:", self.scheme),
+      sock,
+      &self.config,
+      move |conn, config| handle(conn, config, &expected_path, sender.clone()),
+    )
+  }
+
+  fn receive(&mut self) -> fail::Result<Vec<Vec<u8>>> {
+    // a node which stops sending would otherwise hold its last connections open forever
+    self.pool.prune();
+    Ok(self.receiver.try_iter().collect())
+  }
+}
@@ -0,0 +1,120 @@
+use mesher::{prelude::*, test_support::url};
+use mesher_basic::{Channel, ChannelHub};
+
+use std::{
+  sync::{mpsc, Arc, Barrier},
+  thread,
+};
+
+fn make_mesher(transport: Channel, listen: Option<&str>) -> (Mesher, encrypt::PublicKey) {
+  let (pk, sk) = encrypt::gen_keypair();
+  let mut m = Mesher::unsigned(vec![sk]);
+  m.add_transport_instance("chan", transport);
+  if let Some(path) = listen {
+    m.listen_on(path).expect("Failed to listen");
+  }
+  (m, pk)
+}
+
+fn wait_for_message(m: &mut Mesher) -> Vec<u8> {
+  loop {
+    let mut received = m.receive().expect("Failed to receive");
+    if let Some(msg) = received.pop() {
+      return msg.into_contents();
+    }
+    thread::yield_now();
+  }
+}
+
+#[test]
+fn pair_across_threads() {
+  let (a, b) = Channel::pair();
+  let (key_tx, key_rx) = mpsc::channel();
+
+  let node = thread::spawn(move || {
+    let (mut m, pk) = make_mesher(b, None);
+    key_tx.send(pk).expect("Failed to share key");
+    wait_for_message(&mut m)
+  });
+
+  let (mut app, app_pk) = make_mesher(a, None);
+  let node_pk = key_rx.recv().expect("Failed to get key");
+  let mut packet = Packet::unsigned();
+  packet.add_hop("chan:anything".to_owned(), &app_pk);
+  packet.add_message(&[1, 2, 3], &node_pk);
+  app.launch(packet).expect("Failed to send");
+
+  assert_eq!(node.join().expect("Node thread panicked"), vec![1, 2, 3]);
+}
+
+#[test]
+fn hub_across_threads() {
+  let hub = ChannelHub::new();
+  let (key_tx, key_rx) = mpsc::channel();
+  // every packet goes to both nodes, so neither can drop its channel until everything's been sent
+  let sent = Arc::new(Barrier::new(3));
+
+  let mut nodes = vec![];
+  for name in &["n1", "n2"] {
+    let transport = hub.transport();
+    let key_tx = key_tx.clone();
+    let sent = sent.clone();
+    nodes.push(thread::spawn(move || {
+      let (mut m, pk) = make_mesher(transport, Some(&format!("chan:{}", name)));
+      key_tx.send(pk).expect("Failed to share key");
+      let message = wait_for_message(&mut m);
+      sent.wait();
+      message
+    }));
+  }
+
+  let (mut app, app_pk) = make_mesher(hub.transport(), None);
+  let keys: Vec<_> = (0..2).map(|_| key_rx.recv().expect("Failed to get key")).collect();
+  // whichever node shared its key first gets the first message
+  for (i, key) in keys.iter().enumerate() {
+    let mut packet = Packet::unsigned();
+    packet.add_hop("chan:n1".to_owned(), &app_pk);
+    packet.add_hop("chan:n2".to_owned(), &app_pk);
+    packet.add_message(&[i as u8], key);
+    app.launch(packet).expect("Failed to send");
+  }
+  sent.wait();
+
+  let mut received: Vec<_> = nodes
+    .into_iter()
+    .map(|n| n.join().expect("Node thread panicked"))
+    .collect();
+  received.sort();
+  assert_eq!(received, vec![vec![0], vec![1]]);
+}
+
+#[test]
+fn hub_needs_listener() {
+  let hub = ChannelHub::new();
+  let (mut app, app_pk) = make_mesher(hub.transport(), None);
+  let mut packet = Packet::unsigned();
+  packet.add_hop("chan:nobody".to_owned(), &app_pk);
+  match app.launch(packet) {
+    Err(fail::MesherFail::SendFailure(_)) => (),
+    _ => panic!("Send should have failed"),
+  }
+}
+
+#[test]
+fn hub_paths_kept_apart() {
+  let hub = ChannelHub::new();
+  let mut a = hub.transport();
+  let mut b = hub.transport();
+  let mut sender = hub.transport();
+  a.listen(url("chan:node/a")).expect("Failed to listen");
+  b.listen(url("chan:node/b")).expect("Failed to listen");
+
+  sender.send(url("chan:node/a"), vec![1]).expect("Failed to send");
+  sender.send(url("chan://node/b"), vec![2]).expect("Failed to send");
+  assert_eq!(a.receive().expect("Failed to receive"), vec![vec![1]]);
+  assert_eq!(b.receive().expect("Failed to receive"), vec![vec![2]]);
+  match sender.send(url("chan:node"), vec![3]) {
+    Err(fail::MesherFail::SendFailure(_)) => (),
+    other => panic!("Expected SendFailure, got {:?}", other),
+  }
+}
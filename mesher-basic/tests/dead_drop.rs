@@ -0,0 +1,238 @@
+use mesher::{prelude::*, test_support::url};
+use mesher_basic::{DeadDrop, DeadDropConfig};
+
+use std::{
+  fs,
+  path::{Path, PathBuf},
+  time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// A fresh, empty directory for the test.
+fn drop_dir(name: &str) -> PathBuf {
+  let dir = std::env::temp_dir().join(format!("mesher-drop-{}-{}", name, std::process::id()));
+  let _ = fs::remove_dir_all(&dir);
+  fs::create_dir_all(&dir).expect("Failed to create directory");
+  dir
+}
+
+fn dir_url(dir: &Path) -> MesherUrl {
+  url(&format!("dir:{}", dir.display()))
+}
+
+fn file_names(dir: &Path) -> Vec<String> {
+  let mut names: Vec<_> = fs::read_dir(dir)
+    .expect("Failed to read directory")
+    .map(|e| {
+      e.expect("Failed to read entry")
+        .file_name()
+        .to_string_lossy()
+        .into_owned()
+    })
+    .collect();
+  names.sort();
+  names
+}
+
+#[test]
+fn round_trip_in_order() {
+  let dir = drop_dir("order");
+  let mut sender = DeadDrop::new("dir").expect("Failed to create");
+  let mut receiver = DeadDrop::new("dir").expect("Failed to create");
+  receiver.listen(dir_url(&dir)).expect("Failed to listen");
+
+  for i in 0..5 {
+    sender.send(dir_url(&dir), vec![i; 10]).expect("Failed to send");
+  }
+  assert_eq!(file_names(&dir).len(), 5);
+  let received = receiver.receive().expect("Failed to receive");
+  assert_eq!(received, (0..5).map(|i| vec![i; 10]).collect::<Vec<_>>());
+  assert!(file_names(&dir).is_empty());
+  assert!(receiver.receive().expect("Failed to receive").is_empty());
+}
+
+#[test]
+fn ignores_incomplete_and_foreign_files() {
+  let dir = drop_dir("incomplete");
+  fs::write(dir.join("0000-in-progress.partial"), b"half").expect("Failed to write");
+  fs::write(dir.join("notes.txt"), b"not a blob").expect("Failed to write");
+  fs::write(dir.join("0001-done.blob"), b"whole").expect("Failed to write");
+
+  let mut receiver = DeadDrop::new("dir").expect("Failed to create");
+  receiver.listen(dir_url(&dir)).expect("Failed to listen");
+  assert_eq!(receiver.receive().expect("Failed to receive"), vec![b"whole".to_vec()]);
+  assert_eq!(file_names(&dir), vec!["0000-in-progress.partial", "notes.txt"]);
+}
+
+#[test]
+fn cleans_up_abandoned_files() {
+  let dir = drop_dir("abandoned");
+  fs::write(dir.join("0000-crashed.partial"), b"half").expect("Failed to write");
+
+  let mut receiver = DeadDrop::with_config(
+    "dir",
+    DeadDropConfig {
+      abandoned_after: Duration::from_millis(0),
+      ..DeadDropConfig::default()
+    },
+  );
+  receiver.listen(dir_url(&dir)).expect("Failed to listen");
+  std::thread::sleep(Duration::from_millis(10));
+  assert!(receiver.receive().expect("Failed to receive").is_empty());
+  assert!(file_names(&dir).is_empty());
+}
+
+#[test]
+fn abandoned_claims_released() {
+  let dir = drop_dir("released");
+  // a listener claimed this an hour ago, then died before it finished reading it
+  let claimed_at =
+    SystemTime::now().duration_since(UNIX_EPOCH).expect("Clock before 1970") - Duration::from_secs(60 * 60);
+  let claimed = dir.join(format!("0000-crashed.{:024x}.claimed", claimed_at.as_nanos()));
+  fs::write(&claimed, b"whole").expect("Failed to write");
+
+  let mut receiver = DeadDrop::with_config(
+    "dir",
+    DeadDropConfig {
+      abandoned_after: Duration::from_secs(60),
+      ..DeadDropConfig::default()
+    },
+  );
+  receiver.listen(dir_url(&dir)).expect("Failed to listen");
+  assert_eq!(receiver.receive().expect("Failed to receive"), vec![b"whole".to_vec()]);
+  assert!(file_names(&dir).is_empty());
+}
+
+#[test]
+fn claim_time_used_for_abandonment() {
+  let dir = drop_dir("claim-time");
+  // a blob written long ago, e.g. on another machine, which a listener has only just claimed
+  let claimed_at = SystemTime::now().duration_since(UNIX_EPOCH).expect("Clock before 1970");
+  let claimed = dir.join(format!("0000-old.{:024x}.claimed", claimed_at.as_nanos()));
+  fs::write(&claimed, b"whole").expect("Failed to write");
+  let written = SystemTime::now() - Duration::from_secs(2 * 60 * 60);
+  fs::File::options()
+    .write(true)
+    .open(&claimed)
+    .and_then(|f| f.set_modified(written))
+    .expect("Failed to set modification time");
+
+  let mut receiver = DeadDrop::new("dir").expect("Failed to create");
+  receiver.listen(dir_url(&dir)).expect("Failed to listen");
+  assert!(receiver.receive().expect("Failed to receive").is_empty());
+  assert!(claimed.exists());
+}
+
+#[cfg(unix)]
+#[test]
+fn symlinks_not_followed() {
+  let dir = drop_dir("symlinks");
+  let outside = drop_dir("symlinks-outside");
+  fs::write(outside.join("secret"), b"private").expect("Failed to write");
+  std::os::unix::fs::symlink(outside.join("secret"), dir.join("0000-link.blob")).expect("Failed to link");
+  std::os::unix::fs::symlink(outside.join("secret"), dir.join("0001-link.partial")).expect("Failed to link");
+
+  let mut receiver = DeadDrop::with_config(
+    "dir",
+    DeadDropConfig {
+      abandoned_after: Duration::from_millis(0),
+      ..DeadDropConfig::default()
+    },
+  );
+  receiver.listen(dir_url(&dir)).expect("Failed to listen");
+  assert!(receiver.receive().expect("Failed to receive").is_empty());
+  assert_eq!(file_names(&dir), vec!["0000-link.blob", "0001-link.partial"]);
+  assert_eq!(fs::read(outside.join("secret")).expect("Failed to read"), b"private");
+}
+
+#[test]
+fn leaves_oversized_blobs() {
+  let dir = drop_dir("oversized");
+  let mut sender = DeadDrop::new("dir").expect("Failed to create");
+  let mut receiver = DeadDrop::with_config(
+    "dir",
+    DeadDropConfig {
+      max_blob_size: 4,
+      ..DeadDropConfig::default()
+    },
+  );
+  receiver.listen(dir_url(&dir)).expect("Failed to listen");
+  sender.send(dir_url(&dir), vec![0; 5]).expect("Failed to send");
+  sender.send(dir_url(&dir), vec![1; 4]).expect("Failed to send");
+
+  assert_eq!(receiver.receive().expect("Failed to receive"), vec![vec![1; 4]]);
+  assert_eq!(file_names(&dir).len(), 1);
+}
+
+#[test]
+fn each_blob_received_once() {
+  let dir = drop_dir("once");
+  let mut sender = DeadDrop::new("dir").expect("Failed to create");
+  for i in 0..20 {
+    sender.send(dir_url(&dir), vec![i]).expect("Failed to send");
+  }
+
+  let handles: Vec<_> = (0..4)
+    .map(|_| {
+      let dir = dir.clone();
+      std::thread::spawn(move || {
+        let mut receiver = DeadDrop::new("dir").expect("Failed to create");
+        receiver.listen(dir_url(&dir)).expect("Failed to listen");
+        receiver.receive().expect("Failed to receive")
+      })
+    })
+    .collect();
+  let mut received: Vec<_> = handles
+    .into_iter()
+    .flat_map(|h| h.join().expect("Receiver panicked"))
+    .collect();
+  received.sort();
+  assert_eq!(received, (0..20).map(|i| vec![i]).collect::<Vec<_>>());
+}
+
+#[test]
+fn needs_existing_directory() {
+  let dir = std::env::temp_dir().join(format!("mesher-drop-missing-{}", std::process::id()));
+  let mut t = DeadDrop::new("dir").expect("Failed to create");
+  match t.send(dir_url(&dir), vec![1]) {
+    Err(fail::MesherFail::SendFailure(_)) => (),
+    _ => panic!("Should have failed to send"),
+  }
+  match t.listen(dir_url(&dir)) {
+    Err(fail::MesherFail::ListenFailure(_)) => (),
+    _ => panic!("Should have failed to listen"),
+  }
+  assert!(!dir.exists());
+}
+
+#[test]
+fn through_mesher() {
+  let dir = drop_dir("mesher");
+  let (k_dest, s_dest) = encrypt::gen_keypair();
+  let mut m_dest = Mesher::unsigned(vec![s_dest]);
+  m_dest
+    .add_transport::<DeadDrop>("dir")
+    .expect("Failed to add transport");
+  m_dest.add_alias("file", "dir").expect("Failed to add alias");
+  m_dest
+    .listen_on(&format!("file:{}", dir.display()))
+    .expect("Failed to listen");
+
+  let (k_source, s_source) = encrypt::gen_keypair();
+  let mut m_source = Mesher::unsigned(vec![s_source]);
+  m_source
+    .add_transport::<DeadDrop>("dir")
+    .expect("Failed to add transport");
+
+  let mut packet = Packet::unsigned();
+  packet.add_hop(format!("dir:{}", dir.display()), &k_source);
+  packet.add_message(&[1, 2, 3], &k_dest);
+  m_source.launch(packet).expect("Failed to send");
+
+  let received = m_dest
+    .receive()
+    .expect("Failed to receive")
+    .into_iter()
+    .map(|m| m.into_contents())
+    .collect::<Vec<_>>();
+  assert_eq!(received, vec![vec![1, 2, 3]]);
+}
@@ -0,0 +1,261 @@
+use mesher::{prelude::*, test_support::url};
+use mesher_basic::{DnsConfig, DnsServer, DnsServerConfig, DNS};
+
+use std::{
+  net::{SocketAddr, UdpSocket},
+  thread,
+  time::Duration,
+};
+
+fn server(config: DnsServerConfig) -> DnsServer {
+  DnsServer::start("127.0.0.1:0", "tunnel.example.com", config).expect("Failed to start server")
+}
+
+/// A transport which sends its queries to the resolver, and checks its mailboxes every time it's asked to.
+fn eager(resolver: SocketAddr) -> DNS {
+  DNS::with_config(
+    "dns",
+    DnsConfig {
+      resolver,
+      timeout: Duration::from_millis(500),
+      poll_interval: Duration::from_secs(0),
+      ..DnsConfig::default()
+    },
+  )
+}
+
+/// A stub resolver which passes queries along to the server, changing the case of every name like some real resolvers do.
+fn case_changing_resolver(server: SocketAddr) -> SocketAddr {
+  let socket = UdpSocket::bind("127.0.0.1:0").expect("Failed to bind resolver");
+  let addr = socket.local_addr().expect("Failed to get resolver address");
+  thread::spawn(move || {
+    let upstream = UdpSocket::bind("127.0.0.1:0").expect("Failed to bind upstream");
+    let mut buf = [0; 4096];
+    loop {
+      let (len, client) = socket.recv_from(&mut buf).expect("Failed to receive query");
+      let mut query = buf[..len].to_vec();
+      for (i, b) in query.iter_mut().enumerate().skip(12) {
+        if b.is_ascii_lowercase() && i % 2 == 0 {
+          *b = b.to_ascii_uppercase();
+        }
+      }
+      upstream.send_to(&query, server).expect("Failed to forward query");
+      let len = upstream.recv(&mut buf).expect("Failed to receive response");
+      socket.send_to(&buf[..len], client).expect("Failed to forward response");
+    }
+  });
+  addr
+}
+
+#[test]
+fn round_trip() {
+  let server = server(DnsServerConfig::default());
+  let mut sender = eager(server.addr());
+  let mut receiver = eager(server.addr());
+  receiver
+    .listen(url("dns:tunnel.example.com/alice"))
+    .expect("Failed to listen");
+
+  let blobs = vec![vec![], vec![1; 10], (0..3000).map(|i| i as u8).collect::<Vec<_>>()];
+  for blob in &blobs {
+    sender
+      .send(url("dns:tunnel.example.com/alice"), blob.clone())
+      .expect("Failed to send");
+  }
+  assert_eq!(receiver.receive().expect("Failed to receive"), blobs);
+  assert!(receiver.receive().expect("Failed to receive").is_empty());
+}
+
+#[test]
+fn mailboxes_kept_apart() {
+  let server = server(DnsServerConfig::default());
+  let mut sender = eager(server.addr());
+  let mut alice = eager(server.addr());
+  alice
+    .listen(url("dns:tunnel.example.com/alice"))
+    .expect("Failed to listen");
+
+  sender
+    .send(url("dns:tunnel.example.com/bob"), vec![1])
+    .expect("Failed to send");
+  sender
+    .send(url("dns:tunnel.example.com/alice"), vec![2])
+    .expect("Failed to send");
+  assert_eq!(alice.receive().expect("Failed to receive"), vec![vec![2]]);
+}
+
+#[test]
+fn through_case_changing_resolver() {
+  let server = server(DnsServerConfig::default());
+  let resolver = case_changing_resolver(server.addr());
+  let mut sender = eager(resolver);
+  let mut receiver = eager(resolver);
+  receiver
+    .listen(url("dns:Tunnel.Example.com./Alice"))
+    .expect("Failed to listen");
+
+  let blob = (0..1000).map(|i| (i * 7) as u8).collect::<Vec<_>>();
+  sender
+    .send(url("dns:tunnel.example.com/alice"), blob.clone())
+    .expect("Failed to send");
+  assert_eq!(receiver.receive().expect("Failed to receive"), vec![blob]);
+}
+
+#[test]
+fn server_limits() {
+  let server = server(DnsServerConfig {
+    max_blob_size: 500,
+    max_queued: 2,
+    ..DnsServerConfig::default()
+  });
+  let mut sender = eager(server.addr());
+
+  match sender.send(url("dns:tunnel.example.com/alice"), vec![1; 501]) {
+    Err(fail::MesherFail::SendFailure(_)) => (),
+    other => panic!("Sent a blob larger than the server allows: {:?}", other),
+  }
+  for i in 0..2 {
+    sender
+      .send(url("dns:tunnel.example.com/alice"), vec![i; 500])
+      .expect("Failed to send");
+  }
+  match sender.send(url("dns:tunnel.example.com/alice"), vec![3]) {
+    Err(fail::MesherFail::SendFailure(_)) => (),
+    other => panic!("Sent to a full mailbox: {:?}", other),
+  }
+}
+
+#[test]
+fn server_limits_total() {
+  // "alice" or "bob" plus 500 bytes each
+  let server = server(DnsServerConfig {
+    max_stored: 1010,
+    ..DnsServerConfig::default()
+  });
+  let mut sender = eager(server.addr());
+  let mut alice = eager(server.addr());
+  alice
+    .listen(url("dns:tunnel.example.com/alice"))
+    .expect("Failed to listen");
+
+  sender
+    .send(url("dns:tunnel.example.com/alice"), vec![1; 500])
+    .expect("Failed to send");
+  sender
+    .send(url("dns:tunnel.example.com/bob"), vec![2; 500])
+    .expect("Failed to send");
+  match sender.send(url("dns:tunnel.example.com/carol"), vec![3]) {
+    Err(fail::MesherFail::SendFailure(_)) => (),
+    other => panic!("Sent to a full server: {:?}", other),
+  }
+  assert_eq!(alice.receive().expect("Failed to receive"), vec![vec![1; 500]]);
+  sender
+    .send(url("dns:tunnel.example.com/carol"), vec![3])
+    .expect("Failed to send");
+}
+
+#[test]
+fn oversized_blob_discarded() {
+  let server = server(DnsServerConfig::default());
+  let mut sender = eager(server.addr());
+  let mut receiver = DNS::with_config(
+    "dns",
+    DnsConfig {
+      resolver: server.addr(),
+      poll_interval: Duration::from_secs(0),
+      max_packet_size: 100,
+      ..DnsConfig::default()
+    },
+  );
+  receiver
+    .listen(url("dns:tunnel.example.com/alice"))
+    .expect("Failed to listen");
+
+  sender
+    .send(url("dns:tunnel.example.com/alice"), vec![1; 101])
+    .expect("Failed to send");
+  sender
+    .send(url("dns:tunnel.example.com/alice"), vec![2; 100])
+    .expect("Failed to send");
+  assert_eq!(receiver.receive().expect("Failed to receive"), vec![vec![2; 100]]);
+}
+
+#[test]
+fn receive_limited() {
+  let server = server(DnsServerConfig::default());
+  let mut sender = eager(server.addr());
+  let mut receiver = DNS::with_config(
+    "dns",
+    DnsConfig {
+      resolver: server.addr(),
+      poll_interval: Duration::from_secs(60 * 60),
+      max_blobs_per_receive: 2,
+      ..DnsConfig::default()
+    },
+  );
+  receiver
+    .listen(url("dns:tunnel.example.com/alice"))
+    .expect("Failed to listen");
+
+  for i in 0..5 {
+    sender
+      .send(url("dns:tunnel.example.com/alice"), vec![i])
+      .expect("Failed to send");
+  }
+  // the rest are left for the next calls, which don't wait for the poll interval while there might be more
+  assert_eq!(receiver.receive().expect("Failed to receive"), vec![vec![0], vec![1]]);
+  assert_eq!(receiver.receive().expect("Failed to receive"), vec![vec![2], vec![3]]);
+  assert_eq!(receiver.receive().expect("Failed to receive"), vec![vec![4]]);
+  sender
+    .send(url("dns:tunnel.example.com/alice"), vec![5])
+    .expect("Failed to send");
+  assert!(receiver.receive().expect("Failed to receive").is_empty());
+}
+
+#[test]
+fn other_zones_refused() {
+  let server = server(DnsServerConfig::default());
+  let mut sender = eager(server.addr());
+
+  match sender.send(url("dns:elsewhere.example.com/alice"), vec![1]) {
+    Err(fail::MesherFail::SendFailure(_)) => (),
+    other => panic!("Sent to a zone the server doesn't serve: {:?}", other),
+  }
+}
+
+#[test]
+fn nothing_answering() {
+  let unused = UdpSocket::bind("127.0.0.1:0").expect("Failed to bind");
+  let addr = unused.local_addr().expect("Failed to get address");
+  drop(unused);
+  let mut sender = DNS::with_config(
+    "dns",
+    DnsConfig {
+      resolver: addr,
+      timeout: Duration::from_millis(100),
+      retries: 1,
+      ..DnsConfig::default()
+    },
+  );
+
+  match sender.send(url("dns:tunnel.example.com/alice"), vec![1]) {
+    Err(fail::MesherFail::SendFailure(_)) => (),
+    other => panic!("Sent with nothing answering: {:?}", other),
+  }
+}
+
+#[test]
+fn invalid_paths() {
+  let mut t = DNS::new("dns").expect("Failed to create");
+  for path in &[
+    "dns:tunnel.example.com",
+    "dns:tunnel.example.com/alice/bob",
+    "dns:tunnel_example.com/alice",
+    "dns:/alice",
+  ] {
+    match t.listen(url(path)) {
+      Err(fail::MesherFail::InvalidURL(_)) => (),
+      other => panic!("Listened on {}: {:?}", path, other),
+    }
+  }
+}
@@ -0,0 +1,556 @@
+#![cfg(feature = "email")]
+
+use mesher::{prelude::*, test_support::url};
+use mesher_basic::{AfterReading, Email, EmailConfig, MailSecurity, MailServer, MailStore};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use std::{
+  fs,
+  io::{self, prelude::*, BufReader},
+  net::{SocketAddr, TcpListener, TcpStream},
+  path::{Path, PathBuf},
+  sync::{Arc, Mutex},
+  thread,
+  time::Duration,
+};
+
+struct Stored {
+  uid: u32,
+  flags: Vec<String>,
+  data: Vec<u8>,
+}
+
+#[derive(Default)]
+struct Mailbox {
+  next_uid: u32,
+  messages: Vec<Stored>,
+  recipients: Vec<String>,
+}
+
+impl Mailbox {
+  fn store(&mut self, data: Vec<u8>) {
+    self.next_uid += 1;
+    self.messages.push(Stored {
+      uid: self.next_uid,
+      flags: vec![],
+      data,
+    });
+  }
+}
+
+fn has_mesher_header(data: &[u8]) -> bool {
+  let text = String::from_utf8_lossy(data);
+  let head = text.split("\r\n\r\n").next().unwrap_or_default();
+  head.lines().any(|l| l.to_ascii_lowercase().starts_with("x-mesher:"))
+}
+
+fn read_line(reader: &mut impl BufRead) -> io::Result<Option<String>> {
+  let mut line = String::new();
+  if reader.read_line(&mut line)? == 0 {
+    return Ok(None);
+  }
+  Ok(Some(line.trim_end_matches(['\r', '\n']).to_owned()))
+}
+
+fn smtp_session(stream: TcpStream, mailbox: Arc<Mutex<Mailbox>>) -> io::Result<()> {
+  let mut reader = BufReader::new(stream.try_clone()?);
+  let mut writer = stream;
+  let auth = format!("PLAIN {}", STANDARD.encode("\0mesher\0hunter2"));
+  let mut authed = false;
+  writer.write_all(b"220 mock ESMTP\r\n")?;
+  while let Some(line) = read_line(&mut reader)? {
+    let (verb, arg) = line.split_once(' ').unwrap_or((&line, ""));
+    let reply = match verb.to_ascii_uppercase().as_str() {
+      "EHLO" => "250-mock\r\n250 AUTH PLAIN",
+      "AUTH" if arg == auth => {
+        authed = true;
+        "235 welcome"
+      }
+      "AUTH" => "535 wrong password",
+      "MAIL" if authed => "250 ok",
+      "MAIL" => "530 log in first",
+      "RCPT" => {
+        let to = arg.trim_start_matches("TO:").trim_matches(['<', '>']);
+        mailbox.lock().unwrap().recipients.push(to.to_owned());
+        "250 ok"
+      }
+      "DATA" => {
+        writer.write_all(b"354 go ahead\r\n")?;
+        let mut data = vec![];
+        loop {
+          let mut line = vec![];
+          if reader.read_until(b'\n', &mut line)? == 0 {
+            return Ok(());
+          }
+          if line == b".\r\n" {
+            break;
+          }
+          data.extend_from_slice(line.strip_prefix(b".").unwrap_or(&line));
+        }
+        mailbox.lock().unwrap().store(data);
+        "250 queued"
+      }
+      "QUIT" => {
+        writer.write_all(b"221 bye\r\n")?;
+        return Ok(());
+      }
+      _ => "502 unknown command",
+    };
+    writer.write_all(format!("{}\r\n", reply).as_bytes())?;
+  }
+  Ok(())
+}
+
+fn imap_session(stream: TcpStream, mailbox: Arc<Mutex<Mailbox>>) -> io::Result<()> {
+  let mut reader = BufReader::new(stream.try_clone()?);
+  let mut writer = stream;
+  let mut authed = false;
+  writer.write_all(b"* OK mock IMAP ready\r\n")?;
+  while let Some(line) = read_line(&mut reader)? {
+    let (tag, rest) = line.split_once(' ').unwrap_or((&line, ""));
+    let (verb, args) = rest.split_once(' ').unwrap_or((rest, ""));
+    let mut mailbox = mailbox.lock().unwrap();
+    let mut out = String::new();
+    let mut literal = None;
+    let status = match verb.to_ascii_uppercase().as_str() {
+      "LOGIN" if args == "\"mesher\" \"hunter2\"" => {
+        authed = true;
+        "OK logged in"
+      }
+      "LOGIN" => "NO wrong password",
+      _ if !authed => "BAD log in first",
+      "SELECT" => {
+        out = format!(
+          "* {} EXISTS\r\n* OK [UIDVALIDITY 7] UIDs valid\r\n",
+          mailbox.messages.len()
+        );
+        "OK [READ-WRITE] selected"
+      }
+      "EXPUNGE" => {
+        mailbox.messages.retain(|m| !m.flags.iter().any(|f| f == "\\Deleted"));
+        "OK expunged"
+      }
+      "LOGOUT" => {
+        writer.write_all(format!("* BYE logging out\r\n{} OK bye\r\n", tag).as_bytes())?;
+        return Ok(());
+      }
+      "UID" => {
+        let (command, args) = args.split_once(' ').unwrap_or((args, ""));
+        match command.to_ascii_uppercase().as_str() {
+          "SEARCH" => {
+            assert!(args.contains("HEADER X-Mesher"), "not searching by header: {}", args);
+            let unseen = args.contains("UNSEEN");
+            let uids: Vec<String> = mailbox
+              .messages
+              .iter()
+              .filter(|m| !m.flags.iter().any(|f| f == "\\Deleted"))
+              .filter(|m| !unseen || !m.flags.iter().any(|f| f == "\\Seen"))
+              .filter(|m| has_mesher_header(&m.data))
+              .map(|m| m.uid.to_string())
+              .collect();
+            out = format!(
+              "* SEARCH{}\r\n",
+              uids.iter().map(|uid| format!(" {}", uid)).collect::<String>()
+            );
+            "OK searched"
+          }
+          "FETCH" => {
+            let (uid, item) = args.split_once(' ').unwrap_or((args, ""));
+            let limit: usize = item
+              .strip_prefix("BODY.PEEK[]<0.")
+              .and_then(|l| l.strip_suffix('>'))
+              .and_then(|l| l.parse().ok())
+              .expect("not a partial fetch");
+            let uid: u32 = uid.parse().expect("invalid UID");
+            if let Some((seq, m)) = mailbox.messages.iter().enumerate().find(|(_, m)| m.uid == uid) {
+              let data = m.data[..m.data.len().min(limit)].to_vec();
+              out = format!("* {} FETCH (UID {} BODY[]<0> {{{}}}\r\n", seq + 1, uid, data.len());
+              literal = Some(data);
+            }
+            "OK fetched"
+          }
+          "STORE" => {
+            let mut words = args.splitn(3, ' ');
+            let uid: u32 = words.next().unwrap_or_default().parse().expect("invalid UID");
+            assert_eq!(words.next(), Some("+FLAGS.SILENT"));
+            let flags = words.next().unwrap_or_default().trim_matches(['(', ')']);
+            if let Some(m) = mailbox.messages.iter_mut().find(|m| m.uid == uid) {
+              m.flags.extend(flags.split(' ').map(str::to_owned));
+            }
+            "OK stored"
+          }
+          _ => "BAD unknown command",
+        }
+      }
+      _ => "BAD unknown command",
+    };
+    writer.write_all(out.as_bytes())?;
+    if let Some(data) = literal {
+      writer.write_all(&data)?;
+      writer.write_all(b")\r\n")?;
+    }
+    writer.write_all(format!("{} {}\r\n", tag, status).as_bytes())?;
+  }
+  Ok(())
+}
+
+fn serve(session: fn(TcpStream, Arc<Mutex<Mailbox>>) -> io::Result<()>, mailbox: Arc<Mutex<Mailbox>>) -> SocketAddr {
+  let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind");
+  let addr = listener.local_addr().expect("Failed to get address");
+  thread::spawn(move || {
+    for stream in listener.incoming().flatten() {
+      let mailbox = mailbox.clone();
+      thread::spawn(move || session(stream, mailbox));
+    }
+  });
+  addr
+}
+
+/// An SMTP server which delivers everything into a single IMAP mailbox, whatever it's addressed to.
+struct MockMail {
+  smtp: SocketAddr,
+  imap: SocketAddr,
+  mailbox: Arc<Mutex<Mailbox>>,
+}
+
+impl MockMail {
+  fn start() -> MockMail {
+    let mailbox = Arc::new(Mutex::new(Mailbox::default()));
+    MockMail {
+      smtp: serve(smtp_session, mailbox.clone()),
+      imap: serve(imap_session, mailbox.clone()),
+      mailbox,
+    }
+  }
+
+  fn config(&self, after_reading: AfterReading) -> EmailConfig {
+    let login = Some(("mesher".to_owned(), "hunter2".to_owned()));
+    EmailConfig {
+      from: "mesher@example.net".to_owned(),
+      store: MailStore::Servers {
+        smtp: MailServer {
+          login: login.clone(),
+          ..MailServer::new(&self.smtp.to_string(), MailSecurity::Plain)
+        },
+        imap: MailServer {
+          login,
+          ..MailServer::new(&self.imap.to_string(), MailSecurity::Plain)
+        },
+      },
+      after_reading,
+      timeout: Duration::from_secs(5),
+      poll_interval: Duration::from_secs(0),
+      ..EmailConfig::default()
+    }
+  }
+
+  fn transport(&self, after_reading: AfterReading) -> Email {
+    Email::with_config("email", self.config(after_reading))
+  }
+
+  fn deliver(&self, message: &str) {
+    self.mailbox.lock().unwrap().store(message.as_bytes().to_vec());
+  }
+
+  /// The flags on every message still in the mailbox.
+  fn flags(&self) -> Vec<Vec<String>> {
+    self
+      .mailbox
+      .lock()
+      .unwrap()
+      .messages
+      .iter()
+      .map(|m| m.flags.clone())
+      .collect()
+  }
+}
+
+#[test]
+fn round_trip() {
+  let mail = MockMail::start();
+  let mut sender = mail.transport(AfterReading::Delete);
+  let mut receiver = mail.transport(AfterReading::Delete);
+  receiver
+    .listen(url("email:alice@example.com"))
+    .expect("Failed to listen");
+
+  // line endings and dots in the blob mustn't be touched by anything along the way
+  let blobs = vec![vec![], vec![1; 10], b".\r\n\n\r.".to_vec(), vec![2; 5000]];
+  for blob in &blobs {
+    sender
+      .send(url("email:alice@example.com"), blob.clone())
+      .expect("Failed to send");
+  }
+  assert_eq!(mail.mailbox.lock().unwrap().recipients, vec!["alice@example.com"; 4]);
+  assert_eq!(receiver.receive().expect("Failed to receive"), blobs);
+  assert!(mail.flags().is_empty());
+  assert!(receiver.receive().expect("Failed to receive").is_empty());
+}
+
+#[test]
+fn flagged_instead_of_deleted() {
+  let mail = MockMail::start();
+  let mut sender = mail.transport(AfterReading::Flag);
+  let mut receiver = mail.transport(AfterReading::Flag);
+  receiver
+    .listen(url("email:alice@example.com"))
+    .expect("Failed to listen");
+
+  sender
+    .send(url("email:alice@example.com"), vec![1, 2, 3])
+    .expect("Failed to send");
+  assert_eq!(receiver.receive().expect("Failed to receive"), vec![vec![1, 2, 3]]);
+  assert_eq!(mail.flags(), vec![vec!["\\Seen".to_owned()]]);
+  assert!(receiver.receive().expect("Failed to receive").is_empty());
+
+  let mut restarted = mail.transport(AfterReading::Flag);
+  restarted
+    .listen(url("email:alice@example.com"))
+    .expect("Failed to listen");
+  assert!(restarted.receive().expect("Failed to receive").is_empty());
+}
+
+#[test]
+fn addresses_kept_apart() {
+  let mail = MockMail::start();
+  let mut sender = mail.transport(AfterReading::Delete);
+  sender
+    .send(url("email:alice@example.com"), vec![1])
+    .expect("Failed to send");
+  sender
+    .send(url("email:bob@example.com"), vec![2])
+    .expect("Failed to send");
+
+  let mut receiver = mail.transport(AfterReading::Delete);
+  receiver
+    .listen(url("email:alice@example.com"))
+    .expect("Failed to listen");
+  assert_eq!(receiver.receive().expect("Failed to receive"), vec![vec![1]]);
+  // bob's is left alone, untouched
+  assert_eq!(mail.flags(), vec![Vec::<String>::new()]);
+
+  receiver.listen(url("email:bob@example.com")).expect("Failed to listen");
+  assert_eq!(receiver.receive().expect("Failed to receive"), vec![vec![2]]);
+  assert!(mail.flags().is_empty());
+}
+
+#[test]
+fn other_mail_left_alone() {
+  let mail = MockMail::start();
+  mail.deliver("From: <carol@example.org>\r\nTo: <alice@example.com>\r\nSubject: lunch?\r\n\r\nAre you free?\r\n");
+  mail.deliver(
+    "From: <carol@example.org>\r\nTo: <alice@example.com>\r\nX-Mesher: alice@example.com\r\nSubject: mesher\r\n\r\nNo attachment here.\r\n",
+  );
+  let mut receiver = mail.transport(AfterReading::Delete);
+  receiver
+    .listen(url("email:alice@example.com"))
+    .expect("Failed to listen");
+  assert!(receiver.receive().expect("Failed to receive").is_empty());
+  assert_eq!(mail.flags(), vec![Vec::<String>::new(); 2]);
+
+  // the blobs that do arrive are still picked up around them
+  let mut sender = mail.transport(AfterReading::Delete);
+  sender
+    .send(url("email:alice@example.com"), vec![3; 30])
+    .expect("Failed to send");
+  assert_eq!(receiver.receive().expect("Failed to receive"), vec![vec![3; 30]]);
+  assert_eq!(mail.flags().len(), 2);
+}
+
+#[test]
+fn oversized_blob_ignored() {
+  let mail = MockMail::start();
+  let mut sender = mail.transport(AfterReading::Delete);
+  let mut receiver = Email::with_config(
+    "email",
+    EmailConfig {
+      max_packet_size: 100,
+      ..mail.config(AfterReading::Delete)
+    },
+  );
+  receiver
+    .listen(url("email:alice@example.com"))
+    .expect("Failed to listen");
+
+  sender
+    .send(url("email:alice@example.com"), vec![1; 101])
+    .expect("Failed to send");
+  sender
+    .send(url("email:alice@example.com"), vec![2; 100])
+    .expect("Failed to send");
+  assert_eq!(receiver.receive().expect("Failed to receive"), vec![vec![2; 100]]);
+  assert_eq!(mail.flags().len(), 1);
+}
+
+#[test]
+fn wrong_password() {
+  let mail = MockMail::start();
+  let mut config = mail.config(AfterReading::Delete);
+  if let MailStore::Servers { smtp, imap } = &mut config.store {
+    smtp.login = Some(("mesher".to_owned(), "hunter3".to_owned()));
+    imap.login = smtp.login.clone();
+  }
+  let mut wrong = Email::with_config("email", config);
+  match wrong.send(url("email:alice@example.com"), vec![1]) {
+    Err(fail::MesherFail::SendFailure(_)) => (),
+    other => panic!("Expected SendFailure, got {:?}", other),
+  }
+
+  let mut sender = mail.transport(AfterReading::Delete);
+  sender
+    .send(url("email:alice@example.com"), vec![1])
+    .expect("Failed to send");
+  wrong.listen(url("email:alice@example.com")).expect("Failed to listen");
+  assert!(wrong.receive().expect("Failed to receive").is_empty());
+  assert_eq!(mail.flags().len(), 1);
+}
+
+#[test]
+fn nothing_answering() {
+  let closed = TcpListener::bind("127.0.0.1:0")
+    .and_then(|l| l.local_addr())
+    .expect("Failed to find a port")
+    .to_string();
+  let config = EmailConfig {
+    store: MailStore::Servers {
+      smtp: MailServer::new(&closed, MailSecurity::Plain),
+      imap: MailServer::new(&closed, MailSecurity::Plain),
+    },
+    timeout: Duration::from_secs(1),
+    ..EmailConfig::default()
+  };
+  let mut email = Email::with_config("email", config);
+  match email.send(url("email:alice@example.com"), vec![1]) {
+    Err(fail::MesherFail::SendFailure(_)) => (),
+    other => panic!("Expected SendFailure, got {:?}", other),
+  }
+  email.listen(url("email:alice@example.com")).expect("Failed to listen");
+  assert!(email.receive().expect("Failed to receive").is_empty());
+}
+
+/// A fresh, empty directory holding a maildir for each of the addresses.
+fn maildir_root(name: &str, addresses: &[&str]) -> PathBuf {
+  let root = std::env::temp_dir().join(format!("mesher-maildir-{}-{}", name, std::process::id()));
+  let _ = fs::remove_dir_all(&root);
+  for address in addresses {
+    let (local, domain) = address.split_once('@').expect("Invalid address");
+    for sub in ["new", "cur", "tmp"].iter() {
+      fs::create_dir_all(root.join(domain).join(local).join(sub)).expect("Failed to create maildir");
+    }
+  }
+  root
+}
+
+fn maildir_transport(root: &Path, after_reading: AfterReading) -> Email {
+  Email::with_config(
+    "email",
+    EmailConfig {
+      store: MailStore::Maildir(root.to_owned()),
+      after_reading,
+      poll_interval: Duration::from_secs(0),
+      ..EmailConfig::default()
+    },
+  )
+}
+
+fn file_names(dir: &Path) -> Vec<String> {
+  fs::read_dir(dir)
+    .expect("Failed to read directory")
+    .map(|e| {
+      e.expect("Failed to read entry")
+        .file_name()
+        .to_string_lossy()
+        .into_owned()
+    })
+    .collect()
+}
+
+#[test]
+fn maildir_round_trip() {
+  let root = maildir_root("round-trip", &["alice@example.com", "bob@example.com"]);
+  let alice = root.join("example.com").join("alice");
+  let mut sender = maildir_transport(&root, AfterReading::Delete);
+  let mut receiver = maildir_transport(&root, AfterReading::Delete);
+  receiver
+    .listen(url("email:alice@Example.COM"))
+    .expect("Failed to listen");
+
+  sender
+    .send(url("email:alice@example.com"), vec![1; 10])
+    .expect("Failed to send");
+  sender
+    .send(url("email:bob@example.com"), vec![2; 10])
+    .expect("Failed to send");
+  fs::write(alice.join("new").join("1.other.host"), "Subject: hi\r\n\r\nhello\r\n").expect("Failed to write");
+  assert_eq!(file_names(&alice.join("new")).len(), 2);
+
+  assert_eq!(receiver.receive().expect("Failed to receive"), vec![vec![1; 10]]);
+  assert_eq!(file_names(&alice.join("new")), vec!["1.other.host"]);
+  assert!(file_names(&alice.join("cur")).is_empty());
+  assert!(receiver.receive().expect("Failed to receive").is_empty());
+  assert_eq!(file_names(&root.join("example.com").join("bob").join("new")).len(), 1);
+}
+
+#[test]
+fn maildir_flagged_instead_of_deleted() {
+  let root = maildir_root("flagged", &["alice@example.com"]);
+  let alice = root.join("example.com").join("alice");
+  let mut sender = maildir_transport(&root, AfterReading::Flag);
+  let mut receiver = maildir_transport(&root, AfterReading::Flag);
+  receiver
+    .listen(url("email:alice@example.com"))
+    .expect("Failed to listen");
+
+  sender
+    .send(url("email:alice@example.com"), vec![1, 2, 3])
+    .expect("Failed to send");
+  assert_eq!(receiver.receive().expect("Failed to receive"), vec![vec![1, 2, 3]]);
+  assert!(file_names(&alice.join("new")).is_empty());
+  let read = file_names(&alice.join("cur"));
+  assert_eq!(read.len(), 1);
+  assert!(read[0].ends_with(":2,S"));
+
+  let mut restarted = maildir_transport(&root, AfterReading::Flag);
+  restarted
+    .listen(url("email:alice@example.com"))
+    .expect("Failed to listen");
+  assert!(restarted.receive().expect("Failed to receive").is_empty());
+}
+
+#[test]
+fn missing_maildirs() {
+  let root = maildir_root("missing", &[]);
+  let mut email = maildir_transport(&root, AfterReading::Delete);
+  match email.send(url("email:alice@example.com"), vec![1]) {
+    Err(fail::MesherFail::SendFailure(_)) => (),
+    other => panic!("Expected SendFailure, got {:?}", other),
+  }
+  match email.listen(url("email:alice@example.com")) {
+    Err(fail::MesherFail::ListenFailure(_)) => (),
+    other => panic!("Expected ListenFailure, got {:?}", other),
+  }
+}
+
+#[test]
+fn invalid_paths() {
+  let mut email = Email::new("email").expect("Failed to create");
+  for path in &[
+    "email:alice",
+    "email:@example.com",
+    "email:alice@",
+    "email:alice@example.com/inbox",
+    "email:alice@example.com?x=1",
+    "email:..@example.com",
+    "email:alice@..",
+    "email:a%2Fb@example.com",
+  ] {
+    match email.send(url(path), vec![1]) {
+      Err(fail::MesherFail::InvalidURL(_)) => (),
+      other => panic!("Expected InvalidURL for {}, got {:?}", path, other),
+    }
+    match email.listen(url(path)) {
+      Err(fail::MesherFail::InvalidURL(_)) => (),
+      other => panic!("Expected InvalidURL for {}, got {:?}", path, other),
+    }
+  }
+}
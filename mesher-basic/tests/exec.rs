@@ -0,0 +1,138 @@
+#![cfg(unix)]
+
+use mesher::{
+  prelude::*,
+  test_support::{receive_within, url},
+};
+use mesher_basic::{Exec, ExecConfig};
+
+use std::{
+  fs,
+  path::PathBuf,
+  thread::sleep,
+  time::{Duration, Instant},
+};
+
+fn frame(blob: &[u8]) -> Vec<u8> {
+  let mut framed = (blob.len() as u32).to_be_bytes().to_vec();
+  framed.extend_from_slice(blob);
+  framed
+}
+
+fn exec_with(commands: &[(&str, &[&str])]) -> Exec {
+  let mut config = ExecConfig {
+    restart_delay: Duration::from_millis(100),
+    ..ExecConfig::default()
+  };
+  for (name, argv) in commands {
+    config
+      .commands
+      .insert(name.to_string(), argv.iter().map(|a| a.to_string()).collect());
+  }
+  Exec::with_config("exec", config)
+}
+
+fn temp_file(name: &str) -> PathBuf {
+  let path = std::env::temp_dir().join(format!("mesher-exec-test-{}-{}", std::process::id(), name));
+  let _ = fs::remove_file(&path);
+  path
+}
+
+#[test]
+fn loops_back_through_cat() {
+  let mut t = exec_with(&[("loop", &["cat"])]);
+  t.listen(url("exec:loop")).expect("Failed to listen");
+
+  for i in 0..5 {
+    t.send(url("exec:loop"), vec![i; 100]).expect("Failed to send");
+  }
+  let received = receive_within(&mut t, 5);
+  assert_eq!(received, (0..5).map(|i| vec![i; 100]).collect::<Vec<_>>());
+}
+
+#[test]
+fn writes_framed_blobs_to_stdin() {
+  let out = temp_file("stdin");
+  // exits once both blobs have been written out
+  let script = format!("head -c 13 > {}", out.display());
+  let mut t = exec_with(&[("file", &["sh", "-c", &script])]);
+
+  t.send(url("exec:file"), vec![1, 2, 3]).expect("Failed to send");
+  t.send(url("exec:file"), vec![4, 5]).expect("Failed to send");
+
+  let mut expected = frame(&[1, 2, 3]);
+  expected.append(&mut frame(&[4, 5]));
+  let start = Instant::now();
+  while fs::read(&out).unwrap_or_default().len() < expected.len() && start.elapsed() < Duration::from_secs(5) {
+    sleep(Duration::from_millis(10));
+  }
+  assert_eq!(fs::read(&out).expect("Failed to read output"), expected);
+  let _ = fs::remove_file(&out);
+}
+
+#[test]
+fn restarts_exited_command_on_send() {
+  // exits once it's echoed one 5-byte blob back
+  let mut t = exec_with(&[("once", &["head", "-c", "9"])]);
+
+  t.send(url("exec:once"), vec![1; 5]).expect("Failed to send");
+  assert_eq!(receive_within(&mut t, 1), vec![vec![1; 5]]);
+  sleep(Duration::from_millis(100));
+  t.send(url("exec:once"), vec![2; 5]).expect("Failed to send");
+  assert_eq!(receive_within(&mut t, 1), vec![vec![2; 5]]);
+}
+
+#[test]
+fn restarts_listened_command() {
+  let mut t = exec_with(&[("tick", &["printf", "\\000\\000\\000\\003abc"])]);
+  t.listen(url("exec:tick")).expect("Failed to listen");
+
+  let received = receive_within(&mut t, 3);
+  assert_eq!(received, vec![b"abc".to_vec(); 3]);
+}
+
+#[test]
+fn oversized_output_ignored() {
+  let mut config = ExecConfig {
+    max_packet_size: 4,
+    ..ExecConfig::default()
+  };
+  config.commands.insert(
+    "big".to_owned(),
+    vec![
+      "printf".to_owned(),
+      "\\000\\000\\000\\005hello\\000\\000\\000\\001!".to_owned(),
+    ],
+  );
+  let mut t = Exec::with_config("exec", config);
+  t.listen(url("exec:big")).expect("Failed to listen");
+
+  sleep(Duration::from_millis(200));
+  assert_eq!(t.receive().expect("Failed to receive"), Vec::<Vec<u8>>::new());
+}
+
+#[test]
+fn unknown_command_rejected() {
+  let mut t = exec_with(&[("loop", &["cat"])]);
+  match t.send(url("exec:rm"), vec![1]) {
+    Err(fail::MesherFail::InvalidURL(_)) => (),
+    other => panic!("Sent to an unconfigured command: {:?}", other),
+  }
+  match t.listen(url("exec:loop/extra")) {
+    Err(fail::MesherFail::InvalidURL(_)) => (),
+    other => panic!("Listened on a path with extra parts: {:?}", other),
+  }
+}
+
+#[test]
+fn missing_program_fails() {
+  let mut t = exec_with(&[("missing", &["/nonexistent/mesher-carrier"])]);
+  match t.send(url("exec:missing"), vec![1]) {
+    Err(fail::MesherFail::SendFailure(_)) => (),
+    other => panic!("Sent to a missing program: {:?}", other),
+  }
+  match t.listen(url("exec:missing")) {
+    Err(fail::MesherFail::ListenFailure(_)) => (),
+    other => panic!("Listened on a missing program: {:?}", other),
+  }
+}
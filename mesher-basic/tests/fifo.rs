@@ -0,0 +1,151 @@
+#![cfg(unix)]
+
+use mesher::{
+  prelude::*,
+  test_support::{receive_within, url},
+};
+use mesher_basic::{Fifo, FifoConfig};
+
+use std::{
+  fs,
+  os::unix::fs::{FileTypeExt, PermissionsExt},
+  path::{Path, PathBuf},
+  process::Command,
+  thread::sleep,
+  time::Duration,
+};
+
+/// A fresh FIFO path for the test, which nothing is using yet.
+fn fifo_path(name: &str) -> PathBuf {
+  let path = std::env::temp_dir().join(format!("mesher-fifo-{}-{}", name, std::process::id()));
+  let _ = fs::remove_file(&path);
+  path
+}
+
+fn fifo_url(path: &Path) -> MesherUrl {
+  url(&format!("fifo:{}", path.display()))
+}
+
+#[test]
+fn round_trip() {
+  let path = fifo_path("round-trip");
+  let mut receiver = Fifo::new("fifo").expect("Failed to create");
+  receiver.listen(fifo_url(&path)).expect("Failed to listen");
+  let mut sender = Fifo::new("fifo").expect("Failed to create");
+
+  for i in 0..5 {
+    sender.send(fifo_url(&path), vec![i; 1000]).expect("Failed to send");
+  }
+  let received = receive_within(&mut receiver, 5);
+  assert_eq!(received, (0..5).map(|i| vec![i; 1000]).collect::<Vec<_>>());
+}
+
+#[test]
+fn created_and_removed() {
+  let path = fifo_path("created");
+  let mut receiver = Fifo::new("fifo").expect("Failed to create");
+  receiver
+    .listen(url(&format!("fifo:{}?perm=0600", path.display())))
+    .expect("Failed to listen");
+
+  let meta = fs::metadata(&path).expect("FIFO wasn't created");
+  assert!(meta.file_type().is_fifo());
+  assert_eq!(meta.permissions().mode() & 0o777, 0o600);
+  drop(receiver);
+  assert!(!path.exists(), "FIFO wasn't removed");
+}
+
+#[test]
+fn existing_fifo_kept() {
+  let path = fifo_path("existing");
+  let status = Command::new("mkfifo")
+    .arg(&path)
+    .status()
+    .expect("Failed to run mkfifo");
+  assert!(status.success());
+  let mut receiver = Fifo::new("fifo").expect("Failed to create");
+  receiver.listen(fifo_url(&path)).expect("Failed to listen");
+
+  drop(receiver);
+  assert!(path.exists(), "FIFO someone else made was removed");
+  fs::remove_file(&path).expect("Failed to clean up");
+}
+
+#[test]
+fn nothing_reading() {
+  let path = fifo_path("nothing-reading");
+  let status = Command::new("mkfifo")
+    .arg(&path)
+    .status()
+    .expect("Failed to run mkfifo");
+  assert!(status.success());
+  let mut sender = Fifo::new("fifo").expect("Failed to create");
+
+  match sender.send(fifo_url(&path), vec![1]) {
+    Err(fail::MesherFail::SendFailure(_)) => (),
+    other => panic!("Sent with nothing reading: {:?}", other),
+  }
+  match sender.send(fifo_url(&fifo_path("missing")), vec![1]) {
+    Err(fail::MesherFail::SendFailure(_)) => (),
+    other => panic!("Sent to a missing FIFO: {:?}", other),
+  }
+  fs::remove_file(&path).expect("Failed to clean up");
+}
+
+#[test]
+fn regular_file_rejected() {
+  let path = fifo_path("regular");
+  fs::write(&path, b"not a fifo").expect("Failed to write file");
+  let mut t = Fifo::new("fifo").expect("Failed to create");
+
+  match t.listen(fifo_url(&path)) {
+    Err(fail::MesherFail::ListenFailure(_)) => (),
+    other => panic!("Listened on a regular file: {:?}", other),
+  }
+  match t.send(fifo_url(&path), vec![1]) {
+    Err(fail::MesherFail::SendFailure(_)) => (),
+    other => panic!("Sent to a regular file: {:?}", other),
+  }
+  assert_eq!(fs::read(&path).expect("Failed to read file"), b"not a fifo");
+  fs::remove_file(&path).expect("Failed to clean up");
+}
+
+#[test]
+fn shell_writer_received() {
+  let path = fifo_path("shell");
+  let mut receiver = Fifo::new("fifo").expect("Failed to create");
+  receiver.listen(fifo_url(&path)).expect("Failed to listen");
+
+  let status = Command::new("sh")
+    .arg("-c")
+    .arg(r#"printf '\000\000\000\005hello' > "$1""#)
+    .arg("sh")
+    .arg(&path)
+    .status()
+    .expect("Failed to run sh");
+  assert!(status.success());
+  assert_eq!(receive_within(&mut receiver, 1), vec![b"hello".to_vec()]);
+}
+
+#[test]
+fn oversized_frame_dropped() {
+  let path = fifo_path("oversized");
+  let mut receiver = Fifo::with_config("fifo", FifoConfig { max_packet_size: 100 });
+  receiver.listen(fifo_url(&path)).expect("Failed to listen");
+  let mut sender = Fifo::new("fifo").expect("Failed to create");
+
+  sender.send(fifo_url(&path), vec![1; 1000]).expect("Failed to send");
+  // give the listener a chance to throw the oversized one away first
+  sleep(Duration::from_millis(100));
+  sender.send(fifo_url(&path), vec![2; 100]).expect("Failed to send");
+  assert_eq!(receive_within(&mut receiver, 1), vec![vec![2; 100]]);
+}
+
+#[test]
+fn host_rejected() {
+  let mut t = Fifo::new("fifo").expect("Failed to create");
+  match t.listen(url("fifo://somehost/tmp/fifo")) {
+    Err(fail::MesherFail::InvalidURL(_)) => (),
+    other => panic!("Listened on a path with a host: {:?}", other),
+  }
+}
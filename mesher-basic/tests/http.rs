@@ -0,0 +1,237 @@
+use mesher::{prelude::*, test_support::url};
+use mesher_basic::{HttpConfig, MailboxConfig, MailboxServer, HTTP};
+
+use std::{
+  io::prelude::*,
+  net::TcpStream,
+  time::{Duration, Instant},
+};
+
+fn server(config: MailboxConfig) -> MailboxServer {
+  MailboxServer::start("localhost:0", config).expect("Failed to start server")
+}
+
+/// A transport which checks its mailboxes every time it's asked to.
+fn eager() -> HTTP {
+  HTTP::with_config(
+    "http",
+    HttpConfig {
+      poll_interval: Duration::from_secs(0),
+      ..HttpConfig::default()
+    },
+  )
+}
+
+#[test]
+fn round_trip() {
+  let server = server(MailboxConfig::default());
+  let mailbox = format!("http://{}/alice", server.addr());
+  let mut sender = HTTP::new("http").expect("Failed to create");
+  let mut receiver = eager();
+  receiver.listen(url(&mailbox)).expect("Failed to listen");
+
+  for i in 0..3 {
+    sender.send(url(&mailbox), vec![i; 100]).expect("Failed to send");
+  }
+  let received = receiver.receive().expect("Failed to receive");
+  assert_eq!(received, (0..3).map(|i| vec![i; 100]).collect::<Vec<_>>());
+  assert!(receiver.receive().expect("Failed to receive").is_empty());
+}
+
+#[test]
+fn mailboxes_are_separate() {
+  let server = server(MailboxConfig::default());
+  let mut sender = HTTP::new("http").expect("Failed to create");
+  // the // is optional, like any other path
+  sender
+    .send(url(&format!("http:{}/alice", server.addr())), vec![1])
+    .expect("Failed to send");
+  sender
+    .send(url(&format!("http:{}/bob?ignored=yes", server.addr())), vec![2])
+    .expect("Failed to send");
+
+  let mut bob = eager();
+  bob
+    .listen(url(&format!("http://{}/bob", server.addr())))
+    .expect("Failed to listen");
+  assert_eq!(bob.receive().expect("Failed to receive"), vec![vec![2]]);
+}
+
+#[test]
+fn server_limits() {
+  let server = server(MailboxConfig {
+    max_blob_size: 10,
+    max_queued: 2,
+    ..MailboxConfig::default()
+  });
+  let mailbox = format!("http://{}/full", server.addr());
+  let mut sender = HTTP::new("http").expect("Failed to create");
+
+  match sender.send(url(&mailbox), vec![0; 11]) {
+    Err(fail::MesherFail::SendFailure(_)) => (),
+    _ => panic!("Should have rejected oversized blob"),
+  }
+  sender.send(url(&mailbox), vec![1]).expect("Failed to send");
+  sender.send(url(&mailbox), vec![2]).expect("Failed to send");
+  assert!(sender.send(url(&mailbox), vec![3]).is_err());
+
+  let mut receiver = eager();
+  receiver.listen(url(&mailbox)).expect("Failed to listen");
+  assert_eq!(receiver.receive().expect("Failed to receive"), vec![vec![1], vec![2]]);
+  sender.send(url(&mailbox), vec![4]).expect("Failed to send");
+}
+
+#[test]
+fn server_limits_total() {
+  // "/a" and "/b" plus a four byte blob each
+  let server = server(MailboxConfig {
+    max_stored: 12,
+    ..MailboxConfig::default()
+  });
+  let mut sender = HTTP::new("http").expect("Failed to create");
+  let mailbox = |name: &str| url(&format!("http://{}/{}", server.addr(), name));
+
+  sender.send(mailbox("a"), vec![1; 4]).expect("Failed to send");
+  sender.send(mailbox("b"), vec![2; 4]).expect("Failed to send");
+  assert!(sender.send(mailbox("c"), vec![]).is_err());
+
+  let mut receiver = eager();
+  receiver.listen(mailbox("a")).expect("Failed to listen");
+  assert_eq!(receiver.receive().expect("Failed to receive"), vec![vec![1; 4]]);
+  sender.send(mailbox("c"), vec![3; 4]).expect("Failed to send");
+}
+
+#[test]
+fn respects_poll_interval() {
+  let server = server(MailboxConfig::default());
+  let mailbox = format!("http://{}/slow", server.addr());
+  let mut sender = HTTP::new("http").expect("Failed to create");
+  let mut receiver = HTTP::with_config(
+    "http",
+    HttpConfig {
+      poll_interval: Duration::from_secs(60 * 60),
+      ..HttpConfig::default()
+    },
+  );
+  receiver.listen(url(&mailbox)).expect("Failed to listen");
+
+  sender.send(url(&mailbox), vec![1]).expect("Failed to send");
+  assert_eq!(receiver.receive().expect("Failed to receive"), vec![vec![1]]);
+  sender.send(url(&mailbox), vec![2]).expect("Failed to send");
+  assert!(receiver.receive().expect("Failed to receive").is_empty());
+}
+
+#[test]
+fn unreachable_server_is_skipped() {
+  let addr = server(MailboxConfig::default()).addr();
+  // the server's been dropped, so nothing's there any more
+  let mut t = eager();
+  t.listen(url(&format!("http://{}/gone", addr)))
+    .expect("Failed to listen");
+  assert!(t.receive().expect("Failed to receive").is_empty());
+  assert!(t.send(url(&format!("http://{}/gone", addr)), vec![1]).is_err());
+}
+
+#[test]
+fn get_response_is_framed() {
+  let server = server(MailboxConfig::default());
+  let mut sender = HTTP::new("http").expect("Failed to create");
+  sender
+    .send(url(&format!("http://{}/raw", server.addr())), vec![7, 8])
+    .expect("Failed to send");
+
+  let mut conn = TcpStream::connect(server.addr()).expect("Failed to connect");
+  conn.write_all(b"GET /raw HTTP/1.0\r\n\r\n").expect("Failed to write");
+  let mut response = vec![];
+  conn.read_to_end(&mut response).expect("Failed to read");
+  assert!(response.starts_with(b"HTTP/1.0 200"));
+  assert!(response.ends_with(&[0, 0, 0, 2, 7, 8]));
+}
+
+#[test]
+fn any_scheme_works() {
+  let server = server(MailboxConfig::default());
+  let mailbox = format!("mailbox://{}/alias?tls=off", server.addr());
+  let mut t = HTTP::with_config(
+    "mailbox",
+    HttpConfig {
+      poll_interval: Duration::from_secs(0),
+      ..HttpConfig::default()
+    },
+  );
+  t.listen(url(&mailbox)).expect("Failed to listen");
+  t.send(url(&mailbox), vec![1]).expect("Failed to send");
+  assert_eq!(t.receive().expect("Failed to receive"), vec![vec![1]]);
+}
+
+#[test]
+fn rejects_bad_urls() {
+  let mut t = HTTP::new("http").expect("Failed to create");
+  for bad in &["http:/mailbox", "http://localhost/mailbox?tls=maybe"] {
+    match t.listen(url(bad)) {
+      Err(fail::MesherFail::InvalidURL(_)) => (),
+      _ => panic!("Should have rejected {}", bad),
+    }
+  }
+}
+
+#[test]
+fn stalled_client_is_cut_off() {
+  let server = server(MailboxConfig {
+    read_timeout: Duration::from_secs(2),
+    ..MailboxConfig::default()
+  });
+  let mailbox = format!("http://{}/stalled", server.addr());
+
+  // promises a body that never arrives
+  let mut stalled = TcpStream::connect(server.addr()).expect("Failed to connect");
+  stalled
+    .write_all(b"POST /stalled HTTP/1.1\r\nContent-Length: 100\r\n\r\nabc")
+    .expect("Failed to write");
+
+  // everyone else carries on regardless, without waiting for it to time out
+  let start = Instant::now();
+  let mut sender = HTTP::new("http").expect("Failed to create");
+  let mut receiver = eager();
+  receiver.listen(url(&mailbox)).expect("Failed to listen");
+  sender.send(url(&mailbox), vec![1]).expect("Failed to send");
+  assert_eq!(receiver.receive().expect("Failed to receive"), vec![vec![1]]);
+  assert!(start.elapsed() < Duration::from_secs(1));
+
+  // and the stalled client gets dropped once it's out of time, without its partial blob being stored
+  stalled
+    .set_read_timeout(Some(Duration::from_secs(5)))
+    .expect("Failed to set timeout");
+  let mut response = vec![];
+  stalled.read_to_end(&mut response).expect("Should have been closed");
+  assert!(response.starts_with(b"HTTP/1.1 408"));
+  assert!(receiver.receive().expect("Failed to receive").is_empty());
+}
+
+#[test]
+fn through_mesher() {
+  let server = server(MailboxConfig::default());
+  let mailbox = format!("http://{}/dest", server.addr());
+
+  let (k_dest, s_dest) = encrypt::gen_keypair();
+  let mut m_dest = Mesher::unsigned(vec![s_dest]);
+  m_dest.add_transport::<HTTP>("http").expect("Failed to add transport");
+  m_dest.listen_on(&mailbox).expect("Failed to listen");
+
+  let (k_source, s_source) = encrypt::gen_keypair();
+  let mut m_source = Mesher::unsigned(vec![s_source]);
+  m_source.add_transport::<HTTP>("http").expect("Failed to add transport");
+
+  let mut packet = Packet::unsigned();
+  packet.add_hop(mailbox.clone(), &k_source);
+  packet.add_message(&[1, 2, 3], &k_dest);
+  m_source.launch(packet).expect("Failed to send");
+
+  let received = m_dest
+    .receive()
+    .expect("Failed to receive")
+    .into_iter()
+    .map(|m| m.into_contents())
+    .collect::<Vec<_>>();
+  assert_eq!(received, vec![vec![1, 2, 3]]);
+}
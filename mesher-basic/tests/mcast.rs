@@ -0,0 +1,127 @@
+use mesher::{
+  prelude::*,
+  test_support::{receive_within, url},
+};
+use mesher_basic::{Mcast, McastConfig, UdpConfig};
+
+use std::{net::Ipv4Addr, thread::sleep, time::Duration};
+
+/// Keeps everything on the loopback interface, so the tests work without a network.
+fn loopback() -> McastConfig {
+  McastConfig {
+    interface_v4: Ipv4Addr::LOCALHOST,
+    ..McastConfig::default()
+  }
+}
+
+#[test]
+fn every_listener_receives() {
+  let mut first = Mcast::with_config("mcast", loopback());
+  first.listen(url("mcast:239.255.77.1:18640")).expect("Failed to listen");
+  let mut second = Mcast::with_config("mcast", loopback());
+  second
+    .listen(url("mcast:239.255.77.1:18640"))
+    .expect("Failed to listen");
+  let mut sender = Mcast::with_config("mcast", loopback());
+
+  for i in 0..3 {
+    sender
+      .send(url("mcast:239.255.77.1:18640"), vec![i; 100])
+      .expect("Failed to send");
+  }
+  let expected = (0..3).map(|i| vec![i; 100]).collect::<Vec<_>>();
+  assert_eq!(receive_within(&mut first, 3), expected);
+  assert_eq!(receive_within(&mut second, 3), expected);
+}
+
+#[test]
+fn other_groups_ignored() {
+  let mut listener = Mcast::with_config("mcast", loopback());
+  listener
+    .listen(url("mcast:239.255.77.2:18641"))
+    .expect("Failed to listen");
+  let mut sender = Mcast::with_config("mcast", loopback());
+
+  sender
+    .send(url("mcast:239.255.77.3:18641"), vec![1])
+    .expect("Failed to send");
+  sender
+    .send(url("mcast:239.255.77.2:18641"), vec![2])
+    .expect("Failed to send");
+  assert_eq!(receive_within(&mut listener, 1), vec![vec![2]]);
+  sleep(Duration::from_millis(100));
+  assert_eq!(listener.receive().expect("Failed to receive"), Vec::<Vec<u8>>::new());
+}
+
+#[test]
+fn other_groups_on_port_ignored() {
+  let mut listener = Mcast::with_config("mcast", loopback());
+  listener
+    .listen(url("mcast:239.255.77.6:18644"))
+    .expect("Failed to listen");
+  // with something on the machine joined to the other group, its blobs reach the port too
+  let mut other = Mcast::with_config("mcast", loopback());
+  other.listen(url("mcast:239.255.77.7:18644")).expect("Failed to listen");
+  let mut sender = Mcast::with_config("mcast", loopback());
+
+  sender
+    .send(url("mcast:239.255.77.7:18644"), vec![1])
+    .expect("Failed to send");
+  sender
+    .send(url("mcast:239.255.77.6:18644"), vec![2])
+    .expect("Failed to send");
+  assert_eq!(receive_within(&mut listener, 1), vec![vec![2]]);
+  assert_eq!(receive_within(&mut other, 1), vec![vec![1]]);
+  sleep(Duration::from_millis(100));
+  assert_eq!(listener.receive().expect("Failed to receive"), Vec::<Vec<u8>>::new());
+  assert_eq!(other.receive().expect("Failed to receive"), Vec::<Vec<u8>>::new());
+}
+
+#[test]
+fn listening_twice_receives_once() {
+  let mut t = Mcast::with_config("mcast", loopback());
+  t.listen(url("mcast:239.255.77.4:18642")).expect("Failed to listen");
+  t.listen(url("mcast:239.255.77.4:18642")).expect("Failed to listen");
+
+  t.send(url("mcast:239.255.77.4:18642"), vec![1, 2, 3])
+    .expect("Failed to send");
+  assert_eq!(receive_within(&mut t, 1), vec![vec![1, 2, 3]]);
+  sleep(Duration::from_millis(100));
+  assert_eq!(t.receive().expect("Failed to receive"), Vec::<Vec<u8>>::new());
+}
+
+#[test]
+fn fragments_large_blobs() {
+  let config = McastConfig {
+    udp: UdpConfig {
+      mtu: 100,
+      ..UdpConfig::default()
+    },
+    ..loopback()
+  };
+  let mut t = Mcast::with_config("mcast", config);
+  t.listen(url("mcast:239.255.77.5:18643?mode=fragment"))
+    .expect("Failed to listen");
+
+  match t.send(url("mcast:239.255.77.5:18643"), vec![1; 1000]) {
+    Err(fail::MesherFail::SendFailure(_)) => (),
+    other => panic!("Sent a blob larger than the MTU whole: {:?}", other),
+  }
+  let blob = (0..1000).map(|i| i as u8).collect::<Vec<_>>();
+  t.send(url("mcast:239.255.77.5:18643?mode=fragment"), blob.clone())
+    .expect("Failed to send");
+  assert_eq!(receive_within(&mut t, 1), vec![blob]);
+}
+
+#[test]
+fn unicast_rejected() {
+  let mut t = Mcast::new("mcast").expect("Failed to create");
+  match t.send(url("mcast:127.0.0.1:18646"), vec![1]) {
+    Err(fail::MesherFail::InvalidURL(_)) => (),
+    other => panic!("Sent to a unicast address: {:?}", other),
+  }
+  match t.listen(url("mcast:127.0.0.1:18646")) {
+    Err(fail::MesherFail::InvalidURL(_)) => (),
+    other => panic!("Listened on a unicast address: {:?}", other),
+  }
+}
@@ -0,0 +1,144 @@
+use mesher::{
+  prelude::*,
+  test_support::{receive_within, url},
+};
+use mesher_basic::{Quic, QuicConfig};
+
+use std::{thread::sleep, time::Duration};
+
+fn listening_on(path: &str, config: QuicConfig) -> Quic {
+  let mut t = Quic::with_config("quic", config).expect("Failed to create");
+  t.listen(url(path)).expect("Failed to listen");
+  t
+}
+
+fn config() -> QuicConfig {
+  QuicConfig::self_signed().expect("Failed to generate certificate")
+}
+
+#[test]
+fn round_trip() {
+  let mut receiver = listening_on("quic:localhost:18650", config());
+  let mut sender = Quic::new("quic").expect("Failed to create");
+  let path = format!("quic:localhost:18650?pin={}", receiver.fingerprint());
+
+  for i in 0..5 {
+    sender.send(url(&path), vec![i; 1000]).expect("Failed to send");
+  }
+  let mut received = receive_within(&mut receiver, 5);
+  // each blob is its own stream, so they can overtake each other
+  received.sort();
+  assert_eq!(received, (0..5).map(|i| vec![i; 1000]).collect::<Vec<_>>());
+}
+
+#[test]
+fn wrong_pin_rejected() {
+  let _receiver = listening_on("quic:localhost:18651", config());
+  let mut sender = Quic::new("quic").expect("Failed to create");
+
+  match sender.send(url("quic:localhost:18651?pin=00112233"), vec![1]) {
+    Err(fail::MesherFail::SendFailure(_)) => (),
+    other => panic!("Sent to an unpinned listener: {:?}", other),
+  }
+}
+
+#[test]
+fn configured_pins_used() {
+  let mut receiver = listening_on("quic:localhost:18652", config());
+  let mut sender_config = config();
+  sender_config.pins.push(receiver.fingerprint().to_uppercase());
+  let mut sender = Quic::with_config("quic", sender_config).expect("Failed to create");
+
+  sender
+    .send(url("quic:localhost:18652"), vec![1, 2, 3])
+    .expect("Failed to send");
+  assert_eq!(receive_within(&mut receiver, 1), vec![vec![1, 2, 3]]);
+}
+
+#[test]
+fn oversized_blob_dropped() {
+  let mut receiver = listening_on(
+    "quic:localhost:18653",
+    QuicConfig {
+      max_packet_size: 100,
+      ..config()
+    },
+  );
+  let mut sender = Quic::new("quic").expect("Failed to create");
+  let path = format!("quic:localhost:18653?pin={}", receiver.fingerprint());
+
+  // the listener may have acknowledged it all before noticing it's too big, so this can look like it worked
+  let _ = sender.send(url(&path), vec![1; 1000]);
+  sender.send(url(&path), vec![2; 100]).expect("Failed to send");
+  assert_eq!(receive_within(&mut receiver, 1), vec![vec![2; 100]]);
+}
+
+#[test]
+fn reconnects_after_idle() {
+  let short_idle = || QuicConfig {
+    idle_timeout: Duration::from_millis(200),
+    ..config()
+  };
+  let mut receiver = listening_on("quic:localhost:18654", short_idle());
+  let mut sender = Quic::with_config("quic", short_idle()).expect("Failed to create");
+  let path = format!("quic:localhost:18654?pin={}", receiver.fingerprint());
+
+  sender.send(url(&path), vec![1]).expect("Failed to send");
+  sleep(Duration::from_millis(500));
+  // this one goes out over a resumed session
+  sender.send(url(&path), vec![2]).expect("Failed to send");
+  assert_eq!(receive_within(&mut receiver, 2), vec![vec![1], vec![2]]);
+}
+
+#[test]
+fn early_data_only_when_enabled() {
+  for (port, early_data) in [(18657, false), (18658, true)] {
+    let short_idle = || QuicConfig {
+      idle_timeout: Duration::from_millis(200),
+      early_data,
+      ..config()
+    };
+    let path = format!("quic:localhost:{}", port);
+    let mut receiver = listening_on(&path, short_idle());
+    let mut sender = Quic::with_config("quic", short_idle()).expect("Failed to create");
+    let path = format!("{}?pin={}", path, receiver.fingerprint());
+
+    sender.send(url(&path), vec![1]).expect("Failed to send");
+    sleep(Duration::from_millis(500));
+    sender.send(url(&path), vec![2]).expect("Failed to send");
+    assert_eq!(receive_within(&mut receiver, 2), vec![vec![1], vec![2]]);
+    assert_eq!(receiver.early_data_received(), early_data as usize);
+  }
+}
+
+#[test]
+fn survives_rebind() {
+  let mut receiver = listening_on("quic:localhost:18655", config());
+  let mut sender = Quic::new("quic").expect("Failed to create");
+  let path = format!("quic:localhost:18655?pin={}", receiver.fingerprint());
+
+  sender.send(url(&path), vec![1]).expect("Failed to send");
+  sender.rebind().expect("Failed to rebind");
+  sender.send(url(&path), vec![2]).expect("Failed to send");
+  let mut received = receive_within(&mut receiver, 2);
+  received.sort();
+  assert_eq!(received, vec![vec![1], vec![2]]);
+}
+
+#[test]
+fn nothing_listening() {
+  let mut sender = Quic::with_config(
+    "quic",
+    QuicConfig {
+      read_timeout: Duration::from_millis(500),
+      accept_any_certificate: true,
+      ..config()
+    },
+  )
+  .expect("Failed to create");
+
+  match sender.send(url("quic:localhost:18656"), vec![1]) {
+    Err(fail::MesherFail::SendFailure(_)) => (),
+    other => panic!("Sent to nothing: {:?}", other),
+  }
+}
@@ -0,0 +1,224 @@
+use mesher::{
+  prelude::*,
+  test_support::{receive_within, url},
+};
+use mesher_basic::{Socks5Proxy, TcpConfig, TlsConfig, TCP, TLS, WS};
+
+use std::{
+  io::{self, prelude::*},
+  net::{Shutdown, SocketAddr, TcpListener, TcpStream},
+  sync::{Arc, Mutex},
+  thread::spawn,
+};
+
+/// A just-good-enough SOCKS5 server, which sends anything for a `.onion` host to the same port on localhost.
+struct StubProxy {
+  addr: SocketAddr,
+  /// Every host and port asked for, as the client sent them.
+  requested: Arc<Mutex<Vec<(String, u16)>>>,
+}
+
+impl StubProxy {
+  fn start(credentials: Option<(&'static str, &'static str)>) -> StubProxy {
+    let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind proxy");
+    let addr = listener.local_addr().expect("Failed to get proxy address");
+    let requested = Arc::new(Mutex::new(vec![]));
+    let log = requested.clone();
+    spawn(move || {
+      for conn in listener.incoming() {
+        let log = log.clone();
+        spawn(move || {
+          let _ = serve(conn?, credentials, &log);
+          Ok::<(), io::Error>(())
+        });
+      }
+    });
+    StubProxy { addr, requested }
+  }
+
+  fn config(&self, credentials: Option<(&str, &str)>) -> TcpConfig {
+    TcpConfig {
+      proxy: Some(Socks5Proxy {
+        addr: self.addr,
+        credentials: credentials.map(|(u, p)| (u.to_owned(), p.to_owned())),
+      }),
+      ..TcpConfig::default()
+    }
+  }
+
+  fn requested(&self) -> Vec<(String, u16)> {
+    self.requested.lock().expect("poisoned lock?").clone()
+  }
+}
+
+fn read_bytes(conn: &mut TcpStream, len: usize) -> io::Result<Vec<u8>> {
+  let mut buf = vec![0; len];
+  conn.read_exact(&mut buf)?;
+  Ok(buf)
+}
+
+fn serve(mut conn: TcpStream, credentials: Option<(&str, &str)>, log: &Mutex<Vec<(String, u16)>>) -> io::Result<()> {
+  let greeting = read_bytes(&mut conn, 2)?;
+  let methods = read_bytes(&mut conn, greeting[1] as usize)?;
+  match credentials {
+    None => conn.write_all(&[5, 0])?,
+    Some(_) if !methods.contains(&2) => return conn.write_all(&[5, 0xFF]),
+    Some((user, pass)) => {
+      conn.write_all(&[5, 2])?;
+      let header = read_bytes(&mut conn, 2)?;
+      let given_user = read_bytes(&mut conn, header[1] as usize)?;
+      let pass_len = read_bytes(&mut conn, 1)?;
+      let given_pass = read_bytes(&mut conn, pass_len[0] as usize)?;
+      if given_user != user.as_bytes() || given_pass != pass.as_bytes() {
+        return conn.write_all(&[1, 1]);
+      }
+      conn.write_all(&[1, 0])?;
+    }
+  }
+
+  let request = read_bytes(&mut conn, 4)?;
+  let host = match request[3] {
+    1 => read_bytes(&mut conn, 4)?
+      .iter()
+      .map(|b| b.to_string())
+      .collect::<Vec<_>>()
+      .join("."),
+    3 => {
+      let len = read_bytes(&mut conn, 1)?;
+      String::from_utf8(read_bytes(&mut conn, len[0] as usize)?).expect("Non-UTF-8 hostname")
+    }
+    _ => return conn.write_all(&[5, 8, 0, 1, 0, 0, 0, 0, 0, 0]),
+  };
+  let port = read_bytes(&mut conn, 2)?;
+  let port = u16::from_be_bytes([port[0], port[1]]);
+  log.lock().expect("poisoned lock?").push((host.clone(), port));
+
+  let target_host = if host.ends_with(".onion") { "127.0.0.1" } else { &host };
+  let target = match TcpStream::connect((target_host, port)) {
+    Ok(t) => t,
+    Err(_) => return conn.write_all(&[5, 5, 0, 1, 0, 0, 0, 0, 0, 0]),
+  };
+  conn.write_all(&[5, 0, 0, 1, 127, 0, 0, 1, 0, 0])?;
+
+  let (mut up_from, mut up_to) = (conn.try_clone()?, target.try_clone()?);
+  spawn(move || {
+    let _ = io::copy(&mut up_from, &mut up_to);
+    let _ = up_to.shutdown(Shutdown::Write);
+  });
+  let (mut down_from, mut down_to) = (target, conn);
+  let _ = io::copy(&mut down_from, &mut down_to);
+  let _ = down_to.shutdown(Shutdown::Write);
+  Ok(())
+}
+
+#[test]
+fn tcp_through_proxy() {
+  let proxy = StubProxy::start(None);
+  let mut receiver = TCP::new("tcp").expect("Failed to create");
+  receiver
+    .listen(url("tcp:127.0.0.1:18620?mode=framed"))
+    .expect("Failed to listen");
+  let mut sender = TCP::with_config("tcp", proxy.config(None));
+
+  for i in 0..3 {
+    sender
+      .send(url("tcp:127.0.0.1:18620?mode=framed"), vec![i; 100])
+      .expect("Failed to send");
+  }
+  let received = receive_within(&mut receiver, 3);
+  assert_eq!(received, (0..3).map(|i| vec![i; 100]).collect::<Vec<_>>());
+  // the connection is reused, so the proxy only gets asked once
+  assert_eq!(proxy.requested(), vec![("127.0.0.1".to_owned(), 18620)]);
+}
+
+#[test]
+fn onion_resolved_at_proxy() {
+  let proxy = StubProxy::start(None);
+  let mut receiver = TCP::new("tcp").expect("Failed to create");
+  receiver
+    .listen(url("tcp:127.0.0.1:18621?mode=oneshot"))
+    .expect("Failed to listen");
+  let mut sender = TCP::with_config("tcp", proxy.config(None));
+
+  sender
+    .send(
+      url("tcp:mesherexampleaddressxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxx.onion:18621?mode=oneshot"),
+      vec![1, 2, 3],
+    )
+    .expect("Failed to send");
+  assert_eq!(receive_within(&mut receiver, 1), vec![vec![1, 2, 3]]);
+  assert_eq!(
+    proxy.requested(),
+    vec![(
+      "mesherexampleaddressxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxx.onion".to_owned(),
+      18621
+    )]
+  );
+}
+
+#[test]
+fn credentials_checked() {
+  let proxy = StubProxy::start(Some(("mesher", "hunter2")));
+  let mut receiver = TCP::new("tcp").expect("Failed to create");
+  receiver.listen(url("tcp:127.0.0.1:18622")).expect("Failed to listen");
+
+  let mut anonymous = TCP::with_config("tcp", proxy.config(None));
+  match anonymous.send(url("tcp:127.0.0.1:18622"), vec![1]) {
+    Err(fail::MesherFail::SendFailure(_)) => (),
+    other => panic!("Sent without credentials: {:?}", other),
+  }
+  let mut wrong = TCP::with_config("tcp", proxy.config(Some(("mesher", "hunter3"))));
+  match wrong.send(url("tcp:127.0.0.1:18622"), vec![2]) {
+    Err(fail::MesherFail::SendFailure(_)) => (),
+    other => panic!("Sent with the wrong credentials: {:?}", other),
+  }
+  let mut right = TCP::with_config("tcp", proxy.config(Some(("mesher", "hunter2"))));
+  right.send(url("tcp:127.0.0.1:18622"), vec![3]).expect("Failed to send");
+
+  assert_eq!(receive_within(&mut receiver, 1), vec![vec![3]]);
+}
+
+#[test]
+fn proxy_failure_reported() {
+  let proxy = StubProxy::start(None);
+  // nothing is listening on the other side of the proxy
+  let mut sender = TCP::with_config("tcp", proxy.config(None));
+  match sender.send(url("tcp:127.0.0.1:18623"), vec![1]) {
+    Err(fail::MesherFail::SendFailure(_)) => (),
+    other => panic!("Sent to nothing: {:?}", other),
+  }
+}
+
+#[test]
+fn tls_through_proxy() {
+  let proxy = StubProxy::start(None);
+  let mut receiver =
+    TLS::with_config("tls", TlsConfig::self_signed().expect("Failed to generate")).expect("Failed to create");
+  receiver.listen(url("tls:127.0.0.1:18624")).expect("Failed to listen");
+  let pin = receiver.fingerprint();
+  let mut config = TlsConfig::self_signed().expect("Failed to generate");
+  config.tcp = proxy.config(None);
+  let mut sender = TLS::with_config("tls", config).expect("Failed to create");
+
+  sender
+    .send(url(&format!("tls:hidden.onion:18624?pin={}", pin)), vec![4, 5, 6])
+    .expect("Failed to send");
+  assert_eq!(receive_within(&mut receiver, 1), vec![vec![4, 5, 6]]);
+  assert_eq!(proxy.requested(), vec![("hidden.onion".to_owned(), 18624)]);
+}
+
+#[test]
+fn ws_through_proxy() {
+  let proxy = StubProxy::start(None);
+  let mut receiver = WS::new("ws").expect("Failed to create");
+  receiver
+    .listen(url("ws:127.0.0.1:18625/mesher"))
+    .expect("Failed to listen");
+  let mut sender = WS::with_config("ws", proxy.config(None));
+
+  sender
+    .send(url("ws://hidden.onion:18625/mesher"), vec![7, 8, 9])
+    .expect("Failed to send");
+  assert_eq!(receive_within(&mut receiver, 1), vec![vec![7, 8, 9]]);
+  assert_eq!(proxy.requested(), vec![("hidden.onion".to_owned(), 18625)]);
+}
@@ -0,0 +1,254 @@
+use mesher::{
+  prelude::*,
+  test_support::{receive_within, url},
+};
+use mesher_basic::{TcpConfig, TCP};
+
+use std::{
+  io::prelude::*,
+  net::{TcpListener, TcpStream},
+  thread::sleep,
+  time::{Duration, Instant},
+};
+
+fn read_frame(from: &mut impl Read) -> Vec<u8> {
+  let mut len = [0; 4];
+  from.read_exact(&mut len).expect("Failed to read length");
+  let mut blob = vec![0; u32::from_be_bytes(len) as usize];
+  from.read_exact(&mut blob).expect("Failed to read blob");
+  blob
+}
+
+#[test]
+fn reuses_connection() {
+  let listener = TcpListener::bind("localhost:18570").expect("Failed to bind");
+  let mut t = TCP::new("tcp").expect("Failed to create");
+
+  t.send(url("tcp:localhost:18570?mode=framed"), vec![1, 2, 3])
+    .expect("Failed to send");
+  t.send(url("tcp:localhost:18570?mode=framed"), vec![4, 5])
+    .expect("Failed to send");
+
+  let (mut conn, _) = listener.accept().expect("Failed to accept");
+  assert_eq!(read_frame(&mut conn), vec![1, 2, 3]);
+  assert_eq!(read_frame(&mut conn), vec![4, 5]);
+}
+
+#[test]
+fn reconnects_after_close() {
+  let listener = TcpListener::bind("localhost:18571").expect("Failed to bind");
+  let mut t = TCP::new("tcp").expect("Failed to create");
+
+  t.send(url("tcp:localhost:18571?mode=framed"), vec![1])
+    .expect("Failed to send");
+  {
+    let (mut conn, _) = listener.accept().expect("Failed to accept");
+    assert_eq!(read_frame(&mut conn), vec![1]);
+  }
+  sleep(Duration::from_millis(50));
+
+  t.send(url("tcp:localhost:18571?mode=framed"), vec![2])
+    .expect("Failed to send");
+  let (mut conn, _) = listener.accept().expect("Failed to accept");
+  assert_eq!(read_frame(&mut conn), vec![2]);
+}
+
+#[test]
+fn idle_connections_expire() {
+  let listener = TcpListener::bind("localhost:18572").expect("Failed to bind");
+  let mut t = TCP::with_config(
+    "tcp",
+    TcpConfig {
+      idle_timeout: Duration::from_millis(50),
+      ..TcpConfig::default()
+    },
+  );
+
+  t.send(url("tcp:localhost:18572?mode=framed"), vec![1])
+    .expect("Failed to send");
+  sleep(Duration::from_millis(100));
+  t.send(url("tcp:localhost:18572?mode=framed"), vec![2])
+    .expect("Failed to send");
+
+  let (mut first, _) = listener.accept().expect("Failed to accept");
+  assert_eq!(read_frame(&mut first), vec![1]);
+  let mut rest = vec![];
+  first.read_to_end(&mut rest).expect("Failed to read");
+  assert!(rest.is_empty(), "Idle connection should have been closed");
+  let (mut second, _) = listener.accept().expect("Failed to accept");
+  assert_eq!(read_frame(&mut second), vec![2]);
+}
+
+#[test]
+fn framed_round_trip() {
+  let mut sender = TCP::new("tcp").expect("Failed to create");
+  let mut receiver = TCP::new("tcp").expect("Failed to create");
+  receiver
+    .listen(url("tcp:localhost:18573?mode=framed"))
+    .expect("Failed to listen");
+
+  for i in 0..5 {
+    sender
+      .send(url("tcp:localhost:18573?mode=framed"), vec![i; 1000])
+      .expect("Failed to send");
+  }
+  let received = receive_within(&mut receiver, 5);
+  assert_eq!(received, (0..5).map(|i| vec![i; 1000]).collect::<Vec<_>>());
+}
+
+#[test]
+fn oneshot_round_trip() {
+  let mut sender = TCP::new("tcp").expect("Failed to create");
+  let mut receiver = TCP::new("tcp").expect("Failed to create");
+  receiver
+    .listen(url("tcp:localhost:18574?mode=oneshot"))
+    .expect("Failed to listen");
+
+  sender
+    .send(url("tcp:localhost:18574?mode=oneshot"), vec![1, 2, 3])
+    .expect("Failed to send");
+  assert_eq!(receive_within(&mut receiver, 1), vec![vec![1, 2, 3]]);
+}
+
+#[test]
+fn oneshot_by_default() {
+  let listener = TcpListener::bind("localhost:18568").expect("Failed to bind");
+  let mut t = TCP::new("tcp").expect("Failed to create");
+
+  t.send(url("tcp:localhost:18568"), vec![1, 2, 3])
+    .expect("Failed to send");
+  let (mut conn, _) = listener.accept().expect("Failed to accept");
+  let mut blob = vec![];
+  conn.read_to_end(&mut blob).expect("Failed to read");
+  assert_eq!(blob, vec![1, 2, 3]);
+}
+
+#[test]
+fn idle_connections_closed_on_receive() {
+  let listener = TcpListener::bind("localhost:18569").expect("Failed to bind");
+  let mut t = TCP::with_config(
+    "tcp",
+    TcpConfig {
+      idle_timeout: Duration::from_millis(50),
+      ..TcpConfig::default()
+    },
+  );
+
+  t.send(url("tcp:localhost:18569?mode=framed"), vec![1])
+    .expect("Failed to send");
+  sleep(Duration::from_millis(100));
+  t.receive().expect("Failed to receive");
+
+  let (mut conn, _) = listener.accept().expect("Failed to accept");
+  assert_eq!(read_frame(&mut conn), vec![1]);
+  let mut rest = vec![];
+  conn.read_to_end(&mut rest).expect("Failed to read");
+  assert!(rest.is_empty(), "Idle connection should have been closed");
+}
+
+#[test]
+fn unknown_mode() {
+  let mut t = TCP::new("tcp").expect("Failed to create");
+  match t.send(url("tcp:localhost:18575?mode=carrier-pigeon"), vec![]) {
+    Err(fail::MesherFail::InvalidURL(_)) => (),
+    _ => panic!("Should have rejected mode"),
+  }
+}
+
+fn listening(port: u16, config: TcpConfig) -> TCP {
+  let mut t = TCP::with_config("tcp", config);
+  t.listen(url(&format!("tcp:localhost:{}?mode=framed", port)))
+    .expect("Failed to listen");
+  t
+}
+
+/// Whether the listener has closed the connection, rather than just leaving it waiting.
+fn closed_by_listener(conn: &mut TcpStream) -> bool {
+  conn
+    .set_read_timeout(Some(Duration::from_secs(2)))
+    .expect("Failed to set timeout");
+  match conn.read(&mut [0]) {
+    Ok(n) => n == 0,
+    Err(e) => e.kind() == std::io::ErrorKind::ConnectionReset,
+  }
+}
+
+#[test]
+fn rejects_oversized_frames() {
+  let mut receiver = listening(
+    18576,
+    TcpConfig {
+      max_packet_size: 100,
+      ..TcpConfig::default()
+    },
+  );
+  let mut conn = TcpStream::connect("localhost:18576").expect("Failed to connect");
+  conn.write_all(&101u32.to_be_bytes()).expect("Failed to write");
+  conn.write_all(&[0; 101]).expect("Failed to write");
+  assert!(closed_by_listener(&mut conn));
+  assert!(receiver.receive().expect("Failed to receive").is_empty());
+}
+
+#[test]
+fn slow_clients_time_out_without_blocking() {
+  let mut receiver = listening(
+    18577,
+    TcpConfig {
+      read_timeout: Duration::from_millis(200),
+      ..TcpConfig::default()
+    },
+  );
+
+  // starts a frame, then stalls
+  let mut slow = TcpStream::connect("localhost:18577").expect("Failed to connect");
+  slow.write_all(&[0, 0, 0, 10, 1]).expect("Failed to write");
+
+  let mut sender = TCP::new("tcp").expect("Failed to create");
+  sender
+    .send(url("tcp:localhost:18577?mode=framed"), vec![1, 2, 3])
+    .expect("Failed to send");
+  assert_eq!(receive_within(&mut receiver, 1), vec![vec![1, 2, 3]]);
+
+  let start = Instant::now();
+  assert!(closed_by_listener(&mut slow));
+  assert!(start.elapsed() < Duration::from_secs(2));
+}
+
+#[test]
+fn limits_concurrent_connections() {
+  let _receiver = listening(
+    18578,
+    TcpConfig {
+      max_connections: 2,
+      ..TcpConfig::default()
+    },
+  );
+  let mut conns: Vec<_> = (0..3)
+    .map(|_| {
+      let conn = TcpStream::connect("localhost:18578").expect("Failed to connect");
+      sleep(Duration::from_millis(50));
+      conn
+    })
+    .collect();
+  assert!(closed_by_listener(&mut conns[2]));
+  conns[0].set_nonblocking(true).expect("Failed to set nonblocking");
+  let still_open = matches!(conns[0].read(&mut [0]), Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock);
+  assert!(still_open);
+}
+
+#[test]
+fn limits_connection_rate() {
+  let _receiver = listening(
+    18579,
+    TcpConfig {
+      max_connections_per_ip: 2,
+      ..TcpConfig::default()
+    },
+  );
+  let mut conns: Vec<_> = (0..3)
+    .map(|_| TcpStream::connect("localhost:18579").expect("Failed to connect"))
+    .collect();
+  // closing the first two doesn't free up room, since the limit is on the rate, not the number
+  conns.drain(..2);
+  assert!(closed_by_listener(&mut conns[0]));
+}
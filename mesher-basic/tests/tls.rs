@@ -0,0 +1,131 @@
+use mesher::{
+  prelude::*,
+  test_support::{receive_within, url},
+};
+use mesher_basic::{TlsConfig, TLS};
+
+use std::{io::prelude::*, net::TcpListener, thread::sleep, time::Duration};
+
+fn listening(port: u16) -> TLS {
+  let mut t = TLS::new("tls").expect("Failed to create");
+  t.listen(url(&format!("tls:localhost:{}", port)))
+    .expect("Failed to listen");
+  t
+}
+
+#[test]
+fn self_signed_round_trip() {
+  let mut receiver = listening(18580);
+  let mut sender = TLS::new("tls").expect("Failed to create");
+  let path = format!("tls:localhost:18580?pin={}", receiver.fingerprint());
+
+  for i in 0..5 {
+    sender.send(url(&path), vec![i; 1000]).expect("Failed to send");
+  }
+  let received = receive_within(&mut receiver, 5);
+  assert_eq!(received, (0..5).map(|i| vec![i; 1000]).collect::<Vec<_>>());
+}
+
+#[test]
+fn url_pin() {
+  let mut receiver = listening(18581);
+  let mut sender = TLS::new("tls").expect("Failed to create");
+
+  let pinned = format!("tls:localhost:18581?pin={}", receiver.fingerprint().to_uppercase());
+  sender.send(url(&pinned), vec![1, 2, 3]).expect("Failed to send");
+  assert_eq!(receive_within(&mut receiver, 1), vec![vec![1, 2, 3]]);
+
+  let wrong = format!("tls:localhost:18581?pin={}", sender.fingerprint());
+  match sender.send(url(&wrong), vec![4]) {
+    Err(fail::MesherFail::SendFailure(_)) => (),
+    _ => panic!("Should have rejected certificate"),
+  }
+  sleep(Duration::from_millis(100));
+  assert!(receiver.receive().expect("Failed to receive").is_empty());
+}
+
+#[test]
+fn config_pins() {
+  let mut receiver = listening(18582);
+
+  let mut untrusting = TlsConfig::self_signed().expect("Failed to generate certificate");
+  untrusting.pins.push("00".repeat(32));
+  let mut untrusting = TLS::with_config("tls", untrusting).expect("Failed to create");
+  assert!(untrusting.send(url("tls:localhost:18582"), vec![1]).is_err());
+
+  let mut trusting = TlsConfig::self_signed().expect("Failed to generate certificate");
+  trusting.pins.push(receiver.fingerprint());
+  let mut trusting = TLS::with_config("tls", trusting).expect("Failed to create");
+  trusting
+    .send(url("tls:localhost:18582"), vec![2])
+    .expect("Failed to send");
+
+  assert_eq!(receive_within(&mut receiver, 1), vec![vec![2]]);
+}
+
+#[test]
+fn unpinned_needs_opt_in() {
+  let mut receiver = listening(18585);
+
+  let mut sender = TLS::new("tls").expect("Failed to create");
+  match sender.send(url("tls:localhost:18585"), vec![1]) {
+    Err(fail::MesherFail::SendFailure(_)) => (),
+    other => panic!("Sent without checking the certificate: {:?}", other),
+  }
+
+  let mut trusting = TlsConfig::self_signed().expect("Failed to generate certificate");
+  trusting.accept_any_certificate = true;
+  let mut trusting = TLS::with_config("tls", trusting).expect("Failed to create");
+  trusting
+    .send(url("tls:localhost:18585"), vec![2])
+    .expect("Failed to send");
+
+  assert_eq!(receive_within(&mut receiver, 1), vec![vec![2]]);
+}
+
+#[test]
+fn hides_framing() {
+  let listener = TcpListener::bind("localhost:18583").expect("Failed to bind");
+  // nothing will answer the handshake, so this fails, but not before sending the client hello
+  std::thread::spawn(|| {
+    let mut config = TlsConfig::self_signed().expect("Failed to generate certificate");
+    config.accept_any_certificate = true;
+    let mut sender = TLS::with_config("tls", config).expect("Failed to create");
+    let _ = sender.send(url("tls:localhost:18583"), vec![0xAB; 64]);
+  });
+
+  let (mut conn, _) = listener.accept().expect("Failed to accept");
+  let mut hello = [0; 5];
+  conn.read_exact(&mut hello).expect("Failed to read");
+  // a TLS handshake record, rather than a frame length
+  assert_eq!(hello[0], 0x16);
+  assert_ne!(&hello[..4], &64u32.to_be_bytes());
+}
+
+#[test]
+fn through_mesher() {
+  let dest_config = TlsConfig::self_signed().expect("Failed to generate certificate");
+  let pinned = format!("tls:localhost:18584?pin={}", dest_config.identity.fingerprint());
+  let (k_dest, s_dest) = encrypt::gen_keypair();
+  let mut m_dest = Mesher::unsigned(vec![s_dest]);
+  m_dest.add_transport_instance("tls", TLS::with_config("tls", dest_config).expect("Failed to create"));
+  m_dest.listen_on("tls:localhost:18584").expect("Failed to listen");
+
+  let (k_source, s_source) = encrypt::gen_keypair();
+  let mut m_source = Mesher::unsigned(vec![s_source]);
+  m_source.add_transport::<TLS>("tls").expect("Failed to add transport");
+
+  let mut packet = Packet::unsigned();
+  packet.add_hop(pinned, &k_source);
+  packet.add_message(&[1, 2, 3], &k_dest);
+  m_source.launch(packet).expect("Failed to send");
+
+  sleep(Duration::from_millis(200));
+  let received = m_dest
+    .receive()
+    .expect("Failed to receive")
+    .into_iter()
+    .map(|m| m.into_contents())
+    .collect::<Vec<_>>();
+  assert_eq!(received, vec![vec![1, 2, 3]]);
+}
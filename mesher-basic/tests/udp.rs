@@ -0,0 +1,182 @@
+use mesher::{
+  prelude::*,
+  test_support::{receive_within, url},
+};
+use mesher_basic::{UdpConfig, UDP};
+
+use std::{net::UdpSocket, thread::sleep, time::Duration};
+
+fn small_mtu() -> UdpConfig {
+  UdpConfig {
+    mtu: 100,
+    ..UdpConfig::default()
+  }
+}
+
+#[test]
+fn whole_round_trip() {
+  let mut receiver = UDP::new("udp").expect("Failed to create");
+  receiver.listen(url("udp:localhost:18590")).expect("Failed to listen");
+  let mut sender = UDP::new("udp").expect("Failed to create");
+
+  sender
+    .send(url("udp:localhost:18590"), vec![1, 2, 3])
+    .expect("Failed to send");
+  assert_eq!(receive_within(&mut receiver, 1), vec![vec![1, 2, 3]]);
+}
+
+#[test]
+fn respects_mtu() {
+  let mut sender = UDP::with_config("udp", small_mtu());
+  assert_eq!(sender.mtu(), 100);
+  sender
+    .send(url("udp:localhost:18591"), vec![0; 100])
+    .expect("Failed to send");
+  match sender.send(url("udp:localhost:18591"), vec![0; 101]) {
+    Err(fail::MesherFail::SendFailure(_)) => (),
+    _ => panic!("Should have refused to send"),
+  }
+}
+
+#[test]
+fn fragment_round_trip() {
+  let mut receiver = UDP::new("udp").expect("Failed to create");
+  receiver
+    .listen(url("udp:localhost:18592?mode=fragment"))
+    .expect("Failed to listen");
+  let mut sender = UDP::with_config("udp", small_mtu());
+
+  let big: Vec<u8> = (0..2000).map(|i| i as u8).collect();
+  sender
+    .send(url("udp:localhost:18592?mode=fragment"), big.clone())
+    .expect("Failed to send");
+  sender
+    .send(url("udp:localhost:18592?mode=fragment"), vec![])
+    .expect("Failed to send");
+  assert_eq!(receive_within(&mut receiver, 2), vec![big, vec![]]);
+}
+
+fn fragment(id: u32, index: u16, count: u16, body: &[u8]) -> Vec<u8> {
+  let mut datagram = vec![];
+  datagram.extend_from_slice(&id.to_be_bytes());
+  datagram.extend_from_slice(&index.to_be_bytes());
+  datagram.extend_from_slice(&count.to_be_bytes());
+  datagram.extend_from_slice(body);
+  datagram
+}
+
+#[test]
+fn reassembles_out_of_order() {
+  let mut receiver = UDP::new("udp").expect("Failed to create");
+  receiver
+    .listen(url("udp:localhost:18593?mode=fragment"))
+    .expect("Failed to listen");
+
+  let raw = UdpSocket::bind("localhost:0").expect("Failed to bind");
+  for datagram in &[
+    fragment(7, 2, 3, &[5]),
+    fragment(7, 0, 3, &[1, 2]),
+    // a duplicate is ignored
+    fragment(7, 0, 3, &[9, 9]),
+    fragment(7, 1, 3, &[3, 4]),
+  ] {
+    raw.send_to(datagram, "localhost:18593").expect("Failed to send");
+  }
+  assert_eq!(receive_within(&mut receiver, 1), vec![vec![1, 2, 3, 4, 5]]);
+}
+
+#[test]
+fn drops_oversized_reassembly() {
+  let mut receiver = UDP::with_config(
+    "udp",
+    UdpConfig {
+      max_blob_size: 3,
+      ..UdpConfig::default()
+    },
+  );
+  receiver
+    .listen(url("udp:localhost:18594?mode=fragment"))
+    .expect("Failed to listen");
+
+  let raw = UdpSocket::bind("localhost:0").expect("Failed to bind");
+  raw
+    .send_to(&fragment(1, 0, 2, &[1, 2]), "localhost:18594")
+    .expect("Failed to send");
+  raw
+    .send_to(&fragment(1, 1, 2, &[3, 4]), "localhost:18594")
+    .expect("Failed to send");
+  raw
+    .send_to(&fragment(2, 0, 1, &[5]), "localhost:18594")
+    .expect("Failed to send");
+  assert_eq!(receive_within(&mut receiver, 1), vec![vec![5]]);
+  sleep(Duration::from_millis(100));
+  assert!(receiver.receive().expect("Failed to receive").is_empty());
+}
+
+#[test]
+fn ignores_too_many_fragments() {
+  let mut receiver = UDP::with_config(
+    "udp",
+    UdpConfig {
+      mtu: 10,
+      max_blob_size: 10,
+      ..UdpConfig::default()
+    },
+  );
+  receiver
+    .listen(url("udp:localhost:18596?mode=fragment"))
+    .expect("Failed to listen");
+
+  // at 2 bytes a fragment, a 10 byte blob never needs more than 6
+  let raw = UdpSocket::bind("localhost:0").expect("Failed to bind");
+  for index in 0..7 {
+    raw
+      .send_to(&fragment(1, index, 7, &[index as u8]), "localhost:18596")
+      .expect("Failed to send");
+  }
+  raw
+    .send_to(&fragment(2, 0, 1, &[9]), "localhost:18596")
+    .expect("Failed to send");
+  assert_eq!(receive_within(&mut receiver, 1), vec![vec![9]]);
+  sleep(Duration::from_millis(100));
+  assert!(receiver.receive().expect("Failed to receive").is_empty());
+}
+
+#[test]
+fn limits_reassembly_memory() {
+  let mut receiver = UDP::with_config(
+    "udp",
+    UdpConfig {
+      // just enough for two blobs of two one-byte fragments
+      max_reassembly_memory: 2 * (2 * std::mem::size_of::<Option<Vec<u8>>>() + 2),
+      ..UdpConfig::default()
+    },
+  );
+  receiver
+    .listen(url("udp:localhost:18597?mode=fragment"))
+    .expect("Failed to listen");
+
+  let raw = UdpSocket::bind("localhost:0").expect("Failed to bind");
+  for datagram in &[
+    fragment(1, 0, 2, &[1]),
+    fragment(2, 0, 2, &[3]),
+    // there's no room for a third
+    fragment(3, 0, 2, &[5]),
+    fragment(3, 1, 2, &[6]),
+    fragment(1, 1, 2, &[2]),
+  ] {
+    raw.send_to(datagram, "localhost:18597").expect("Failed to send");
+  }
+  assert_eq!(receive_within(&mut receiver, 1), vec![vec![1, 2]]);
+  sleep(Duration::from_millis(100));
+  assert!(receiver.receive().expect("Failed to receive").is_empty());
+}
+
+#[test]
+fn unknown_mode() {
+  let mut t = UDP::new("udp").expect("Failed to create");
+  match t.listen(url("udp:localhost:18595?mode=smoke-signals")) {
+    Err(fail::MesherFail::InvalidURL(_)) => (),
+    _ => panic!("Should have rejected mode"),
+  }
+}
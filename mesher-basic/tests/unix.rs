@@ -0,0 +1,160 @@
+#![cfg(unix)]
+
+use mesher::{
+  prelude::*,
+  test_support::{receive_within, url},
+};
+use mesher_basic::{Unix, UnixConfig};
+
+use std::{
+  fs,
+  io::prelude::*,
+  os::unix::{fs::PermissionsExt, net::UnixListener, net::UnixStream},
+  path::{Path, PathBuf},
+  thread::sleep,
+  time::Duration,
+};
+
+/// A fresh socket path for the test, which nothing is using yet.
+fn socket_path(name: &str) -> PathBuf {
+  let path = std::env::temp_dir().join(format!("mesher-unix-{}-{}.sock", name, std::process::id()));
+  let _ = fs::remove_file(&path);
+  path
+}
+
+fn socket_url(path: &Path) -> MesherUrl {
+  url(&format!("unix:{}", path.display()))
+}
+
+#[test]
+fn round_trip() {
+  let path = socket_path("round-trip");
+  let mut receiver = Unix::new("unix").expect("Failed to create");
+  receiver.listen(socket_url(&path)).expect("Failed to listen");
+  let mut sender = Unix::new("unix").expect("Failed to create");
+
+  for i in 0..5 {
+    sender.send(socket_url(&path), vec![i; 1000]).expect("Failed to send");
+  }
+  let received = receive_within(&mut receiver, 5);
+  assert_eq!(received, (0..5).map(|i| vec![i; 1000]).collect::<Vec<_>>());
+}
+
+#[test]
+fn uses_tcp_framing() {
+  let path = socket_path("framing");
+  let mut receiver = Unix::new("unix").expect("Failed to create");
+  receiver.listen(socket_url(&path)).expect("Failed to listen");
+
+  let mut conn = UnixStream::connect(&path).expect("Failed to connect");
+  conn
+    .write_all(&[0, 0, 0, 2, 7, 8, 0, 0, 0, 1, 9])
+    .expect("Failed to write");
+  assert_eq!(receive_within(&mut receiver, 2), vec![vec![7, 8], vec![9]]);
+}
+
+#[test]
+fn idle_connections_closed_on_receive() {
+  let path = socket_path("idle");
+  let listener = UnixListener::bind(&path).expect("Failed to bind");
+  let mut t = Unix::with_config(
+    "unix",
+    UnixConfig {
+      idle_timeout: Duration::from_millis(50),
+      ..UnixConfig::default()
+    },
+  );
+
+  t.send(socket_url(&path), vec![1]).expect("Failed to send");
+  sleep(Duration::from_millis(100));
+  t.receive().expect("Failed to receive");
+
+  let (mut conn, _) = listener.accept().expect("Failed to accept");
+  let mut rest = vec![];
+  conn.read_to_end(&mut rest).expect("Failed to read");
+  assert_eq!(rest, vec![0, 0, 0, 1, 1], "Idle connection should have been closed");
+}
+
+#[test]
+fn replaces_stale_socket() {
+  let path = socket_path("stale");
+  // dropping a std listener leaves its socket file behind
+  drop(UnixListener::bind(&path).expect("Failed to bind"));
+  assert!(path.exists());
+
+  let mut receiver = Unix::new("unix").expect("Failed to create");
+  receiver.listen(socket_url(&path)).expect("Failed to listen");
+  let mut sender = Unix::new("unix").expect("Failed to create");
+  sender.send(socket_url(&path), vec![1]).expect("Failed to send");
+  assert_eq!(receive_within(&mut receiver, 1), vec![vec![1]]);
+}
+
+#[test]
+fn leaves_live_sockets_and_files_alone() {
+  let live = socket_path("live");
+  let _listener = UnixListener::bind(&live).expect("Failed to bind");
+  let file = socket_path("file");
+  fs::write(&file, b"important").expect("Failed to write");
+
+  let mut t = Unix::new("unix").expect("Failed to create");
+  assert!(t.listen(socket_url(&live)).is_err());
+  assert!(t.listen(socket_url(&file)).is_err());
+  assert_eq!(fs::read(&file).expect("Failed to read"), b"important");
+  fs::remove_file(&file).expect("Failed to clean up");
+}
+
+#[test]
+fn sets_permissions() {
+  let path = socket_path("permissions");
+  let mut t = Unix::new("unix").expect("Failed to create");
+  t.listen(url(&format!("unix:{}?perm=0600", path.display())))
+    .expect("Failed to listen");
+  let mode = fs::metadata(&path).expect("Failed to stat").permissions().mode();
+  assert_eq!(mode & 0o7777, 0o600);
+
+  match t.listen(url(&format!("unix:{}?perm=rw-rw----", path.display()))) {
+    Err(fail::MesherFail::InvalidURL(_)) => (),
+    _ => panic!("Should have rejected permissions"),
+  }
+}
+
+#[test]
+fn cleans_up_on_drop() {
+  let path = socket_path("cleanup");
+  {
+    let mut t = Unix::new("unix").expect("Failed to create");
+    t.listen(socket_url(&path)).expect("Failed to listen");
+    assert!(path.exists());
+  }
+  assert!(!path.exists());
+}
+
+#[test]
+fn rejects_oversized_frames() {
+  let path = socket_path("oversized");
+  let mut receiver = Unix::with_config(
+    "unix",
+    UnixConfig {
+      max_packet_size: 100,
+      ..UnixConfig::default()
+    },
+  );
+  receiver.listen(socket_url(&path)).expect("Failed to listen");
+
+  let mut conn = UnixStream::connect(&path).expect("Failed to connect");
+  conn.write_all(&101u32.to_be_bytes()).expect("Failed to write");
+  conn
+    .set_read_timeout(Some(Duration::from_secs(2)))
+    .expect("Failed to set timeout");
+  assert_eq!(conn.read(&mut [0]).expect("Failed to read"), 0);
+  assert!(receiver.receive().expect("Failed to receive").is_empty());
+}
+
+#[test]
+fn needs_absolute_path() {
+  let mut t = Unix::new("unix").expect("Failed to create");
+  match t.send(url("unix:relative.sock"), vec![]) {
+    Err(fail::MesherFail::InvalidURL(_)) => (),
+    _ => panic!("Should have rejected path"),
+  }
+}
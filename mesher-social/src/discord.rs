@@ -0,0 +1,415 @@
+use mesher::prelude::*;
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde_json::{json, Value};
+use std::{
+  collections::HashMap,
+  io::Read,
+  thread::sleep,
+  time::{Duration, Instant},
+};
+
+/// The longest a message's text can be.
+const MAX_CONTENT: usize = 2000;
+
+/// Starts every message carrying a blob, so everything else said in a channel can be ignored.
+/// Blobs too large to fit in the text of a message are sent as an attachment, with just this as the text.
+const MARKER: &str = "mesher";
+
+/// The name given to attachments carrying blobs.
+const ATTACHMENT: &str = "mesher.bin";
+
+/// The most messages Discord will return from a channel's history at once.
+const PAGE: usize = 100;
+
+fn message_id(message: &Value) -> u64 {
+  message["id"]
+    .as_str()
+    .and_then(|id| id.parse::<u64>().ok())
+    .unwrap_or(0)
+}
+
+/// Where a path says to send blobs: the channel, and optionally a webhook posting to it.
+struct Target {
+  channel: u64,
+  webhook: Option<(u64, String)>,
+}
+
+fn target_from_url(path: &MesherUrl) -> fail::Result<Target> {
+  let invalid = || {
+    fail::MesherFail::InvalidURL(format!(
+      "Discord paths look like discord:<channel ID> or discord:<channel ID>/<webhook ID>/<webhook token>, not {}",
+      path
+    ))
+  };
+  let channel = path.authority().parse::<u64>().map_err(|_| invalid())?;
+  let webhook = match path.path() {
+    "" => None,
+    webhook => {
+      let (id, token) = webhook
+        .strip_prefix('/')
+        .unwrap_or_default()
+        .split_once('/')
+        .ok_or_else(invalid)?;
+      let id = id.parse::<u64>().map_err(|_| invalid())?;
+      let valid_token = !token.is_empty()
+        && token
+          .bytes()
+          .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_' || b == b'.');
+      if !valid_token {
+        return Err(invalid());
+      }
+      Some((id, token.to_owned()))
+    }
+  };
+  Ok(Target { channel, webhook })
+}
+
+/// What's sent in a request to post a message.
+enum Body {
+  Empty,
+  Json(Value),
+  /// A `multipart/form-data` body with the given boundary.
+  Multipart(String, Vec<u8>),
+}
+
+/// Builds a message carrying the blob, as an attachment if it's too large to fit in the text.
+fn message_body(blob: &[u8]) -> Body {
+  let encoded = STANDARD.encode(blob);
+  if MARKER.len() + 1 + encoded.len() <= MAX_CONTENT {
+    return Body::Json(json!({ "content": format!("{} {}", MARKER, encoded) }));
+  }
+  let payload = json!({
+    "content": MARKER,
+    "attachments": [{ "id": 0, "filename": ATTACHMENT }],
+  });
+  let boundary = format!("mesher{:016x}{:016x}", rand::random::<u64>(), rand::random::<u64>());
+  let mut body = vec![];
+  body.extend_from_slice(
+    format!(
+      "--{b}\r\nContent-Disposition: form-data; name=\"payload_json\"\r\nContent-Type: application/json\r\n\r\n{p}\r\n\
+       --{b}\r\nContent-Disposition: form-data; name=\"files[0]\"; filename=\"{f}\"\r\nContent-Type: application/octet-stream\r\n\r\n",
+      b = boundary,
+      p = payload,
+      f = ATTACHMENT,
+    )
+    .as_bytes(),
+  );
+  body.extend_from_slice(blob);
+  body.extend_from_slice(format!("\r\n--{}--\r\n", boundary).as_bytes());
+  Body::Multipart(boundary, body)
+}
+
+/// When each of Discord's rate limits will next let a request through.
+#[derive(Default)]
+struct RateLimits {
+  routes: HashMap<String, Instant>,
+  global: Option<Instant>,
+}
+
+impl RateLimits {
+  /// How long until a request can be made on the route.
+  fn wait(&self, route: &str) -> Duration {
+    let now = Instant::now();
+    let until = |i: Option<&Instant>| i.map_or(Duration::from_secs(0), |i| i.saturating_duration_since(now));
+    until(self.routes.get(route)).max(until(self.global.as_ref()))
+  }
+
+  /// Notes down how long to wait, if the response says the route's been used up.
+  fn update(&mut self, route: &str, response: &ureq::Response) {
+    if response.header("X-RateLimit-Remaining") != Some("0") {
+      self.routes.remove(route);
+      return;
+    }
+    if let Some(reset) = response
+      .header("X-RateLimit-Reset-After")
+      .and_then(|r| r.parse::<f64>().ok())
+    {
+      self.limit(route, reset, false);
+    }
+  }
+
+  fn limit(&mut self, route: &str, seconds: f64, global: bool) {
+    let until = Instant::now() + Duration::from_secs_f64(seconds.clamp(0.0, 1e6));
+    if global {
+      self.global = Some(until);
+    } else {
+      self.routes.insert(route.to_owned(), until);
+    }
+  }
+}
+
+/// Settings for a [`Discord`](struct.Discord.html) transport which apply to every channel it uses.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DiscordConfig {
+  /// The token of the bot to read and post to channels as.
+  /// Without one, blobs can still be sent through webhooks, but channels can't be listened on.
+  pub token: Option<String>,
+  /// Where Discord's API is.
+  /// This only needs changing to go through a proxy, or to test against something that isn't Discord.
+  pub api_base: String,
+  /// How long a request can take before it's given up on.
+  pub timeout: Duration,
+  /// How often channels are checked for new messages.
+  /// Calling `receive` more often than this returns nothing, rather than using up the rate limits.
+  pub poll_interval: Duration,
+  /// The largest blob that will be sent or received.
+  /// Discord limits how large attachments can be, depending on the server, so this shouldn't be above the limit for the servers used.
+  pub max_packet_size: usize,
+  /// The longest to wait for a rate limit to run out before making a request.
+  /// If it'd take longer than this, the request fails instead, rather than holding everything else up.
+  pub max_rate_limit_wait: Duration,
+}
+
+impl Default for DiscordConfig {
+  fn default() -> DiscordConfig {
+    DiscordConfig {
+      token: None,
+      api_base: "https://discord.com/api/v10".to_owned(),
+      timeout: Duration::from_secs(30),
+      poll_interval: Duration::from_secs(10),
+      max_packet_size: 8 * 1024 * 1024,
+      max_rate_limit_wait: Duration::from_secs(5),
+    }
+  }
+}
+
+/// Sends and receives blobs through Discord channels, e.g. `discord:123456789012345678`.
+///
+/// Blobs are base64-encoded and posted as messages starting with `mesher`, or as an attachment to one if they're too large to fit in the text.
+/// Anything else said in the channel is ignored, so it can be shared with people.
+///
+/// Posting and reading channels is done as a bot, with the [`DiscordConfig::token`](struct.DiscordConfig.html#structfield.token) it's configured with.
+/// The bot needs permission to view the channels, read their history, send messages, and attach files, and needs the Message Content intent turned on to see what others post.
+/// Blobs can also be sent with no bot at all, through a webhook posting to the channel, by putting it in the path, e.g. `discord:123456789012345678/876543210987654321/webhook-token`.
+/// A node can listen on that path to have blobs sent to it through the webhook, so that whoever's sending doesn't need a bot of their own.
+/// Anyone with the path can post to the channel through it, though, so it should only be shared as widely as that's acceptable.
+///
+/// Listening on a channel receives every blob posted there from then on, including this transport's own.
+/// Channels are only checked every [`DiscordConfig::poll_interval`](struct.DiscordConfig.html#structfield.poll_interval), and one that can't be read is tried again next time.
+/// Discord's rate limits are kept to, waiting out short ones, and failing requests rather than wait out long ones.
+pub struct Discord {
+  agent: ureq::Agent,
+  config: DiscordConfig,
+  limits: RateLimits,
+  /// Each channel being listened on, and the ID of the last message read from it.
+  channels: Vec<(u64, u64)>,
+  last_poll: Option<Instant>,
+}
+
+impl Discord {
+  /// Creates a Discord transport that authenticates with `config.token`.
+  pub fn with_config(_scheme: &str, config: DiscordConfig) -> Discord {
+    Discord {
+      agent: ureq::AgentBuilder::new()
+        .timeout(config.timeout)
+        .user_agent("DiscordBot (https://github.com/nic-hartley/mesher, 0.0.1)")
+        .build(),
+      config,
+      limits: RateLimits::default(),
+      channels: vec![],
+      last_poll: None,
+    }
+  }
+
+  /// Makes a request to the API, keeping to the rate limits for its route, and retrying once if it's rate limited anyway.
+  /// Webhooks carry their own token in the URL, so requests to them aren't made as the bot.
+  fn request(
+    &mut self,
+    method: &str,
+    url: &str,
+    route: &str,
+    as_bot: bool,
+    body: &Body,
+  ) -> Result<ureq::Response, String> {
+    for _ in 0..2 {
+      let wait = self.limits.wait(route);
+      if wait > self.config.max_rate_limit_wait {
+        return Err(format!("rate limited for another {:?}", wait));
+      }
+      sleep(wait);
+
+      let mut request = self.agent.request(method, url);
+      if let (Some(token), true) = (&self.config.token, as_bot) {
+        request = request.set("Authorization", &format!("Bot {}", token));
+      }
+      let result = match body {
+        Body::Empty => request.call(),
+        Body::Json(json) => request
+          .set("Content-Type", "application/json")
+          .send_string(&json.to_string()),
+        Body::Multipart(boundary, bytes) => request
+          .set("Content-Type", &format!("multipart/form-data; boundary={}", boundary))
+          .send_bytes(bytes),
+      };
+      match result {
+        Ok(response) => {
+          self.limits.update(route, &response);
+          return Ok(response);
+        }
+        Err(ureq::Error::Status(429, response)) => {
+          let global = response.header("X-RateLimit-Global").is_some();
+          let header = response.header("Retry-After").and_then(|r| r.parse::<f64>().ok());
+          let body = response
+            .into_string()
+            .ok()
+            .and_then(|b| serde_json::from_str::<Value>(&b).ok());
+          let retry_after = body
+            .as_ref()
+            .and_then(|b| b["retry_after"].as_f64())
+            .or(header)
+            .unwrap_or(1.0);
+          let global = global || body.as_ref().and_then(|b| b["global"].as_bool()) == Some(true);
+          self.limits.limit(route, retry_after, global);
+        }
+        Err(e) => return Err(e.to_string()),
+      }
+    }
+    Err("still rate limited after waiting".to_owned())
+  }
+
+  /// Gets the blob a message is carrying, if it is carrying one.
+  /// Fails if it has one in an attachment that couldn't be downloaded, but might be later.
+  fn blob_from(&self, message: &Value) -> Result<Option<Vec<u8>>, String> {
+    let content = message["content"].as_str().unwrap_or_default();
+    if let Some(encoded) = content.strip_prefix(MARKER).and_then(|c| c.strip_prefix(' ')) {
+      let blob = STANDARD.decode(encoded.trim()).ok();
+      return Ok(blob.filter(|b| b.len() <= self.config.max_packet_size));
+    }
+    if content != MARKER {
+      return Ok(None);
+    }
+    let attachment = match message["attachments"].as_array().and_then(|a| a.first()) {
+      Some(attachment) if attachment["filename"] == ATTACHMENT => attachment,
+      _ => return Ok(None),
+    };
+    let (url, size) = match (attachment["url"].as_str(), attachment["size"].as_u64()) {
+      (Some(url), Some(size)) if size <= self.config.max_packet_size as u64 => (url, size),
+      _ => return Ok(None),
+    };
+    let mut blob = Vec::with_capacity(size as usize);
+    // attachments come from Discord's CDN, which has no rate limits, and wants no token
+    let response = match self.agent.get(url).call() {
+      Ok(response) => response,
+      // the attachment's gone, or was never there, so there's no point trying again
+      Err(ureq::Error::Status(code, _)) if code < 500 => return Ok(None),
+      Err(e) => return Err(e.to_string()),
+    };
+    response
+      .into_reader()
+      .take(self.config.max_packet_size as u64 + 1)
+      .read_to_end(&mut blob)
+      .map_err(|e| e.to_string())?;
+    Ok(Some(blob).filter(|b| b.len() <= self.config.max_packet_size))
+  }
+
+  /// Gets messages from the channel's history, newest first: the oldest ones after the given ID, or the newest ones if there isn't one.
+  fn history(&mut self, channel: u64, after: Option<u64>, limit: usize) -> Result<Vec<Value>, String> {
+    let mut url = format!("{}/channels/{}/messages?limit={}", self.config.api_base, channel, limit);
+    if let Some(after) = after {
+      url.push_str(&format!("&after={}", after));
+    }
+    let response = self.request("GET", &url, &format!("history/{}", channel), true, &Body::Empty)?;
+    let page = response.into_string().map_err(|e| e.to_string())?;
+    match serde_json::from_str::<Value>(&page) {
+      Ok(Value::Array(messages)) => Ok(messages),
+      _ => Err("channel history wasn't a list of messages".to_owned()),
+    }
+  }
+
+  /// Gets every blob posted to the channel since it was last checked.
+  fn collect(&mut self, index: usize, blobs: &mut Vec<Vec<u8>>) -> Result<(), String> {
+    loop {
+      let (channel, after) = self.channels[index];
+      let mut messages = self.history(channel, Some(after), PAGE)?;
+      let count = messages.len();
+      // they come newest first, but should be handed over in the order they were sent
+      messages.sort_by_key(message_id);
+      for message in messages {
+        // a message whose attachment couldn't be downloaded this time is tried again next time, along with everything after it
+        blobs.extend(self.blob_from(&message)?);
+        self.channels[index].1 = self.channels[index].1.max(message_id(&message));
+      }
+      if count < PAGE {
+        return Ok(());
+      }
+    }
+  }
+}
+
+impl Transport for Discord {
+  fn new(scheme: &str) -> fail::Result<Self> {
+    Ok(Discord::with_config(scheme, DiscordConfig::default()))
+  }
+
+  fn send(&mut self, path: MesherUrl, blob: Vec<u8>) -> fail::Result<()> {
+    let target = target_from_url(&path)?;
+    if blob.len() > self.config.max_packet_size {
+      return Err(fail::MesherFail::SendFailure(format!(
+        "Blob of {} bytes is larger than the {} allowed",
+        blob.len(),
+        self.config.max_packet_size
+      )));
+    }
+    let (url, route, as_bot) = match &target.webhook {
+      Some((id, token)) => (
+        format!("{}/webhooks/{}/{}", self.config.api_base, id, token),
+        format!("webhook/{}", id),
+        false,
+      ),
+      None if self.config.token.is_some() => (
+        format!("{}/channels/{}/messages", self.config.api_base, target.channel),
+        format!("post/{}", target.channel),
+        true,
+      ),
+      None => {
+        return Err(fail::MesherFail::SendFailure(format!(
+          "Can't post to {} without a bot token or a webhook",
+          path
+        )))
+      }
+    };
+    self
+      .request("POST", &url, &route, as_bot, &message_body(&blob))
+      .map_err(|e| fail::MesherFail::SendFailure(format!("Failed to post to {}: {}", path, e)))?;
+    Ok(())
+  }
+
+  fn listen(&mut self, path: MesherUrl) -> fail::Result<()> {
+    let target = target_from_url(&path)?;
+    if self.config.token.is_none() {
+      return Err(fail::MesherFail::ListenFailure(format!(
+        "Can't read {} without a bot token",
+        path
+      )));
+    }
+    if !self.channels.iter().any(|(c, _)| *c == target.channel) {
+      // only what's posted from now on is wanted, so reading starts after whatever was posted last
+      let latest = self
+        .history(target.channel, None, 1)
+        .map_err(|e| fail::MesherFail::ListenFailure(format!("Failed to read {}: {}", path, e)))?;
+      self
+        .channels
+        .push((target.channel, latest.first().map_or(0, message_id)));
+      // check the new channel straight away, rather than waiting for the next poll
+      self.last_poll = None;
+    }
+    Ok(())
+  }
+
+  fn receive(&mut self) -> fail::Result<Vec<Vec<u8>>> {
+    if let Some(last) = self.last_poll {
+      if last.elapsed() < self.config.poll_interval {
+        return Ok(vec![]);
+      }
+    }
+    self.last_poll = Some(Instant::now());
+
+    let mut blobs = vec![];
+    for index in 0..self.channels.len() {
+      // Discord might be down, or rate limiting, for a while, so the channel is just skipped until next time
+      let _ = self.collect(index, &mut blobs);
+    }
+    Ok(blobs)
+  }
+}
@@ -0,0 +1,412 @@
+use mesher::prelude::*;
+
+use std::{
+  collections::HashMap,
+  fs,
+  io::{self, prelude::*},
+  path::PathBuf,
+  process::{Command, Stdio},
+  thread::{self, sleep},
+  time::{Duration, Instant},
+};
+
+/// The name of the file each blob is stored in.
+const BLOB_FILE: &str = "mesher.bin";
+
+/// How many times sending tries to push a commit, when someone else keeps pushing to the branch first.
+const PUSH_ATTEMPTS: usize = 5;
+
+/// Whether a branch name is simple enough to be put into refs and command lines as-is.
+fn is_safe_branch(branch: &str) -> bool {
+  !branch.is_empty()
+    && branch.split('/').all(|piece| {
+      !piece.is_empty()
+        && !piece.starts_with('.')
+        && !piece.starts_with('-')
+        && !piece.ends_with(".lock")
+        && !piece.contains("..")
+        && piece
+          .bytes()
+          .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_' || b == b'.')
+    })
+}
+
+/// Runs git, giving it the input and returning its output, and killing it if it takes longer than the timeout.
+fn run(mut command: Command, input: &[u8], timeout: Duration) -> io::Result<Vec<u8>> {
+  let mut child = command
+    .env("GIT_TERMINAL_PROMPT", "0")
+    .stdin(Stdio::piped())
+    .stdout(Stdio::piped())
+    .stderr(Stdio::piped())
+    .spawn()?;
+  // all at once, on other threads, so that none of the pipes fill up and leave git waiting
+  let stdin = child.stdin.take();
+  let input = input.to_vec();
+  thread::spawn(move || stdin.map(|mut s| s.write_all(&input)));
+  let read_all = |pipe: Option<Box<dyn Read + Send>>| {
+    thread::spawn(move || {
+      let mut data = vec![];
+      if let Some(mut pipe) = pipe {
+        let _ = pipe.read_to_end(&mut data);
+      }
+      data
+    })
+  };
+  let stdout = read_all(child.stdout.take().map(|p| Box::new(p) as _));
+  let stderr = read_all(child.stderr.take().map(|p| Box::new(p) as _));
+
+  let deadline = Instant::now() + timeout;
+  let status = loop {
+    if let Some(status) = child.try_wait()? {
+      break status;
+    }
+    if Instant::now() > deadline {
+      let _ = child.kill();
+      let _ = child.wait();
+      return Err(io::Error::new(io::ErrorKind::TimedOut, "git took too long"));
+    }
+    sleep(Duration::from_millis(10));
+  };
+  let stdout = stdout.join().unwrap_or_default();
+  if !status.success() {
+    let stderr = stderr.join().unwrap_or_default();
+    return Err(io::Error::other(String::from_utf8_lossy(&stderr).trim().to_owned()));
+  }
+  Ok(stdout)
+}
+
+/// Settings for a [`Git`](struct.Git.html) transport which apply to every repository it uses.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GitConfig {
+  /// The repositories paths can refer to, by name.
+  /// Each is anything `git fetch` and `git push` accept, e.g. `"drop" => "git@github.com:alice/dotfiles.git"`, or a local path.
+  pub repositories: HashMap<String, String>,
+  /// Where the local repository which commits are fetched into and made in is kept.
+  ///
+  /// If this is `None`, a new one is made in the temporary directory, and deleted when the transport is dropped.
+  /// Otherwise, it's kept, along with the last commit received from each branch, so a transport using it again later picks up from where the last one left off.
+  /// It's created if it doesn't exist, and shouldn't be used by two transports at once.
+  pub cache_dir: Option<PathBuf>,
+  /// The name and email address commits are made by.
+  pub author: (String, String),
+  /// The message on every commit made.
+  pub message: String,
+  /// How long to wait for git before giving up, e.g. while it's fetching from a slow server.
+  pub timeout: Duration,
+  /// How often branches are fetched to check for new commits.
+  /// Calling `receive` more often than this returns nothing, rather than hammering the server.
+  pub poll_interval: Duration,
+  /// The largest blob that will be accepted from a commit.
+  /// Commits carrying larger ones are skipped.
+  pub max_packet_size: usize,
+}
+
+impl Default for GitConfig {
+  fn default() -> GitConfig {
+    GitConfig {
+      repositories: HashMap::new(),
+      cache_dir: None,
+      author: ("mesher".to_owned(), "mesher@localhost".to_owned()),
+      message: "Update".to_owned(),
+      timeout: Duration::from_secs(60),
+      poll_interval: Duration::from_secs(60),
+      max_packet_size: 8 * 1024 * 1024,
+    }
+  }
+}
+
+/// A branch being listened on.
+struct Listening {
+  repository: String,
+  branch: String,
+  /// The last commit on it that's been received, if there have been any.
+  last: Option<String>,
+}
+
+/// Sends and receives blobs through a git repository, e.g. `git:drop/notes`, as commits on one of its branches.
+///
+/// Paths name a repository, which has to be set up ahead of time in [`GitConfig::repositories`](struct.GitConfig.html#structfield.repositories), and a branch in it.
+/// Paths can come from other nodes, and git can be told to run arbitrary commands to reach some repositories, so letting them give the repository directly would let anyone run anything.
+///
+/// Sending commits the blob as the only file in a new commit on top of the branch, creating the branch if it doesn't exist, and pushes it.
+/// If someone else pushed first, it's committed on top of theirs instead, and pushed again.
+/// Receiving fetches every branch being listened on, and reads the blob out of every commit since the last one it saw; commits without one are skipped.
+/// Commits are never removed, so anyone who can read the repository can see every blob ever sent through it, and the branch should be used only for this.
+///
+/// Code hosting sites are happy to store any number of small commits, and talking to them looks like any other developer's work:
+///
+/// ```
+/// use mesher::prelude::*;
+/// use mesher_social::{Git, GitConfig};
+///
+/// let mut config = GitConfig::default();
+/// config.repositories.insert("drop".to_owned(), "git@github.com:alice/dotfiles.git".to_owned());
+/// let mut m = Mesher::unsigned(vec![encrypt::gen_keypair().1]);
+/// m.add_transport_instance("git", Git::with_config("git", config));
+/// ```
+///
+/// Everything's done by running `git`, which has to be installed, and uses whatever credentials it's set up with.
+/// Branches are only fetched every [`GitConfig::poll_interval`](struct.GitConfig.html#structfield.poll_interval), and one that can't be reached is skipped until the next time.
+pub struct Git {
+  config: GitConfig,
+  /// The local repository, once it's been created.
+  cache: Option<PathBuf>,
+  listening: Vec<Listening>,
+  last_poll: Option<Instant>,
+}
+
+impl Git {
+  /// Creates a git transport that pushes to and pulls from `config.repositories`.
+  pub fn with_config(_scheme: &str, config: GitConfig) -> Git {
+    Git {
+      config,
+      cache: None,
+      listening: vec![],
+      last_poll: None,
+    }
+  }
+
+  /// Splits a path into the name of the repository and the branch.
+  fn branch_from_url(&self, path: &MesherUrl) -> fail::Result<(String, String)> {
+    let repository = path.authority();
+    let branch = path.path().strip_prefix('/').unwrap_or_default();
+    // the repository's name goes into refs as well, so it has to be just as safe
+    let valid =
+      self.config.repositories.contains_key(repository) && is_safe_branch(repository) && is_safe_branch(branch);
+    if !valid || !path.query().is_empty() {
+      return Err(fail::MesherFail::InvalidURL(format!(
+        "Git paths look like git:repository/branch, with the repository configured, not {}",
+        path
+      )));
+    }
+    Ok((repository.to_owned(), branch.to_owned()))
+  }
+
+  /// Runs git in the local repository, creating it first if it doesn't exist yet.
+  fn git(&mut self, args: &[&str], input: &[u8]) -> io::Result<Vec<u8>> {
+    let cache = match &self.cache {
+      Some(cache) => cache.clone(),
+      None => {
+        let dir = self.config.cache_dir.clone().unwrap_or_else(|| {
+          std::env::temp_dir().join(format!(
+            "mesher-git-{}-{:016x}",
+            std::process::id(),
+            rand::random::<u64>()
+          ))
+        });
+        if !dir.join("HEAD").is_file() {
+          fs::create_dir_all(&dir)?;
+          let mut init = Command::new("git");
+          init.arg("init").arg("--bare").arg("--quiet").arg(&dir);
+          run(init, &[], self.config.timeout)?;
+        }
+        self.cache = Some(dir.clone());
+        dir
+      }
+    };
+    let mut command = Command::new("git");
+    command
+      .arg("--git-dir")
+      .arg(&cache)
+      .args(args)
+      .env("GIT_AUTHOR_NAME", &self.config.author.0)
+      .env("GIT_AUTHOR_EMAIL", &self.config.author.1)
+      .env("GIT_COMMITTER_NAME", &self.config.author.0)
+      .env("GIT_COMMITTER_EMAIL", &self.config.author.1);
+    run(command, input, self.config.timeout)
+  }
+
+  /// Runs git and gets the first line of what it prints, e.g. a commit ID.
+  fn git_line(&mut self, args: &[&str], input: &[u8]) -> io::Result<String> {
+    let output = self.git(args, input)?;
+    Ok(
+      String::from_utf8_lossy(&output)
+        .lines()
+        .next()
+        .unwrap_or_default()
+        .to_owned(),
+    )
+  }
+
+  /// Fetches the branch's latest commit, returning it, or `None` if the branch doesn't exist yet.
+  fn fetch(&mut self, repository: &str, branch: &str) -> io::Result<Option<String>> {
+    let url = self.config.repositories[repository].clone();
+    let head = format!("refs/heads/{}", branch);
+    // checked first, since fetching fails the same way for a missing branch as for an unreachable repository
+    let listed = self.git_line(&["ls-remote", "--", &url, &head], &[])?;
+    if listed.is_empty() {
+      return Ok(None);
+    }
+    let fetched = format!("refs/mesher/remote/{}/{}", repository, branch);
+    self.git(
+      &[
+        "fetch",
+        "--quiet",
+        "--no-tags",
+        "--",
+        &url,
+        &format!("+{}:{}", head, fetched),
+      ],
+      &[],
+    )?;
+    self
+      .git_line(&["rev-parse", "--verify", &format!("{}^{{commit}}", fetched)], &[])
+      .map(Some)
+  }
+
+  /// Where the last commit received from a branch is kept in the local repository, so it's remembered if that's kept.
+  fn seen_ref(repository: &str, branch: &str) -> String {
+    format!("refs/mesher/seen/{}/{}", repository, branch)
+  }
+
+  /// Gets the blob out of a commit, if it's carrying one that isn't too large.
+  fn blob_from(&mut self, commit: &str) -> io::Result<Option<Vec<u8>>> {
+    let listing = self.git(&["ls-tree", "-l", "-z", commit], &[])?;
+    for entry in listing.split(|b| *b == 0) {
+      // `<mode> blob <id> <size>\t<name>`
+      let entry = String::from_utf8_lossy(entry);
+      let (info, name) = match entry.split_once('\t') {
+        Some(split) => split,
+        None => continue,
+      };
+      let info = info.split_whitespace().collect::<Vec<_>>();
+      if name != BLOB_FILE || info.len() != 4 || info[1] != "blob" {
+        continue;
+      }
+      if info[3]
+        .parse::<usize>()
+        .map_or(true, |size| size > self.config.max_packet_size)
+      {
+        return Ok(None);
+      }
+      return self.git(&["cat-file", "blob", info[2]], &[]).map(Some);
+    }
+    Ok(None)
+  }
+
+  /// Reads every blob committed to the branch since the last commit received from it.
+  fn collect(&mut self, index: usize, blobs: &mut Vec<Vec<u8>>) -> io::Result<()> {
+    let repository = self.listening[index].repository.clone();
+    let branch = self.listening[index].branch.clone();
+    let tip = match self.fetch(&repository, &branch)? {
+      Some(tip) => tip,
+      None => return Ok(()),
+    };
+    let range = match &self.listening[index].last {
+      Some(last) if *last == tip => return Ok(()),
+      Some(last) => format!("{}..{}", last, tip),
+      None => tip.clone(),
+    };
+    let commits = self.git(&["rev-list", "--reverse", "--topo-order", &range, "--"], &[])?;
+    for commit in String::from_utf8_lossy(&commits).lines() {
+      // a commit that couldn't be read is tried again next time, rather than skipped
+      blobs.extend(self.blob_from(commit)?);
+      self.git(&["update-ref", &Git::seen_ref(&repository, &branch), commit], &[])?;
+      self.listening[index].last = Some(commit.to_owned());
+    }
+    Ok(())
+  }
+}
+
+impl Drop for Git {
+  fn drop(&mut self) {
+    if self.config.cache_dir.is_none() {
+      if let Some(cache) = &self.cache {
+        let _ = fs::remove_dir_all(cache);
+      }
+    }
+  }
+}
+
+impl Transport for Git {
+  fn new(scheme: &str) -> fail::Result<Self> {
+    Ok(Git::with_config(scheme, GitConfig::default()))
+  }
+
+  fn send(&mut self, path: MesherUrl, blob: Vec<u8>) -> fail::Result<()> {
+    let (repository, branch) = self.branch_from_url(&path)?;
+    let failed = |e: io::Error| fail::MesherFail::SendFailure(format!("Failed to commit to {}: {}", path, e));
+    let blob_id = self
+      .git_line(&["hash-object", "-w", "--stdin"], &blob)
+      .map_err(failed)?;
+    let listing = format!("100644 blob {}\t{}\n", blob_id, BLOB_FILE);
+    let tree = self.git_line(&["mktree"], listing.as_bytes()).map_err(failed)?;
+    let url = self.config.repositories[&repository].clone();
+    let message = self.config.message.clone();
+
+    let mut result = Ok(vec![]);
+    for _ in 0..PUSH_ATTEMPTS {
+      // the branch is fetched again every time, since a failed push usually means someone else pushed first
+      result = self.fetch(&repository, &branch).and_then(|parent| {
+        let mut args = vec!["commit-tree", &tree, "-m", &message];
+        if let Some(parent) = &parent {
+          args.extend(&["-p", parent]);
+        }
+        let commit = self.git_line(&args, &[])?;
+        self.git(
+          &[
+            "push",
+            "--quiet",
+            "--",
+            &url,
+            &format!("{}:refs/heads/{}", commit, branch),
+          ],
+          &[],
+        )
+      });
+      if result.is_ok() {
+        break;
+      }
+    }
+    result.map(|_| ()).map_err(failed)
+  }
+
+  fn listen(&mut self, path: MesherUrl) -> fail::Result<()> {
+    let (repository, branch) = self.branch_from_url(&path)?;
+    if self
+      .listening
+      .iter()
+      .any(|l| l.repository == repository && l.branch == branch)
+    {
+      return Ok(());
+    }
+    // a kept local repository remembers where the last transport using it got to...
+    let seen = Git::seen_ref(&repository, &branch);
+    let last = match self.git_line(&["rev-parse", "--verify", "--quiet", &seen], &[]) {
+      Ok(last) if !last.is_empty() => Some(last),
+      // ...but otherwise, only commits from now on are received
+      _ => {
+        let failed = |e: io::Error| fail::MesherFail::ListenFailure(format!("Failed to fetch {}: {}", path, e));
+        let tip = self.fetch(&repository, &branch).map_err(failed)?;
+        if let Some(tip) = &tip {
+          self.git(&["update-ref", &seen, tip], &[]).map_err(failed)?;
+        }
+        tip
+      }
+    };
+    self.listening.push(Listening {
+      repository,
+      branch,
+      last,
+    });
+    // check the new branch straight away, rather than waiting for the next poll
+    self.last_poll = None;
+    Ok(())
+  }
+
+  fn receive(&mut self) -> fail::Result<Vec<Vec<u8>>> {
+    if let Some(last) = self.last_poll {
+      if last.elapsed() < self.config.poll_interval {
+        return Ok(vec![]);
+      }
+    }
+    self.last_poll = Some(Instant::now());
+
+    let mut blobs = vec![];
+    for index in 0..self.listening.len() {
+      // the repository might be unreachable for a while, so the branch is just skipped until it's back
+      let _ = self.collect(index, &mut blobs);
+    }
+    Ok(blobs)
+  }
+}
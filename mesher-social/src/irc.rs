@@ -0,0 +1,746 @@
+use mesher::prelude::*;
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use std::{
+  collections::{HashMap, HashSet},
+  io::{self, prelude::*, BufReader},
+  net::{Shutdown, TcpStream, ToSocketAddrs},
+  sync::{
+    mpsc::{channel, Receiver, Sender, TryRecvError},
+    Arc, Condvar, Mutex,
+  },
+  thread::{sleep, Builder},
+  time::{Duration, Instant},
+};
+
+/// The longest line IRC allows, including the CRLF at the end.
+const MAX_LINE: usize = 512;
+
+/// The longest line that will be read, which is more than IRC allows, to leave room for IRCv3 message tags.
+/// Anything longer is split up and ignored.
+const MAX_READ: usize = 16 * 1024;
+
+/// Room left in every line for the `:nick!user@host ` prefix the server adds when passing it along, other than the nick.
+/// Usernames are at most 10 characters on nearly every network, and hostnames at most 63.
+const PREFIX_ROOM: usize = ":!".len() + 10 + "@".len() + 63 + " ".len();
+
+/// Starts every line carrying part of a blob, so everything else said in a channel can be ignored.
+const MARKER: &str = "mesher";
+
+/// The most lines a single blob can be split into, which keeps the index and count in each one to at most 5 digits.
+const MAX_PIECES: usize = 99_999;
+
+/// How many blobs one nick can have partway through being received at once, so no one sender can take up all the room for reassembly.
+const MAX_PARTIAL_PER_SENDER: usize = 4;
+
+/// Roughly how much memory each received line takes up in a partial blob, on top of its text.
+const PIECE_OVERHEAD: usize = std::mem::size_of::<(usize, String)>();
+
+fn is_channel(target: &str) -> bool {
+  target.starts_with('#') || target.starts_with('&')
+}
+
+/// Splits a path into the server's host and port, and the channel or nick to send to.
+fn target_from_url(path: &MesherUrl) -> fail::Result<(String, u16, String)> {
+  let target = path.path().strip_prefix('/').unwrap_or_default();
+  let valid_target = !target.is_empty()
+    && !target.starts_with(':')
+    && target.bytes().all(|b| b > b' ' && b != b',' && b != b'/' && b != 0x7F);
+  if path.host().is_empty() || !valid_target {
+    return Err(fail::MesherFail::InvalidURL(format!(
+      "IRC paths look like irc:irc.example.net/#channel or irc:irc.example.net/nick, not {}",
+      path
+    )));
+  }
+  Ok((path.host().to_owned(), path.port().unwrap_or(6667), target.to_owned()))
+}
+
+/// Splits a blob into the text of the lines it's sent as, each short enough to still fit in a line once the server adds who it's from.
+fn to_lines(blob: &[u8], target: &str, nick: &str) -> io::Result<Vec<String>> {
+  let encoded = STANDARD.encode(blob);
+  let id = format!("{:08x}", rand::random::<u32>());
+  let digits = MAX_PIECES.to_string().len();
+  let overhead = nick.len()
+    + PREFIX_ROOM
+    + "PRIVMSG  :".len()
+    + target.len()
+    + MARKER.len()
+    + 1
+    + id.len()
+    + 1
+    + digits
+    + 1
+    + digits
+    + 1
+    + "\r\n".len();
+  let room = MAX_LINE.saturating_sub(overhead);
+  if room == 0 {
+    return Err(io::Error::other("no room left in lines for any data"));
+  }
+  let pieces = if encoded.is_empty() {
+    vec![""]
+  } else {
+    encoded
+      .as_bytes()
+      .chunks(room)
+      // base64 is all ASCII, so it can be split anywhere
+      .map(|piece| std::str::from_utf8(piece).expect("base64 isn't ASCII?"))
+      .collect()
+  };
+  if pieces.len() > MAX_PIECES {
+    return Err(io::Error::other("blob too large"));
+  }
+  let count = pieces.len();
+  Ok(
+    pieces
+      .into_iter()
+      .enumerate()
+      .map(|(index, piece)| format!("{} {} {}/{} {}", MARKER, id, index, count, piece))
+      .collect(),
+  )
+}
+
+/// A line from the server, split into its parts.
+struct Message<'a> {
+  /// Who it's from, without the leading `:`.
+  prefix: Option<&'a str>,
+  command: &'a str,
+  params: Vec<&'a str>,
+}
+
+impl<'a> Message<'a> {
+  fn parse(line: &'a str) -> Option<Message<'a>> {
+    let mut rest = line;
+    // IRCv3 message tags, which nothing here needs
+    if rest.starts_with('@') {
+      rest = rest.split_once(' ')?.1;
+    }
+    let prefix = match rest.strip_prefix(':') {
+      Some(prefixed) => {
+        let (prefix, after) = prefixed.split_once(' ')?;
+        rest = after;
+        Some(prefix)
+      }
+      None => None,
+    };
+    let (command, mut rest) = rest.split_once(' ').unwrap_or((rest, ""));
+    let mut params = vec![];
+    while !rest.is_empty() {
+      if let Some(trailing) = rest.strip_prefix(':') {
+        params.push(trailing);
+        break;
+      }
+      let (param, after) = rest.split_once(' ').unwrap_or((rest, ""));
+      if !param.is_empty() {
+        params.push(param);
+      }
+      rest = after;
+    }
+    Some(Message {
+      prefix,
+      command,
+      params,
+    })
+  }
+
+  /// The nick of whoever it's from, if it's from a user.
+  fn nick(&self) -> Option<&'a str> {
+    self.prefix.map(|p| p.split('!').next().unwrap_or(p))
+  }
+}
+
+/// A blob which has had some, but not all, of its lines received.
+struct Partial {
+  count: usize,
+  pieces: HashMap<usize, String>,
+  size: usize,
+  started: Instant,
+}
+
+impl Partial {
+  /// Roughly how much memory the partial blob is taking up.
+  fn footprint(&self) -> usize {
+    self.size + self.pieces.len() * PIECE_OVERHEAD
+  }
+}
+
+/// Puts blobs back together from the lines they were split into, keeping track of who sent each one where.
+struct Reassembler {
+  max_packet_size: usize,
+  max_memory: usize,
+  timeout: Duration,
+  partial: HashMap<(String, String, String), Partial>,
+  memory: usize,
+}
+
+impl Reassembler {
+  fn new(config: &IrcConfig) -> Reassembler {
+    Reassembler {
+      max_packet_size: config.max_packet_size,
+      max_memory: config.max_reassembly_memory,
+      timeout: config.reassembly_timeout,
+      partial: HashMap::new(),
+      memory: 0,
+    }
+  }
+
+  /// Stops holding on to a partial blob, returning it if it was there.
+  fn remove(&mut self, key: &(String, String, String)) -> Option<Partial> {
+    let partial = self.partial.remove(key)?;
+    self.memory -= partial.footprint();
+    Some(partial)
+  }
+
+  /// Takes a line someone said, returning the blob it finishes, if it's the last piece needed for one.
+  fn add(&mut self, from: &str, target: &str, text: &str) -> Option<Vec<u8>> {
+    let mut words = text.splitn(4, ' ');
+    if words.next() != Some(MARKER) {
+      return None;
+    }
+    let id = words.next()?;
+    let (index, count) = words.next()?.split_once('/')?;
+    let (index, count) = match (index.parse::<usize>(), count.parse::<usize>()) {
+      (Ok(index), Ok(count)) if index < count && count <= MAX_PIECES && id.len() <= 16 => (index, count),
+      _ => return None,
+    };
+    // an empty blob is sent as a line with nothing after the header, which servers might trim the space off of
+    let piece = words.next().unwrap_or_default();
+
+    let timeout = self.timeout;
+    let mut freed = 0;
+    self.partial.retain(|_, p| {
+      let keep = p.started.elapsed() < timeout;
+      if !keep {
+        freed += p.footprint();
+      }
+      keep
+    });
+    self.memory -= freed;
+
+    let from = from.to_ascii_lowercase();
+    let key = (from.clone(), target.to_ascii_lowercase(), id.to_owned());
+    if !self.partial.contains_key(&key)
+      && self.partial.keys().filter(|(f, _, _)| *f == from).count() >= MAX_PARTIAL_PER_SENDER
+    {
+      return None;
+    }
+    let partial = self.partial.entry(key.clone()).or_insert_with(|| Partial {
+      count,
+      pieces: HashMap::new(),
+      size: 0,
+      started: Instant::now(),
+    });
+    let before = partial.footprint();
+    partial.size += piece.len();
+    if let Some(old) = partial.pieces.insert(index, piece.to_owned()) {
+      partial.size -= old.len();
+    }
+    self.memory = self.memory - before + partial.footprint();
+    if partial.count != count || partial.size > self.max_packet_size.div_ceil(3) * 4 || self.memory > self.max_memory {
+      self.remove(&key);
+      return None;
+    }
+    if partial.pieces.len() < count {
+      return None;
+    }
+
+    let partial = self.remove(&key)?;
+    let encoded = (0..count)
+      .filter_map(|i| partial.pieces.get(&i))
+      .cloned()
+      .collect::<String>();
+    STANDARD
+      .decode(encoded)
+      .ok()
+      .filter(|blob| blob.len() <= self.max_packet_size)
+  }
+}
+
+#[derive(Default)]
+struct Status {
+  /// The nick the server says this connection has, once it's registered.
+  nick: Option<String>,
+  /// Every channel the server says this connection has joined, lowercased.
+  joined: HashSet<String>,
+  /// Why the server last refused a nick or a channel, if it has.
+  refused: Option<String>,
+  closed: bool,
+  /// Everything blobs are being received from, lowercased: channels, and the connection's own nick if it's being sent messages directly.
+  listening: HashSet<String>,
+}
+
+type Shared = Arc<(Mutex<Status>, Condvar)>;
+
+fn write_line(writer: &Mutex<TcpStream>, line: &str) -> io::Result<()> {
+  let mut writer = writer.lock().expect("poisoned lock?");
+  writer.write_all(format!("{}\r\n", line).as_bytes())
+}
+
+/// Reads everything the server sends, answering pings, keeping track of the connection's status, and passing along blobs sent to anything being listened on.
+fn read_lines(
+  stream: TcpStream,
+  writer: Arc<Mutex<TcpStream>>,
+  shared: Shared,
+  sender: Sender<Vec<u8>>,
+  mut reassembler: Reassembler,
+) {
+  let mut reader = BufReader::new(stream);
+  let mut buf = vec![];
+  loop {
+    buf.clear();
+    match (&mut reader).take(MAX_READ as u64).read_until(b'\n', &mut buf) {
+      Ok(0) | Err(_) => break,
+      Ok(_) => (),
+    }
+    let line = String::from_utf8_lossy(&buf);
+    let message = match Message::parse(line.trim_end_matches(['\r', '\n'])) {
+      Some(message) => message,
+      None => continue,
+    };
+
+    let (lock, cvar) = &*shared;
+    match message.command {
+      "PING" => {
+        let _ = write_line(&writer, &format!("PONG :{}", message.params.last().unwrap_or(&"")));
+      }
+      "001" => {
+        lock.lock().expect("poisoned lock?").nick = message.params.first().map(|n| n.to_string());
+        cvar.notify_all();
+      }
+      "NICK" => {
+        let mut status = lock.lock().expect("poisoned lock?");
+        if message.nick().map(str::to_ascii_lowercase) == status.nick.as_ref().map(|n| n.to_ascii_lowercase()) {
+          status.nick = message.params.first().map(|n| n.to_string());
+        }
+        cvar.notify_all();
+      }
+      "JOIN" => {
+        let mut status = lock.lock().expect("poisoned lock?");
+        if message.nick().map(str::to_ascii_lowercase) == status.nick.as_ref().map(|n| n.to_ascii_lowercase()) {
+          if let Some(channel) = message.params.first() {
+            status.joined.insert(channel.to_ascii_lowercase());
+          }
+        }
+        cvar.notify_all();
+      }
+      // nicks that are invalid or taken, and channels that can't be joined
+      "432" | "433" | "436" | "437" | "403" | "405" | "471" | "473" | "474" | "475" | "477" => {
+        lock.lock().expect("poisoned lock?").refused = Some(message.params.get(1..).unwrap_or_default().join(" "));
+        cvar.notify_all();
+      }
+      "PRIVMSG" => {
+        let (from, target, text) = match (message.nick(), message.params.first(), message.params.get(1)) {
+          (Some(from), Some(target), Some(text)) => (from, target, text),
+          _ => continue,
+        };
+        if !lock
+          .lock()
+          .expect("poisoned lock?")
+          .listening
+          .contains(&target.to_ascii_lowercase())
+        {
+          continue;
+        }
+        if let Some(blob) = reassembler.add(from, target, text) {
+          if sender.send(blob).is_err() {
+            break;
+          }
+        }
+      }
+      "ERROR" => break,
+      _ => (),
+    }
+  }
+  shared.0.lock().expect("poisoned lock?").closed = true;
+  shared.1.notify_all();
+}
+
+/// Connects to the first address the host resolves to that answers within the timeout.
+fn connect(host: &str, port: u16, timeout: Duration) -> io::Result<TcpStream> {
+  let mut last_error = io::Error::other(format!("{} doesn't resolve to anything", host));
+  for addr in (host, port).to_socket_addrs()? {
+    match TcpStream::connect_timeout(&addr, timeout) {
+      Ok(stream) => return Ok(stream),
+      Err(e) => last_error = e,
+    }
+  }
+  Err(last_error)
+}
+
+/// A connection to a single server, registered and ready to send.
+struct Connection {
+  writer: Arc<Mutex<TcpStream>>,
+  shared: Shared,
+  /// Every channel a `JOIN` has been sent for, lowercased, whether or not it's been confirmed.
+  joining: HashSet<String>,
+  last_line: Option<Instant>,
+}
+
+impl Connection {
+  /// Connects and registers, then starts listening on the targets, without waiting to find out whether any channels were joined.
+  fn open(
+    host: &str,
+    port: u16,
+    nick: &str,
+    targets: &[String],
+    config: &IrcConfig,
+    sender: Sender<Vec<u8>>,
+  ) -> io::Result<Connection> {
+    let stream = connect(host, port, config.timeout)?;
+    let reader = stream.try_clone()?;
+    let writer = Arc::new(Mutex::new(stream));
+    let shared = Shared::default();
+    let reassembler = Reassembler::new(config);
+    let (thread_writer, thread_shared) = (writer.clone(), shared.clone());
+    Builder::new()
+      .name(format!("IRC {}:{} reader", host, port))
+      .spawn(move || read_lines(reader, thread_writer, thread_shared, sender, reassembler))?;
+
+    let mut conn = Connection {
+      writer,
+      shared,
+      joining: HashSet::new(),
+      last_line: None,
+    };
+    if let Some(password) = &config.password {
+      conn.write(&format!("PASS {}", password))?;
+    }
+    conn.write(&format!("NICK {}", nick))?;
+    conn.write("USER mesher 0 * :mesher")?;
+    conn.wait_for(config.timeout, |status| status.nick.is_some())?;
+    for target in targets {
+      if is_channel(target) {
+        conn.join(target)?;
+      }
+      conn.start_listening(target);
+    }
+    Ok(conn)
+  }
+
+  fn write(&mut self, line: &str) -> io::Result<()> {
+    let written = write_line(&self.writer, line);
+    if written.is_err() {
+      self.shared.0.lock().expect("poisoned lock?").closed = true;
+    }
+    written
+  }
+
+  fn is_closed(&self) -> bool {
+    self.shared.0.lock().expect("poisoned lock?").closed
+  }
+
+  fn nick(&self) -> String {
+    let status = self.shared.0.lock().expect("poisoned lock?");
+    status.nick.clone().unwrap_or_default()
+  }
+
+  /// Waits until the check passes, failing if the server refuses something first, or the connection closes.
+  fn wait_for(&self, timeout: Duration, check: impl Fn(&Status) -> bool) -> io::Result<()> {
+    let (lock, cvar) = &*self.shared;
+    let deadline = Instant::now() + timeout;
+    let mut status = lock.lock().expect("poisoned lock?");
+    loop {
+      if check(&status) {
+        return Ok(());
+      }
+      if let Some(why) = status.refused.take() {
+        return Err(io::Error::other(format!("refused by server: {}", why)));
+      }
+      if status.closed {
+        return Err(io::Error::new(io::ErrorKind::ConnectionAborted, "connection closed"));
+      }
+      let remaining = deadline.saturating_duration_since(Instant::now());
+      if remaining == Duration::from_secs(0) {
+        return Err(io::Error::new(io::ErrorKind::TimedOut, "no response from server"));
+      }
+      status = cvar.wait_timeout(status, remaining).expect("poisoned lock?").0;
+    }
+  }
+
+  /// Joins the channel, if it hasn't been already, without waiting to find out whether it worked.
+  fn join(&mut self, channel: &str) -> io::Result<()> {
+    let channel = channel.to_ascii_lowercase();
+    if !self.joining.contains(&channel) {
+      self.write(&format!("JOIN {}", channel))?;
+      self.joining.insert(channel);
+    }
+    Ok(())
+  }
+
+  /// Starts passing along blobs sent to a channel or this connection's nick, joining the channel or changing the nick first if needed.
+  fn listen(&mut self, target: &str, timeout: Duration) -> io::Result<()> {
+    let lower = target.to_ascii_lowercase();
+    self.shared.0.lock().expect("poisoned lock?").refused = None;
+    if is_channel(target) {
+      self.join(target)?;
+      self.wait_for(timeout, |status| status.joined.contains(&lower))?;
+    } else if !self.nick().eq_ignore_ascii_case(target) {
+      self.write(&format!("NICK {}", target))?;
+      self.wait_for(timeout, |status| {
+        status.nick.as_ref().map(|n| n.to_ascii_lowercase()) == Some(lower.clone())
+      })?;
+    }
+    self.start_listening(target);
+    Ok(())
+  }
+
+  /// Starts passing along blobs sent to a channel or nick.
+  fn start_listening(&mut self, target: &str) {
+    let mut status = self.shared.0.lock().expect("poisoned lock?");
+    status.listening.insert(target.to_ascii_lowercase());
+  }
+
+  fn privmsg(&mut self, target: &str, text: &str, interval: Duration) -> io::Result<()> {
+    if let Some(last) = self.last_line {
+      if let Some(wait) = interval.checked_sub(last.elapsed()) {
+        sleep(wait);
+      }
+    }
+    self.write(&format!("PRIVMSG {} :{}", target, text))?;
+    self.last_line = Some(Instant::now());
+    Ok(())
+  }
+}
+
+impl Drop for Connection {
+  fn drop(&mut self) {
+    let _ = self.write("QUIT");
+    // the reader thread stops once the connection's closed
+    let _ = self.writer.lock().expect("poisoned lock?").shutdown(Shutdown::Both);
+  }
+}
+
+/// Everything to do with one server: what's listened on there, and the connection to it, if there is one.
+struct Server {
+  host: String,
+  port: u16,
+  channels: Vec<String>,
+  nick: Option<String>,
+  connection: Option<Connection>,
+  last_attempt: Option<Instant>,
+  /// The result of reconnecting in the background, if that's underway.
+  reconnecting: Option<Receiver<io::Result<Connection>>>,
+}
+
+impl Server {
+  fn is_listened_on(&self) -> bool {
+    !self.channels.is_empty() || self.nick.is_some()
+  }
+
+  fn targets(&self) -> Vec<String> {
+    self.channels.iter().chain(&self.nick).cloned().collect()
+  }
+
+  /// Takes the connection made in the background, if there is one, optionally waiting for it to be finished.
+  fn finish_reconnecting(&mut self, wait: bool) {
+    let result = match &self.reconnecting {
+      Some(reconnecting) if wait => reconnecting.recv().map_err(|_| TryRecvError::Disconnected),
+      Some(reconnecting) => reconnecting.try_recv(),
+      None => return,
+    };
+    match result {
+      Ok(Ok(conn)) => self.connection = Some(conn),
+      // if the server's still unreachable, it's tried again after the delay
+      Ok(Err(_)) | Err(TryRecvError::Disconnected) => (),
+      Err(TryRecvError::Empty) => return,
+    }
+    self.reconnecting = None;
+  }
+
+  /// Starts connecting in the background, so that receiving doesn't have to wait for the server.
+  fn reconnect(&mut self, config: &IrcConfig, sender: &Sender<Vec<u8>>) {
+    self.last_attempt = Some(Instant::now());
+    let (done, reconnecting) = channel();
+    let (host, port, targets) = (self.host.clone(), self.port, self.targets());
+    let nick = self.nick.clone().unwrap_or_else(|| config.nick.clone());
+    let (config, sender) = (config.clone(), sender.clone());
+    let spawned = Builder::new()
+      .name(format!("IRC {}:{} reconnect", host, port))
+      .spawn(move || {
+        let _ = done.send(Connection::open(&host, port, &nick, &targets, &config, sender));
+      });
+    if spawned.is_ok() {
+      self.reconnecting = Some(reconnecting);
+    }
+  }
+
+  /// Gets the connection to the server, connecting and listening on everything that should be if there isn't one yet, or it's closed.
+  fn connection(&mut self, config: &IrcConfig, sender: &Sender<Vec<u8>>) -> io::Result<&mut Connection> {
+    // connecting and registering both time out, so this won't be long
+    self.finish_reconnecting(true);
+    if self.connection.as_ref().is_some_and(Connection::is_closed) {
+      self.connection = None;
+    }
+    if self.connection.is_none() {
+      self.last_attempt = Some(Instant::now());
+      let nick = self.nick.as_ref().unwrap_or(&config.nick);
+      let conn = Connection::open(&self.host, self.port, nick, &self.targets(), config, sender.clone())?;
+      self.connection = Some(conn);
+    }
+    Ok(self.connection.as_mut().expect("connection disappeared?"))
+  }
+}
+
+/// Settings for an [`IRC`](struct.IRC.html) transport which apply to every server it uses.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IrcConfig {
+  /// The nick to use on servers where no nick is being listened on.
+  /// The default is random, and short enough for any server.
+  pub nick: String,
+  /// The server password to connect with, if the servers need one.
+  pub password: Option<String>,
+  /// How long to wait for a server to let this connect, join a channel, or change nick.
+  pub timeout: Duration,
+  /// The shortest time between lines sent to the same server.
+  /// Most servers disconnect anyone who sends lines too quickly, and large blobs can be a lot of lines.
+  pub send_interval: Duration,
+  /// The largest blob that will be put back together from lines received.
+  /// Lines for anything larger are ignored.
+  pub max_packet_size: usize,
+  /// How long to hold on to the lines of a blob, waiting for the rest of them.
+  pub reassembly_timeout: Duration,
+  /// The most memory a listener will use holding on to the lines of incomplete blobs, across every sender.
+  /// Lines which would go over this are ignored, along with the rest of the blob they're part of.
+  pub max_reassembly_memory: usize,
+  /// The shortest time between attempts to reconnect to a server that's being listened on, after being disconnected.
+  pub reconnect_delay: Duration,
+}
+
+impl Default for IrcConfig {
+  fn default() -> IrcConfig {
+    IrcConfig {
+      nick: format!("m{:08x}", rand::random::<u32>()),
+      password: None,
+      timeout: Duration::from_secs(30),
+      send_interval: Duration::from_millis(500),
+      max_packet_size: 64 * 1024,
+      reassembly_timeout: Duration::from_secs(300),
+      max_reassembly_memory: 4 * 1024 * 1024,
+      reconnect_delay: Duration::from_secs(30),
+    }
+  }
+}
+
+/// Sends and receives blobs through IRC, either in a channel, e.g. `irc:irc.example.net/#mesher`, or directly to a nick, e.g. `irc:irc.example.net:6667/alice`.
+///
+/// Blobs are base64-encoded and split into as many `PRIVMSG` lines as they need, each starting with `mesher`, an ID for the blob, and which line of how many it is.
+/// Lines are kept short enough that they still fit in IRC's 512-byte limit once the server adds who they're from, and are put back together by whoever receives them.
+/// Anything else said in a channel is ignored, so it can be shared with people.
+///
+/// Listening on a channel joins it, and receives every blob sent there by anyone else.
+/// Listening on a nick takes that nick on the server, and receives every blob sent to it directly; only one nick can be listened on per server.
+/// Everything else uses [`IrcConfig::nick`](struct.IrcConfig.html#structfield.nick).
+///
+/// There's one connection to each server, which is made the first time it's needed, and remade in the background when receiving if it's lost and anything's listened on there.
+/// Lines are sent no faster than [`IrcConfig::send_interval`](struct.IrcConfig.html#structfield.send_interval) allows, so sending large blobs takes a while.
+/// Connections aren't encrypted, so the server, and anyone in the channel, sees everything sent.
+pub struct IRC {
+  sender: Sender<Vec<u8>>,
+  receiver: Receiver<Vec<u8>>,
+  config: IrcConfig,
+  servers: HashMap<(String, u16), Server>,
+}
+
+impl IRC {
+  /// Creates an IRC transport with its own nick, pacing, and reconnect settings.
+  pub fn with_config(_scheme: &str, config: IrcConfig) -> IRC {
+    let (sender, receiver) = channel();
+    IRC {
+      sender,
+      receiver,
+      config,
+      servers: HashMap::new(),
+    }
+  }
+
+  fn server(&mut self, host: String, port: u16) -> &mut Server {
+    self
+      .servers
+      .entry((host.to_ascii_lowercase(), port))
+      .or_insert_with(|| Server {
+        host,
+        port,
+        channels: vec![],
+        nick: None,
+        connection: None,
+        last_attempt: None,
+        reconnecting: None,
+      })
+  }
+}
+
+impl Transport for IRC {
+  fn new(scheme: &str) -> fail::Result<Self> {
+    Ok(IRC::with_config(scheme, IrcConfig::default()))
+  }
+
+  fn send(&mut self, path: MesherUrl, blob: Vec<u8>) -> fail::Result<()> {
+    let (host, port, target) = target_from_url(&path)?;
+    let send_fail = |e: io::Error| fail::MesherFail::SendFailure(format!("Failed to send to {}: {}", path, e));
+    let (config, sender) = (self.config.clone(), self.sender.clone());
+    let conn = self
+      .server(host, port)
+      .connection(&config, &sender)
+      .map_err(send_fail)?;
+    // most channels only take messages from people in them
+    if is_channel(&target) {
+      conn.join(&target).map_err(send_fail)?;
+    }
+    for line in to_lines(&blob, &target, &conn.nick()).map_err(send_fail)? {
+      conn.privmsg(&target, &line, config.send_interval).map_err(send_fail)?;
+    }
+    Ok(())
+  }
+
+  fn listen(&mut self, path: MesherUrl) -> fail::Result<()> {
+    let (host, port, target) = target_from_url(&path)?;
+    let listen_fail = |e: io::Error| fail::MesherFail::ListenFailure(format!("Failed to listen on {}: {}", path, e));
+    let (config, sender) = (self.config.clone(), self.sender.clone());
+    let server = self.server(host, port);
+    let lower = target.to_ascii_lowercase();
+
+    if is_channel(&target) {
+      if server.channels.contains(&lower) {
+        return Ok(());
+      }
+      server.channels.push(lower.clone());
+    } else {
+      match &server.nick {
+        Some(nick) if nick.eq_ignore_ascii_case(&target) => return Ok(()),
+        Some(nick) => {
+          return Err(fail::MesherFail::ListenFailure(format!(
+            "Already listening as {} on {}",
+            nick, path
+          )))
+        }
+        None => server.nick = Some(target.clone()),
+      }
+    }
+
+    // a new connection listens on everything by itself, but an existing one needs to be told
+    let listened = server
+      .connection(&config, &sender)
+      .and_then(|conn| conn.listen(&target, config.timeout));
+    if let Err(e) = listened {
+      server.channels.retain(|c| *c != lower);
+      if !is_channel(&target) {
+        server.nick = None;
+      }
+      return Err(listen_fail(e));
+    }
+    Ok(())
+  }
+
+  fn receive(&mut self) -> fail::Result<Vec<Vec<u8>>> {
+    let reconnect_delay = self.config.reconnect_delay;
+    for server in self.servers.values_mut() {
+      server.finish_reconnecting(false);
+      let lost = server.connection.as_ref().is_none_or(Connection::is_closed);
+      let waited = server.last_attempt.is_none_or(|last| last.elapsed() >= reconnect_delay);
+      if server.is_listened_on() && lost && waited && server.reconnecting.is_none() {
+        server.reconnect(&self.config, &self.sender);
+      }
+    }
+    Ok(self.receiver.try_iter().collect())
+  }
+}
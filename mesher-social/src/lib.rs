@@ -1 +1,22 @@
+//! Transports for mesher that pass messages through social and collaboration services.
+//!
+//! `Transport::new` builds a transport with default settings, which is what `Mesher::add_transport` uses. To use
+//! other settings, build the transport with its `with_config` (or a similar constructor) and register the
+//! instance with `Mesher::add_transport_instance`.
+
 extern crate mesher;
+
+mod discord;
+pub use discord::{Discord, DiscordConfig};
+
+mod git;
+pub use git::{Git, GitConfig};
+
+mod irc;
+pub use irc::{IrcConfig, IRC};
+
+mod matrix;
+pub use matrix::{Matrix, MatrixConfig};
+
+mod paste;
+pub use paste::{HttpPaste, Paste, PasteConfig, PasteService};
@@ -0,0 +1,383 @@
+use mesher::prelude::*;
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde_json::{json, Value};
+use std::{
+  collections::{HashMap, HashSet},
+  fmt::Display,
+  sync::{
+    atomic::{AtomicBool, Ordering},
+    mpsc::{channel, Receiver, Sender},
+    Arc, Mutex,
+  },
+  thread::{sleep, Builder},
+  time::Duration,
+};
+
+/// The type of the events blobs are sent as, so everything else in a room can be ignored.
+const EVENT_TYPE: &str = "eco.cybers.mesher.blob";
+
+/// The longest to wait out a rate limit before trying again; any longer, and the request just fails.
+const MAX_RATE_LIMIT_WAIT: Duration = Duration::from_secs(10);
+
+/// Percent-encodes everything but the characters that can always go in a URL, so room IDs and aliases can be put in one.
+fn encode(piece: &str) -> String {
+  piece
+    .bytes()
+    .map(|b| match b {
+      b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (b as char).to_string(),
+      _ => format!("%{:02X}", b),
+    })
+    .collect()
+}
+
+/// Gets the room ID or alias out of a path.
+fn room_from_url(path: &MesherUrl) -> fail::Result<String> {
+  let room = path.authority();
+  let valid = (room.starts_with('!') || room.starts_with('#'))
+    && room.len() > 1
+    && room[1..].contains(':')
+    && path.path().is_empty()
+    && path.query().is_empty()
+    && room.bytes().all(|b| b.is_ascii_graphic());
+  if !valid {
+    return Err(fail::MesherFail::InvalidURL(format!(
+      "Matrix paths look like matrix:!roomid:example.org or matrix:#alias:example.org, not {}",
+      path
+    )));
+  }
+  Ok(room.to_owned())
+}
+
+/// Settings for a [`Matrix`](struct.Matrix.html) transport.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MatrixConfig {
+  /// The base URL of the homeserver with the account to use, e.g. `https://matrix.example.org`.
+  pub homeserver: String,
+  /// The account's access token.
+  pub access_token: String,
+  /// Where to pick up from when first listening, as returned by [`Matrix::since`](struct.Matrix.html#method.since) before a restart.
+  /// If it's `None`, only events sent after listening starts are received.
+  pub since: Option<String>,
+  /// How long a request can take before it's given up on, not counting the time the homeserver's asked to wait for new events.
+  pub timeout: Duration,
+  /// How long the homeserver's asked to wait for new events before responding with nothing.
+  pub sync_timeout: Duration,
+  /// How long to wait before trying again when the homeserver can't be reached.
+  pub retry_delay: Duration,
+  /// The largest blob that will be sent or received.
+  /// Homeservers reject events larger than 64 KiB, including everything else in them, and blobs are base64-encoded, so this shouldn't be raised much.
+  pub max_packet_size: usize,
+}
+
+impl Default for MatrixConfig {
+  fn default() -> MatrixConfig {
+    MatrixConfig {
+      homeserver: "https://matrix.org".to_owned(),
+      access_token: String::new(),
+      since: None,
+      timeout: Duration::from_secs(30),
+      sync_timeout: Duration::from_secs(30),
+      retry_delay: Duration::from_secs(10),
+      max_packet_size: 32 * 1024,
+    }
+  }
+}
+
+/// Talks to the homeserver, shared between the transport and its sync thread.
+#[derive(Clone)]
+struct Client {
+  agent: ureq::Agent,
+  homeserver: String,
+  access_token: String,
+  timeout: Duration,
+}
+
+impl Client {
+  fn request(&self, method: &str, endpoint: &str, timeout: Duration, body: Option<&Value>) -> Result<Value, String> {
+    let url = format!(
+      "{}/_matrix/client/v3/{}",
+      self.homeserver.trim_end_matches('/'),
+      endpoint
+    );
+    let mut tries = 0;
+    loop {
+      let request = self
+        .agent
+        .request(method, &url)
+        .timeout(timeout + self.timeout)
+        .set("Authorization", &format!("Bearer {}", self.access_token));
+      let result = match body {
+        Some(body) => request
+          .set("Content-Type", "application/json")
+          .send_string(&body.to_string()),
+        None => request.call(),
+      };
+      let failed = |e: &dyn Display| format!("{} {}: {}", method, endpoint, e);
+      match result {
+        Ok(response) => {
+          let text = response.into_string().map_err(|e| failed(&e))?;
+          return serde_json::from_str(&text).map_err(|e| failed(&e));
+        }
+        Err(ureq::Error::Status(429, response)) if tries == 0 => {
+          let error = response
+            .into_string()
+            .ok()
+            .and_then(|b| serde_json::from_str::<Value>(&b).ok())
+            .unwrap_or_default();
+          let wait = Duration::from_millis(error["retry_after_ms"].as_u64().unwrap_or(1000));
+          if wait > MAX_RATE_LIMIT_WAIT {
+            return Err(failed(&format!("rate limited for another {:?}", wait)));
+          }
+          sleep(wait);
+          tries += 1;
+        }
+        Err(ureq::Error::Status(code, response)) => {
+          let error = response.into_string().unwrap_or_default();
+          return Err(failed(&format!("status {}: {}", code, error)));
+        }
+        Err(e) => return Err(failed(&e)),
+      }
+    }
+  }
+
+  /// Joins the room, if the account isn't in it already, returning its ID.
+  fn join(&self, room: &str) -> Result<String, String> {
+    let joined = self.request(
+      "POST",
+      &format!("join/{}", encode(room)),
+      Duration::from_secs(0),
+      Some(&json!({})),
+    )?;
+    joined["room_id"]
+      .as_str()
+      .map(str::to_owned)
+      .ok_or_else(|| format!("joining {} didn't say what room it joined", room))
+  }
+
+  /// Gets everything that's happened since the given point, waiting up to the timeout for something to.
+  fn sync(&self, since: Option<&str>, timeout: Duration) -> Result<Value, String> {
+    // only timelines are wanted, and only blobs in them
+    let filter = json!({
+      "presence": { "types": [] },
+      "account_data": { "types": [] },
+      "room": {
+        "state": { "types": [] },
+        "ephemeral": { "types": [] },
+        "account_data": { "types": [] },
+        "timeline": { "types": [EVENT_TYPE], "limit": 100 },
+      },
+    });
+    let mut endpoint = format!(
+      "sync?timeout={}&filter={}",
+      timeout.as_millis(),
+      encode(&filter.to_string())
+    );
+    if let Some(since) = since {
+      endpoint.push_str(&format!("&since={}", encode(since)));
+    }
+    self.request("GET", &endpoint, timeout, None)
+  }
+}
+
+/// Everything the transport and its sync thread share.
+struct Shared {
+  /// The IDs of the rooms being listened on.
+  rooms: Mutex<HashSet<String>>,
+  since: Mutex<Option<String>>,
+  stop: AtomicBool,
+}
+
+/// Pulls every blob out of a sync response, for the rooms being listened on.
+fn blobs_from(sync: &Value, rooms: &HashSet<String>, max_size: usize) -> Vec<Vec<u8>> {
+  let mut blobs = vec![];
+  let joined = match sync["rooms"]["join"].as_object() {
+    Some(joined) => joined,
+    None => return blobs,
+  };
+  for (room, state) in joined {
+    if !rooms.contains(room) {
+      continue;
+    }
+    for event in state["timeline"]["events"].as_array().into_iter().flatten() {
+      if event["type"] != EVENT_TYPE {
+        continue;
+      }
+      let blob = event["content"]["blob"]
+        .as_str()
+        .and_then(|b| STANDARD.decode(b).ok())
+        .filter(|b| b.len() <= max_size);
+      blobs.extend(blob);
+    }
+  }
+  blobs
+}
+
+fn sync_thread(client: Client, config: MatrixConfig, shared: Arc<Shared>, sender: Sender<Vec<u8>>) {
+  while !shared.stop.load(Ordering::SeqCst) {
+    let since = shared.since.lock().expect("poisoned lock?").clone();
+    let sync = match client.sync(since.as_deref(), config.sync_timeout) {
+      Ok(sync) => sync,
+      Err(_) => {
+        sleep(config.retry_delay);
+        continue;
+      }
+    };
+    let blobs = blobs_from(
+      &sync,
+      &shared.rooms.lock().expect("poisoned lock?"),
+      config.max_packet_size,
+    );
+    for blob in blobs {
+      if sender.send(blob).is_err() {
+        // the transport's gone, so there's nobody left to hand them to
+        return;
+      }
+    }
+    if let Some(next) = sync["next_batch"].as_str() {
+      *shared.since.lock().expect("poisoned lock?") = Some(next.to_owned());
+    }
+  }
+}
+
+/// Sends and receives blobs through Matrix rooms, e.g. `matrix:!EXAMPLEroomid:example.org` or `matrix:#mesher:example.org`.
+///
+/// Everything is done through one account on one homeserver, set in the [`MatrixConfig`](struct.MatrixConfig.html).
+/// Rooms are federated, so the room can be on any homeserver, and everyone using it can use their own; homeservers hold on to events until they're fetched, relaying them for nodes that aren't online.
+///
+/// Blobs are sent as events with their own type, `eco.cybers.mesher.blob`, carrying the base64-encoded blob, so everything else in the room is ignored, and they don't show up in most clients.
+/// The account joins each room the first time it's sent to or listened on, so the room has to let it join.
+///
+/// Listening runs a `/sync` long-poll in the background, receiving every blob sent to the rooms listened on after the first one was listened on.
+/// Where it's got up to can be saved with [`since`](#method.since), and passed back through [`MatrixConfig::since`](struct.MatrixConfig.html#structfield.since) after a restart, to pick up everything sent in between.
+/// If a lot is sent to a room while nothing's syncing, only the last 100 events are fetched, and anything before them is lost.
+pub struct Matrix {
+  client: Client,
+  config: MatrixConfig,
+  shared: Arc<Shared>,
+  sender: Sender<Vec<u8>>,
+  receiver: Receiver<Vec<u8>>,
+  /// The IDs of the rooms that have been joined, by the ID or alias they were joined with.
+  joined: HashMap<String, String>,
+  syncing: bool,
+}
+
+impl Matrix {
+  /// Creates a Matrix transport that talks to `config.homeserver` with `config.access_token`.
+  pub fn with_config(_scheme: &str, config: MatrixConfig) -> Matrix {
+    let (sender, receiver) = channel();
+    Matrix {
+      client: Client {
+        agent: ureq::AgentBuilder::new().build(),
+        homeserver: config.homeserver.clone(),
+        access_token: config.access_token.clone(),
+        timeout: config.timeout,
+      },
+      shared: Arc::new(Shared {
+        rooms: Mutex::new(HashSet::new()),
+        since: Mutex::new(config.since.clone()),
+        stop: AtomicBool::new(false),
+      }),
+      config,
+      sender,
+      receiver,
+      joined: HashMap::new(),
+      syncing: false,
+    }
+  }
+
+  /// Where receiving has got up to, to pass back through [`MatrixConfig::since`](struct.MatrixConfig.html#structfield.since) after a restart.
+  /// `None` if nothing's been listened on yet, and there was nowhere to start from configured.
+  pub fn since(&self) -> Option<String> {
+    self.shared.since.lock().expect("poisoned lock?").clone()
+  }
+
+  fn join(&mut self, room: &str) -> Result<String, String> {
+    if let Some(id) = self.joined.get(room) {
+      return Ok(id.clone());
+    }
+    let id = self.client.join(room)?;
+    self.joined.insert(room.to_owned(), id.clone());
+    Ok(id)
+  }
+
+  fn start_syncing(&mut self) -> Result<(), String> {
+    if self.syncing {
+      return Ok(());
+    }
+    // without anywhere to start from, it starts from now, which has to be found out before listening returns
+    if self.since().is_none() {
+      let sync = self.client.sync(None, Duration::from_secs(0))?;
+      let next = sync["next_batch"]
+        .as_str()
+        .ok_or_else(|| "homeserver didn't say where to sync from".to_owned())?;
+      *self.shared.since.lock().expect("poisoned lock?") = Some(next.to_owned());
+    }
+    let (client, config, shared, sender) = (
+      self.client.clone(),
+      self.config.clone(),
+      self.shared.clone(),
+      self.sender.clone(),
+    );
+    Builder::new()
+      .name(format!("Matrix sync {}", self.config.homeserver))
+      .spawn(move || sync_thread(client, config, shared, sender))
+      .map_err(|e| e.to_string())?;
+    self.syncing = true;
+    Ok(())
+  }
+}
+
+impl Transport for Matrix {
+  fn new(scheme: &str) -> fail::Result<Self> {
+    Ok(Matrix::with_config(scheme, MatrixConfig::default()))
+  }
+
+  fn send(&mut self, path: MesherUrl, blob: Vec<u8>) -> fail::Result<()> {
+    let room = room_from_url(&path)?;
+    if blob.len() > self.config.max_packet_size {
+      return Err(fail::MesherFail::SendFailure(format!(
+        "Blob of {} bytes is larger than the {} allowed",
+        blob.len(),
+        self.config.max_packet_size
+      )));
+    }
+    let send_fail = |e| fail::MesherFail::SendFailure(format!("Failed to send to {}: {}", path, e));
+    let id = self.join(&room).map_err(send_fail)?;
+    // the homeserver only sends the event once per transaction ID, even if the request's retried
+    let txn = format!("{:016x}{:016x}", rand::random::<u64>(), rand::random::<u64>());
+    let endpoint = format!("rooms/{}/send/{}/{}", encode(&id), EVENT_TYPE, txn);
+    let content = json!({ "blob": STANDARD.encode(&blob) });
+    self
+      .client
+      .request("PUT", &endpoint, Duration::from_secs(0), Some(&content))
+      .map_err(send_fail)?;
+    Ok(())
+  }
+
+  fn listen(&mut self, path: MesherUrl) -> fail::Result<()> {
+    let room = room_from_url(&path)?;
+    let listen_fail = |e| fail::MesherFail::ListenFailure(format!("Failed to listen on {}: {}", path, e));
+    let id = self.join(&room).map_err(listen_fail)?;
+    // it's added first so that nothing sent there is missed by a sync that's already going
+    let added = self.shared.rooms.lock().expect("poisoned lock?").insert(id.clone());
+    if let Err(e) = self.start_syncing() {
+      if added {
+        self.shared.rooms.lock().expect("poisoned lock?").remove(&id);
+      }
+      return Err(listen_fail(e));
+    }
+    Ok(())
+  }
+
+  fn receive(&mut self) -> fail::Result<Vec<Vec<u8>>> {
+    Ok(self.receiver.try_iter().collect())
+  }
+}
+
+impl Drop for Matrix {
+  fn drop(&mut self) {
+    // the sync thread notices once its current request is done
+    self.shared.stop.store(true, Ordering::SeqCst);
+  }
+}
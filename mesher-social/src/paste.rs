@@ -0,0 +1,279 @@
+use mesher::prelude::*;
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use std::{
+  collections::HashSet,
+  fmt::Display,
+  time::{Duration, Instant},
+};
+
+/// Starts every paste carrying a blob, so anything else in a listing can be ignored.
+const MARKER: &str = "mesher";
+
+/// Whether a listing's name or a paste's ID can be put into a URL as-is, without letting it point anywhere else.
+fn is_safe(piece: &str) -> bool {
+  !piece.is_empty()
+    && piece != "."
+    && piece != ".."
+    && piece
+      .bytes()
+      .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_' || b == b'.' || b == b'~')
+}
+
+fn to_text(blob: &[u8]) -> String {
+  format!("{} {}", MARKER, STANDARD.encode(blob))
+}
+
+/// Gets the blob back out of a paste, if it's carrying one that isn't too large.
+fn from_text(text: &str, max_size: usize) -> Option<Vec<u8>> {
+  // services tend to add a newline at the end, or mangle whitespace in other ways
+  let mut words = text.split_whitespace();
+  let encoded = match (words.next(), words.next(), words.next()) {
+    (Some(MARKER), encoded, None) => encoded.unwrap_or_default(),
+    _ => return None,
+  };
+  if encoded.len() / 4 * 3 > max_size + 2 {
+    return None;
+  }
+  STANDARD.decode(encoded).ok().filter(|blob| blob.len() <= max_size)
+}
+
+/// A paste service that the [`Paste`](struct.Paste.html) transport posts blobs to and reads them back from.
+///
+/// Every path names a listing: some set of pastes that can be read back by anyone who knows where it is, like an account's pastes or a tag.
+/// [`HttpPaste`](struct.HttpPaste.html) is the one used by default, but paste services all have their own APIs, so implementing this is how to use any other, e.g. a self-hosted PrivateBin.
+/// The text that's posted is already printable ASCII, and only pastes that are still in the listing are ever fetched, so services which expire pastes are fine.
+pub trait PasteService: Send {
+  /// Checks whether the path names a listing this service can use, failing with `InvalidURL` if it doesn't.
+  fn check_path(&self, path: &MesherUrl) -> fail::Result<()>;
+  /// Posts a new paste with the text to the listing.
+  fn post(&mut self, path: &MesherUrl, text: &str) -> fail::Result<()>;
+  /// Gets the IDs of every paste currently in the listing, in any order.
+  fn list(&mut self, path: &MesherUrl) -> fail::Result<Vec<String>>;
+  /// Gets the text of a paste in the listing, or `None` if it's gone since it was listed.
+  fn fetch(&mut self, path: &MesherUrl, id: &str) -> fail::Result<Option<String>>;
+}
+
+/// A [`PasteService`](trait.PasteService.html) speaking a minimal REST API, which is easy to self-host or put in front of something else.
+///
+/// Paths look like `paste:paste.example.com/listing`, naming the listing at `https://paste.example.com/listing`:
+///
+/// - `POST` to the listing's URL adds a paste with the request body as its text.
+///   Any 2xx response means it was added.
+/// - `GET` on the listing's URL responds with the IDs of every paste in it, one per line.
+///   An empty or unknown listing is just an empty response.
+/// - `GET` on the listing's URL, followed by `/` and the ID, responds with the paste's text, or 404 if there's no such paste.
+///
+/// Anyone who knows a listing's URL can read everything in it, so its name should be long and random, like a shared secret.
+pub struct HttpPaste {
+  agent: ureq::Agent,
+  scheme: &'static str,
+}
+
+impl HttpPaste {
+  /// Creates a service which talks to paste servers over HTTPS, giving up on requests after the timeout.
+  pub fn new(timeout: Duration) -> HttpPaste {
+    HttpPaste {
+      agent: ureq::AgentBuilder::new().timeout(timeout).build(),
+      scheme: "https",
+    }
+  }
+
+  /// Creates a service which talks to paste servers over plain HTTP, e.g. for one running on the same machine.
+  pub fn plain(timeout: Duration) -> HttpPaste {
+    HttpPaste {
+      scheme: "http",
+      ..HttpPaste::new(timeout)
+    }
+  }
+
+  fn listing_url(&self, path: &MesherUrl) -> fail::Result<String> {
+    self.check_path(path)?;
+    Ok(format!("{}://{}{}", self.scheme, path.authority(), path.path()))
+  }
+}
+
+impl Default for HttpPaste {
+  fn default() -> HttpPaste {
+    HttpPaste::new(Duration::from_secs(30))
+  }
+}
+
+impl PasteService for HttpPaste {
+  fn check_path(&self, path: &MesherUrl) -> fail::Result<()> {
+    let listing = path.path().strip_prefix('/').unwrap_or_default();
+    if path.host().is_empty() || path.authority().contains('@') || !listing.split('/').all(is_safe) {
+      return Err(fail::MesherFail::InvalidURL(format!(
+        "Paste paths look like paste:paste.example.com/listing, not {}",
+        path
+      )));
+    }
+    Ok(())
+  }
+
+  fn post(&mut self, path: &MesherUrl, text: &str) -> fail::Result<()> {
+    let url = self.listing_url(path)?;
+    self
+      .agent
+      .post(&url)
+      .set("Content-Type", "text/plain")
+      .send_string(text)
+      .map_err(|e| fail::MesherFail::SendFailure(format!("Failed to post paste to {}: {}", url, e)))?;
+    Ok(())
+  }
+
+  fn list(&mut self, path: &MesherUrl) -> fail::Result<Vec<String>> {
+    let url = self.listing_url(path)?;
+    let failed = |e: &dyn Display| fail::MesherFail::ReceiveFailure(format!("Failed to list pastes in {}: {}", url, e));
+    let listing = self
+      .agent
+      .get(&url)
+      .call()
+      .map_err(|e| failed(&e))?
+      .into_string()
+      .map_err(|e| failed(&e))?;
+    Ok(
+      listing
+        .lines()
+        .map(str::trim)
+        .filter(|id| !id.is_empty())
+        .map(str::to_owned)
+        .collect(),
+    )
+  }
+
+  fn fetch(&mut self, path: &MesherUrl, id: &str) -> fail::Result<Option<String>> {
+    if !is_safe(id) {
+      // the server listed it, but it can't be fetched without going somewhere else entirely
+      return Ok(None);
+    }
+    let url = format!("{}/{}", self.listing_url(path)?, id);
+    let failed = |e: &dyn Display| fail::MesherFail::ReceiveFailure(format!("Failed to fetch {}: {}", url, e));
+    let response = match self.agent.get(&url).call() {
+      Ok(response) => response,
+      Err(ureq::Error::Status(404, _)) => return Ok(None),
+      Err(e) => return Err(failed(&e)),
+    };
+    response.into_string().map(Some).map_err(|e| failed(&e))
+  }
+}
+
+/// Settings for a [`Paste`](struct.Paste.html) transport which apply to every listing it uses.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PasteConfig {
+  /// How often listings are checked for new pastes.
+  /// Calling `receive` more often than this returns nothing, rather than hammering the service.
+  pub poll_interval: Duration,
+  /// The largest blob that will be accepted from a paste.
+  /// Pastes carrying larger ones are ignored.
+  pub max_packet_size: usize,
+}
+
+impl Default for PasteConfig {
+  fn default() -> PasteConfig {
+    PasteConfig {
+      poll_interval: Duration::from_secs(60),
+      max_packet_size: 512 * 1024,
+    }
+  }
+}
+
+/// Sends and receives blobs through a paste service, as pastes in a listing both sides know about.
+///
+/// Sending posts the blob as a new paste, base64-encoded behind a short marker.
+/// Receiving lists every listing it's listening on, and fetches each paste it hasn't seen before; anything in them that isn't carrying a blob is ignored.
+/// Pastes usually can't be deleted by whoever reads them, so they're left where they are, and forgotten about once they drop out of the listing.
+/// That also means a freshly created transport picks up everything that's still in its listings, including blobs it's seen before it was restarted.
+///
+/// By default it talks to servers speaking [`HttpPaste`](struct.HttpPaste.html)'s API, but any other service can be used by implementing [`PasteService`](trait.PasteService.html) for it:
+///
+/// ```
+/// use mesher::prelude::*;
+/// use mesher_social::{HttpPaste, Paste, PasteConfig};
+/// use std::time::Duration;
+///
+/// let service = HttpPaste::new(Duration::from_secs(10));
+/// let mut m = Mesher::unsigned(vec![encrypt::gen_keypair().1]);
+/// m.add_transport_instance("paste", Paste::with_service("paste", service, PasteConfig::default()));
+/// ```
+///
+/// Listings are only checked every [`PasteConfig::poll_interval`](struct.PasteConfig.html#structfield.poll_interval), and one that can't be reached is skipped until the next check.
+pub struct Paste {
+  service: Box<dyn PasteService>,
+  config: PasteConfig,
+  /// Each listing being listened on, with the IDs of the pastes in it that have already been fetched.
+  listings: Vec<(MesherUrl, HashSet<String>)>,
+  last_poll: Option<Instant>,
+}
+
+impl Paste {
+  /// Creates a paste transport that posts to the default service, [`HttpPaste`].
+  pub fn with_config(scheme: &str, config: PasteConfig) -> Paste {
+    Paste::with_service(scheme, HttpPaste::default(), config)
+  }
+
+  /// Creates a paste transport that posts to `service` instead, e.g. a self-hosted one.
+  pub fn with_service<S: PasteService + 'static>(_scheme: &str, service: S, config: PasteConfig) -> Paste {
+    Paste {
+      service: Box::new(service),
+      config,
+      listings: vec![],
+      last_poll: None,
+    }
+  }
+
+  /// Fetches every paste in the listing that hasn't been seen yet, and forgets about any that have left it.
+  fn collect(&mut self, index: usize, blobs: &mut Vec<Vec<u8>>) -> fail::Result<()> {
+    let (path, seen) = &mut self.listings[index];
+    let listed = self.service.list(path)?.into_iter().collect::<HashSet<_>>();
+    seen.retain(|id| listed.contains(id));
+    for id in listed {
+      if seen.contains(&id) {
+        continue;
+      }
+      // a paste that couldn't be fetched is tried again next time, rather than marked as seen
+      if let Some(text) = self.service.fetch(path, &id)? {
+        blobs.extend(from_text(&text, self.config.max_packet_size));
+      }
+      seen.insert(id);
+    }
+    Ok(())
+  }
+}
+
+impl Transport for Paste {
+  fn new(scheme: &str) -> fail::Result<Self> {
+    Ok(Paste::with_config(scheme, PasteConfig::default()))
+  }
+
+  fn send(&mut self, path: MesherUrl, blob: Vec<u8>) -> fail::Result<()> {
+    self.service.check_path(&path)?;
+    self.service.post(&path, &to_text(&blob))
+  }
+
+  fn listen(&mut self, path: MesherUrl) -> fail::Result<()> {
+    self.service.check_path(&path)?;
+    if !self.listings.iter().any(|(p, _)| *p == path) {
+      self.listings.push((path, HashSet::new()));
+      // check the new listing straight away, rather than waiting for the next poll
+      self.last_poll = None;
+    }
+    Ok(())
+  }
+
+  fn receive(&mut self) -> fail::Result<Vec<Vec<u8>>> {
+    if let Some(last) = self.last_poll {
+      if last.elapsed() < self.config.poll_interval {
+        return Ok(vec![]);
+      }
+    }
+    self.last_poll = Some(Instant::now());
+
+    let mut blobs = vec![];
+    for index in 0..self.listings.len() {
+      // the service might be down for a while, so the listing is just skipped until it's back
+      let _ = self.collect(index, &mut blobs);
+    }
+    Ok(blobs)
+  }
+}
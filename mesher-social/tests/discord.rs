@@ -0,0 +1,474 @@
+use mesher::{prelude::*, test_support::url};
+use mesher_social::{Discord, DiscordConfig};
+
+use serde_json::{json, Value};
+use std::{
+  collections::HashMap,
+  net::SocketAddr,
+  sync::{Arc, Mutex},
+  thread,
+  time::{Duration, Instant},
+};
+use tiny_http::{Header, Method, Request, Response, Server};
+
+const TOKEN: &str = "bot-token";
+const CHANNEL: u64 = 111_111_111_111_111_111;
+const WEBHOOK: u64 = 222_222_222_222_222_222;
+const WEBHOOK_TOKEN: &str = "webhook-token";
+
+fn channel_path() -> MesherUrl {
+  url(&format!("discord:{}", CHANNEL))
+}
+
+fn webhook_path() -> MesherUrl {
+  url(&format!("discord:{}/{}/{}", CHANNEL, WEBHOOK, WEBHOOK_TOKEN))
+}
+
+struct Message {
+  id: u64,
+  content: String,
+  attachment: Option<Vec<u8>>,
+}
+
+/// A rate limit on one route: how many requests can be made per window, and how many have been made in this one.
+struct Bucket {
+  remaining: usize,
+  reset: Instant,
+}
+
+#[derive(Default)]
+struct MockState {
+  messages: Vec<Message>,
+  last_id: u64,
+  /// How many requests each route allows per window, or `None` for no limit.
+  limit: Option<(usize, Duration)>,
+  buckets: HashMap<String, Bucket>,
+  /// How many of the next requests to rate limit anyway, and for how long, like a global rate limit.
+  forced: Option<(usize, f64)>,
+  rate_limited: usize,
+  attachments_posted: usize,
+  /// The status attachments are served with instead of their contents, if any.
+  attachment_error: Option<u16>,
+}
+
+impl MockState {
+  fn add(&mut self, content: &str, attachment: Option<Vec<u8>>) {
+    // real IDs are mostly a timestamp, but all that matters is that they go up
+    self.last_id += 1;
+    self.messages.push(Message {
+      id: self.last_id,
+      content: content.to_owned(),
+      attachment,
+    });
+  }
+
+  /// Whether the request has to be rate limited, and the headers describing its route's limit if not.
+  fn check_limit(&mut self, route: &str) -> Result<Vec<Header>, Response<std::io::Cursor<Vec<u8>>>> {
+    if let Some((count, seconds)) = self.forced {
+      if count > 0 {
+        self.forced = Some((count - 1, seconds));
+        self.rate_limited += 1;
+        let body = json!({ "message": "You are being rate limited.", "retry_after": seconds, "global": true });
+        return Err(
+          Response::from_string(body.to_string())
+            .with_status_code(429)
+            .with_header(Header::from_bytes("X-RateLimit-Global", "true").unwrap()),
+        );
+      }
+    }
+    let (limit, window) = match self.limit {
+      Some(limit) => limit,
+      None => return Ok(vec![]),
+    };
+    let now = Instant::now();
+    let bucket = self.buckets.entry(route.to_owned()).or_insert(Bucket {
+      remaining: limit,
+      reset: now + window,
+    });
+    if now >= bucket.reset {
+      bucket.remaining = limit;
+      bucket.reset = now + window;
+    }
+    let reset_after = bucket.reset.saturating_duration_since(now).as_secs_f64();
+    if bucket.remaining == 0 {
+      self.rate_limited += 1;
+      let body = json!({ "message": "You are being rate limited.", "retry_after": reset_after, "global": false });
+      return Err(Response::from_string(body.to_string()).with_status_code(429));
+    }
+    bucket.remaining -= 1;
+    Ok(vec![
+      Header::from_bytes("X-RateLimit-Limit", limit.to_string()).unwrap(),
+      Header::from_bytes("X-RateLimit-Remaining", bucket.remaining.to_string()).unwrap(),
+      Header::from_bytes("X-RateLimit-Reset-After", format!("{:.3}", reset_after)).unwrap(),
+    ])
+  }
+}
+
+/// Just enough of Discord's API to test against: one channel, with a webhook posting to it.
+struct MockDiscord {
+  addr: SocketAddr,
+  state: Arc<Mutex<MockState>>,
+}
+
+fn header<'r>(request: &'r Request, name: &str) -> Option<&'r str> {
+  request
+    .headers()
+    .iter()
+    .find(|h| h.field.as_str().as_str().eq_ignore_ascii_case(name))
+    .map(|h| h.value.as_str())
+}
+
+/// Pulls the message out of a post, either JSON or multipart with the blob attached.
+fn read_post(request: &mut Request) -> (String, Option<Vec<u8>>) {
+  let content_type = header(request, "Content-Type").unwrap_or_default().to_owned();
+  let mut body = vec![];
+  request.as_reader().read_to_end(&mut body).unwrap();
+  if content_type == "application/json" {
+    let json: Value = serde_json::from_slice(&body).unwrap();
+    return (json["content"].as_str().unwrap().to_owned(), None);
+  }
+  let boundary = content_type.split_once("boundary=").unwrap().1;
+  let delimiter = format!("\r\n--{}", boundary);
+  let find = |haystack: &[u8], needle: &[u8]| haystack.windows(needle.len()).position(|w| w == needle);
+  let mut content = String::new();
+  let mut attachment = None;
+  let mut rest = &body[..];
+  while let Some(start) = find(rest, b"Content-Disposition") {
+    rest = &rest[start..];
+    let headers_end = find(rest, b"\r\n\r\n").unwrap();
+    let headers = String::from_utf8_lossy(&rest[..headers_end]).into_owned();
+    let data = &rest[headers_end + 4..];
+    let end = find(data, delimiter.as_bytes()).unwrap();
+    if headers.contains("name=\"payload_json\"") {
+      let json: Value = serde_json::from_slice(&data[..end]).unwrap();
+      content = json["content"].as_str().unwrap().to_owned();
+    } else if headers.contains("filename=\"mesher.bin\"") {
+      attachment = Some(data[..end].to_vec());
+    }
+    rest = &data[end..];
+  }
+  (content, attachment)
+}
+
+impl MockDiscord {
+  fn start() -> MockDiscord {
+    let server = Server::http("127.0.0.1:0").expect("Failed to start mock server");
+    let addr = server.server_addr().to_ip().expect("Mock server isn't on IP");
+    let state = Arc::new(Mutex::new(MockState::default()));
+    let server_state = state.clone();
+    thread::spawn(move || {
+      for mut request in server.incoming_requests() {
+        let response = handle(&mut request, addr, &server_state);
+        let _ = request.respond(response);
+      }
+    });
+    MockDiscord { addr, state }
+  }
+
+  fn config(&self, token: Option<&str>) -> DiscordConfig {
+    DiscordConfig {
+      token: token.map(str::to_owned),
+      api_base: format!("http://{}/api", self.addr),
+      timeout: Duration::from_secs(5),
+      poll_interval: Duration::from_secs(0),
+      ..DiscordConfig::default()
+    }
+  }
+
+  fn bot(&self) -> Discord {
+    Discord::with_config("discord", self.config(Some(TOKEN)))
+  }
+
+  /// Posts a message directly, like someone else in the channel.
+  fn add(&self, content: &str) {
+    self.state.lock().unwrap().add(content, None);
+  }
+
+  fn limit(&self, count: usize, window: Duration) {
+    self.state.lock().unwrap().limit = Some((count, window));
+  }
+
+  fn force_rate_limit(&self, count: usize, seconds: f64) {
+    self.state.lock().unwrap().forced = Some((count, seconds));
+  }
+
+  fn rate_limited(&self) -> usize {
+    self.state.lock().unwrap().rate_limited
+  }
+
+  fn attachments_posted(&self) -> usize {
+    self.state.lock().unwrap().attachments_posted
+  }
+
+  fn fail_attachments(&self, status: Option<u16>) {
+    self.state.lock().unwrap().attachment_error = status;
+  }
+}
+
+fn handle(request: &mut Request, addr: SocketAddr, state: &Mutex<MockState>) -> Response<std::io::Cursor<Vec<u8>>> {
+  let (path, query) = {
+    let url = request.url().to_owned();
+    match url.split_once('?') {
+      Some((path, query)) => (path.to_owned(), query.to_owned()),
+      None => (url, String::new()),
+    }
+  };
+  let as_bot = header(request, "Authorization") == Some(&format!("Bot {}", TOKEN));
+  let channel = format!("/api/channels/{}/messages", CHANNEL);
+  let webhook = format!("/api/webhooks/{}/{}", WEBHOOK, WEBHOOK_TOKEN);
+
+  if let Some(id) = path.strip_prefix("/attachments/") {
+    let state = state.lock().unwrap();
+    if let Some(status) = state.attachment_error {
+      return Response::from_data(vec![]).with_status_code(status);
+    }
+    let id = id.parse::<u64>().unwrap();
+    let message = state.messages.iter().find(|m| m.id == id).unwrap();
+    return Response::from_data(message.attachment.clone().unwrap());
+  }
+  let route = match (request.method(), path.as_str()) {
+    (Method::Post, p) if p == channel && as_bot => "post",
+    (Method::Get, p) if p == channel && as_bot => "history",
+    (Method::Post, p) if p == webhook => "webhook",
+    (_, p) if p == channel => return Response::from_string("401: Unauthorized").with_status_code(401),
+    _ => return Response::from_string("404: Not Found").with_status_code(404),
+  };
+  let mut headers = match state.lock().unwrap().check_limit(route) {
+    Ok(headers) => headers,
+    Err(response) => return response,
+  };
+  headers.push(Header::from_bytes("Content-Type", "application/json").unwrap());
+
+  let body = if route == "history" {
+    let param = |name: &str| {
+      query
+        .split('&')
+        .find_map(|p| p.strip_prefix(&format!("{}=", name)))
+        .and_then(|v| v.parse::<u64>().ok())
+    };
+    let limit = param("limit").unwrap_or(50) as usize;
+    let state = state.lock().unwrap();
+    // the oldest messages after the one given, or else the newest ones
+    let chosen = match param("after") {
+      Some(after) => state
+        .messages
+        .iter()
+        .filter(|m| m.id > after)
+        .take(limit)
+        .collect::<Vec<_>>(),
+      None => state.messages.iter().rev().take(limit).rev().collect(),
+    };
+    let mut page = chosen
+      .into_iter()
+      .map(|m| {
+        let attachments = match &m.attachment {
+          Some(a) => json!([{
+            "id": m.id.to_string(),
+            "filename": "mesher.bin",
+            "size": a.len(),
+            "url": format!("http://{}/attachments/{}", addr, m.id),
+          }]),
+          None => json!([]),
+        };
+        json!({ "id": m.id.to_string(), "content": m.content, "attachments": attachments })
+      })
+      .collect::<Vec<_>>();
+    page.reverse();
+    Value::Array(page)
+  } else {
+    let (content, attachment) = read_post(request);
+    let mut state = state.lock().unwrap();
+    if attachment.is_some() {
+      state.attachments_posted += 1;
+    }
+    state.add(&content, attachment);
+    json!({ "id": state.last_id.to_string(), "content": content })
+  };
+  let mut response = Response::from_string(body.to_string());
+  for header in headers {
+    response.add_header(header);
+  }
+  response
+}
+
+#[test]
+fn round_trip() {
+  let server = MockDiscord::start();
+  let mut receiver = server.bot();
+  receiver.listen(channel_path()).expect("Failed to listen");
+  let mut sender = server.bot();
+
+  let blobs = vec![
+    vec![],
+    vec![1; 10],
+    (0..1400).map(|i| i as u8).collect::<Vec<_>>(),
+    (0..10_000).map(|i| (i * 7) as u8).collect::<Vec<_>>(),
+  ];
+  for blob in &blobs {
+    sender.send(channel_path(), blob.clone()).expect("Failed to send");
+  }
+  assert_eq!(receiver.receive().expect("Failed to receive"), blobs);
+  assert_eq!(server.attachments_posted(), 1);
+  assert!(receiver.receive().expect("Failed to receive").is_empty());
+}
+
+#[test]
+fn through_webhook() {
+  let server = MockDiscord::start();
+  let mut receiver = server.bot();
+  receiver.listen(webhook_path()).expect("Failed to listen");
+  let mut sender = Discord::with_config("discord", server.config(None));
+
+  sender.send(webhook_path(), vec![1, 2, 3]).expect("Failed to send");
+  sender.send(webhook_path(), vec![4; 5000]).expect("Failed to send");
+  assert_eq!(
+    receiver.receive().expect("Failed to receive"),
+    vec![vec![1, 2, 3], vec![4; 5000]]
+  );
+}
+
+#[test]
+fn only_new_messages() {
+  let server = MockDiscord::start();
+  let mut sender = server.bot();
+  sender.send(channel_path(), vec![1]).expect("Failed to send");
+  let mut receiver = server.bot();
+  receiver.listen(channel_path()).expect("Failed to listen");
+  sender.send(channel_path(), vec![2]).expect("Failed to send");
+  assert_eq!(receiver.receive().expect("Failed to receive"), vec![vec![2]]);
+}
+
+#[test]
+fn history_paged_through() {
+  let server = MockDiscord::start();
+  let mut receiver = server.bot();
+  receiver.listen(channel_path()).expect("Failed to listen");
+
+  let blobs = (0..250u32).map(|i| i.to_be_bytes().to_vec()).collect::<Vec<_>>();
+  for blob in &blobs {
+    let encoded = base64::Engine::encode(&base64::engine::general_purpose::STANDARD, blob);
+    server.add(&format!("mesher {}", encoded));
+  }
+  assert_eq!(receiver.receive().expect("Failed to receive"), blobs);
+}
+
+#[test]
+fn missing_attachments_skipped() {
+  let server = MockDiscord::start();
+  let mut receiver = server.bot();
+  receiver.listen(channel_path()).expect("Failed to listen");
+  let mut sender = server.bot();
+
+  server.fail_attachments(Some(404));
+  sender.send(channel_path(), vec![1; 5000]).expect("Failed to send");
+  sender.send(channel_path(), vec![2]).expect("Failed to send");
+  assert_eq!(receiver.receive().expect("Failed to receive"), vec![vec![2]]);
+  server.fail_attachments(None);
+  assert!(receiver.receive().expect("Failed to receive").is_empty());
+}
+
+#[test]
+fn unavailable_attachments_retried() {
+  let server = MockDiscord::start();
+  let mut receiver = server.bot();
+  receiver.listen(channel_path()).expect("Failed to listen");
+  let mut sender = server.bot();
+
+  server.fail_attachments(Some(503));
+  sender.send(channel_path(), vec![1; 5000]).expect("Failed to send");
+  sender.send(channel_path(), vec![2]).expect("Failed to send");
+  assert!(receiver.receive().expect("Failed to receive").is_empty());
+  server.fail_attachments(None);
+  assert_eq!(
+    receiver.receive().expect("Failed to receive"),
+    vec![vec![1; 5000], vec![2]]
+  );
+}
+
+#[test]
+fn chatter_ignored() {
+  let server = MockDiscord::start();
+  let mut receiver = server.bot();
+  receiver.listen(channel_path()).expect("Failed to listen");
+
+  server.add("hello everyone");
+  server.add("mesher");
+  server.add("mesher !!!not base64!!!");
+  server.add("meshers AQID");
+  server.add("mesher AQID");
+  assert_eq!(receiver.receive().expect("Failed to receive"), vec![vec![1, 2, 3]]);
+}
+
+#[test]
+fn rate_limits_kept_to() {
+  let server = MockDiscord::start();
+  server.limit(2, Duration::from_millis(300));
+  let mut sender = server.bot();
+  let mut receiver = server.bot();
+  receiver.listen(channel_path()).expect("Failed to listen");
+
+  for i in 0..5 {
+    sender.send(channel_path(), vec![i]).expect("Failed to send");
+  }
+  assert_eq!(
+    receiver.receive().expect("Failed to receive"),
+    (0..5).map(|i| vec![i]).collect::<Vec<_>>()
+  );
+  assert_eq!(server.rate_limited(), 0);
+}
+
+#[test]
+fn rate_limited_anyway() {
+  let server = MockDiscord::start();
+  let mut t = server.bot();
+  t.listen(channel_path()).expect("Failed to listen");
+
+  server.force_rate_limit(1, 0.1);
+  t.send(channel_path(), vec![1]).expect("Failed to send");
+  assert_eq!(server.rate_limited(), 1);
+
+  server.force_rate_limit(1, 60.0);
+  let start = Instant::now();
+  match t.send(channel_path(), vec![2]) {
+    Err(fail::MesherFail::SendFailure(_)) => (),
+    other => panic!("Sent while rate limited for a minute: {:?}", other),
+  }
+  assert!(start.elapsed() < Duration::from_secs(5));
+  // the limit was global, so reading has to wait too
+  assert!(t.receive().expect("Failed to receive").is_empty());
+  assert_eq!(server.rate_limited(), 2);
+}
+
+#[test]
+fn no_token() {
+  let server = MockDiscord::start();
+  let mut t = Discord::with_config("discord", server.config(None));
+
+  match t.listen(channel_path()) {
+    Err(fail::MesherFail::ListenFailure(_)) => (),
+    other => panic!("Listened without a bot token: {:?}", other),
+  }
+  match t.send(channel_path(), vec![1]) {
+    Err(fail::MesherFail::SendFailure(_)) => (),
+    other => panic!("Sent without a bot token or webhook: {:?}", other),
+  }
+}
+
+#[test]
+fn invalid_paths() {
+  let mut t = Discord::new("discord").expect("Failed to create");
+  for path in &[
+    "discord:general",
+    "discord:/123",
+    "discord:123/456",
+    "discord:123/456/",
+    "discord:123/abc/token",
+    "discord:123/456/tok%2Fen",
+    "discord:123/456/token/extra",
+  ] {
+    match t.send(url(path), vec![1]) {
+      Err(fail::MesherFail::InvalidURL(_)) => (),
+      other => panic!("Sent to {}: {:?}", path, other),
+    }
+  }
+}
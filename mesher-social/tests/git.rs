@@ -0,0 +1,259 @@
+use mesher::{prelude::*, test_support::url};
+use mesher_social::{Git, GitConfig};
+
+use std::{
+  fs,
+  io::prelude::*,
+  path::{Path, PathBuf},
+  process::{Command, Stdio},
+  thread,
+  time::Duration,
+};
+
+/// A fresh directory for the test, under the given name.
+fn test_dir(test: &str, name: &str) -> PathBuf {
+  let dir = std::env::temp_dir().join(format!("mesher-git-test-{}-{}-{}", test, name, std::process::id()));
+  let _ = fs::remove_dir_all(&dir);
+  dir
+}
+
+/// Runs git in the repository, returning what it prints.
+fn git(repo: &Path, args: &[&str], input: &str) -> String {
+  let mut child = Command::new("git")
+    .arg("--git-dir")
+    .arg(repo)
+    .args(args)
+    .env("GIT_AUTHOR_NAME", "carol")
+    .env("GIT_AUTHOR_EMAIL", "carol@example.com")
+    .env("GIT_COMMITTER_NAME", "carol")
+    .env("GIT_COMMITTER_EMAIL", "carol@example.com")
+    .stdin(Stdio::piped())
+    .stdout(Stdio::piped())
+    .spawn()
+    .expect("Failed to run git");
+  child
+    .stdin
+    .take()
+    .expect("No stdin")
+    .write_all(input.as_bytes())
+    .expect("Failed to write to git");
+  let output = child.wait_with_output().expect("Failed to run git");
+  assert!(output.status.success(), "git {:?} failed", args);
+  String::from_utf8(output.stdout)
+    .expect("git printed non-UTF-8")
+    .trim()
+    .to_owned()
+}
+
+/// A fresh, empty bare repository, standing in for one on a code hosting site.
+fn bare_repo(test: &str) -> PathBuf {
+  let repo = test_dir(test, "remote");
+  let status = Command::new("git")
+    .args(["init", "--bare", "--quiet"])
+    .arg(&repo)
+    .status()
+    .expect("Failed to run git");
+  assert!(status.success());
+  repo
+}
+
+fn config(repo: &Path) -> GitConfig {
+  let mut config = GitConfig {
+    timeout: Duration::from_secs(10),
+    poll_interval: Duration::from_secs(0),
+    ..GitConfig::default()
+  };
+  config
+    .repositories
+    .insert("drop".to_owned(), repo.to_string_lossy().into_owned());
+  config
+}
+
+fn transport(repo: &Path) -> Git {
+  Git::with_config("git", config(repo))
+}
+
+/// Commits a file to the branch some other way, like someone using the repository for something else would.
+fn commit_file(repo: &Path, branch: &str, name: &str, contents: &str) {
+  let blob = git(repo, &["hash-object", "-w", "--stdin"], contents);
+  let tree = git(repo, &["mktree"], &format!("100644 blob {}\t{}\n", blob, name));
+  let head = format!("refs/heads/{}", branch);
+  let parent = git(repo, &["for-each-ref", "--format=%(objectname)", &head], "");
+  let mut args = vec!["commit-tree", &tree, "-m", "Unrelated"];
+  if !parent.is_empty() {
+    args.extend(["-p", &parent]);
+  }
+  let commit = git(repo, &args, "");
+  git(repo, &["update-ref", &head, &commit], "");
+}
+
+fn commit_count(repo: &Path, branch: &str) -> usize {
+  git(repo, &["rev-list", "--count", branch], "")
+    .parse()
+    .expect("Invalid count")
+}
+
+#[test]
+fn round_trip() {
+  let repo = bare_repo("round-trip");
+  let mut sender = transport(&repo);
+  let mut receiver = transport(&repo);
+  receiver.listen(url("git:drop/notes")).expect("Failed to listen");
+
+  let blobs = vec![vec![], vec![1; 10], vec![2; 100_000]];
+  for blob in &blobs {
+    sender
+      .send(url("git:drop/notes"), blob.clone())
+      .expect("Failed to send");
+  }
+  assert_eq!(commit_count(&repo, "notes"), 3);
+  assert_eq!(receiver.receive().expect("Failed to receive"), blobs);
+  assert!(receiver.receive().expect("Failed to receive").is_empty());
+
+  sender.send(url("git:drop/notes"), vec![3]).expect("Failed to send");
+  assert_eq!(receiver.receive().expect("Failed to receive"), vec![vec![3]]);
+}
+
+#[test]
+fn only_new_commits() {
+  let repo = bare_repo("only-new");
+  let mut sender = transport(&repo);
+  sender.send(url("git:drop/notes"), vec![1]).expect("Failed to send");
+
+  let mut receiver = transport(&repo);
+  receiver.listen(url("git:drop/notes")).expect("Failed to listen");
+  assert!(receiver.receive().expect("Failed to receive").is_empty());
+  sender.send(url("git:drop/notes"), vec![2]).expect("Failed to send");
+  assert_eq!(receiver.receive().expect("Failed to receive"), vec![vec![2]]);
+}
+
+#[test]
+fn branches_kept_apart() {
+  let repo = bare_repo("branches");
+  let mut sender = transport(&repo);
+  let mut receiver = transport(&repo);
+  receiver.listen(url("git:drop/alice")).expect("Failed to listen");
+
+  sender.send(url("git:drop/alice"), vec![1]).expect("Failed to send");
+  sender.send(url("git:drop/bob"), vec![2]).expect("Failed to send");
+  assert_eq!(receiver.receive().expect("Failed to receive"), vec![vec![1]]);
+  assert_eq!(commit_count(&repo, "bob"), 1);
+}
+
+#[test]
+fn other_commits_skipped() {
+  let repo = bare_repo("other");
+  let mut sender = transport(&repo);
+  let mut receiver = Git::with_config(
+    "git",
+    GitConfig {
+      max_packet_size: 100,
+      ..config(&repo)
+    },
+  );
+  receiver.listen(url("git:drop/notes")).expect("Failed to listen");
+
+  commit_file(&repo, "notes", "README.md", "Nothing to see here.\n");
+  sender
+    .send(url("git:drop/notes"), vec![1; 101])
+    .expect("Failed to send");
+  commit_file(&repo, "notes", "mesher.txt", "Still nothing.\n");
+  sender
+    .send(url("git:drop/notes"), vec![2; 100])
+    .expect("Failed to send");
+  assert_eq!(receiver.receive().expect("Failed to receive"), vec![vec![2; 100]]);
+  assert_eq!(commit_count(&repo, "notes"), 4);
+}
+
+#[test]
+fn resumed_from_kept_cache() {
+  let repo = bare_repo("resumed");
+  let cache = test_dir("resumed", "cache");
+  let kept = GitConfig {
+    cache_dir: Some(cache.clone()),
+    ..config(&repo)
+  };
+  let mut sender = transport(&repo);
+
+  let mut receiver = Git::with_config("git", kept.clone());
+  receiver.listen(url("git:drop/notes")).expect("Failed to listen");
+  sender.send(url("git:drop/notes"), vec![1]).expect("Failed to send");
+  assert_eq!(receiver.receive().expect("Failed to receive"), vec![vec![1]]);
+  drop(receiver);
+  assert!(cache.is_dir());
+
+  sender.send(url("git:drop/notes"), vec![2]).expect("Failed to send");
+  sender.send(url("git:drop/notes"), vec![3]).expect("Failed to send");
+  let mut restarted = Git::with_config("git", kept);
+  restarted.listen(url("git:drop/notes")).expect("Failed to listen");
+  assert_eq!(restarted.receive().expect("Failed to receive"), vec![vec![2], vec![3]]);
+}
+
+#[test]
+fn senders_racing() {
+  let repo = bare_repo("racing");
+  let mut receiver = transport(&repo);
+  receiver.listen(url("git:drop/notes")).expect("Failed to listen");
+
+  let senders: Vec<_> = (0..2u8)
+    .map(|i| {
+      let repo = repo.clone();
+      thread::spawn(move || {
+        let mut sender = transport(&repo);
+        for j in 0..3u8 {
+          sender.send(url("git:drop/notes"), vec![i, j]).expect("Failed to send");
+        }
+      })
+    })
+    .collect();
+  for sender in senders {
+    sender.join().expect("Sender panicked");
+  }
+
+  let mut received = receiver.receive().expect("Failed to receive");
+  received.sort();
+  let expected: Vec<_> = (0..2u8).flat_map(|i| (0..3u8).map(move |j| vec![i, j])).collect();
+  assert_eq!(received, expected);
+}
+
+#[test]
+fn unreachable_repository() {
+  let missing = test_dir("unreachable", "remote");
+  let mut git = transport(&missing);
+  match git.send(url("git:drop/notes"), vec![1]) {
+    Err(fail::MesherFail::SendFailure(_)) => (),
+    other => panic!("Expected SendFailure, got {:?}", other),
+  }
+  match git.listen(url("git:drop/notes")) {
+    Err(fail::MesherFail::ListenFailure(_)) => (),
+    other => panic!("Expected ListenFailure, got {:?}", other),
+  }
+}
+
+#[test]
+fn invalid_paths() {
+  let repo = bare_repo("invalid");
+  let mut git = transport(&repo);
+  for path in &[
+    "git:elsewhere/notes",
+    "git:drop",
+    "git:drop/",
+    "git:drop/notes/",
+    "git:drop/a//b",
+    "git:drop/../notes",
+    "git:drop/-notes",
+    "git:drop/a..b",
+    "git:drop/notes.lock",
+    "git:drop/notes?x=1",
+    "git:drop/no%20tes",
+  ] {
+    match git.send(url(path), vec![1]) {
+      Err(fail::MesherFail::InvalidURL(_)) => (),
+      other => panic!("Expected InvalidURL for {}, got {:?}", path, other),
+    }
+    match git.listen(url(path)) {
+      Err(fail::MesherFail::InvalidURL(_)) => (),
+      other => panic!("Expected InvalidURL for {}, got {:?}", path, other),
+    }
+  }
+}
@@ -0,0 +1,296 @@
+use mesher::{
+  prelude::*,
+  test_support::{receive_within, url},
+};
+use mesher_social::{IrcConfig, IRC};
+
+use std::{
+  collections::{HashMap, HashSet},
+  io::{prelude::*, BufReader},
+  net::{SocketAddr, TcpListener, TcpStream},
+  sync::{Arc, Mutex},
+  thread,
+  time::Duration,
+};
+
+/// As long as a hostname can be, so lines passed along have the largest prefix they could.
+const HOST: &str = "a-very-long-hostname-which-takes-up-all-sixty-three-characters-";
+
+#[derive(Default)]
+struct StubState {
+  clients: HashMap<String, TcpStream>,
+  channels: HashMap<String, HashSet<String>>,
+  longest_line: usize,
+}
+
+/// Just enough of an IRC server to test against: nicks, channels, and messages to either.
+/// Clients have to answer a ping before they're registered, like on many real servers.
+struct StubServer {
+  addr: SocketAddr,
+  state: Arc<Mutex<StubState>>,
+}
+
+impl StubServer {
+  fn start() -> StubServer {
+    let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind stub server");
+    let addr = listener.local_addr().expect("Failed to get stub server address");
+    let state = Arc::new(Mutex::new(StubState::default()));
+    let server_state = state.clone();
+    thread::spawn(move || {
+      for stream in listener.incoming() {
+        let (stream, state) = (stream.expect("Failed to accept"), server_state.clone());
+        thread::spawn(move || serve(stream, state));
+      }
+    });
+    StubServer { addr, state }
+  }
+
+  fn path(&self, target: &str) -> MesherUrl {
+    url(&format!("irc:{}/{}", self.addr, target))
+  }
+
+  fn longest_line(&self) -> usize {
+    self.state.lock().unwrap().longest_line
+  }
+}
+
+fn send_to(stream: &mut TcpStream, line: &str) {
+  let _ = stream.write_all(format!("{}\r\n", line).as_bytes());
+}
+
+fn serve(stream: TcpStream, state: Arc<Mutex<StubState>>) {
+  let mut out = stream.try_clone().expect("Failed to clone stream");
+  let (mut nick, mut user, mut pinged, mut ponged, mut registered) = (None::<String>, false, false, false, false);
+  for line in BufReader::new(stream).lines() {
+    let line = match line {
+      Ok(line) => line,
+      Err(_) => break,
+    };
+    let (command, rest) = line.split_once(' ').unwrap_or((&line, ""));
+    match command {
+      "NICK" => {
+        let new = rest.to_owned();
+        let mut state = state.lock().unwrap();
+        if state.clients.contains_key(&new.to_lowercase()) {
+          send_to(&mut out, &format!(":stub 433 * {} :Nickname is already in use", new));
+          continue;
+        }
+        if let (true, Some(old)) = (registered, &nick) {
+          let conn = state.clients.remove(&old.to_lowercase()).unwrap();
+          state.clients.insert(new.to_lowercase(), conn);
+          for members in state.channels.values_mut() {
+            if members.remove(&old.to_lowercase()) {
+              members.insert(new.to_lowercase());
+            }
+          }
+          send_to(&mut out, &format!(":{}!mesher@{} NICK :{}", old, HOST, new));
+        }
+        nick = Some(new);
+      }
+      "USER" => user = true,
+      "PONG" => ponged = true,
+      "JOIN" if registered => {
+        let nick = nick.as_ref().unwrap();
+        let mut state = state.lock().unwrap();
+        state
+          .channels
+          .entry(rest.to_lowercase())
+          .or_default()
+          .insert(nick.to_lowercase());
+        send_to(&mut out, &format!(":{}!mesher@{} JOIN {}", nick, HOST, rest));
+      }
+      "PRIVMSG" if registered => {
+        let nick = nick.as_ref().unwrap();
+        let (target, text) = rest.split_once(" :").unwrap();
+        let relayed = format!(":{}!mesher@{} PRIVMSG {} :{}", nick, HOST, target, text);
+        let mut state = state.lock().unwrap();
+        state.longest_line = state.longest_line.max(relayed.len() + 2);
+        // real servers cut off anything that doesn't fit
+        let relayed = &relayed[..relayed.len().min(510)];
+        let recipients = match state.channels.get(&target.to_lowercase()) {
+          Some(members) => members.iter().filter(|m| **m != nick.to_lowercase()).cloned().collect(),
+          None => vec![target.to_lowercase()],
+        };
+        for recipient in recipients {
+          if let Some(conn) = state.clients.get_mut(&recipient) {
+            send_to(conn, relayed);
+          }
+        }
+      }
+      "QUIT" => break,
+      _ => (),
+    }
+    if !registered && user {
+      if let Some(nick) = &nick {
+        if !pinged {
+          send_to(&mut out, "PING :stub");
+          pinged = true;
+        }
+        if !ponged {
+          continue;
+        }
+        registered = true;
+        let conn = out.try_clone().expect("Failed to clone stream");
+        state.lock().unwrap().clients.insert(nick.to_lowercase(), conn);
+        send_to(&mut out, &format!(":stub 001 {} :Welcome", nick));
+      }
+    }
+  }
+  if let Some(nick) = nick {
+    let mut state = state.lock().unwrap();
+    state.clients.remove(&nick.to_lowercase());
+    for members in state.channels.values_mut() {
+      members.remove(&nick.to_lowercase());
+    }
+  }
+}
+
+/// A transport which sends lines as fast as it can.
+fn fast() -> IRC {
+  IRC::with_config(
+    "irc",
+    IrcConfig {
+      send_interval: Duration::from_secs(0),
+      timeout: Duration::from_secs(5),
+      ..IrcConfig::default()
+    },
+  )
+}
+
+#[test]
+fn channel_round_trip() {
+  let server = StubServer::start();
+  let mut receiver = fast();
+  receiver.listen(server.path("#mesher")).expect("Failed to listen");
+  let mut sender = fast();
+
+  let blobs = vec![vec![], vec![1; 10], (0..2000).map(|i| i as u8).collect::<Vec<_>>()];
+  for blob in &blobs {
+    sender
+      .send(server.path("#mesher"), blob.clone())
+      .expect("Failed to send");
+  }
+  assert_eq!(receive_within(&mut receiver, 3), blobs);
+}
+
+#[test]
+fn direct_messages() {
+  let server = StubServer::start();
+  let mut alice = fast();
+  alice.listen(server.path("alice")).expect("Failed to listen");
+  let mut bob = fast();
+  bob.listen(server.path("bob")).expect("Failed to listen");
+  let mut sender = fast();
+
+  sender
+    .send(server.path("Alice"), vec![1, 2, 3])
+    .expect("Failed to send");
+  sender.send(server.path("bob"), vec![4, 5, 6]).expect("Failed to send");
+  assert_eq!(receive_within(&mut alice, 1), vec![vec![1, 2, 3]]);
+  assert_eq!(receive_within(&mut bob, 1), vec![vec![4, 5, 6]]);
+}
+
+#[test]
+fn lines_fit_once_prefixed() {
+  let server = StubServer::start();
+  let mut receiver = fast();
+  receiver.listen(server.path("#mesher")).expect("Failed to listen");
+  let mut sender = IRC::with_config(
+    "irc",
+    IrcConfig {
+      nick: "a-rather-long-nickname".to_owned(),
+      send_interval: Duration::from_secs(0),
+      ..IrcConfig::default()
+    },
+  );
+
+  let blob = (0..5000).map(|i| (i * 7) as u8).collect::<Vec<_>>();
+  sender
+    .send(server.path("#mesher"), blob.clone())
+    .expect("Failed to send");
+  assert_eq!(receive_within(&mut receiver, 1), vec![blob]);
+  assert!(
+    server.longest_line() <= 512,
+    "Sent a {}-byte line",
+    server.longest_line()
+  );
+}
+
+#[test]
+fn chatter_ignored() {
+  let server = StubServer::start();
+  let mut receiver = fast();
+  receiver.listen(server.path("#mesher")).expect("Failed to listen");
+
+  let mut human = TcpStream::connect(server.addr).expect("Failed to connect");
+  let mut lines = BufReader::new(human.try_clone().expect("Failed to clone stream")).lines();
+  send_to(&mut human, "NICK human");
+  send_to(&mut human, "USER human 0 * :human");
+  assert_eq!(lines.next().unwrap().unwrap(), "PING :stub");
+  send_to(&mut human, "PONG :stub");
+  lines.next();
+  send_to(&mut human, "JOIN #mesher");
+  send_to(&mut human, "PRIVMSG #mesher :hello everyone");
+  send_to(&mut human, "PRIVMSG #mesher :mesher nonsense");
+  send_to(&mut human, "PRIVMSG #mesher :mesher 1234 0/1 !!!not base64!!!");
+  send_to(&mut human, "PRIVMSG #mesher :mesher 5678 0/1 AQID");
+  assert_eq!(receive_within(&mut receiver, 1), vec![vec![1, 2, 3]]);
+}
+
+#[test]
+fn partial_blobs_limited_per_sender() {
+  let server = StubServer::start();
+  let mut receiver = fast();
+  receiver.listen(server.path("#mesher")).expect("Failed to listen");
+
+  let mut human = TcpStream::connect(server.addr).expect("Failed to connect");
+  let mut lines = BufReader::new(human.try_clone().expect("Failed to clone stream")).lines();
+  send_to(&mut human, "NICK human");
+  send_to(&mut human, "USER human 0 * :human");
+  assert_eq!(lines.next().unwrap().unwrap(), "PING :stub");
+  send_to(&mut human, "PONG :stub");
+  lines.next();
+  send_to(&mut human, "JOIN #mesher");
+  for id in 0..4 {
+    send_to(&mut human, &format!("PRIVMSG #mesher :mesher {} 0/2 AQ", id));
+  }
+  // this one would be complete, but there's no room left for anything else from the same sender
+  send_to(&mut human, "PRIVMSG #mesher :mesher 4 0/1 BAUG");
+  send_to(&mut human, "PRIVMSG #mesher :mesher 0 1/2 ID");
+  assert_eq!(receive_within(&mut receiver, 1), vec![vec![1, 2, 3]]);
+  thread::sleep(Duration::from_millis(100));
+  assert!(receiver.receive().expect("Failed to receive").is_empty());
+}
+
+#[test]
+fn nick_taken() {
+  let server = StubServer::start();
+  let mut first = fast();
+  first.listen(server.path("alice")).expect("Failed to listen");
+  let mut second = fast();
+
+  match second.listen(server.path("alice")) {
+    Err(fail::MesherFail::ListenFailure(_)) => (),
+    other => panic!("Listened on a nick someone else has: {:?}", other),
+  }
+  match first.listen(server.path("bob")) {
+    Err(fail::MesherFail::ListenFailure(_)) => (),
+    other => panic!("Listened on two nicks on one server: {:?}", other),
+  }
+}
+
+#[test]
+fn invalid_paths() {
+  let mut t = IRC::new("irc").expect("Failed to create");
+  for path in &[
+    "irc:localhost:6667",
+    "irc:localhost/",
+    "irc:/#mesher",
+    "irc:localhost/a,b",
+  ] {
+    match t.listen(url(path)) {
+      Err(fail::MesherFail::InvalidURL(_)) => (),
+      other => panic!("Listened on {}: {:?}", path, other),
+    }
+  }
+}
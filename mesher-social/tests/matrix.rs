@@ -0,0 +1,376 @@
+use mesher::{
+  prelude::*,
+  test_support::{receive_for, receive_within, url},
+};
+use mesher_social::{Matrix, MatrixConfig};
+
+use serde_json::{json, Value};
+use std::{
+  collections::{HashMap, HashSet},
+  io::Cursor,
+  net::SocketAddr,
+  sync::{Arc, Condvar, Mutex},
+  thread,
+  time::{Duration, Instant},
+};
+use tiny_http::{Method, Request, Response, Server};
+
+const ROOM: &str = "!room:localhost";
+const OTHER_ROOM: &str = "!other:localhost";
+const ALIAS: &str = "#mesher:localhost";
+const EVENT_TYPE: &str = "eco.cybers.mesher.blob";
+
+fn room_path(room: &str) -> MesherUrl {
+  url(&format!("matrix:{}", room))
+}
+
+struct Event {
+  room: String,
+  kind: String,
+  content: Value,
+}
+
+#[derive(Default)]
+struct MockState {
+  /// Every event sent, in order; a sync token is just how many of them there were when it was made.
+  events: Vec<Event>,
+  members: HashMap<String, HashSet<String>>,
+  transactions: HashSet<(String, String)>,
+  /// How many of the next sends to rate limit.
+  rate_limit: usize,
+  rate_limited: usize,
+}
+
+/// Just enough of a homeserver's client-server API to test against: joining, sending, and syncing, for two users in two rooms.
+struct MockHomeserver {
+  addr: SocketAddr,
+  state: Arc<(Mutex<MockState>, Condvar)>,
+}
+
+fn percent_decode(s: &str) -> String {
+  let bytes = s.as_bytes();
+  let mut out = vec![];
+  let mut i = 0;
+  while i < bytes.len() {
+    if bytes[i] == b'%' {
+      out.push(u8::from_str_radix(&s[i + 1..i + 3], 16).unwrap());
+      i += 3;
+    } else {
+      out.push(bytes[i]);
+      i += 1;
+    }
+  }
+  String::from_utf8(out).unwrap()
+}
+
+fn json_response(code: u16, body: Value) -> Response<Cursor<Vec<u8>>> {
+  Response::from_string(body.to_string()).with_status_code(code)
+}
+
+fn handle(request: &mut Request, state: &(Mutex<MockState>, Condvar)) -> Response<Cursor<Vec<u8>>> {
+  let user = match request
+    .headers()
+    .iter()
+    .find(|h| h.field.as_str().as_str().eq_ignore_ascii_case("Authorization"))
+    .map(|h| h.value.as_str())
+  {
+    Some("Bearer alice-token") => "@alice:localhost",
+    Some("Bearer bob-token") => "@bob:localhost",
+    _ => return json_response(401, json!({ "errcode": "M_UNKNOWN_TOKEN", "error": "Unknown token" })),
+  };
+  let full = request.url().to_owned();
+  let (path, query) = full.split_once('?').unwrap_or((&full, ""));
+  let param = |name: &str| {
+    query
+      .split('&')
+      .find_map(|p| p.strip_prefix(&format!("{}=", name)))
+      .map(percent_decode)
+  };
+  let parts = path
+    .trim_start_matches("/_matrix/client/v3/")
+    .split('/')
+    .map(percent_decode)
+    .collect::<Vec<_>>();
+  let (lock, wake) = state;
+
+  match (
+    request.method(),
+    parts.iter().map(String::as_str).collect::<Vec<_>>().as_slice(),
+  ) {
+    (Method::Post, ["join", room]) => {
+      let room = if *room == ALIAS { ROOM } else { room };
+      if room != ROOM && room != OTHER_ROOM {
+        return json_response(404, json!({ "errcode": "M_NOT_FOUND", "error": "No such room" }));
+      }
+      let mut state = lock.lock().unwrap();
+      state
+        .members
+        .entry(room.to_owned())
+        .or_default()
+        .insert(user.to_owned());
+      json_response(200, json!({ "room_id": room }))
+    }
+    (Method::Put, ["rooms", room, "send", kind, txn]) => {
+      let mut body = String::new();
+      request.as_reader().read_to_string(&mut body).unwrap();
+      let mut state = lock.lock().unwrap();
+      if state.rate_limit > 0 {
+        state.rate_limit -= 1;
+        state.rate_limited += 1;
+        return json_response(
+          429,
+          json!({ "errcode": "M_LIMIT_EXCEEDED", "error": "Too many requests", "retry_after_ms": 100 }),
+        );
+      }
+      if !state.members.get(*room).is_some_and(|m| m.contains(user)) {
+        return json_response(403, json!({ "errcode": "M_FORBIDDEN", "error": "Not in room" }));
+      }
+      if state.transactions.insert((user.to_owned(), txn.to_string())) {
+        state.events.push(Event {
+          room: room.to_string(),
+          kind: kind.to_string(),
+          content: serde_json::from_str(&body).unwrap(),
+        });
+        wake.notify_all();
+      }
+      json_response(200, json!({ "event_id": format!("${}", txn) }))
+    }
+    (Method::Get, ["sync"]) => {
+      let since = param("since").map(|s| s.parse::<usize>().unwrap());
+      let timeout = Duration::from_millis(param("timeout").unwrap_or_default().parse().unwrap_or(0));
+      let start = Instant::now();
+      let mut state = lock.lock().unwrap();
+      // wait for something to happen, if asked to and there's nothing yet
+      while since.is_some_and(|s| s >= state.events.len()) && start.elapsed() < timeout {
+        state = wake.wait_timeout(state, timeout - start.elapsed()).unwrap().0;
+      }
+      // an initial sync only has recent events, but this one's recent is everything
+      let from = since.unwrap_or(0);
+      let mut rooms = serde_json::Map::new();
+      for event in &state.events[from..] {
+        if !state.members.get(&event.room).is_some_and(|m| m.contains(user)) {
+          continue;
+        }
+        let room = rooms
+          .entry(event.room.clone())
+          .or_insert_with(|| json!({ "timeline": { "events": [], "limited": false } }));
+        room["timeline"]["events"]
+          .as_array_mut()
+          .unwrap()
+          .push(json!({ "type": event.kind, "content": event.content, "sender": "@someone:localhost" }));
+      }
+      json_response(
+        200,
+        json!({ "next_batch": state.events.len().to_string(), "rooms": { "join": rooms } }),
+      )
+    }
+    _ => json_response(
+      404,
+      json!({ "errcode": "M_UNRECOGNIZED", "error": "Unrecognized request" }),
+    ),
+  }
+}
+
+impl MockHomeserver {
+  fn start() -> MockHomeserver {
+    let server = Server::http("127.0.0.1:0").expect("Failed to start mock homeserver");
+    let addr = server.server_addr().to_ip().expect("Mock homeserver isn't on IP");
+    let state = Arc::new((Mutex::new(MockState::default()), Condvar::new()));
+    let server_state = state.clone();
+    thread::spawn(move || {
+      for mut request in server.incoming_requests() {
+        let state = server_state.clone();
+        // syncs wait around, so everything gets its own thread
+        thread::spawn(move || {
+          let response = handle(&mut request, &state);
+          let _ = request.respond(response);
+        });
+      }
+    });
+    MockHomeserver { addr, state }
+  }
+
+  fn config(&self, token: &str) -> MatrixConfig {
+    MatrixConfig {
+      homeserver: format!("http://{}", self.addr),
+      access_token: token.to_owned(),
+      timeout: Duration::from_secs(5),
+      sync_timeout: Duration::from_millis(500),
+      retry_delay: Duration::from_millis(100),
+      ..MatrixConfig::default()
+    }
+  }
+
+  fn user(&self, token: &str) -> Matrix {
+    Matrix::with_config("matrix", self.config(token))
+  }
+
+  /// Sends an event directly, like someone else in the room.
+  fn add(&self, kind: &str, content: Value) {
+    let (lock, wake) = &*self.state;
+    lock.lock().unwrap().events.push(Event {
+      room: ROOM.to_owned(),
+      kind: kind.to_owned(),
+      content,
+    });
+    wake.notify_all();
+  }
+
+  fn rate_limit(&self, count: usize) {
+    self.state.0.lock().unwrap().rate_limit = count;
+  }
+
+  fn rate_limited(&self) -> usize {
+    self.state.0.lock().unwrap().rate_limited
+  }
+}
+
+#[test]
+fn round_trip() {
+  let server = MockHomeserver::start();
+  let mut alice = server.user("alice-token");
+  alice.listen(room_path(ROOM)).expect("Failed to listen");
+  let mut bob = server.user("bob-token");
+
+  let blobs = vec![vec![], vec![1; 10], (0..20_000).map(|i| i as u8).collect::<Vec<_>>()];
+  for blob in &blobs {
+    bob.send(room_path(ROOM), blob.clone()).expect("Failed to send");
+  }
+  assert_eq!(receive_within(&mut alice, 3), blobs);
+}
+
+#[test]
+fn through_alias() {
+  let server = MockHomeserver::start();
+  let mut alice = server.user("alice-token");
+  alice.listen(room_path(ALIAS)).expect("Failed to listen");
+  let mut bob = server.user("bob-token");
+
+  bob.send(room_path(ALIAS), vec![1, 2, 3]).expect("Failed to send");
+  bob.send(room_path(ROOM), vec![4, 5, 6]).expect("Failed to send");
+  assert_eq!(receive_within(&mut alice, 2), vec![vec![1, 2, 3], vec![4, 5, 6]]);
+}
+
+#[test]
+fn only_new_events() {
+  let server = MockHomeserver::start();
+  let mut bob = server.user("bob-token");
+  bob.send(room_path(ROOM), vec![1]).expect("Failed to send");
+
+  let mut alice = server.user("alice-token");
+  alice.listen(room_path(ROOM)).expect("Failed to listen");
+  bob.send(room_path(ROOM), vec![2]).expect("Failed to send");
+  assert_eq!(receive_for(&mut alice, 2, Duration::from_secs(1)), vec![vec![2]]);
+}
+
+#[test]
+fn resumed_from_since() {
+  let server = MockHomeserver::start();
+  let mut bob = server.user("bob-token");
+  let mut alice = server.user("alice-token");
+  alice.listen(room_path(ROOM)).expect("Failed to listen");
+  bob.send(room_path(ROOM), vec![1]).expect("Failed to send");
+  assert_eq!(receive_within(&mut alice, 1), vec![vec![1]]);
+  let since = alice.since();
+  assert!(since.is_some());
+  drop(alice);
+
+  bob.send(room_path(ROOM), vec![2]).expect("Failed to send");
+  let mut alice = Matrix::with_config(
+    "matrix",
+    MatrixConfig {
+      since,
+      ..server.config("alice-token")
+    },
+  );
+  alice.listen(room_path(ROOM)).expect("Failed to listen");
+  assert_eq!(receive_for(&mut alice, 2, Duration::from_secs(1)), vec![vec![2]]);
+}
+
+#[test]
+fn other_events_ignored() {
+  let server = MockHomeserver::start();
+  let mut alice = server.user("alice-token");
+  alice.listen(room_path(ROOM)).expect("Failed to listen");
+
+  server.add(
+    "m.room.message",
+    json!({ "msgtype": "m.text", "body": "hello everyone" }),
+  );
+  server.add("m.room.message", json!({ "msgtype": "m.text", "blob": "AQID" }));
+  server.add(EVENT_TYPE, json!({ "blob": "!!!not base64!!!" }));
+  server.add(EVENT_TYPE, json!({ "something": "else" }));
+  server.add(EVENT_TYPE, json!({ "blob": "AQID" }));
+  assert_eq!(receive_for(&mut alice, 2, Duration::from_secs(1)), vec![vec![1, 2, 3]]);
+}
+
+#[test]
+fn other_rooms_ignored() {
+  let server = MockHomeserver::start();
+  let mut alice = server.user("alice-token");
+  // sending joins the other room, but doesn't listen there
+  alice.send(room_path(OTHER_ROOM), vec![0]).expect("Failed to send");
+  alice.listen(room_path(ROOM)).expect("Failed to listen");
+  let mut bob = server.user("bob-token");
+
+  bob.send(room_path(OTHER_ROOM), vec![1]).expect("Failed to send");
+  bob.send(room_path(ROOM), vec![2]).expect("Failed to send");
+  assert_eq!(receive_for(&mut alice, 2, Duration::from_secs(1)), vec![vec![2]]);
+}
+
+#[test]
+fn rate_limited() {
+  let server = MockHomeserver::start();
+  let mut alice = server.user("alice-token");
+  alice.listen(room_path(ROOM)).expect("Failed to listen");
+  let mut bob = server.user("bob-token");
+
+  server.rate_limit(1);
+  bob.send(room_path(ROOM), vec![1]).expect("Failed to send");
+  assert_eq!(server.rate_limited(), 1);
+  assert_eq!(receive_within(&mut alice, 1), vec![vec![1]]);
+}
+
+#[test]
+fn oversized_blob_refused() {
+  let server = MockHomeserver::start();
+  let mut bob = server.user("bob-token");
+
+  match bob.send(room_path(ROOM), vec![1; 32 * 1024 + 1]) {
+    Err(fail::MesherFail::SendFailure(_)) => (),
+    other => panic!("Sent a blob too large for an event: {:?}", other),
+  }
+}
+
+#[test]
+fn bad_token() {
+  let server = MockHomeserver::start();
+  let mut eve = server.user("eve-token");
+
+  match eve.listen(room_path(ROOM)) {
+    Err(fail::MesherFail::ListenFailure(_)) => (),
+    other => panic!("Listened with a bad token: {:?}", other),
+  }
+  match eve.send(room_path(ROOM), vec![1]) {
+    Err(fail::MesherFail::SendFailure(_)) => (),
+    other => panic!("Sent with a bad token: {:?}", other),
+  }
+}
+
+#[test]
+fn invalid_paths() {
+  let mut t = Matrix::new("matrix").expect("Failed to create");
+  for path in &[
+    "matrix:room",
+    "matrix:!room",
+    "matrix:@alice:example.org",
+    "matrix:!room:example.org/extra",
+    "matrix:!room:example.org?x=y",
+    "matrix:/!room:example.org",
+  ] {
+    match t.listen(url(path)) {
+      Err(fail::MesherFail::InvalidURL(_)) => (),
+      other => panic!("Listened on {}: {:?}", path, other),
+    }
+  }
+}
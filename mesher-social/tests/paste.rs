@@ -0,0 +1,284 @@
+use mesher::{prelude::*, test_support::url};
+use mesher_social::{HttpPaste, Paste, PasteConfig, PasteService};
+
+use std::{
+  collections::HashMap,
+  net::SocketAddr,
+  sync::{Arc, Mutex},
+  thread,
+  time::Duration,
+};
+use tiny_http::{Method, Response, Server};
+
+#[derive(Default)]
+struct MockState {
+  /// Every listing's pastes, as their IDs and text, oldest first.
+  listings: HashMap<String, Vec<(String, String)>>,
+  next_id: usize,
+  fetches: usize,
+}
+
+/// Just enough of a paste server speaking `HttpPaste`'s API to test against.
+/// Like a lot of real ones, it adds a newline to the end of every paste.
+struct MockServer {
+  addr: SocketAddr,
+  state: Arc<Mutex<MockState>>,
+}
+
+impl MockServer {
+  fn start() -> MockServer {
+    let server = Server::http("127.0.0.1:0").expect("Failed to start mock server");
+    let addr = server.server_addr().to_ip().expect("Mock server isn't on IP");
+    let state = Arc::new(Mutex::new(MockState::default()));
+    let server_state = state.clone();
+    thread::spawn(move || {
+      for mut request in server.incoming_requests() {
+        let mut state = server_state.lock().unwrap();
+        let path = request.url().trim_start_matches('/').to_owned();
+        let response = match request.method() {
+          Method::Post => {
+            let mut text = String::new();
+            request.as_reader().read_to_string(&mut text).unwrap();
+            let id = format!("p{}", state.next_id);
+            state.next_id += 1;
+            state.listings.entry(path).or_default().push((id, text));
+            Response::from_data(vec![]).with_status_code(201)
+          }
+          Method::Get => match state.listings.get(&path) {
+            Some(pastes) => {
+              let ids = pastes.iter().map(|(id, _)| format!("{}\n", id)).collect::<String>();
+              Response::from_data(ids.into_bytes())
+            }
+            None => {
+              let (listing, id) = path.rsplit_once('/').unwrap_or_default();
+              let text = state
+                .listings
+                .get(listing)
+                .and_then(|pastes| pastes.iter().find(|(i, _)| i == id))
+                .map(|(_, text)| format!("{}\n", text));
+              state.fetches += 1;
+              match text {
+                Some(text) => Response::from_data(text.into_bytes()),
+                None => Response::from_data(vec![]).with_status_code(404),
+              }
+            }
+          },
+          _ => Response::from_data(vec![]).with_status_code(405),
+        };
+        let _ = request.respond(response);
+      }
+    });
+    MockServer { addr, state }
+  }
+
+  fn path(&self, listing: &str) -> MesherUrl {
+    url(&format!("paste:{}/{}", self.addr, listing))
+  }
+
+  /// Adds a paste directly, like someone else using the same listing.
+  fn add(&self, listing: &str, text: &str) {
+    let mut state = self.state.lock().unwrap();
+    let id = format!("p{}", state.next_id);
+    state.next_id += 1;
+    state
+      .listings
+      .entry(listing.to_owned())
+      .or_default()
+      .push((id, text.to_owned()));
+  }
+
+  /// Removes the oldest paste in the listing, like a service expiring it.
+  fn expire(&self, listing: &str) {
+    self.state.lock().unwrap().listings.get_mut(listing).unwrap().remove(0);
+  }
+
+  fn fetches(&self) -> usize {
+    self.state.lock().unwrap().fetches
+  }
+}
+
+/// A transport talking to local paste servers, which checks its listings every time it's asked to.
+fn eager() -> Paste {
+  Paste::with_service(
+    "paste",
+    HttpPaste::plain(Duration::from_secs(5)),
+    PasteConfig {
+      poll_interval: Duration::from_secs(0),
+      ..PasteConfig::default()
+    },
+  )
+}
+
+fn sorted(mut blobs: Vec<Vec<u8>>) -> Vec<Vec<u8>> {
+  blobs.sort();
+  blobs
+}
+
+#[test]
+fn round_trip() {
+  let server = MockServer::start();
+  let mut sender = eager();
+  let mut receiver = eager();
+  receiver.listen(server.path("3f9a61c2")).expect("Failed to listen");
+
+  let blobs = vec![vec![], vec![1; 10], (0..3000).map(|i| i as u8).collect::<Vec<_>>()];
+  for blob in &blobs {
+    sender
+      .send(server.path("3f9a61c2"), blob.clone())
+      .expect("Failed to send");
+  }
+  assert_eq!(sorted(receiver.receive().expect("Failed to receive")), sorted(blobs));
+}
+
+#[test]
+fn pastes_only_fetched_once() {
+  let server = MockServer::start();
+  let mut sender = eager();
+  let mut receiver = eager();
+  receiver.listen(server.path("3f9a61c2")).expect("Failed to listen");
+
+  sender.send(server.path("3f9a61c2"), vec![1]).expect("Failed to send");
+  assert_eq!(receiver.receive().expect("Failed to receive"), vec![vec![1]]);
+  assert!(receiver.receive().expect("Failed to receive").is_empty());
+  assert_eq!(server.fetches(), 1);
+
+  server.expire("3f9a61c2");
+  sender.send(server.path("3f9a61c2"), vec![2]).expect("Failed to send");
+  assert_eq!(receiver.receive().expect("Failed to receive"), vec![vec![2]]);
+  assert_eq!(server.fetches(), 2);
+}
+
+#[test]
+fn listings_kept_apart() {
+  let server = MockServer::start();
+  let mut sender = eager();
+  let mut alice = eager();
+  alice.listen(server.path("alice")).expect("Failed to listen");
+
+  sender.send(server.path("bob"), vec![1]).expect("Failed to send");
+  sender.send(server.path("alice"), vec![2]).expect("Failed to send");
+  assert_eq!(alice.receive().expect("Failed to receive"), vec![vec![2]]);
+}
+
+#[test]
+fn other_pastes_ignored() {
+  let server = MockServer::start();
+  let mut receiver = eager();
+  receiver.listen(server.path("3f9a61c2")).expect("Failed to listen");
+
+  server.add("3f9a61c2", "fn main() { println!(\"hello\"); }");
+  server.add("3f9a61c2", "mesher !!!not base64!!!");
+  server.add("3f9a61c2", "mesher AQID");
+  assert_eq!(receiver.receive().expect("Failed to receive"), vec![vec![1, 2, 3]]);
+}
+
+#[test]
+fn oversized_blob_ignored() {
+  let server = MockServer::start();
+  let mut sender = eager();
+  let mut receiver = Paste::with_service(
+    "paste",
+    HttpPaste::plain(Duration::from_secs(5)),
+    PasteConfig {
+      poll_interval: Duration::from_secs(0),
+      max_packet_size: 100,
+    },
+  );
+  receiver.listen(server.path("3f9a61c2")).expect("Failed to listen");
+
+  sender
+    .send(server.path("3f9a61c2"), vec![1; 101])
+    .expect("Failed to send");
+  sender
+    .send(server.path("3f9a61c2"), vec![2; 100])
+    .expect("Failed to send");
+  assert_eq!(receiver.receive().expect("Failed to receive"), vec![vec![2; 100]]);
+}
+
+#[test]
+fn nothing_answering() {
+  let unused = std::net::TcpListener::bind("127.0.0.1:0").expect("Failed to bind");
+  let addr = unused.local_addr().expect("Failed to get address");
+  drop(unused);
+  let path = url(&format!("paste:{}/3f9a61c2", addr));
+  let mut t = eager();
+
+  match t.send(path.clone(), vec![1]) {
+    Err(fail::MesherFail::SendFailure(_)) => (),
+    other => panic!("Sent with nothing answering: {:?}", other),
+  }
+  t.listen(path).expect("Failed to listen");
+  assert!(t.receive().expect("Failed to receive").is_empty());
+}
+
+/// A service keeping its pastes in memory, to check other services can be plugged in.
+#[derive(Clone, Default)]
+struct MemoryService {
+  pastes: Arc<Mutex<HashMap<String, Vec<String>>>>,
+}
+
+impl PasteService for MemoryService {
+  fn check_path(&self, path: &MesherUrl) -> fail::Result<()> {
+    if path.path().is_empty() {
+      return Err(fail::MesherFail::InvalidURL(path.to_string()));
+    }
+    Ok(())
+  }
+
+  fn post(&mut self, path: &MesherUrl, text: &str) -> fail::Result<()> {
+    let mut pastes = self.pastes.lock().unwrap();
+    pastes.entry(path.path().to_owned()).or_default().push(text.to_owned());
+    Ok(())
+  }
+
+  fn list(&mut self, path: &MesherUrl) -> fail::Result<Vec<String>> {
+    let pastes = self.pastes.lock().unwrap();
+    let count = pastes.get(path.path()).map_or(0, Vec::len);
+    Ok((0..count).map(|i| i.to_string()).collect())
+  }
+
+  fn fetch(&mut self, path: &MesherUrl, id: &str) -> fail::Result<Option<String>> {
+    let pastes = self.pastes.lock().unwrap();
+    let index = id.parse::<usize>().unwrap();
+    Ok(pastes.get(path.path()).and_then(|p| p.get(index)).cloned())
+  }
+}
+
+#[test]
+fn other_services() {
+  let service = MemoryService::default();
+  let config = PasteConfig {
+    poll_interval: Duration::from_secs(0),
+    ..PasteConfig::default()
+  };
+  let mut sender = Paste::with_service("memo", service.clone(), config.clone());
+  let mut receiver = Paste::with_service("memo", service, config);
+  receiver.listen(url("memo:/listing")).expect("Failed to listen");
+
+  sender
+    .send(url("memo:/listing"), vec![1, 2, 3])
+    .expect("Failed to send");
+  assert_eq!(receiver.receive().expect("Failed to receive"), vec![vec![1, 2, 3]]);
+  match sender.send(url("memo:listing"), vec![1]) {
+    Err(fail::MesherFail::InvalidURL(_)) => (),
+    other => panic!("Sent to a path the service rejected: {:?}", other),
+  }
+}
+
+#[test]
+fn invalid_paths() {
+  let mut t = Paste::new("paste").expect("Failed to create");
+  for path in &[
+    "paste:paste.example.com",
+    "paste:paste.example.com/",
+    "paste:/listing",
+    "paste:paste.example.com/../admin",
+    "paste:paste.example.com/a%2Fb",
+    "paste:user@paste.example.com/listing",
+  ] {
+    match t.listen(url(path)) {
+      Err(fail::MesherFail::InvalidURL(_)) => (),
+      other => panic!("Listened on {}: {:?}", path, other),
+    }
+  }
+}
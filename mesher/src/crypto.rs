@@ -13,5 +13,5 @@ pub mod encrypt {
 pub mod sign {
   pub use sodiumoxide::crypto::sign::{gen_keypair, PublicKey, SecretKey};
 
-  pub(crate) use sodiumoxide::crypto::sign::{sign, verify};
+  pub(crate) use sodiumoxide::crypto::sign::{sign, verify, SIGNATUREBYTES};
 }
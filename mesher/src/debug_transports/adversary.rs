@@ -0,0 +1,492 @@
+use std::{
+  collections::{hash_map::DefaultHasher, HashMap, HashSet},
+  hash::{Hash, Hasher},
+  sync::{Arc, Mutex},
+  time::{Duration, Instant},
+};
+
+use crate::{debug_transports::SimNetwork, packet::Chunk, prelude::*};
+
+/// The fewest journeys which must reach a position before [`Adversary::check_position_hiding`](struct.Adversary.html#method.check_position_hiding) will judge the chunk order there.
+const MIN_ORDER_SAMPLES: usize = 8;
+
+/// Whether an observation was of a blob coming in or going out.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+  /// The node received the blob.
+  /// It can't tell which of the paths it was listening on the blob arrived through, so all of them are included.
+  Received {
+    /// Every path the node was listening on when it received the blob.
+    upstream: Vec<String>,
+  },
+  /// The node sent the blob out along the path.
+  Sent {
+    /// The path it was sent along.
+    downstream: String,
+  },
+}
+
+/// What a node could learn from a blob's structure, using only its own secret keys.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Shape {
+  /// How many chunks are in the main path.
+  pub main_chunks: usize,
+  /// How many chunks are in each reply path.
+  pub reply_chunks: Vec<usize>,
+  /// The indices of the main path's chunks which the node was able to decrypt.
+  pub decryptable: Vec<usize>,
+  /// How many of the decryptable chunks told the node to forward the packet.
+  pub hops: usize,
+  /// How many of the decryptable chunks were messages for the node.
+  pub messages: usize,
+  chunk_hashes: Vec<u64>,
+}
+
+impl Shape {
+  fn of(blob: &[u8], keys: &[encrypt::SecretKey]) -> Option<Shape> {
+    let mut blocks = bincode::deserialize::<Vec<Vec<Vec<u8>>>>(blob).ok()?;
+    if blocks.is_empty() {
+      return None;
+    }
+    let replies: Vec<_> = blocks.split_off(1).into_iter().map(Arc::new).collect();
+    let main = blocks.pop()?;
+
+    let mut shape = Shape {
+      main_chunks: main.len(),
+      reply_chunks: replies.iter().map(|r| r.len()).collect(),
+      decryptable: vec![],
+      hops: 0,
+      messages: 0,
+      chunk_hashes: main.iter().map(hash_of).collect(),
+    };
+    for (idx, chunk) in main.iter().enumerate() {
+      // the node doesn't need to know the signer to strip a signature off, so try it both ways
+      let opened = keys.iter().find_map(|k| {
+        encrypt::open(chunk, k).ok().or_else(|| {
+          chunk
+            .get(sign::SIGNATUREBYTES..)
+            .and_then(|unsigned| encrypt::open(unsigned, k).ok())
+        })
+      });
+      if let Some(opened) = opened {
+        shape.decryptable.push(idx);
+        match Chunk::deserialize(opened, &replies) {
+          Ok(Chunk::Transport(_)) => shape.hops += 1,
+          Ok(Chunk::Message(_, _)) => shape.messages += 1,
+          Err(_) => (),
+        }
+      }
+    }
+    Some(shape)
+  }
+
+  fn is_intermediary(&self) -> bool {
+    self.hops > 0 && self.messages == 0
+  }
+
+  /// Everything about the shape that the position of the node in the path doesn't legitimately change.
+  fn public_part(&self) -> (usize, &[usize]) {
+    (self.main_chunks, &self.reply_chunks)
+  }
+}
+
+fn hash_of<T: Hash>(t: &T) -> u64 {
+  let mut hasher = DefaultHasher::new();
+  t.hash(&mut hasher);
+  hasher.finish()
+}
+
+/// A single thing a node saw.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Observation {
+  /// The name of the node that saw it.
+  pub node: String,
+  /// Which journey it happened during; see [`Adversary::next_journey`](struct.Adversary.html#method.next_journey).
+  pub journey: usize,
+  /// When it happened, either since the adversary was created or on the simulated network's clock.
+  pub time: Duration,
+  /// Whether the blob was coming in or going out, and along what path.
+  pub event: Event,
+  /// The size of the blob, in bytes.
+  pub size: usize,
+  /// What the node could tell about the blob's structure, or `None` if it couldn't be parsed as a packet at all.
+  pub shape: Option<Shape>,
+}
+
+/// One way that packets have been found to leak information they shouldn't.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Leak {
+  /// Intermediary nodes in the same journey saw packets of different sizes or structures, so they could tell roughly where they were.
+  ShapeVaries {
+    /// The journey it happened in.
+    journey: usize,
+  },
+  /// Every intermediary at this position in the path found its chunk at the same index, so the index gives away the position.
+  FixedChunkOrder {
+    /// The intermediary's position in the path, counting from 0 for the first one to receive the packet.
+    position: usize,
+    /// The index its chunk was always found at.
+    index: usize,
+  },
+  /// The exact same encrypted chunk appeared in the packets of two different journeys, so any node seeing both could link them.
+  SharedChunk {
+    /// The two journeys, lowest first.
+    journeys: (usize, usize),
+  },
+}
+
+struct AdversaryState {
+  start: Instant,
+  clock: Option<SimNetwork>,
+  journey: usize,
+  observations: Vec<Observation>,
+}
+
+impl AdversaryState {
+  fn now(&self) -> Duration {
+    match &self.clock {
+      Some(net) => net.now(),
+      None => self.start.elapsed(),
+    }
+  }
+}
+
+/// Records exactly what each node in a network can observe, to check that mesher's anonymity guarantees hold.
+///
+/// Every node's transport is wrapped with [`Adversary::watch`](#method.watch), along with the secret keys the node holds.
+/// The wrapper records each blob the node sends or receives, along with everything it can tell about it with those keys, as an [`Observation`](struct.Observation.html).
+/// Those can be inspected directly, or checked against the guarantees in the README with the `check_*` methods.
+///
+/// Most checks compare *journeys*, i.e. the observations made while a single packet travels along its path.
+/// Call [`Adversary::next_journey`](#method.next_journey) before launching each packet so they're grouped properly.
+///
+/// Clones share the same observations.
+#[derive(Clone)]
+pub struct Adversary {
+  state: Arc<Mutex<AdversaryState>>,
+}
+
+impl Default for Adversary {
+  fn default() -> Adversary {
+    Adversary::new()
+  }
+}
+
+impl Adversary {
+  /// Creates an adversary which timestamps observations with the time since it was created.
+  pub fn new() -> Adversary {
+    Adversary {
+      state: Arc::new(Mutex::new(AdversaryState {
+        start: Instant::now(),
+        clock: None,
+        journey: 0,
+        observations: vec![],
+      })),
+    }
+  }
+
+  /// Creates an adversary which timestamps observations with the simulated network's virtual clock.
+  pub fn simulated(network: &SimNetwork) -> Adversary {
+    let adversary = Adversary::new();
+    adversary.state().clock = Some(network.clone());
+    adversary
+  }
+
+  fn state(&self) -> std::sync::MutexGuard<'_, AdversaryState> {
+    self.state.lock().expect("poisoned lock?")
+  }
+
+  /// Wraps a node's transport, so that everything it sees is recorded under the given name.
+  ///
+  /// The keys should be the same ones the node's mesher has, so the adversary sees exactly what the node could.
+  pub fn watch<T: Transport>(&self, node: &str, keys: Vec<encrypt::SecretKey>, inner: T) -> Watched<T> {
+    Watched {
+      adversary: self.clone(),
+      node: node.to_owned(),
+      keys,
+      listening: vec![],
+      inner,
+    }
+  }
+
+  /// Starts a new journey, so that everything observed from now on is grouped separately from what came before.
+  pub fn next_journey(&self) {
+    self.state().journey += 1;
+  }
+
+  /// Every observation made so far, in the order they were made.
+  pub fn observations(&self) -> Vec<Observation> {
+    self.state().observations.clone()
+  }
+
+  /// Every observation made so far by the given node, in the order they were made.
+  pub fn observations_of(&self, node: &str) -> Vec<Observation> {
+    self
+      .state()
+      .observations
+      .iter()
+      .filter(|o| o.node == node)
+      .cloned()
+      .collect()
+  }
+
+  /// For each journey, the sizes and shapes seen by the intermediary nodes, in the order they received the packet.
+  ///
+  /// An intermediary is a node which was told to forward the packet, but had no messages in it.
+  fn intermediaries(&self) -> HashMap<usize, Vec<(usize, Shape)>> {
+    let mut journeys: HashMap<usize, Vec<(usize, Shape)>> = HashMap::new();
+    for obs in self.state().observations.iter() {
+      if let (Event::Received { .. }, Some(shape)) = (&obs.event, &obs.shape) {
+        if shape.is_intermediary() {
+          journeys.entry(obs.journey).or_default().push((obs.size, shape.clone()));
+        }
+      }
+    }
+    journeys
+  }
+
+  /// Checks that intermediary nodes can't tell where in the path they are.
+  ///
+  /// Within each journey, every intermediary must see a packet of the same size and structure.
+  /// Across journeys, the index of an intermediary's chunk in the main path mustn't depend on its position in the path.
+  /// That second check needs several journeys to reach each position before it can say anything; positions reached fewer times are skipped.
+  ///
+  /// Returns every leak found, or `Ok` if there were none.
+  pub fn check_position_hiding(&self) -> Result<(), Vec<Leak>> {
+    let mut leaks = vec![];
+    let intermediaries = self.intermediaries();
+
+    let mut journeys: Vec<_> = intermediaries.keys().copied().collect();
+    journeys.sort_unstable();
+    for journey in journeys {
+      let seen = &intermediaries[&journey];
+      let consistent = seen
+        .windows(2)
+        .all(|w| w[0].0 == w[1].0 && w[0].1.public_part() == w[1].1.public_part());
+      if !consistent {
+        leaks.push(Leak::ShapeVaries { journey });
+      }
+    }
+
+    let mut by_position: HashMap<usize, Vec<&Shape>> = HashMap::new();
+    for seen in intermediaries.values() {
+      for (position, (_, shape)) in seen.iter().enumerate() {
+        by_position.entry(position).or_default().push(shape);
+      }
+    }
+    let mut positions: Vec<_> = by_position.keys().copied().collect();
+    positions.sort_unstable();
+    for position in positions {
+      let shapes = &by_position[&position];
+      if shapes.len() < MIN_ORDER_SAMPLES || shapes.iter().all(|s| s.main_chunks < 2) {
+        continue;
+      }
+      let indices: HashSet<_> = shapes.iter().map(|s| s.decryptable.first().copied()).collect();
+      if indices.len() == 1 {
+        if let Some(Some(index)) = indices.into_iter().next() {
+          leaks.push(Leak::FixedChunkOrder { position, index });
+        }
+      }
+    }
+
+    if leaks.is_empty() {
+      Ok(())
+    } else {
+      Err(leaks)
+    }
+  }
+
+  /// Checks that no encrypted chunk is reused between journeys, which would let any node that sees both packets link them.
+  ///
+  /// Note that replies *necessarily* reuse the chunks in their reply path, so journeys containing replies will always fail this check.
+  ///
+  /// Returns every leak found, or `Ok` if there were none.
+  pub fn check_unlinkable(&self) -> Result<(), Vec<Leak>> {
+    let mut seen_in: HashMap<u64, HashSet<usize>> = HashMap::new();
+    for obs in self.state().observations.iter() {
+      if let Some(shape) = &obs.shape {
+        for hash in &shape.chunk_hashes {
+          seen_in.entry(*hash).or_default().insert(obs.journey);
+        }
+      }
+    }
+    let mut linked = HashSet::new();
+    for journeys in seen_in.values() {
+      let mut journeys: Vec<_> = journeys.iter().copied().collect();
+      journeys.sort_unstable();
+      for (i, a) in journeys.iter().enumerate() {
+        for b in &journeys[i + 1..] {
+          linked.insert((*a, *b));
+        }
+      }
+    }
+    let mut linked: Vec<_> = linked.into_iter().collect();
+    linked.sort_unstable();
+    if linked.is_empty() {
+      Ok(())
+    } else {
+      Err(
+        linked
+          .into_iter()
+          .map(|journeys| Leak::SharedChunk { journeys })
+          .collect(),
+      )
+    }
+  }
+}
+
+/// A transport wrapped by [`Adversary::watch`](struct.Adversary.html#method.watch), recording everything its node observes.
+///
+/// These can only be created through `Adversary::watch`; trying to create one through [`Mesher::add_transport`](../struct.Mesher.html#method.add_transport) will fail.
+pub struct Watched<T: Transport> {
+  adversary: Adversary,
+  node: String,
+  keys: Vec<encrypt::SecretKey>,
+  listening: Vec<String>,
+  inner: T,
+}
+
+impl<T: Transport> Watched<T> {
+  fn observe(&self, event: Event, blob: &[u8]) {
+    let shape = Shape::of(blob, &self.keys);
+    let mut state = self.adversary.state();
+    let obs = Observation {
+      node: self.node.clone(),
+      journey: state.journey,
+      time: state.now(),
+      event,
+      size: blob.len(),
+      shape,
+    };
+    state.observations.push(obs);
+  }
+}
+
+impl<T: Transport> Transport for Watched<T> {
+  fn new(_scheme: &str) -> fail::Result<Self> {
+    Err(fail::MesherFail::SetupFailure(
+      "Watched must be created through Adversary::watch".to_owned(),
+    ))
+  }
+
+  fn send(&mut self, path: MesherUrl, blob: Vec<u8>) -> fail::Result<()> {
+    self.observe(
+      Event::Sent {
+        downstream: path.to_string(),
+      },
+      &blob,
+    );
+    self.inner.send(path, blob)
+  }
+
+  fn listen(&mut self, path: MesherUrl) -> fail::Result<()> {
+    self.listening.push(path.to_string());
+    self.inner.listen(path)
+  }
+
+  fn receive(&mut self) -> fail::Result<Vec<Vec<u8>>> {
+    let received = self.inner.receive()?;
+    for blob in &received {
+      self.observe(
+        Event::Received {
+          upstream: self.listening.clone(),
+        },
+        blob,
+      );
+    }
+    Ok(received)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::test_support::url;
+
+  /// Serializes a packet the way a broken serializer would: without shuffling, so chunks stay in the order they were added.
+  fn unshuffled(path: &[(&str, &encrypt::PublicKey)]) -> Vec<u8> {
+    let mut packet = Packet::unsigned();
+    for (hop, key) in path {
+      packet.add_hop((*hop).to_owned(), key);
+    }
+    bincode::serialize(&vec![packet.main_path]).expect("Failed to serialize")
+  }
+
+  #[test]
+  fn detects_fixed_order() {
+    let net = SimNetwork::new(0);
+    let adversary = Adversary::simulated(&net);
+    let keys: Vec<_> = (0..3).map(|_| encrypt::gen_keypair()).collect();
+    let mut nodes: Vec<_> = (0..3)
+      .map(|i| {
+        let mut node = adversary.watch(
+          &format!("n{}", i),
+          vec![keys[i].1.clone()],
+          net.node(&format!("n{}", i)),
+        );
+        node.listen(url(&format!("sim:n{}", i))).expect("Failed to listen");
+        node
+      })
+      .collect();
+    let mut source = net.node("source");
+
+    for _ in 0..MIN_ORDER_SAMPLES {
+      adversary.next_journey();
+      let blob = unshuffled(&[
+        ("sim:n1", &keys[0].0),
+        ("sim:n2", &keys[1].0),
+        ("sim:nowhere", &keys[2].0),
+      ]);
+      source.send(url("sim:n0"), blob).expect("Failed to send");
+      for (i, node) in nodes.iter_mut().enumerate() {
+        for blob in node.receive().expect("Failed to receive") {
+          let next = format!("sim:n{}", i + 1);
+          node.send(url(&next), blob).expect("Failed to forward");
+        }
+      }
+    }
+
+    let leaks = adversary.check_position_hiding().expect_err("Should have found leaks");
+    assert!(leaks.contains(&Leak::FixedChunkOrder { position: 0, index: 0 }));
+    assert!(leaks.contains(&Leak::FixedChunkOrder { position: 2, index: 2 }));
+    assert_eq!(adversary.check_unlinkable(), Ok(()));
+  }
+
+  #[test]
+  fn records_paths() {
+    let (pk, sk) = encrypt::gen_keypair();
+    let net = SimNetwork::new(0);
+    let adversary = Adversary::simulated(&net);
+    let mut node = adversary.watch("node", vec![sk], net.node("node"));
+    node.listen(url("sim:node")).expect("Failed to listen");
+
+    let mut packet = Packet::unsigned();
+    packet.add_hop("sim:elsewhere".to_owned(), &pk);
+    packet.add_message(&[1], &pk);
+    let blob = packet.serialize().expect("Failed to serialize");
+    net
+      .node("other")
+      .send(url("sim:node"), blob.clone())
+      .expect("Failed to send");
+    node.receive().expect("Failed to receive");
+    node.send(url("sim:elsewhere"), blob.clone()).expect("Failed to send");
+
+    let seen = adversary.observations_of("node");
+    assert_eq!(seen.len(), 2);
+    assert_eq!(
+      seen[0].event,
+      Event::Received {
+        upstream: vec!["sim:node".to_owned()]
+      }
+    );
+    assert_eq!(
+      seen[1].event,
+      Event::Sent {
+        downstream: "sim:elsewhere".to_owned()
+      }
+    );
+    let shape = seen[0].shape.as_ref().expect("Should be a packet");
+    assert_eq!((shape.main_chunks, shape.hops, shape.messages), (2, 1, 1));
+    assert_eq!(seen[0].size, blob.len());
+  }
+}
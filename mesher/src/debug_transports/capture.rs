@@ -0,0 +1,414 @@
+use std::{
+  fs::File,
+  io::{BufReader, Read, Write},
+  path::Path,
+  time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::prelude::*;
+
+const MAGIC: &[u8; 8] = b"MESHCAP2";
+
+/// Which way a captured blob was going.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+  /// The blob was sent out through the transport, and it didn't report an error.
+  Sent,
+  /// The blob was received from the transport.
+  Received,
+}
+
+/// A single blob captured by a [`Recorder`](struct.Recorder.html).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Record {
+  /// Whether the blob was sent or received.
+  pub direction: Direction,
+  /// When the blob was sent or received.
+  pub time: SystemTime,
+  /// How many calls to `receive` there had been before this one, so all the blobs from a single call share a number.
+  /// Sent blobs have the number of the next call.
+  pub batch: u64,
+  /// The path the blob was sent along.
+  /// Transports don't report which path a blob was received on, so this is `None` for received blobs.
+  pub path: Option<String>,
+  /// The blob itself, exactly as sent or received.
+  pub blob: Vec<u8>,
+}
+
+type RawRecord = (u8, u64, u64, Option<String>, Vec<u8>);
+
+impl Record {
+  fn into_raw(self) -> RawRecord {
+    let direction = match self.direction {
+      Direction::Sent => 0,
+      Direction::Received => 1,
+    };
+    let micros = self.time.duration_since(UNIX_EPOCH).unwrap_or_default().as_micros() as u64;
+    (direction, micros, self.batch, self.path, self.blob)
+  }
+
+  fn from_raw((direction, micros, batch, path, blob): RawRecord) -> fail::Result<Record> {
+    let direction = match direction {
+      0 => Direction::Sent,
+      1 => Direction::Received,
+      d => {
+        return Err(fail::MesherFail::Other(
+          format!("invalid direction in capture: {}", d).into(),
+        ))
+      }
+    };
+    Ok(Record {
+      direction,
+      time: UNIX_EPOCH + Duration::from_micros(micros),
+      batch,
+      path,
+      blob,
+    })
+  }
+}
+
+/// Reads every record out of a capture written by a [`Recorder`](struct.Recorder.html).
+pub fn read_capture<R: Read>(mut from: R) -> fail::Result<Vec<Record>> {
+  let mut magic = [0; 8];
+  from
+    .read_exact(&mut magic)
+    .map_err(|e| fail::MesherFail::Other(Box::new(e)))?;
+  if &magic != MAGIC {
+    return Err(fail::MesherFail::Other("not a mesher capture".into()));
+  }
+  let mut records = vec![];
+  loop {
+    match bincode::deserialize_from::<_, RawRecord>(&mut from) {
+      Ok(raw) => records.push(Record::from_raw(raw)?),
+      Err(e) => match *e {
+        bincode::ErrorKind::Io(ref io) if io.kind() == std::io::ErrorKind::UnexpectedEof => break,
+        _ => return Err(fail::MesherFail::Other(e)),
+      },
+    }
+  }
+  Ok(records)
+}
+
+/// Wraps another transport, writing every blob sent or received through it to a capture.
+/// Blobs the transport fails to send aren't captured.
+///
+/// The capture can be read with [`read_capture`](fn.read_capture.html), or fed back into a mesher with [`Replay`](struct.Replay.html).
+/// Each record is written and flushed as soon as it's sent or received, so the capture is complete up to the moment a program crashes.
+///
+/// Since it needs something to wrap, this can't be created through [`Mesher::add_transport`](../struct.Mesher.html#method.add_transport):
+///
+/// ```no_run
+/// # use mesher::prelude::*;
+/// use mesher::debug_transports::{InMemory, Recorder};
+///
+/// # let mut some_mesher = Mesher::unsigned(vec![]);
+/// let inner = InMemory::new("inmem").expect("Failed to create InMemory transport");
+/// let recorder = Recorder::to_file(inner, "traffic.meshcap").expect("Failed to create capture file");
+/// some_mesher.add_transport_instance("inmem", recorder);
+/// ```
+pub struct Recorder<T: Transport> {
+  inner: T,
+  out: Box<dyn Write>,
+  batch: u64,
+}
+
+impl<T: Transport> Recorder<T> {
+  /// Wraps the transport, writing the capture to the given writer.
+  pub fn wrap<W: Write + 'static>(inner: T, mut out: W) -> fail::Result<Recorder<T>> {
+    out.write_all(MAGIC).map_err(|e| fail::MesherFail::Other(Box::new(e)))?;
+    Ok(Recorder {
+      inner,
+      out: Box::new(out),
+      batch: 0,
+    })
+  }
+
+  /// Wraps the transport, writing the capture to a file at the given path.
+  /// If the file already exists, it's overwritten.
+  pub fn to_file<P: AsRef<Path>>(inner: T, path: P) -> fail::Result<Recorder<T>> {
+    let file = File::create(path).map_err(|e| fail::MesherFail::Other(Box::new(e)))?;
+    Recorder::wrap(inner, file)
+  }
+
+  fn record(&mut self, record: Record) -> fail::Result<()> {
+    let bytes = bincode::serialize(&record.into_raw()).map_err(|e| fail::MesherFail::Other(Box::new(e)))?;
+    self
+      .out
+      .write_all(&bytes)
+      .and_then(|_| self.out.flush())
+      .map_err(|e| fail::MesherFail::Other(Box::new(e)))
+  }
+}
+
+impl<T: Transport> Transport for Recorder<T> {
+  fn new(_scheme: &str) -> fail::Result<Self> {
+    Err(fail::MesherFail::SetupFailure(
+      "Recorder must be created through Recorder::wrap or Recorder::to_file".to_owned(),
+    ))
+  }
+
+  fn send(&mut self, path: MesherUrl, blob: Vec<u8>) -> fail::Result<()> {
+    let path_str = path.to_string();
+    // blobs which never made it out weren't part of the traffic, so they're left out
+    self.inner.send(path, blob.clone())?;
+    self.record(Record {
+      direction: Direction::Sent,
+      time: SystemTime::now(),
+      batch: self.batch,
+      path: Some(path_str),
+      blob,
+    })
+  }
+
+  fn listen(&mut self, path: MesherUrl) -> fail::Result<()> {
+    self.inner.listen(path)
+  }
+
+  fn receive(&mut self) -> fail::Result<Vec<Vec<u8>>> {
+    let received = self.inner.receive()?;
+    let time = SystemTime::now();
+    for blob in &received {
+      self.record(Record {
+        direction: Direction::Received,
+        time,
+        batch: self.batch,
+        path: None,
+        blob: blob.clone(),
+      })?;
+    }
+    self.batch += 1;
+    Ok(received)
+  }
+}
+
+/// Feeds the blobs received in a capture back into a mesher, to reproduce what happened when it was recorded.
+///
+/// Each call to `receive` returns the blobs from the call to `receive` with the same number in the capture, until the capture runs out.
+/// Calls which didn't receive anything don't leave any records behind, so they're replayed as receiving nothing too, keeping everything after them in step.
+/// Sent blobs in the capture are ignored, and anything sent through this transport is discarded, as is anything passed to `listen`.
+pub struct Replay {
+  /// The blobs from each captured call that received any, by the call's number, in order.
+  batches: std::iter::Peekable<std::vec::IntoIter<(u64, Vec<Vec<u8>>)>>,
+  /// The number of the next call to `receive`.
+  next: u64,
+}
+
+impl Replay {
+  /// Creates a transport replaying the given records.
+  pub fn from_records(mut records: Vec<Record>) -> Replay {
+    records.retain(|r| r.direction == Direction::Received);
+    // keeps blobs from the same batch in the order they were received
+    records.sort_by_key(|r| r.batch);
+    let mut batches: Vec<(u64, Vec<Vec<u8>>)> = vec![];
+    for record in records {
+      match batches.last_mut() {
+        Some((number, batch)) if *number == record.batch => batch.push(record.blob),
+        _ => batches.push((record.batch, vec![record.blob])),
+      }
+    }
+    Replay {
+      batches: batches.into_iter().peekable(),
+      next: 0,
+    }
+  }
+
+  /// Creates a transport replaying the capture read from the reader.
+  pub fn from_reader<R: Read>(from: R) -> fail::Result<Replay> {
+    Ok(Replay::from_records(read_capture(from)?))
+  }
+
+  /// Creates a transport replaying the capture in the file at the given path.
+  pub fn open<P: AsRef<Path>>(path: P) -> fail::Result<Replay> {
+    let file = File::open(path).map_err(|e| fail::MesherFail::Other(Box::new(e)))?;
+    Replay::from_reader(BufReader::new(file))
+  }
+}
+
+impl Transport for Replay {
+  fn new(_scheme: &str) -> fail::Result<Self> {
+    Err(fail::MesherFail::SetupFailure(
+      "Replay must be created from a capture".to_owned(),
+    ))
+  }
+
+  fn send(&mut self, _path: MesherUrl, _blob: Vec<u8>) -> fail::Result<()> {
+    Ok(())
+  }
+
+  fn listen(&mut self, _path: MesherUrl) -> fail::Result<()> {
+    Ok(())
+  }
+
+  fn receive(&mut self) -> fail::Result<Vec<Vec<u8>>> {
+    let number = self.next;
+    self.next += 1;
+    match self.batches.peek() {
+      Some((next, _)) if *next == number => Ok(self.batches.next().map(|(_, b)| b).unwrap_or_default()),
+      _ => Ok(vec![]),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{debug_transports::InMemory, test_support::url};
+
+  fn capture_path(name: &str) -> std::path::PathBuf {
+    std::env::temp_dir().join(format!("mesher-capture-{}-{}.meshcap", name, std::process::id()))
+  }
+
+  #[test]
+  fn records_both_directions() {
+    let path = capture_path("both");
+    {
+      let inner = InMemory::new("inmem").expect("Failed to create");
+      let mut t = Recorder::to_file(inner, &path).expect("Failed to create recorder");
+      t.listen(url("inmem:capture_both")).expect("Failed to listen");
+      t.send(url("inmem:capture_both"), vec![1, 2]).expect("Failed to send");
+      t.send(url("inmem:capture_both"), vec![3]).expect("Failed to send");
+      assert_eq!(t.receive().expect("Failed to receive"), vec![vec![1, 2], vec![3]]);
+    }
+
+    let records = read_capture(File::open(&path).expect("Failed to open capture")).expect("Failed to read");
+    std::fs::remove_file(&path).expect("Failed to clean up");
+    let summary: Vec<_> = records
+      .iter()
+      .map(|r| (r.direction, r.path.as_deref(), r.blob.clone()))
+      .collect();
+    assert_eq!(
+      summary,
+      vec![
+        (Direction::Sent, Some("inmem:capture_both"), vec![1, 2]),
+        (Direction::Sent, Some("inmem:capture_both"), vec![3]),
+        (Direction::Received, None, vec![1, 2]),
+        (Direction::Received, None, vec![3]),
+      ]
+    );
+    assert_eq!(records[2].time, records[3].time);
+    let batches: Vec<_> = records.iter().map(|r| r.batch).collect();
+    assert_eq!(batches, vec![0, 0, 0, 0]);
+  }
+
+  /// A transport which can't send anything.
+  struct Broken;
+
+  impl Transport for Broken {
+    fn new(_scheme: &str) -> fail::Result<Self> {
+      Ok(Broken)
+    }
+
+    fn send(&mut self, _path: MesherUrl, _blob: Vec<u8>) -> fail::Result<()> {
+      Err(fail::MesherFail::SendFailure("broken".to_owned()))
+    }
+
+    fn listen(&mut self, _path: MesherUrl) -> fail::Result<()> {
+      Ok(())
+    }
+
+    fn receive(&mut self) -> fail::Result<Vec<Vec<u8>>> {
+      Ok(vec![])
+    }
+  }
+
+  #[test]
+  fn failed_sends_not_recorded() {
+    let path = capture_path("failed");
+    {
+      let mut t = Recorder::to_file(Broken, &path).expect("Failed to create recorder");
+      assert!(t.send(url("broken:nowhere"), vec![1]).is_err());
+    }
+
+    let records = read_capture(File::open(&path).expect("Failed to open capture")).expect("Failed to read");
+    std::fs::remove_file(&path).expect("Failed to clean up");
+    assert!(records.is_empty());
+  }
+
+  #[test]
+  fn replay_batches_by_number() {
+    // two receives can easily land on the same timestamp, so it mustn't merge them
+    let time = SystemTime::now();
+    let record = |batch, blob| Record {
+      direction: Direction::Received,
+      time,
+      batch,
+      path: None,
+      blob,
+    };
+    let mut replay = Replay::from_records(vec![record(0, vec![1]), record(0, vec![2]), record(1, vec![3])]);
+    assert_eq!(replay.receive().expect("Failed to receive"), vec![vec![1], vec![2]]);
+    assert_eq!(replay.receive().expect("Failed to receive"), vec![vec![3]]);
+    assert!(replay.receive().expect("Failed to receive").is_empty());
+  }
+
+  #[test]
+  fn replay_keeps_gaps() {
+    let time = SystemTime::now();
+    let record = |batch, blob| Record {
+      direction: Direction::Received,
+      time,
+      batch,
+      path: None,
+      blob,
+    };
+    let sent = Record {
+      direction: Direction::Sent,
+      path: Some("inmem:gaps".to_owned()),
+      ..record(1, vec![9])
+    };
+    let mut replay = Replay::from_records(vec![record(2, vec![1]), sent, record(2, vec![2]), record(5, vec![3])]);
+    let replayed: Vec<_> = (0..7).map(|_| replay.receive().expect("Failed to receive")).collect();
+    assert_eq!(
+      replayed,
+      vec![
+        vec![],
+        vec![],
+        vec![vec![1], vec![2]],
+        vec![],
+        vec![],
+        vec![vec![3]],
+        vec![],
+      ]
+    );
+  }
+
+  #[test]
+  fn rejects_non_captures() {
+    assert!(read_capture(&b"not a capture at all"[..]).is_err());
+  }
+
+  #[test]
+  fn replays_into_mesher() {
+    let path = capture_path("replay");
+    let (pk, sk) = encrypt::gen_keypair();
+    {
+      let mut m = Mesher::unsigned(vec![sk.clone()]);
+      let inner = InMemory::new("inmem").expect("Failed to create");
+      m.add_transport_instance(
+        "inmem",
+        Recorder::to_file(inner, &path).expect("Failed to create recorder"),
+      );
+      m.listen_on("inmem:capture_replay").expect("Failed to listen");
+
+      let mut packet = Packet::unsigned();
+      packet.add_hop("inmem:capture_replay".to_owned(), &pk);
+      packet.add_message(&[1], &pk);
+      m.launch(packet).expect("Failed to send");
+      let received = m.receive().expect("Failed to receive");
+      assert_eq!(received.len(), 1);
+    }
+
+    let mut m = Mesher::unsigned(vec![sk]);
+    m.add_transport_instance("inmem", Replay::open(&path).expect("Failed to open capture"));
+    std::fs::remove_file(&path).expect("Failed to clean up");
+    let replayed: Vec<_> = m
+      .receive()
+      .expect("Failed to receive")
+      .into_iter()
+      .map(|m| m.into_contents())
+      .collect();
+    assert_eq!(replayed, vec![vec![1]]);
+    // the capture only had one receive in it
+    assert_eq!(m.receive().expect("Failed to receive"), vec![]);
+  }
+}
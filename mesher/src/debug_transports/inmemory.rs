@@ -3,7 +3,7 @@ use std::{collections::HashMap, sync::Mutex};
 use crate::prelude::*;
 
 lazy_static! {
-  static ref PACKETS: Mutex<HashMap<String, Vec<Vec<u8>>>> = Mutex::new(HashMap::new());
+  static ref PACKETS: Mutex<HashMap<MesherUrl, Vec<Vec<u8>>>> = Mutex::new(HashMap::new());
 }
 
 /// A Transport implementation which "transports" data by storing and retrieving it from an in-memory store.
@@ -24,7 +24,7 @@ lazy_static! {
 /// ```
 #[allow(dead_code)]
 pub struct InMemory {
-  listening: Vec<String>,
+  listening: Vec<MesherUrl>,
 }
 
 impl Transport for InMemory {
@@ -32,7 +32,7 @@ impl Transport for InMemory {
     Ok(InMemory { listening: vec![] })
   }
 
-  fn send(&mut self, path: String, blob: Vec<u8>) -> fail::Result<()> {
+  fn send(&mut self, path: MesherUrl, blob: Vec<u8>) -> fail::Result<()> {
     let mut packets = PACKETS.lock().expect("poisoned lock?");
     match packets.get_mut(&path) {
       Some(v) => v.push(blob),
@@ -43,7 +43,7 @@ impl Transport for InMemory {
     Ok(())
   }
 
-  fn listen(&mut self, path: String) -> fail::Result<()> {
+  fn listen(&mut self, path: MesherUrl) -> fail::Result<()> {
     self.listening.push(path);
     Ok(())
   }
@@ -68,13 +68,14 @@ impl Transport for InMemory {
 #[cfg(test)]
 mod tests {
   use super::*;
+  use crate::test_support::url;
 
   #[test]
   fn send_and_receive() {
     let mut t = InMemory::new("inmem").expect("Failed to create");
 
-    t.listen("inmem:1".to_owned()).expect("Failed to listen");
-    t.send("inmem:1".to_owned(), vec![1, 2, 3, 4]).expect("Failed to send");
+    t.listen(url("inmem:1")).expect("Failed to listen");
+    t.send(url("inmem:1"), vec![1, 2, 3, 4]).expect("Failed to send");
     let received = t.receive().expect("Failed to receive");
     assert_eq!(received, vec![vec![1, 2, 3, 4]]);
   }
@@ -83,9 +84,9 @@ mod tests {
   fn send_2_and_receive() {
     let mut t = InMemory::new("inmem").expect("Failed to create");
 
-    t.listen("inmem:2".to_owned()).expect("Failed to listen");
-    t.send("inmem:2".to_owned(), vec![1, 2, 3, 4]).expect("Failed to send");
-    t.send("inmem:2".to_owned(), vec![5, 6, 7, 8]).expect("Failed to send");
+    t.listen(url("inmem:2")).expect("Failed to listen");
+    t.send(url("inmem:2"), vec![1, 2, 3, 4]).expect("Failed to send");
+    t.send(url("inmem:2"), vec![5, 6, 7, 8]).expect("Failed to send");
     let received = t.receive().expect("Failed to receive");
     assert_eq!(received, vec![vec![1, 2, 3, 4], vec![5, 6, 7, 8]]);
   }
@@ -94,9 +95,8 @@ mod tests {
   fn send_and_receive_out_of_order() {
     let mut t = InMemory::new("inmem").expect("Failed to create");
 
-    t.send("inmem:3".to_owned(), vec![9, 10, 11, 12])
-      .expect("Failed to send");
-    t.listen("inmem:3".to_owned()).expect("Failed to listen");
+    t.send(url("inmem:3"), vec![9, 10, 11, 12]).expect("Failed to send");
+    t.listen(url("inmem:3")).expect("Failed to listen");
     let received = t.receive().expect("Failed to receive");
     assert_eq!(received, vec![vec![9, 10, 11, 12]]);
   }
@@ -105,8 +105,18 @@ mod tests {
   fn receive_blank() {
     let mut t = InMemory::new("inmem").expect("Failed to create");
 
-    t.listen("inmem:4".to_owned()).expect("Failed to listen");
+    t.listen(url("inmem:4")).expect("Failed to listen");
     let received = t.receive().expect("Failed to receive");
     assert_eq!(received, Vec::<Vec<u8>>::new());
   }
+
+  #[test]
+  fn optional_slashes_same_path() {
+    let mut t = InMemory::new("inmem").expect("Failed to create");
+
+    t.listen(url("inmem:5")).expect("Failed to listen");
+    t.send(url("inmem://5"), vec![13]).expect("Failed to send");
+    let received = t.receive().expect("Failed to receive");
+    assert_eq!(received, vec![vec![13]]);
+  }
 }
@@ -6,3 +6,12 @@
 
 mod inmemory;
 pub use inmemory::InMemory;
+
+mod simulated;
+pub use simulated::{Faults, SimNetwork, SimNode, SimStats};
+
+mod capture;
+pub use capture::{read_capture, Direction, Record, Recorder, Replay};
+
+mod adversary;
+pub use adversary::{Adversary, Event, Leak, Observation, Shape, Watched};
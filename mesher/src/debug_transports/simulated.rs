@@ -0,0 +1,510 @@
+use std::{
+  collections::{HashMap, HashSet},
+  convert::TryFrom,
+  sync::{Arc, Mutex},
+  time::Duration,
+};
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::prelude::*;
+
+/// The faults a [`SimNetwork`](struct.SimNetwork.html) injects into every packet sent over it.
+///
+/// The default is a perfect network: nothing is dropped or duplicated, and everything arrives instantly, in order.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Faults {
+  /// The chance, from 0 to 1, that any given packet is silently dropped.
+  pub drop_rate: f64,
+  /// The chance, from 0 to 1, that any given packet is delivered twice.
+  pub duplicate_rate: f64,
+  /// The shortest time a packet can take to arrive.
+  pub min_delay: Duration,
+  /// The longest time a packet can take to arrive.
+  /// Each packet's delay is picked uniformly between this and `min_delay`.
+  pub max_delay: Duration,
+  /// Whether a packet can overtake one sent before it over the same link, if it happens to be given a shorter delay.
+  /// If this is false, every link is first-in-first-out.
+  pub reorder: bool,
+}
+
+impl Faults {
+  /// Checks that both rates are actual probabilities, i.e. from 0 to 1 and not NaN, and that both delays fit in a `u64` of nanoseconds, i.e. are under about 584 years.
+  fn validate(&self) -> fail::Result<()> {
+    for (name, rate) in &[("drop_rate", self.drop_rate), ("duplicate_rate", self.duplicate_rate)] {
+      if !(0.0..=1.0).contains(rate) {
+        return Err(fail::MesherFail::SetupFailure(format!(
+          "{} must be from 0 to 1, got {}",
+          name, rate
+        )));
+      }
+    }
+    for (name, delay) in &[("min_delay", self.min_delay), ("max_delay", self.max_delay)] {
+      if u64::try_from(delay.as_nanos()).is_err() {
+        return Err(fail::MesherFail::SetupFailure(format!(
+          "{} must be at most {:?}, got {:?}",
+          name,
+          Duration::from_nanos(u64::MAX),
+          delay
+        )));
+      }
+    }
+    Ok(())
+  }
+}
+
+impl Default for Faults {
+  fn default() -> Faults {
+    Faults {
+      drop_rate: 0.0,
+      duplicate_rate: 0.0,
+      min_delay: Duration::from_secs(0),
+      max_delay: Duration::from_secs(0),
+      reorder: false,
+    }
+  }
+}
+
+/// Counts of what's happened to the packets sent over a [`SimNetwork`](struct.SimNetwork.html).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SimStats {
+  /// How many packets nodes have tried to send.
+  pub sent: usize,
+  /// How many were lost, whether to random drops, partitions, missing links, or nobody listening.
+  pub dropped: usize,
+  /// How many extra copies were created by random duplication.
+  pub duplicated: usize,
+  /// How many have actually been picked up by a node.
+  pub delivered: usize,
+}
+
+struct InFlight {
+  deliver_at: Duration,
+  seq: u64,
+  to: String,
+  blob: Vec<u8>,
+}
+
+struct SimState {
+  rng: StdRng,
+  now: Duration,
+  faults: Faults,
+  links: Option<HashSet<(String, String)>>,
+  groups: HashMap<String, usize>,
+  listeners: HashMap<String, String>,
+  last_arrival: HashMap<(String, String), Duration>,
+  in_flight: Vec<InFlight>,
+  next_seq: u64,
+  stats: SimStats,
+}
+
+impl SimState {
+  fn can_reach(&self, from: &str, to: &str) -> bool {
+    if from == to {
+      return true;
+    }
+    let linked = match &self.links {
+      None => true,
+      Some(links) => links.contains(&(from.to_owned(), to.to_owned())),
+    };
+    let group = |node| self.groups.get(node).copied().unwrap_or(0);
+    linked && group(from) == group(to)
+  }
+
+  fn pick_delay(&mut self) -> Duration {
+    let (min, max) = (self.faults.min_delay, self.faults.max_delay);
+    if max <= min {
+      return min;
+    }
+    // `Faults::validate` made sure both fit
+    let nanos = |d: Duration| u64::try_from(d.as_nanos()).unwrap_or(u64::MAX);
+    let picked = self.rng.gen_range(nanos(min), nanos(max).saturating_add(1));
+    Duration::from_nanos(picked)
+  }
+
+  fn schedule(&mut self, from: &str, to: &str, blob: Vec<u8>) {
+    let mut deliver_at = self.now + self.pick_delay();
+    let link = (from.to_owned(), to.to_owned());
+    if !self.faults.reorder {
+      if let Some(&last) = self.last_arrival.get(&link) {
+        deliver_at = deliver_at.max(last);
+      }
+    }
+    self.last_arrival.insert(link, deliver_at);
+    self.in_flight.push(InFlight {
+      deliver_at,
+      seq: self.next_seq,
+      to: to.to_owned(),
+      blob,
+    });
+    self.next_seq += 1;
+  }
+
+  fn send(&mut self, from: &str, path: &MesherUrl, blob: Vec<u8>) {
+    self.stats.sent += 1;
+    let dest = path.authority();
+    let reachable = match self.listeners.get(dest) {
+      Some(to) => self.can_reach(from, to),
+      None => false,
+    };
+    // always roll both, so whether a packet is dropped doesn't change the random numbers later packets get
+    let dropped = self.rng.gen_bool(self.faults.drop_rate);
+    let duplicated = self.rng.gen_bool(self.faults.duplicate_rate);
+    if !reachable || dropped {
+      self.stats.dropped += 1;
+      return;
+    }
+    if duplicated {
+      self.stats.duplicated += 1;
+      self.schedule(from, dest, blob.clone());
+    }
+    self.schedule(from, dest, blob);
+  }
+
+  fn receive(&mut self, listening: &[String]) -> Vec<Vec<u8>> {
+    let now = self.now;
+    let (mut ready, rest): (Vec<_>, Vec<_>) = self
+      .in_flight
+      .drain(..)
+      .partition(|p| p.deliver_at <= now && listening.contains(&p.to));
+    self.in_flight = rest;
+    ready.sort_by_key(|p| (p.deliver_at, p.seq));
+    self.stats.delivered += ready.len();
+    ready.into_iter().map(|p| p.blob).collect()
+  }
+}
+
+/// A simulated network, for writing reproducible tests involving several meshers.
+///
+/// Unlike [`InMemory`](struct.InMemory.html), each `SimNetwork` is entirely separate from every other, so tests don't need to worry about picking unique paths.
+/// Beyond that, it can model an imperfect network, with the faults described in [`Faults`](struct.Faults.html), as well as partitions and arbitrary topologies.
+///
+/// Everything random is driven by a seeded RNG, and time is virtual: it only passes when [`SimNetwork::advance`](#method.advance) is called.
+/// As long as the meshers attached to it do the same things in the same order, a network with the same seed will behave identically every time.
+///
+/// Each mesher gets its own node, through [`SimNetwork::node`](#method.node).
+/// The authority of a path is the address on the network, e.g. `sim:alice` is the address `alice`:
+///
+/// ```
+/// # use mesher::prelude::*;
+/// use mesher::debug_transports::SimNetwork;
+///
+/// let net = SimNetwork::new(1234);
+/// let mut alice = Mesher::unsigned(vec![encrypt::gen_keypair().1]);
+/// alice.add_transport_instance("sim", net.node("alice"));
+/// alice.listen_on("sim:alice").expect("Failed to listen");
+/// ```
+///
+/// Clones share the same underlying network.
+#[derive(Clone)]
+pub struct SimNetwork {
+  state: Arc<Mutex<SimState>>,
+}
+
+impl SimNetwork {
+  /// Creates a perfect, fully connected network, seeding its RNG with the given seed.
+  pub fn new(seed: u64) -> SimNetwork {
+    SimNetwork::build(seed, Faults::default())
+  }
+
+  /// Creates a fully connected network which injects the given faults, seeding its RNG with the given seed.
+  ///
+  /// Fails if either rate in the faults isn't from 0 to 1.
+  pub fn with_faults(seed: u64, faults: Faults) -> fail::Result<SimNetwork> {
+    faults.validate()?;
+    Ok(SimNetwork::build(seed, faults))
+  }
+
+  fn build(seed: u64, faults: Faults) -> SimNetwork {
+    SimNetwork {
+      state: Arc::new(Mutex::new(SimState {
+        rng: StdRng::seed_from_u64(seed),
+        now: Duration::from_secs(0),
+        faults,
+        links: None,
+        groups: HashMap::new(),
+        listeners: HashMap::new(),
+        last_arrival: HashMap::new(),
+        in_flight: vec![],
+        next_seq: 0,
+        stats: SimStats::default(),
+      })),
+    }
+  }
+
+  fn state(&self) -> std::sync::MutexGuard<'_, SimState> {
+    self.state.lock().expect("poisoned lock?")
+  }
+
+  /// Creates the transport for a single node on the network, to be added to a mesher with [`Mesher::add_transport_instance`](../struct.Mesher.html#method.add_transport_instance).
+  ///
+  /// The name identifies the node for [`SimNetwork::link`](#method.link) and [`SimNetwork::partition`](#method.partition).
+  /// It's separate from the addresses the node listens on, though it's usually convenient to make them the same.
+  pub fn node(&self, name: &str) -> SimNode {
+    SimNode {
+      network: self.clone(),
+      name: name.to_owned(),
+      listening: vec![],
+    }
+  }
+
+  /// Changes the faults injected into packets sent from now on.
+  /// Packets already in flight are unaffected.
+  ///
+  /// Fails, leaving the current faults in place, if either rate in the new ones isn't from 0 to 1.
+  pub fn set_faults(&self, faults: Faults) -> fail::Result<()> {
+    faults.validate()?;
+    self.state().faults = faults;
+    Ok(())
+  }
+
+  /// Adds a link from each node to the other.
+  ///
+  /// By default, the network is fully connected: every node can reach every other.
+  /// Once any link is added, though, nodes can only reach the ones they're explicitly linked to.
+  pub fn link(&self, a: &str, b: &str) {
+    let mut state = self.state();
+    let links = state.links.get_or_insert_with(HashSet::new);
+    links.insert((a.to_owned(), b.to_owned()));
+    links.insert((b.to_owned(), a.to_owned()));
+  }
+
+  /// Removes the link between two nodes, if there was one.
+  ///
+  /// Note that this has no effect on a fully connected network; use [`SimNetwork::link`](#method.link) to set up the topology first.
+  pub fn unlink(&self, a: &str, b: &str) {
+    if let Some(links) = &mut self.state().links {
+      links.remove(&(a.to_owned(), b.to_owned()));
+      links.remove(&(b.to_owned(), a.to_owned()));
+    }
+  }
+
+  /// Splits the network into groups which can't reach each other, replacing any previous partition.
+  ///
+  /// Nodes not named in any group are put together into one more group.
+  /// Links still apply within each group.
+  pub fn partition(&self, groups: &[&[&str]]) {
+    let mut state = self.state();
+    state.groups.clear();
+    for (idx, group) in groups.iter().enumerate() {
+      for node in group.iter() {
+        state.groups.insert((*node).to_owned(), idx + 1);
+      }
+    }
+  }
+
+  /// Removes any partition, so every node can reach every other it's linked to.
+  pub fn heal(&self) {
+    self.state().groups.clear();
+  }
+
+  /// The current virtual time, i.e. how much time has been passed to [`SimNetwork::advance`](#method.advance).
+  pub fn now(&self) -> Duration {
+    self.state().now
+  }
+
+  /// Moves the virtual clock forward, making any packets due in that time available to receive.
+  pub fn advance(&self, by: Duration) {
+    self.state().now += by;
+  }
+
+  /// How many packets have been sent but not yet received, including ones which haven't arrived yet.
+  pub fn in_flight(&self) -> usize {
+    self.state().in_flight.len()
+  }
+
+  /// Counts of what's happened to the packets sent so far.
+  pub fn stats(&self) -> SimStats {
+    self.state().stats.clone()
+  }
+}
+
+/// A single node's connection to a [`SimNetwork`](struct.SimNetwork.html).
+///
+/// These can only be created through [`SimNetwork::node`](struct.SimNetwork.html#method.node); trying to create one through [`Mesher::add_transport`](../struct.Mesher.html#method.add_transport) will fail.
+pub struct SimNode {
+  network: SimNetwork,
+  name: String,
+  listening: Vec<String>,
+}
+
+impl Transport for SimNode {
+  fn new(_scheme: &str) -> fail::Result<Self> {
+    Err(fail::MesherFail::SetupFailure(
+      "SimNode must be created through SimNetwork::node".to_owned(),
+    ))
+  }
+
+  fn send(&mut self, path: MesherUrl, blob: Vec<u8>) -> fail::Result<()> {
+    self.network.state().send(&self.name, &path, blob);
+    Ok(())
+  }
+
+  fn listen(&mut self, path: MesherUrl) -> fail::Result<()> {
+    let addr = path.authority().to_owned();
+    self.network.state().listeners.insert(addr.clone(), self.name.clone());
+    self.listening.push(addr);
+    Ok(())
+  }
+
+  fn receive(&mut self) -> fail::Result<Vec<Vec<u8>>> {
+    Ok(self.network.state().receive(&self.listening))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::test_support::url;
+
+  fn pair(net: &SimNetwork) -> (SimNode, SimNode) {
+    let a = net.node("a");
+    let mut b = net.node("b");
+    b.listen(url("sim:b")).expect("Failed to listen");
+    (a, b)
+  }
+
+  #[test]
+  fn networks_are_separate() {
+    let (mut a1, mut b1) = pair(&SimNetwork::new(0));
+    let (_, mut b2) = pair(&SimNetwork::new(0));
+
+    a1.send(url("sim:b"), vec![1]).expect("Failed to send");
+    assert_eq!(b2.receive().expect("Failed to receive"), Vec::<Vec<u8>>::new());
+    assert_eq!(b1.receive().expect("Failed to receive"), vec![vec![1]]);
+  }
+
+  #[test]
+  fn delay_needs_time() {
+    let net = SimNetwork::with_faults(
+      0,
+      Faults {
+        min_delay: Duration::from_millis(10),
+        max_delay: Duration::from_millis(10),
+        ..Faults::default()
+      },
+    )
+    .expect("Invalid faults");
+    let (mut a, mut b) = pair(&net);
+
+    a.send(url("sim:b"), vec![1]).expect("Failed to send");
+    assert_eq!(b.receive().expect("Failed to receive"), Vec::<Vec<u8>>::new());
+    net.advance(Duration::from_millis(9));
+    assert_eq!(b.receive().expect("Failed to receive"), Vec::<Vec<u8>>::new());
+    net.advance(Duration::from_millis(1));
+    assert_eq!(b.receive().expect("Failed to receive"), vec![vec![1]]);
+  }
+
+  #[test]
+  fn fifo_without_reorder() {
+    let net = SimNetwork::with_faults(
+      7,
+      Faults {
+        max_delay: Duration::from_millis(100),
+        ..Faults::default()
+      },
+    )
+    .expect("Invalid faults");
+    let (mut a, mut b) = pair(&net);
+
+    for i in 0..20 {
+      a.send(url("sim:b"), vec![i]).expect("Failed to send");
+    }
+    net.advance(Duration::from_millis(100));
+    let received = b.receive().expect("Failed to receive");
+    assert_eq!(received, (0..20).map(|i| vec![i]).collect::<Vec<_>>());
+  }
+
+  #[test]
+  fn drops_and_duplicates() {
+    let net = SimNetwork::with_faults(
+      0,
+      Faults {
+        drop_rate: 1.0,
+        ..Faults::default()
+      },
+    )
+    .expect("Invalid faults");
+    let (mut a, mut b) = pair(&net);
+    a.send(url("sim:b"), vec![1]).expect("Failed to send");
+    assert_eq!(b.receive().expect("Failed to receive"), Vec::<Vec<u8>>::new());
+
+    net
+      .set_faults(Faults {
+        duplicate_rate: 1.0,
+        ..Faults::default()
+      })
+      .expect("Invalid faults");
+    a.send(url("sim:b"), vec![2]).expect("Failed to send");
+    assert_eq!(b.receive().expect("Failed to receive"), vec![vec![2], vec![2]]);
+
+    let stats = net.stats();
+    assert_eq!(stats.sent, 2);
+    assert_eq!(stats.dropped, 1);
+    assert_eq!(stats.duplicated, 1);
+    assert_eq!(stats.delivered, 2);
+  }
+
+  #[test]
+  fn rates_must_be_probabilities() {
+    for &rate in &[-0.1, 1.5, f64::NAN] {
+      let faults = Faults {
+        drop_rate: rate,
+        ..Faults::default()
+      };
+      assert!(SimNetwork::with_faults(0, faults).is_err());
+      let faults = Faults {
+        duplicate_rate: rate,
+        ..Faults::default()
+      };
+      let net = SimNetwork::new(0);
+      assert!(net.set_faults(faults).is_err());
+      assert_eq!(net.state().faults, Faults::default());
+    }
+  }
+
+  #[test]
+  fn delays_must_fit() {
+    let too_long = Duration::from_nanos(u64::MAX) + Duration::from_nanos(1);
+    let faults = Faults {
+      max_delay: too_long,
+      ..Faults::default()
+    };
+    assert!(SimNetwork::with_faults(0, faults).is_err());
+    let faults = Faults {
+      min_delay: too_long,
+      max_delay: too_long,
+      ..Faults::default()
+    };
+    assert!(SimNetwork::new(0).set_faults(faults).is_err());
+
+    // the longest allowed delay still works
+    let longest = Duration::from_nanos(u64::MAX);
+    let faults = Faults {
+      min_delay: longest - Duration::from_nanos(1),
+      max_delay: longest,
+      ..Faults::default()
+    };
+    let net = SimNetwork::with_faults(0, faults).expect("Failed to create network");
+    assert!(net.state().pick_delay() >= longest - Duration::from_nanos(1));
+  }
+
+  #[test]
+  fn partitions_and_links() {
+    let net = SimNetwork::new(0);
+    let (mut a, mut b) = pair(&net);
+
+    net.partition(&[&["a"]]);
+    a.send(url("sim:b"), vec![1]).expect("Failed to send");
+    assert_eq!(b.receive().expect("Failed to receive"), Vec::<Vec<u8>>::new());
+
+    net.heal();
+    net.link("a", "c");
+    a.send(url("sim:b"), vec![2]).expect("Failed to send");
+    assert_eq!(b.receive().expect("Failed to receive"), Vec::<Vec<u8>>::new());
+
+    net.link("a", "b");
+    a.send(url("sim:b"), vec![3]).expect("Failed to send");
+    assert_eq!(b.receive().expect("Failed to receive"), vec![vec![3]]);
+  }
+}
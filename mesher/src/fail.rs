@@ -30,6 +30,8 @@ pub enum MesherFail {
   InvalidURL(String),
   /// The URL's scheme hasn't been registered with the mesher, so it can't know what transport to use to move the packet.
   UnregisteredScheme(String),
+  /// The scheme is already registered to its own transport, so it can't be made an alias for another one.
+  SchemeInUse(String),
 
   /// The transport being asked to listen on a path wasn't able to.
   SetupFailure(String),
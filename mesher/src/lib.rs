@@ -1,4 +1,11 @@
 #![warn(clippy::all)]
+// `clippy::all` would override the exceptions in Cargo.toml, so they have to be repeated here
+#![allow(
+  clippy::get_first,
+  clippy::legacy_numeric_constants,
+  clippy::needless_borrow,
+  clippy::redundant_closure
+)]
 #![doc(test(attr(deny(warnings))))]
 
 //! For information on the concepts underlying this library, see [the project repo's README](https://github.com/nic-hartley/mesher/blob/master/README.md).
@@ -12,6 +19,8 @@
 //!   If you need them, e.g. for testing, there are debug transports available in [`mesher::debug_transports`](debug_transports/index.html).
 //! - [`struct Packet`](struct.Packet.html) makes building signed and unsigned packets easier.
 //!
+//! Paths are handed to transports as a [`struct MesherUrl`](struct.MesherUrl.html), so they don't need to do their own parsing.
+//!
 //! Also worth mentioning are the types in [`mesher::crypto`](crypto/index.html), which encapsulate the manipulation of crypto primitives.
 //! You'll use them to pass keys into `Mesher` and `Packet`.
 //! They do offer secure keygen, but this crate **will not** handle storing keys for you, if you need that.
@@ -32,11 +41,16 @@ pub mod fail;
 mod mesher;
 mod packet;
 mod transport;
+mod url;
+
+#[doc(hidden)]
+pub mod test_support;
 
 pub use crate::{
   mesher::{Mesher, Message},
   packet::Packet,
   transport::Transport,
+  url::MesherUrl,
 };
 
 pub mod prelude {
@@ -48,5 +62,5 @@ pub mod prelude {
   //! use mesher::prelude::*;
   //! ```
 
-  pub use crate::{crypto::*, fail, Mesher, Message, Packet, Transport};
+  pub use crate::{crypto::*, fail, Mesher, MesherUrl, Message, Packet, Transport};
 }
@@ -1,7 +1,10 @@
 //! Contains all the relevant bits and pieces for meshers themselves.
 
 use crate::prelude::*;
-use std::{collections::HashMap, sync::Arc};
+use std::{
+  collections::{HashMap, HashSet},
+  sync::Arc,
+};
 
 /// Represents a single message received by a mesher.
 #[derive(Debug, PartialEq)]
@@ -33,7 +36,9 @@ impl Message {
 /// It does not manage them in any other way, e.g. keeping them securely on-disk, transmitting them securely to the computer, etc.
 /// (However, you could well use messages passed through mesher to handle some of it.)
 pub struct Mesher {
-  transports: HashMap<String, Box<dyn Transport>>,
+  transports: Vec<Box<dyn Transport>>,
+  schemes: HashMap<String, usize>,
+  aliases: HashSet<String>,
   own_skeys: Vec<encrypt::SecretKey>,
   sender_pkeys: Vec<sign::PublicKey>,
 }
@@ -51,7 +56,9 @@ impl Mesher {
     );
 
     Mesher {
-      transports: HashMap::new(),
+      transports: vec![],
+      schemes: HashMap::new(),
+      aliases: HashSet::new(),
       own_skeys,
       sender_pkeys,
     }
@@ -65,25 +72,22 @@ impl Mesher {
   /// If a signing mesher receives an unsigned packet or vice versa, it'll be a no-op.
   pub fn unsigned(own_skeys: Vec<encrypt::SecretKey>) -> Mesher {
     Mesher {
-      transports: HashMap::new(),
+      transports: vec![],
+      schemes: HashMap::new(),
+      aliases: HashSet::new(),
       own_skeys,
       sender_pkeys: vec![],
     }
   }
 
-  /// Does the massaging necessary to get the transport based on the scheme in the path.
-  /// Will return the appropriate errors if any of it fails.
+  /// Gets the transport registered for the path's scheme, or the appropriate error if there isn't one.
   #[allow(clippy::borrowed_box)] // because we can't easily massage &mut Box<T> into &mut T, apparently
-  fn get_transport_for_path(&mut self, path: &str) -> fail::Result<&mut Box<dyn Transport>> {
-    let scheme = path
-      .splitn(2, ':')
-      .next()
-      .ok_or_else(|| fail::MesherFail::InvalidURL("no colon-delimited scheme segment".to_string()))?
-      .to_owned();
-    self
-      .transports
-      .get_mut(&scheme)
-      .ok_or(fail::MesherFail::UnregisteredScheme(scheme))
+  fn get_transport_for_path(&mut self, path: &MesherUrl) -> fail::Result<&mut Box<dyn Transport>> {
+    let idx = *self
+      .schemes
+      .get(path.scheme())
+      .ok_or_else(|| fail::MesherFail::UnregisteredScheme(path.scheme().to_owned()))?;
+    Ok(&mut self.transports[idx])
   }
 
   /// Does everything you'd expect when mesher receives a packet:
@@ -114,16 +118,52 @@ impl Mesher {
 
   // Sends the given bytes along the given path, getting the appropriate transport.
   fn send_data(&mut self, packet: &[u8], path: &str) -> fail::Result<()> {
-    self
-      .get_transport_for_path(path)?
-      .send(path.to_owned(), packet.to_vec())
+    let path = MesherUrl::parse(path)?;
+    self.get_transport_for_path(&path)?.send(path, packet.to_vec())
   }
 
   /// Adds a transport to the mesher, for it to send and receive data through.
   /// The scheme is passed to the transport exactly as-is.
   /// If an initialization error occurs in the transport, nothing is added to the internal scheme mapping.
+  ///
+  /// If the scheme was already registered, the old transport is replaced, along with any aliases pointing to it.
+  /// If it was registered as an alias, only the alias is pointed at the new transport; the one it was an alias for is left alone.
   pub fn add_transport<T: Transport + 'static>(&mut self, scheme: &str) -> fail::Result<()> {
-    self.transports.insert(scheme.to_owned(), Box::new(T::new(scheme)?));
+    self.add_transport_instance(scheme, T::new(scheme)?);
+    Ok(())
+  }
+
+  /// Adds an already-created transport to the mesher, rather than having the mesher create it.
+  ///
+  /// This is for transports which need more setup than just a scheme, e.g. ones attached to a shared object like a [`SimNetwork`](debug_transports/struct.SimNetwork.html).
+  /// Otherwise, it behaves identically to [`Mesher::add_transport`](#method.add_transport).
+  pub fn add_transport_instance<T: Transport + 'static>(&mut self, scheme: &str, transport: T) {
+    let transport = Box::new(transport);
+    match self.schemes.get(scheme) {
+      Some(&idx) if !self.aliases.remove(scheme) => self.transports[idx] = transport,
+      _ => {
+        self.schemes.insert(scheme.to_owned(), self.transports.len());
+        self.transports.push(transport);
+      }
+    }
+  }
+
+  /// Makes paths with the `alias` scheme go to the same transport instance as `scheme`, e.g. `tcp4` and `tcp6` to `tcp`.
+  ///
+  /// The transport will see the alias as the path's scheme, so it can treat the alias differently if it wants to.
+  /// `scheme` must already be registered, either through [`Mesher::add_transport`](#method.add_transport) or as an alias itself.
+  /// If `alias` was already registered as an alias, it's pointed at the new transport instead.
+  /// If it was registered as a scheme with its own transport, this fails with `SchemeInUse`, since that transport would be left unreachable.
+  pub fn add_alias(&mut self, alias: &str, scheme: &str) -> fail::Result<()> {
+    if self.schemes.contains_key(alias) && !self.aliases.contains(alias) {
+      return Err(fail::MesherFail::SchemeInUse(alias.to_owned()));
+    }
+    let idx = *self
+      .schemes
+      .get(scheme)
+      .ok_or_else(|| fail::MesherFail::UnregisteredScheme(scheme.to_owned()))?;
+    self.schemes.insert(alias.to_owned(), idx);
+    self.aliases.insert(alias.to_owned());
     Ok(())
   }
 
@@ -131,7 +171,8 @@ impl Mesher {
   /// This determines the transport to connect to based on the scheme, then just tells it to listen.
   /// The exact behavior depends on the transport, but will generally involve either setting up some listener, or adding it to a list of internal paths to poll.
   pub fn listen_on(&mut self, path: &str) -> fail::Result<()> {
-    self.get_transport_for_path(path)?.listen(path.to_owned())
+    let path = MesherUrl::parse(path)?;
+    self.get_transport_for_path(&path)?.listen(path)
   }
 
   /// Sends a packet out.
@@ -147,7 +188,7 @@ impl Mesher {
       return Err(fail::MesherFail::NoKeys);
     }
     let mut packets = vec![];
-    for (_, transport) in self.transports.iter_mut() {
+    for transport in self.transports.iter_mut() {
       packets.append(&mut transport.receive()?);
     }
     let mut messages = vec![];
@@ -172,6 +213,73 @@ mod tests {
     }
   }
 
+  #[test]
+  fn aliases_share_transport() {
+    use crate::debug_transports::InMemory;
+
+    let (pk, sk) = encrypt::gen_keypair();
+    let mut m = Mesher::unsigned(vec![sk]);
+    m.add_transport::<InMemory>("inmem").expect("Failed to add transport");
+    m.add_alias("mem", "inmem").expect("Failed to add alias");
+    m.listen_on("mem:aliases_share_transport").expect("Failed to listen");
+
+    let mut packet = Packet::unsigned();
+    packet.add_hop("mem:aliases_share_transport".to_owned(), &pk);
+    packet.add_message(&[1], &pk);
+    m.launch(packet).expect("Failed to send");
+
+    let received = m.receive().expect("Failed to receive");
+    assert_eq!(received.len(), 1);
+    assert_eq!(received[0].contents(), &[1]);
+  }
+
+  #[test]
+  fn transport_added_over_alias() {
+    use crate::debug_transports::InMemory;
+
+    let (pk, sk) = encrypt::gen_keypair();
+    let mut m = Mesher::unsigned(vec![sk]);
+    m.add_transport::<InMemory>("inmem").expect("Failed to add transport");
+    m.add_alias("mem", "inmem").expect("Failed to add alias");
+    m.listen_on("inmem:transport_added_over_alias")
+      .expect("Failed to listen");
+    m.add_transport::<InMemory>("mem").expect("Failed to add transport");
+
+    let mut packet = Packet::unsigned();
+    packet.add_hop("inmem:transport_added_over_alias".to_owned(), &pk);
+    packet.add_message(&[1], &pk);
+    m.launch(packet).expect("Failed to send");
+
+    // the original transport is still the one listening, so it has to still be there
+    let received = m.receive().expect("Failed to receive");
+    assert_eq!(received.len(), 1);
+    assert_eq!(received[0].contents(), &[1]);
+  }
+
+  #[test]
+  fn alias_needs_registered_scheme() {
+    let mut m = Mesher::unsigned(vec![]);
+    match m.add_alias("tcp4", "tcp") {
+      Err(fail::MesherFail::UnregisteredScheme(s)) => assert_eq!(s, "tcp"),
+      _ => unreachable!(),
+    }
+  }
+
+  #[test]
+  fn alias_cant_shadow_scheme() {
+    use crate::debug_transports::InMemory;
+
+    let mut m = Mesher::unsigned(vec![]);
+    m.add_transport::<InMemory>("inmem").expect("Failed to add transport");
+    m.add_transport::<InMemory>("mem").expect("Failed to add transport");
+    match m.add_alias("mem", "inmem") {
+      Err(fail::MesherFail::SchemeInUse(s)) => assert_eq!(s, "mem"),
+      _ => unreachable!(),
+    }
+    m.add_alias("mem2", "inmem").expect("Failed to add alias");
+    m.add_alias("mem2", "mem").expect("Failed to repoint alias");
+  }
+
   #[test]
   #[should_panic(expected = "Provide sender keys. If you don't want any, use Mesher::unsigned instead.")]
   fn signed_mesher_empty_keys_fails() {
@@ -49,7 +49,7 @@ pub(crate) enum Chunk {
 impl Chunk {
   /// Converts a series of bytes from [`Chunk::serialize`](#method.serialize) back to a Chunk, if possible.
   /// Best considered a black box, so it can change freely.
-  fn deserialize(mut from: Vec<u8>, replies: &[Arc<Vec<Vec<u8>>>]) -> Result<Chunk, ()> {
+  pub(crate) fn deserialize(mut from: Vec<u8>, replies: &[Arc<Vec<Vec<u8>>>]) -> Result<Chunk, ()> {
     match from.get(0) {
       Some(0) => {
         let reply = match from[1] {
@@ -0,0 +1,30 @@
+//! Helpers for the tests of this crate and the transport crates built on it.
+//! Not part of the API, so they can change at any time.
+
+use crate::prelude::*;
+
+use std::{
+  thread::sleep,
+  time::{Duration, Instant},
+};
+
+/// Parses a URL the test knows is valid.
+pub fn url(s: &str) -> MesherUrl {
+  MesherUrl::parse(s).expect("Invalid URL")
+}
+
+/// Keeps receiving until `count` blobs have arrived or `within` has passed, returning whatever did arrive.
+pub fn receive_for<T: Transport>(t: &mut T, count: usize, within: Duration) -> Vec<Vec<u8>> {
+  let start = Instant::now();
+  let mut received = vec![];
+  while received.len() < count && start.elapsed() < within {
+    received.append(&mut t.receive().expect("Failed to receive"));
+    sleep(Duration::from_millis(10));
+  }
+  received
+}
+
+/// Like `receive_for`, giving up after 5 seconds, which is plenty for anything running locally.
+pub fn receive_within<T: Transport>(t: &mut T, count: usize) -> Vec<Vec<u8>> {
+  receive_for(t, count, Duration::from_secs(5))
+}
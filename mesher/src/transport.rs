@@ -9,6 +9,7 @@ use crate::prelude::*;
 /// And, of course, it ensures that mesher can operate identically over any communication channel.
 pub trait Transport {
   /// Creates a new instance of this transport method, associated with the given scheme.
+  /// The same instance may later be given aliases, i.e. other schemes it'll also be used for; see [`Mesher::add_alias`](struct.Mesher.html#method.add_alias).
   /// This isn't meant to be called by the end user; it's used by mesher internally.
   /// It should perform as little error-prone work as possible, and what errors happen should be fixable (possibly just by waiting and retrying) to the greatest extent possible.
   fn new(scheme: &str) -> fail::Result<Self>
@@ -17,13 +18,13 @@ pub trait Transport {
 
   /// Sends some bytes through this transport method.
   /// The transport should *not* care about the bytes being sent, only (possibly) the quantity.
-  /// The path will include the `scheme:` prefix.
-  fn send(&mut self, path: String, blob: Vec<u8>) -> fail::Result<()>;
+  /// The path's scheme will be the one this transport was created with, or one of its aliases.
+  fn send(&mut self, path: MesherUrl, blob: Vec<u8>) -> fail::Result<()>;
 
   /// Set up this transport to listen on the given path.
   /// This does not return any messages -- it just tells the transport to listen on/poll on this route to receive future messages.
-  /// The path will include the `scheme:` prefix.
-  fn listen(&mut self, path: String) -> fail::Result<()>;
+  /// The path's scheme will be the one this transport was created with, or one of its aliases.
+  fn listen(&mut self, path: MesherUrl) -> fail::Result<()>;
 
   /// Actually receive the pending messages.
   /// In listen-based transports, this will simply pull the received messages from the listener.
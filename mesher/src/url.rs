@@ -0,0 +1,292 @@
+//! Contains the parsed form of the paths that transports are handed.
+
+use crate::prelude::*;
+
+use std::{
+  fmt,
+  hash::{Hash, Hasher},
+  str::FromStr,
+};
+
+/// A path to send packets along or listen on, parsed into its component pieces.
+///
+/// Paths look like URLs, but are a little more permissive, to keep them short and readable in packets:
+///
+/// - `scheme://authority/path?query` is parsed exactly like a URL.
+/// - `scheme:authority/path?query` is also accepted; the `//` is optional.
+///   This is what lets `tcp:localhost:18540` have `localhost:18540` as its authority.
+/// - `scheme:/path?query` has no authority, only a path, e.g. `unix:/run/mesher/node.sock`.
+///
+/// The query is split into `key=value` pairs on `&`, with both keys and values percent-decoded.
+/// A key with no `=` is given an empty value.
+/// There are no fragments; a `#` is treated like any other character.
+///
+/// Transports receive these instead of raw strings, so that they can pull out the pieces they need without reparsing.
+/// The original string is kept, and is what's returned by [`MesherUrl::as_str`](#method.as_str) and `Display`.
+/// Comparisons use the parsed pieces rather than it, so e.g. `tcp:localhost:18540` and `tcp://localhost:18540` are equal.
+#[derive(Debug, Clone)]
+pub struct MesherUrl {
+  raw: String,
+  scheme: String,
+  authority: String,
+  path: String,
+  query: Vec<(String, String)>,
+}
+
+impl MesherUrl {
+  /// Parses a path into its pieces, failing with [`MesherFail::InvalidURL`](fail/enum.MesherFail.html#variant.InvalidURL) if it's malformed.
+  pub fn parse(raw: &str) -> fail::Result<MesherUrl> {
+    let invalid = |why: &str| fail::MesherFail::InvalidURL(format!("{}: {}", why, raw));
+
+    let colon = raw
+      .find(':')
+      .ok_or_else(|| invalid("no colon-delimited scheme segment"))?;
+    let (scheme, rest) = (&raw[..colon], &raw[colon + 1..]);
+    let mut scheme_chars = scheme.chars();
+    match scheme_chars.next() {
+      Some(c) if c.is_ascii_alphabetic() => (),
+      _ => return Err(invalid("scheme must start with a letter")),
+    }
+    if !scheme_chars.all(|c| c.is_ascii_alphanumeric() || c == '+' || c == '-' || c == '.') {
+      return Err(invalid("scheme contains invalid characters"));
+    }
+
+    let (rest, query) = match rest.find('?') {
+      Some(idx) => (&rest[..idx], Some(&rest[idx + 1..])),
+      None => (rest, None),
+    };
+    let rest = rest.strip_prefix("//").unwrap_or(rest);
+    let (authority, path) = match rest.find('/') {
+      Some(idx) => rest.split_at(idx),
+      None => (rest, ""),
+    };
+
+    let query = match query {
+      Some(q) => q
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+          let (key, value) = match pair.find('=') {
+            Some(idx) => (&pair[..idx], &pair[idx + 1..]),
+            None => (pair, ""),
+          };
+          Ok((percent_decode(key)?, percent_decode(value)?))
+        })
+        .collect::<Result<_, ()>>()
+        .map_err(|_| invalid("invalid percent-encoding in query"))?,
+      None => vec![],
+    };
+
+    Ok(MesherUrl {
+      raw: raw.to_owned(),
+      scheme: scheme.to_owned(),
+      authority: authority.to_owned(),
+      path: path.to_owned(),
+      query,
+    })
+  }
+
+  /// The full, original path, exactly as it was parsed.
+  pub fn as_str(&self) -> &str {
+    &self.raw
+  }
+
+  /// The scheme, which determines the transport used, e.g. `tcp` in `tcp:localhost:18540`.
+  pub fn scheme(&self) -> &str {
+    &self.scheme
+  }
+
+  /// The authority, e.g. `localhost:18540` in `tcp:localhost:18540`.
+  /// Empty if there isn't one.
+  pub fn authority(&self) -> &str {
+    &self.authority
+  }
+
+  /// Splits the authority into its host and port, ignoring any `user@` prefix.
+  fn host_and_port(&self) -> (&str, Option<&str>) {
+    let hostport = match self.authority.rfind('@') {
+      Some(idx) => &self.authority[idx + 1..],
+      None => &self.authority,
+    };
+    if let Some(rest) = hostport.strip_prefix('[') {
+      return match rest.find(']') {
+        Some(idx) => (&rest[..idx], rest[idx + 1..].strip_prefix(':')),
+        None => (hostport, None),
+      };
+    }
+    match hostport.rfind(':') {
+      Some(idx) => (&hostport[..idx], Some(&hostport[idx + 1..])),
+      None => (hostport, None),
+    }
+  }
+
+  /// The host in the authority, e.g. `localhost` in `tcp:localhost:18540`.
+  /// IPv6 addresses have their brackets removed, e.g. `::1` in `tcp:[::1]:18540`.
+  pub fn host(&self) -> &str {
+    self.host_and_port().0
+  }
+
+  /// The port in the authority, e.g. `18540` in `tcp:localhost:18540`.
+  /// `None` if there isn't one, or it isn't a valid port number.
+  pub fn port(&self) -> Option<u16> {
+    self.host_and_port().1.and_then(|p| p.parse().ok())
+  }
+
+  /// The path, including its leading `/`, e.g. `/run/mesher/node.sock` in `unix:/run/mesher/node.sock`.
+  /// Empty if there isn't one.
+  pub fn path(&self) -> &str {
+    &self.path
+  }
+
+  /// All of the decoded query parameters, in the order they appeared.
+  pub fn query(&self) -> &[(String, String)] {
+    &self.query
+  }
+
+  /// The value of the first query parameter with the given key, if there is one.
+  pub fn query_param(&self, key: &str) -> Option<&str> {
+    self.query.iter().find(|(k, _)| k == key).map(|(_, v)| v.as_str())
+  }
+}
+
+impl PartialEq for MesherUrl {
+  fn eq(&self, other: &MesherUrl) -> bool {
+    self.scheme == other.scheme
+      && self.authority == other.authority
+      && self.path == other.path
+      && self.query == other.query
+  }
+}
+
+impl Eq for MesherUrl {}
+
+impl Hash for MesherUrl {
+  fn hash<H: Hasher>(&self, state: &mut H) {
+    self.scheme.hash(state);
+    self.authority.hash(state);
+    self.path.hash(state);
+    self.query.hash(state);
+  }
+}
+
+impl FromStr for MesherUrl {
+  type Err = fail::MesherFail;
+
+  fn from_str(s: &str) -> fail::Result<MesherUrl> {
+    MesherUrl::parse(s)
+  }
+}
+
+impl fmt::Display for MesherUrl {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.write_str(&self.raw)
+  }
+}
+
+fn percent_decode(s: &str) -> Result<String, ()> {
+  let bytes = s.as_bytes();
+  let mut out = Vec::with_capacity(bytes.len());
+  let mut i = 0;
+  while i < bytes.len() {
+    if bytes[i] == b'%' {
+      // from_str_radix would also accept a sign, e.g. %+1
+      let hex = s
+        .get(i + 1..i + 3)
+        .filter(|h| h.bytes().all(|b| b.is_ascii_hexdigit()))
+        .ok_or(())?;
+      out.push(u8::from_str_radix(hex, 16).map_err(|_| ())?);
+      i += 3;
+    } else {
+      out.push(bytes[i]);
+      i += 1;
+    }
+  }
+  String::from_utf8(out).map_err(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn opaque_authority() {
+    let url = MesherUrl::parse("tcp:localhost:18540").expect("Failed to parse");
+    assert_eq!(url.scheme(), "tcp");
+    assert_eq!(url.authority(), "localhost:18540");
+    assert_eq!(url.path(), "");
+    assert!(url.query().is_empty());
+    assert_eq!(url.as_str(), "tcp:localhost:18540");
+  }
+
+  #[test]
+  fn full_url() {
+    let url = MesherUrl::parse("tcp://[::1]:18540/some/path?a=1&b=two%20words&flag").expect("Failed to parse");
+    assert_eq!(url.scheme(), "tcp");
+    assert_eq!(url.authority(), "[::1]:18540");
+    assert_eq!(url.path(), "/some/path");
+    assert_eq!(url.query_param("a"), Some("1"));
+    assert_eq!(url.query_param("b"), Some("two words"));
+    assert_eq!(url.query_param("flag"), Some(""));
+    assert_eq!(url.query_param("missing"), None);
+  }
+
+  #[test]
+  fn host_and_port() {
+    let cases = [
+      ("tcp:localhost:18540", "localhost", Some(18540)),
+      ("tcp://[::1]:18540/x", "::1", Some(18540)),
+      ("tcp:[::1]", "::1", None),
+      ("socks://user@example.onion:80", "example.onion", Some(80)),
+      ("inmem:name", "name", None),
+      ("tcp:host:notaport", "host", None),
+    ];
+    for (raw, host, port) in cases.iter() {
+      let url = MesherUrl::parse(raw).expect("Failed to parse");
+      assert_eq!((url.host(), url.port()), (*host, *port), "{}", raw);
+    }
+  }
+
+  #[test]
+  fn path_only() {
+    let url = MesherUrl::parse("unix:/run/mesher/node.sock?perm=0660").expect("Failed to parse");
+    assert_eq!(url.scheme(), "unix");
+    assert_eq!(url.authority(), "");
+    assert_eq!(url.path(), "/run/mesher/node.sock");
+    assert_eq!(url.query(), &[("perm".to_owned(), "0660".to_owned())]);
+  }
+
+  #[test]
+  fn optional_slashes_equal() {
+    use std::collections::HashSet;
+
+    let short = MesherUrl::parse("tcp:localhost:18540/x?a=1").expect("Failed to parse");
+    let long = MesherUrl::parse("tcp://localhost:18540/x?a=%31").expect("Failed to parse");
+    assert_eq!(short, long);
+    assert_ne!(short.as_str(), long.as_str());
+    let set: HashSet<_> = vec![short, long].into_iter().collect();
+    assert_eq!(set.len(), 1);
+    assert_ne!(
+      MesherUrl::parse("tcp:localhost:18540").expect("Failed to parse"),
+      MesherUrl::parse("tcp:localhost:18541").expect("Failed to parse")
+    );
+  }
+
+  #[test]
+  fn invalid_urls() {
+    for bad in &[
+      "no scheme here",
+      ":empty",
+      "1tcp:localhost",
+      "t cp:localhost",
+      "tcp:x?a=%zz",
+      "tcp:x?a=%+1",
+      "tcp:x?a=%-1",
+      "tcp:x?a=%4",
+    ] {
+      match MesherUrl::parse(bad) {
+        Err(fail::MesherFail::InvalidURL(_)) => (),
+        other => panic!("{:?} parsed as {:?}", bad, other),
+      }
+    }
+  }
+}
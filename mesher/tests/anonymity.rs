@@ -0,0 +1,42 @@
+use mesher::debug_transports::{Adversary, SimNetwork};
+use mesher::prelude::*;
+
+fn make_mesher(net: &SimNetwork, adversary: &Adversary, name: &str) -> (Mesher, encrypt::PublicKey) {
+  let (pk, sk) = encrypt::gen_keypair();
+  let mut m = Mesher::unsigned(vec![sk.clone()]);
+  m.add_transport_instance("sim", adversary.watch(name, vec![sk], net.node(name)));
+  m.listen_on(&format!("sim:{}", name)).expect("failed to listen");
+  (m, pk)
+}
+
+#[test]
+fn guarantees_hold() {
+  let net = SimNetwork::new(0);
+  let adversary = Adversary::simulated(&net);
+  let (mut src, src_pk) = make_mesher(&net, &adversary, "src");
+  let (mut n1, n1_pk) = make_mesher(&net, &adversary, "n1");
+  let (mut n2, n2_pk) = make_mesher(&net, &adversary, "n2");
+  let (mut n3, n3_pk) = make_mesher(&net, &adversary, "n3");
+  let (mut dest, dest_pk) = make_mesher(&net, &adversary, "dest");
+
+  for i in 0..16 {
+    adversary.next_journey();
+    let mut packet = Packet::unsigned();
+    packet.add_hop("sim:n1".to_owned(), &src_pk);
+    packet.add_hop("sim:n2".to_owned(), &n1_pk);
+    packet.add_hop("sim:n3".to_owned(), &n2_pk);
+    packet.add_hop("sim:dest".to_owned(), &n3_pk);
+    packet.add_message(&[i], &dest_pk);
+    src.launch(packet).expect("Failed to send");
+
+    for node in &mut [&mut n1, &mut n2, &mut n3] {
+      assert!(node.receive().expect("Failed to receive").is_empty());
+    }
+    let received = dest.receive().expect("Failed to receive");
+    assert_eq!(received[0].contents(), &[i]);
+  }
+
+  assert_eq!(adversary.observations_of("n2").len(), 32);
+  assert_eq!(adversary.check_position_hiding(), Ok(()));
+  assert_eq!(adversary.check_unlinkable(), Ok(()));
+}
@@ -0,0 +1,114 @@
+use mesher::debug_transports::{Faults, SimNetwork};
+use mesher::prelude::*;
+
+use std::time::Duration;
+
+fn make_mesher(net: &SimNetwork, name: &str) -> (Mesher, encrypt::PublicKey) {
+  let (pk, sk) = encrypt::gen_keypair();
+  let mut m = Mesher::unsigned(vec![sk]);
+  m.add_transport_instance("sim", net.node(name));
+  m.listen_on(&format!("sim:{}", name)).expect("failed to listen");
+  (m, pk)
+}
+
+fn contents(msgs: Vec<Message>) -> Vec<Vec<u8>> {
+  msgs.into_iter().map(|m| m.into_contents()).collect()
+}
+
+#[test]
+fn routes_along_line() {
+  let net = SimNetwork::with_faults(
+    1,
+    Faults {
+      min_delay: Duration::from_millis(5),
+      max_delay: Duration::from_millis(20),
+      ..Faults::default()
+    },
+  )
+  .expect("Invalid faults");
+  net.link("src", "n1");
+  net.link("n1", "dest");
+
+  let (mut src, src_pk) = make_mesher(&net, "src");
+  let (mut n1, n1_pk) = make_mesher(&net, "n1");
+  let (mut dest, dest_pk) = make_mesher(&net, "dest");
+
+  // src can't reach dest directly
+  let mut packet = Packet::unsigned();
+  packet.add_hop("sim:dest".to_owned(), &src_pk);
+  packet.add_message(&[1], &dest_pk);
+  src.launch(packet).expect("Failed to send");
+
+  let mut packet = Packet::unsigned();
+  packet.add_hop("sim:n1".to_owned(), &src_pk);
+  packet.add_hop("sim:dest".to_owned(), &n1_pk);
+  packet.add_message(&[2], &dest_pk);
+  src.launch(packet).expect("Failed to send");
+
+  net.advance(Duration::from_millis(20));
+  n1.receive().expect("Failed to receive");
+  assert_eq!(
+    contents(dest.receive().expect("Failed to receive")),
+    Vec::<Vec<u8>>::new()
+  );
+  net.advance(Duration::from_millis(20));
+  assert_eq!(contents(dest.receive().expect("Failed to receive")), vec![vec![2]]);
+  assert_eq!(net.stats().dropped, 1);
+}
+
+#[test]
+fn replies_cross_network() {
+  let net = SimNetwork::new(2);
+  let (mut sender, sender_pk) = make_mesher(&net, "sender");
+  let (mut receiver, receiver_pk) = make_mesher(&net, "receiver");
+
+  let mut packet = Packet::unsigned();
+  packet.add_hop("sim:receiver".to_owned(), &sender_pk);
+  let mut rh = packet.add_reply_path().expect("Failed to add reply path");
+  rh.add_hop("sim:sender".to_owned(), &receiver_pk);
+  rh.use_for_message(&[1], &receiver_pk);
+  sender.launch(packet).expect("Failed to send");
+
+  let messages = receiver.receive().expect("Failed to receive");
+  assert_eq!(messages[0].contents(), &[1]);
+
+  let mut reply = Packet::unsigned();
+  reply.reply_to(&messages[0]).expect("No reply path");
+  reply.add_message(&[2], &sender_pk);
+  receiver.launch(reply).expect("Failed to reply");
+
+  assert_eq!(contents(sender.receive().expect("Failed to receive")), vec![vec![2]]);
+}
+
+/// Sends the same message repeatedly until it arrives, returning how many tries it took.
+fn retry_until_received(seed: u64) -> usize {
+  let net = SimNetwork::with_faults(
+    seed,
+    Faults {
+      drop_rate: 0.7,
+      ..Faults::default()
+    },
+  )
+  .expect("Invalid faults");
+  let (mut src, src_pk) = make_mesher(&net, "src");
+  let (mut dest, dest_pk) = make_mesher(&net, "dest");
+
+  for tries in 1..=100 {
+    let mut packet = Packet::unsigned();
+    packet.add_hop("sim:dest".to_owned(), &src_pk);
+    packet.add_message(&[tries as u8], &dest_pk);
+    src.launch(packet).expect("Failed to send");
+    net.advance(Duration::from_millis(1));
+    if !dest.receive().expect("Failed to receive").is_empty() {
+      return tries;
+    }
+  }
+  panic!("never received");
+}
+
+#[test]
+fn retries_are_reproducible() {
+  for seed in 0..10 {
+    assert_eq!(retry_until_received(seed), retry_until_received(seed));
+  }
+}